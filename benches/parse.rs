@@ -0,0 +1,165 @@
+//! Benchmarks for message parsing
+//!
+//! Run with `cargo bench`. The `dispatch` group compares `parse_message`, which
+//! matches on the raw code bytes, against the previous version that UTF-8
+//! validated the code and matched on a `&str`.
+
+use criterion::{Criterion, black_box, criterion_group, criterion_main};
+use schengen::protocol::{
+    Message, MessageClientClipboard, MessageClientInfo, MessageClipboardData, MessageClose,
+    MessageCursorEntered, MessageCursorLeft, MessageDragInfo, MessageFileTransfer,
+    MessageHelloBarrier, MessageHelloSynergy, MessageIncompatibleVersion,
+    MessageInfoAcknowledgment, MessageKeepAlive, MessageKeyDown, MessageKeyDownWithLanguage,
+    MessageKeyRepeat, MessageKeyUp, MessageLegacySynergy, MessageMouseButtonDown,
+    MessageMouseButtonUp, MessageMouseMove, MessageMouseRelativeMove, MessageMouseWheel,
+    MessageNoOp, MessageProtocolError, MessageQueryInfo, MessageResetOptions,
+    MessageScreenSaverChange, MessageSecureEncryption, MessageServerBusy, MessageSetOptions,
+    MessageUnknownClient, ProtocolError, ProtocolMessage, Result, parse_message, parse_message_ref,
+    parse_message_with_length,
+};
+
+/// A typical mix of post-handshake traffic, encoded without the length prefix
+fn sample_traffic() -> Vec<Vec<u8>> {
+    let messages: Vec<Message> = vec![
+        MessageMouseMove { x: 100, y: 200 }.into(),
+        MessageMouseMove { x: 101, y: 201 }.into(),
+        MessageMouseWheel {
+            xdelta: 0,
+            ydelta: 120,
+        }
+        .into(),
+        MessageKeyDown {
+            keyid: 0x61,
+            mask: 0,
+            button: 0x26,
+        }
+        .into(),
+        MessageKeyUp {
+            keyid: 0x61,
+            mask: 0,
+            button: 0x26,
+        }
+        .into(),
+        MessageKeepAlive.into(),
+    ];
+    messages
+        .iter()
+        .map(|m| m.to_bytes()[4..].to_vec())
+        .collect()
+}
+
+/// `parse_message` as it was before dispatching on the raw code bytes: the code is
+/// UTF-8 validated and matched as a `&str`
+fn parse_message_str(data: &[u8]) -> Result<Message> {
+    if data.len() < 4 {
+        return Err(ProtocolError::InsufficientData {
+            offset: 0,
+            expected: 4,
+            actual: data.len(),
+        });
+    }
+
+    let code = std::str::from_utf8(&data[0..4]).map_err(|_| ProtocolError::InvalidMessageCode)?;
+
+    let msg = match code {
+        "CNOP" => Message::NoOp(MessageNoOp::from_bytes(data)?),
+        "CBYE" => Message::Close(MessageClose::from_bytes(data)?),
+        "CINN" => Message::CursorEntered(MessageCursorEntered::from_bytes(data)?),
+        "COUT" => Message::CursorLeft(MessageCursorLeft::from_bytes(data)?),
+        "CCLP" => Message::ClientClipboard(MessageClientClipboard::from_bytes(data)?),
+        "CSEC" => Message::ScreenSaverChange(MessageScreenSaverChange::from_bytes(data)?),
+        "CROP" => Message::ResetOptions(MessageResetOptions::from_bytes(data)?),
+        "CIAK" => Message::InfoAcknowledgment(MessageInfoAcknowledgment::from_bytes(data)?),
+        "CALV" => Message::KeepAlive(MessageKeepAlive::from_bytes(data)?),
+        "DKDL" => Message::KeyDownWithLanguage(MessageKeyDownWithLanguage::from_bytes(data)?),
+        "DKDN" => Message::KeyDown(MessageKeyDown::from_bytes(data)?),
+        "DKRP" => Message::KeyRepeat(MessageKeyRepeat::from_bytes(data)?),
+        "DKUP" => Message::KeyUp(MessageKeyUp::from_bytes(data)?),
+        "DMDN" => Message::MouseButtonDown(MessageMouseButtonDown::from_bytes(data)?),
+        "DMUP" => Message::MouseButtonUp(MessageMouseButtonUp::from_bytes(data)?),
+        "DMMV" => Message::MouseMove(MessageMouseMove::from_bytes(data)?),
+        "DMRM" => Message::MouseRelativeMove(MessageMouseRelativeMove::from_bytes(data)?),
+        "DMWM" => Message::MouseWheel(MessageMouseWheel::from_bytes(data)?),
+        "DCLP" => Message::ClipboardData(MessageClipboardData::from_bytes(data)?),
+        "DINF" => Message::ClientInfo(MessageClientInfo::from_bytes(data)?),
+        "DSOP" => Message::SetOptions(MessageSetOptions::from_bytes(data)?),
+        "DFTR" => Message::FileTransfer(MessageFileTransfer::from_bytes(data)?),
+        "DDRG" => Message::DragInfo(MessageDragInfo::from_bytes(data)?),
+        "SECN" => Message::SecureEncryption(MessageSecureEncryption::from_bytes(data)?),
+        "LSYN" => Message::LegacySynergy(MessageLegacySynergy::from_bytes(data)?),
+        "QINF" => Message::QueryInfo(MessageQueryInfo::from_bytes(data)?),
+        "EICV" => Message::IncompatibleVersion(MessageIncompatibleVersion::from_bytes(data)?),
+        "EBSY" => Message::ServerBusy(MessageServerBusy::from_bytes(data)?),
+        "EUNK" => Message::UnknownClient(MessageUnknownClient::from_bytes(data)?),
+        "EBAD" => Message::ProtocolError(MessageProtocolError::from_bytes(data)?),
+        _ if data.starts_with(b"Barrier") => {
+            Message::HelloBarrier(MessageHelloBarrier::from_bytes(data)?)
+        }
+        _ if data.starts_with(b"Synergy") => {
+            Message::HelloSynergy(MessageHelloSynergy::from_bytes(data)?)
+        }
+        _ => return Err(ProtocolError::UnknownMessageCode(code.to_string())),
+    };
+    Ok(msg)
+}
+
+fn bench_dispatch(c: &mut Criterion) {
+    let traffic = sample_traffic();
+    for data in &traffic {
+        assert_eq!(parse_message_str(data), parse_message(data));
+    }
+
+    let mut group = c.benchmark_group("dispatch");
+    group.bench_function("str", |b| {
+        b.iter(|| {
+            for data in &traffic {
+                black_box(parse_message_str(black_box(data)).unwrap());
+            }
+        })
+    });
+    group.bench_function("bytes", |b| {
+        b.iter(|| {
+            for data in &traffic {
+                black_box(parse_message(black_box(data)).unwrap());
+            }
+        })
+    });
+    group.finish();
+}
+
+fn bench_parse(c: &mut Criterion) {
+    let traffic = sample_traffic();
+    let framed: Vec<Vec<u8>> = traffic
+        .iter()
+        .map(|data| {
+            let mut frame = (data.len() as u32).to_be_bytes().to_vec();
+            frame.extend_from_slice(data);
+            frame
+        })
+        .collect();
+
+    c.bench_function("parse_message", |b| {
+        b.iter(|| {
+            for data in &traffic {
+                black_box(parse_message(black_box(data)).unwrap());
+            }
+        })
+    });
+    c.bench_function("parse_message_ref", |b| {
+        b.iter(|| {
+            for data in &traffic {
+                black_box(parse_message_ref(black_box(data)).unwrap());
+            }
+        })
+    });
+    c.bench_function("parse_message_with_length", |b| {
+        b.iter(|| {
+            for data in &framed {
+                black_box(parse_message_with_length(black_box(data)).unwrap());
+            }
+        })
+    });
+}
+
+criterion_group!(benches, bench_dispatch, bench_parse);
+criterion_main!(benches);
@@ -0,0 +1,89 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! A logging proxy between a Synergy/Deskflow client and server
+//!
+//! Prints every message passing through and forwards it unchanged, including
+//! messages this crate does not know about. Only plain TCP connections are supported.
+//!
+//! ```text
+//! cargo run --example proxy -- 127.0.0.1:24802 192.168.1.10:24800
+//! ```
+//!
+//! Then point the client at port 24802 of this host.
+
+use schengen::protocol::{MAX_MESSAGE_LENGTH, Message, parse_message_preserving_unknown};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Forward frames from `from` to `to` until either side closes the connection
+async fn pump(
+    label: &'static str,
+    mut from: OwnedReadHalf,
+    mut to: OwnedWriteHalf,
+) -> std::io::Result<()> {
+    let mut frame = Vec::new();
+    loop {
+        let length = from.read_u32().await? as usize;
+        if length > MAX_MESSAGE_LENGTH {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("frame of {} bytes is too large", length),
+            ));
+        }
+        frame.clear();
+        frame.extend_from_slice(&(length as u32).to_be_bytes());
+        frame.resize(4 + length, 0);
+        from.read_exact(&mut frame[4..]).await?;
+
+        match parse_message_preserving_unknown(&frame[4..]) {
+            Ok(Message::Unknown { code, payload }) => println!(
+                "{} unknown {} len={}",
+                label,
+                String::from_utf8_lossy(&code),
+                payload.len()
+            ),
+            Ok(msg) => println!("{} {}", label, msg),
+            Err(e) => println!("{} unparseable frame: {}", label, e),
+        }
+
+        // Forward the received frame rather than re-encoding the message, so fields
+        // added by a newer peer survive
+        to.write_all(&frame).await?;
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = std::env::args().skip(1);
+    let (Some(listen), Some(server)) = (args.next(), args.next()) else {
+        eprintln!("Usage: proxy <listen-addr> <server-addr>");
+        std::process::exit(1);
+    };
+
+    let listener = TcpListener::bind(&listen).await?;
+    println!("Proxying {} to {}", listen, server);
+    loop {
+        let (client, addr) = listener.accept().await?;
+        let server = server.clone();
+        tokio::spawn(async move {
+            let upstream = match TcpStream::connect(&server).await {
+                Ok(upstream) => upstream,
+                Err(e) => {
+                    eprintln!("Failed to connect to {}: {}", server, e);
+                    return;
+                }
+            };
+            println!("Client {} connected", addr);
+            let (client_read, client_write) = client.into_split();
+            let (server_read, server_write) = upstream.into_split();
+            let result = tokio::select! {
+                result = pump("C->S", client_read, server_write) => result,
+                result = pump("S->C", server_read, client_write) => result,
+            };
+            if let Err(e) = result {
+                println!("Client {} disconnected: {}", addr, e);
+            }
+        });
+    }
+}
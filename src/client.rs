@@ -42,19 +42,37 @@
 //! }
 //! ```
 
+use futures_core::Stream;
+use log::{debug, warn};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::task::{Context, Poll};
 use std::time::{Duration, Instant};
 use thiserror::Error;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpStream;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpSocket, TcpStream, lookup_host};
 use tokio::time::sleep;
+#[cfg(feature = "tracing")]
+use tracing::Instrument;
 
+use crate::protocol::clipboard::{ClipboardContents, ClipboardFormat, ClipboardId};
+use crate::protocol::file_transfer::{self, FILE_CHUNK_SIZE};
 use crate::protocol::{
-    Message, MessageClientInfo, MessageHelloBarrier, MessageKeepAlive, ProtocolError,
-    parse_message_with_length,
+    Capabilities, Dialect, DsopOption, Handshake, HandshakeError, Message, MessageClientClipboard,
+    MessageClientInfo, MessageClose, MessageDragInfo, MessageFileTransfer, MessageKeepAlive,
+    MessageSecureEncryption, MessageSetOptions, ProtocolError, ProtocolVersion, ServerOptions,
+    parse_message_with_length_versioned,
 };
+use crate::transport::{BoxedTransport, Transport};
 
 const DEFAULT_PORT: u16 = 24801;
 
+/// The number of keepalive intervals without any message before the server is
+/// considered gone, as in Deskflow
+const HEARTBEATS_UNTIL_DEATH: u32 = 3;
+
 /// Events from the server that the client application should handle
 #[derive(Debug, Clone)]
 pub enum ClientEvent {
@@ -72,6 +90,14 @@ pub enum ClientEvent {
     /// Key pressed down
     KeyDown { key: u16, mask: u16, button: u16 },
 
+    /// Key pressed down in the given keyboard language (`DKDL`)
+    KeyDownWithLanguage {
+        key: u16,
+        mask: u16,
+        button: u16,
+        lang: String,
+    },
+
     /// Key released
     KeyUp { key: u16, mask: u16, button: u16 },
 
@@ -87,6 +113,9 @@ pub enum ClientEvent {
     MouseMove { x: i16, y: i16 },
 
     /// Mouse moved relative
+    ///
+    /// Sent instead of [`ClientEvent::MouseMove`] while the server has relative mouse
+    /// moves enabled, see [`ServerOptions::relative_mouse_moves`].
     MouseRelativeMove { dx: i16, dy: i16 },
 
     /// Mouse button pressed
@@ -108,14 +137,127 @@ pub enum ClientEvent {
     /// Screen saver state changed
     ScreenSaverChanged { active: bool },
 
-    /// Reset options
+    /// Reset options, see [`Client::options`]
     ResetOptions,
 
-    /// Set options (placeholder - expand as needed)
+    /// Set options, see [`Client::options`]
     SetOptions,
 
     /// Server closed connection
     Close,
+
+    /// The connection ended and the client is about to reconnect, see
+    /// [`Client::run_reconnecting`]
+    ///
+    /// The cursor is no longer on this screen, release any held keys and buttons.
+    Reconnecting { reason: String },
+
+    /// The client reconnected and completed the handshake again
+    Reconnected,
+}
+
+/// Input from the server, as yielded by [`Client::events`]
+///
+/// This is the subset of [`ClientEvent`] that an application driving a virtual input
+/// device needs to react to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InputEvent {
+    /// The cursor entered this screen at the given location
+    Enter { x: i16, y: i16, modifier_mask: u16 },
+    /// The cursor left this screen
+    Leave,
+    /// Key pressed down
+    KeyDown { key: u16, mask: u16, button: u16 },
+    /// Key pressed down in the given keyboard language
+    KeyDownWithLanguage {
+        key: u16,
+        mask: u16,
+        button: u16,
+        lang: String,
+    },
+    /// Key released
+    KeyUp { key: u16, mask: u16, button: u16 },
+    /// Key repeated
+    KeyRepeat {
+        key: u16,
+        mask: u16,
+        count: u16,
+        button: u16,
+    },
+    /// Mouse moved to absolute position
+    MouseMove { x: i16, y: i16 },
+    /// Mouse moved relative
+    MouseRelativeMove { dx: i16, dy: i16 },
+    /// Mouse button pressed
+    MouseButtonDown { button: u8 },
+    /// Mouse button released
+    MouseButtonUp { button: u8 },
+    /// Mouse wheel scrolled
+    MouseWheel { horiz: i16, vert: i16 },
+    /// Clipboard data received
+    ClipboardData {
+        id: u8,
+        sequence: u32,
+        data: Vec<u8>,
+    },
+}
+
+impl InputEvent {
+    /// Convert a [`ClientEvent`], returns `None` for events that are not input
+    fn from_client_event(event: ClientEvent) -> Option<Self> {
+        let input = match event {
+            ClientEvent::CursorEntered {
+                x,
+                y,
+                modifier_mask,
+                ..
+            } => InputEvent::Enter {
+                x,
+                y,
+                modifier_mask,
+            },
+            ClientEvent::CursorLeft => InputEvent::Leave,
+            ClientEvent::KeyDown { key, mask, button } => InputEvent::KeyDown { key, mask, button },
+            ClientEvent::KeyDownWithLanguage {
+                key,
+                mask,
+                button,
+                lang,
+            } => InputEvent::KeyDownWithLanguage {
+                key,
+                mask,
+                button,
+                lang,
+            },
+            ClientEvent::KeyUp { key, mask, button } => InputEvent::KeyUp { key, mask, button },
+            ClientEvent::KeyRepeat {
+                key,
+                mask,
+                count,
+                button,
+            } => InputEvent::KeyRepeat {
+                key,
+                mask,
+                count,
+                button,
+            },
+            ClientEvent::MouseMove { x, y } => InputEvent::MouseMove { x, y },
+            ClientEvent::MouseRelativeMove { dx, dy } => InputEvent::MouseRelativeMove { dx, dy },
+            ClientEvent::MouseButtonDown { button } => InputEvent::MouseButtonDown { button },
+            ClientEvent::MouseButtonUp { button } => InputEvent::MouseButtonUp { button },
+            ClientEvent::MouseWheel { horiz, vert } => InputEvent::MouseWheel { horiz, vert },
+            ClientEvent::ClipboardData { id, sequence, data } => {
+                InputEvent::ClipboardData { id, sequence, data }
+            }
+            ClientEvent::ScreenSaverChanged { .. }
+            | ClientEvent::ResetOptions
+            | ClientEvent::SetOptions
+            | ClientEvent::Close
+            | ClientEvent::Reconnecting { .. }
+            | ClientEvent::Reconnected => return None,
+        };
+        Some(input)
+    }
 }
 
 #[derive(Debug, Error)]
@@ -131,10 +273,164 @@ pub enum ClientError {
 
     #[error("Connection timeout exceeded after {0:?}, could not connect to server")]
     ConnectionTimeoutExceeded(Duration),
+
+    #[error("Handshake not completed within {0:?}, the server is not responding")]
+    HandshakeTimeout(Duration),
+
+    #[error("Server reported a protocol error")]
+    ServerProtocolError,
+
+    #[error("No message from the server within {0:?}, the connection is considered dead")]
+    KeepAliveTimeout(Duration),
+
+    #[error("Clipboard of {size} bytes exceeds the server's limit of {limit} bytes")]
+    ClipboardTooLarge { size: usize, limit: usize },
+
+    #[error("Server rejected the connection: {0}")]
+    HandshakeRejected(HandshakeRejection),
+
+    #[error("Invalid screen dimensions {width}x{height}, both must be nonzero")]
+    InvalidDimensions { width: u16, height: u16 },
+
+    #[error("The connection to the server is closed")]
+    ConnectionClosed,
+
+    #[error("Failed to read {path:?}: {source}")]
+    FileRead {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[error("Cannot drag {0} files, a drag holds at most 65535")]
+    TooManyFiles(usize),
+}
+
+/// Why the server refused the connection during the handshake
+///
+/// See [`ClientError::HandshakeRejected`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandshakeRejection {
+    /// The server doesn't know this client's name (`EUNK`)
+    UnknownClient,
+    /// A client with this name is already connected (`EBSY`)
+    Busy,
+    /// The server doesn't support this client's protocol version (`EICV`), with the
+    /// server's version
+    IncompatibleVersion(ProtocolVersion),
+}
+
+impl HandshakeRejection {
+    /// The rejection sent by the server with `message`, if it is one
+    fn from_message(message: &Message) -> Option<Self> {
+        match message {
+            Message::UnknownClient(_) => Some(Self::UnknownClient),
+            Message::ServerBusy(_) => Some(Self::Busy),
+            Message::IncompatibleVersion(msg) => Some(Self::IncompatibleVersion(
+                ProtocolVersion::new(msg.major_remote, msg.minor_remote),
+            )),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for HandshakeRejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownClient => write!(f, "unknown client name"),
+            Self::Busy => write!(f, "a client with this name is already connected"),
+            Self::IncompatibleVersion(version) => {
+                write!(f, "incompatible protocol version, server has {}", version)
+            }
+        }
+    }
+}
+
+/// How the client reacts to a protocol error (`EBAD`) reported by the server mid-session
+///
+/// See [`Builder::on_server_error`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ServerErrorPolicy {
+    /// Close the connection and return [`ClientError::ServerProtocolError`] from
+    /// [`Client::recv_event`]
+    #[default]
+    Close,
+    /// Log the error and keep processing messages from the server
+    Continue,
+}
+
+/// What to do with clipboard data larger than the server's limit (`CLSZ`)
+///
+/// See [`Client::set_clipboard_with_policy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OversizePolicy {
+    /// Send nothing and return [`ClientError::ClipboardTooLarge`]
+    #[default]
+    Reject,
+    /// Cut the text at the last character that fits the limit
+    Truncate,
+}
+
+/// How long to wait between connection attempts, see [`Builder::backoff`]
+///
+/// # Example
+///
+/// ```
+/// use schengen::client::BackoffStrategy;
+/// use std::time::Duration;
+///
+/// let backoff = BackoffStrategy::Exponential {
+///     base: Duration::from_millis(500),
+///     max: Duration::from_secs(10),
+/// };
+/// let delays: Vec<Duration> = (0..6).map(|retry| backoff.delay(retry)).collect();
+/// assert_eq!(delays[0], Duration::from_millis(500));
+/// assert_eq!(delays[3], Duration::from_secs(4));
+/// assert_eq!(delays[5], Duration::from_secs(10));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackoffStrategy {
+    /// Wait the same time before every retry
+    Fixed(Duration),
+    /// Double the wait after every retry, starting at `base` and up to `max`
+    Exponential { base: Duration, max: Duration },
+}
+
+impl BackoffStrategy {
+    /// The wait before the retry with the given 0-based index
+    pub fn delay(&self, retry: u32) -> Duration {
+        match *self {
+            BackoffStrategy::Fixed(delay) => delay,
+            BackoffStrategy::Exponential { base, max } => base
+                .checked_mul(2u32.saturating_pow(retry))
+                .map_or(max, |delay| delay.min(max)),
+        }
+    }
+}
+
+impl Default for BackoffStrategy {
+    fn default() -> Self {
+        BackoffStrategy::Fixed(Duration::from_secs(1))
+    }
+}
+
+/// A random delay between half of `delay` and `delay`
+fn jitter(delay: Duration) -> Duration {
+    use std::hash::{BuildHasher, Hasher};
+
+    // Every RandomState is seeded differently, which is random enough to spread out
+    // reconnecting clients
+    let random = std::collections::hash_map::RandomState::new()
+        .build_hasher()
+        .finish();
+    let fraction = (random % 1000) as u32;
+    delay / 2 + delay / 2 * fraction / 1000
 }
 
 pub type Result<T> = std::result::Result<T, ClientError>;
 
+/// Callback deciding whether a failed connection attempt is retried, see [`Builder::retry_if`]
+type RetryPredicate = Box<dyn Fn(&ClientError) -> bool + Send + Sync>;
+
 #[doc(hidden)]
 mod sealed {
     pub trait State {}
@@ -177,11 +473,21 @@ pub struct Builder<S: sealed::State = NeedsAddress> {
     host: Option<String>,
     port: u16,
     name: Option<String>,
-    retry_interval: Duration,
+    backoff: BackoffStrategy,
+    backoff_jitter: bool,
     retry_count: Option<usize>,
     connection_timeout: Option<Duration>,
+    handshake_timeout: Option<Duration>,
     width: u16,
     height: u16,
+    server_error_policy: ServerErrorPolicy,
+    capabilities: Capabilities,
+    dialect: Dialect,
+    protocol_version: ProtocolVersion,
+    retry_if: Option<RetryPredicate>,
+    bind_addr: Option<SocketAddr>,
+    #[cfg(feature = "tls")]
+    tls: Option<crate::tls::TlsConfig>,
 }
 
 impl<S: sealed::State> Builder<S> {
@@ -195,7 +501,9 @@ impl<S: sealed::State> Builder<S> {
 
     /// Set the screen dimensions for this client.
     ///
-    /// These dimensions are sent to the server during the handshake.
+    /// These dimensions are sent to the server during the handshake. Connecting fails
+    /// with [`ClientError::InvalidDimensions`] before any connection attempt if either
+    /// is zero.
     pub fn dimensions(mut self, width: u16, height: u16) -> Self {
         self.width = width;
         self.height = height;
@@ -204,9 +512,50 @@ impl<S: sealed::State> Builder<S> {
 
     /// Specify the interval between connection retries if the server is unavailable.
     ///
-    /// The default is 1 second.
+    /// This is the same as [`Builder::backoff`] with [`BackoffStrategy::Fixed`]. The
+    /// default is 1 second.
     pub fn retry_interval(mut self, interval: Duration) -> Self {
-        self.retry_interval = interval;
+        self.backoff = BackoffStrategy::Fixed(interval);
+        self
+    }
+
+    /// Specify how long to wait between connection retries if the server is unavailable.
+    ///
+    /// The [`Builder::retry_count`] and [`Builder::connection_timeout`] limits still
+    /// apply, the last wait is cut short if the timeout expires during it. The default
+    /// is a fixed interval of 1 second.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use schengen::client::{BackoffStrategy, Builder};
+    /// use std::time::Duration;
+    ///
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// let client = Builder::new()
+    ///     .server_addr("synergy-server")?
+    ///     .backoff(BackoffStrategy::Exponential {
+    ///         base: Duration::from_millis(250),
+    ///         max: Duration::from_secs(30),
+    ///     })
+    ///     .backoff_jitter(true)
+    ///     .connect()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn backoff(mut self, backoff: BackoffStrategy) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Randomize the wait between connection retries.
+    ///
+    /// Each wait is picked between half and all of the delay from [`Builder::backoff`],
+    /// so many clients don't reconnect in lockstep when a shared server restarts. The
+    /// default is `false`.
+    pub fn backoff_jitter(mut self, jitter: bool) -> Self {
+        self.backoff_jitter = jitter;
         self
     }
 
@@ -218,14 +567,214 @@ impl<S: sealed::State> Builder<S> {
         self
     }
 
+    /// Decide which connection failures are retried.
+    ///
+    /// The predicate is called with the error of each failed connection attempt. If it
+    /// returns `false`, [`Builder::connect`] stops retrying and returns that error
+    /// immediately. The [`Builder::retry_count`] and [`Builder::connection_timeout`]
+    /// limits still apply to errors that are retried.
+    ///
+    /// The default is to retry all failures.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use schengen::client::{Builder, ClientError};
+    /// use std::io::ErrorKind;
+    ///
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// // Wait for the server to start but fail on anything else, e.g. a typo in the hostname
+    /// let client = Builder::new()
+    ///     .server_addr("synergy-server:24801")?
+    ///     .retry_if(|err| {
+    ///         matches!(err, ClientError::ConnectionFailed(e) if e.kind() == ErrorKind::ConnectionRefused)
+    ///     })
+    ///     .connect()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn retry_if<F>(mut self, predicate: F) -> Self
+    where
+        F: Fn(&ClientError) -> bool + Send + Sync + 'static,
+    {
+        self.retry_if = Some(Box::new(predicate));
+        self
+    }
+
     /// Specify the total timeout for the entire connection process.
     ///
     /// If set, the connection attempts will stop after this duration,
-    /// even if retry_count hasn't been reached.
+    /// even if retry_count hasn't been reached. Unless a [`Builder::handshake_timeout`]
+    /// is set, the handshake once connected is limited to the same duration.
     pub fn connection_timeout(mut self, timeout: Duration) -> Self {
         self.connection_timeout = Some(timeout);
         self
     }
+
+    /// Specify the timeout for the handshake once the connection is established.
+    ///
+    /// If the server doesn't complete the exchange of hello, screen info and options
+    /// within this duration, connecting fails with [`ClientError::HandshakeTimeout`].
+    /// This guards against servers that accept the connection but never answer.
+    ///
+    /// Defaults to the [`Builder::connection_timeout`], without either the handshake
+    /// waits indefinitely.
+    pub fn handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.handshake_timeout = Some(timeout);
+        self
+    }
+
+    /// Connect from a specific local address.
+    ///
+    /// The outgoing socket is bound to this address before connecting, e.g. to pick the
+    /// network interface on a machine with a VPN. Use port 0 to let the system choose
+    /// the port. Only server addresses of the same IP version are tried. Every retry
+    /// binds a new socket.
+    ///
+    /// The default is to let the system choose.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use schengen::client::Builder;
+    ///
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// let client = Builder::new()
+    ///     .server_addr("10.8.0.1")?
+    ///     .bind_addr("10.8.0.2:0".parse()?)
+    ///     .connect()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn bind_addr(mut self, addr: SocketAddr) -> Self {
+        self.bind_addr = Some(addr);
+        self
+    }
+
+    /// Specify how to react if the server reports a protocol error (`EBAD`) after the handshake.
+    ///
+    /// The default is [`ServerErrorPolicy::Close`]. In both cases the last message sent by this
+    /// client is logged to help diagnose what triggered the error.
+    pub fn on_server_error(mut self, policy: ServerErrorPolicy) -> Self {
+        self.server_error_policy = policy;
+        self
+    }
+
+    /// Set the schengen extensions supported by this client.
+    ///
+    /// The capabilities are only sent if the server advertises its own with
+    /// [`DsopOption::SchengenCaps`], other servers never see them. The default is
+    /// [`Capabilities::empty()`].
+    pub fn capabilities(mut self, capabilities: Capabilities) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    /// Set the hello sent to the server in the handshake.
+    ///
+    /// The default is [`Dialect::Barrier`], which Barrier, Input-Leap and Deskflow
+    /// servers expect. Use [`Dialect::Synergy`] for the original Synergy server.
+    pub fn hello_dialect(mut self, dialect: Dialect) -> Self {
+        self.dialect = dialect;
+        self
+    }
+
+    /// Set the protocol version advertised in the hello to the server.
+    ///
+    /// The default is [`ProtocolVersion::CURRENT`]. The version used with the server
+    /// is still negotiated, the older of this and the server's version. This is
+    /// mostly useful to test against a server as if talking to an older client.
+    pub fn protocol_version(mut self, version: ProtocolVersion) -> Self {
+        self.protocol_version = version;
+        self
+    }
+
+    /// Wrap the connection in TLS before the handshake, as required by Deskflow
+    ///
+    /// See the [`tls`](crate::tls) module.
+    #[cfg(feature = "tls")]
+    pub fn tls(mut self, config: crate::tls::TlsConfig) -> Self {
+        self.tls = Some(config);
+        self
+    }
+
+    /// Fail if the dimensions can't be sent in `DINF`
+    fn check_dimensions(&self) -> Result<()> {
+        if self.width == 0 || self.height == 0 {
+            return Err(ClientError::InvalidDimensions {
+                width: self.width,
+                height: self.height,
+            });
+        }
+        Ok(())
+    }
+
+    /// A client on `stream` that has not done the handshake yet
+    fn new_client(&self, stream: BoxedTransport) -> Client {
+        Client {
+            stream,
+            buffer: Vec::with_capacity(4096),
+            width: self.width,
+            height: self.height,
+            server_error_policy: self.server_error_policy,
+            last_sent: None,
+            capabilities: self.capabilities,
+            dialect: self.dialect,
+            peer_capabilities: Capabilities::empty(),
+            // Advertised in the hello, replaced by the negotiated version
+            protocol_version: self.protocol_version,
+            peer_hello: None,
+            enter_sequence: 0,
+            options: ServerOptions::default(),
+            last_received: Instant::now(),
+            builder: None,
+            reconnecting: false,
+            reconnect_pending: false,
+            closed: false,
+            #[cfg(feature = "tracing")]
+            span: tracing::Span::none(),
+        }
+    }
+
+    /// Perform the handshake on `stream` within the [`Builder::handshake_timeout`]
+    async fn handshake(&self, stream: BoxedTransport) -> Result<Client> {
+        let mut client = self.new_client(stream);
+        let client_name = self.name.as_deref().unwrap_or("schengen-client");
+        let handshake = perform_handshake(&mut client, client_name);
+        #[cfg(feature = "tracing")]
+        let handshake =
+            handshake.instrument(tracing::info_span!("handshake", client = %client_name));
+        match self.handshake_timeout.or(self.connection_timeout) {
+            Some(timeout) => tokio::time::timeout(timeout, handshake)
+                .await
+                .map_err(|_| ClientError::HandshakeTimeout(timeout))??,
+            None => handshake.await?,
+        }
+        #[cfg(feature = "tracing")]
+        {
+            client.span = tracing::info_span!(
+                "connection",
+                client = %client_name,
+                version = %client.protocol_version,
+            );
+            client.span.in_scope(|| tracing::info!("connected"));
+        }
+        Ok(client)
+    }
+
+    /// Set up the transport on a connected stream, performing the TLS handshake if enabled
+    async fn transport<T: Transport + 'static>(&self, stream: T) -> Result<BoxedTransport> {
+        #[cfg(feature = "tls")]
+        if let Some(tls) = &self.tls {
+            return tls
+                .connect(self.host.as_deref(), stream)
+                .await
+                .map_err(ClientError::ConnectionFailed);
+        }
+        Ok(Box::new(stream))
+    }
 }
 
 // Methods only available on the initial builder state
@@ -243,11 +792,21 @@ impl Builder<NeedsAddress> {
             host: None,
             port: DEFAULT_PORT,
             name: None,
-            retry_interval: Duration::from_secs(1),
+            backoff: BackoffStrategy::default(),
+            backoff_jitter: false,
             retry_count: None,
             connection_timeout: None,
+            handshake_timeout: None,
             width: 1920,
             height: 1080,
+            server_error_policy: ServerErrorPolicy::default(),
+            capabilities: Capabilities::empty(),
+            dialect: Dialect::default(),
+            protocol_version: ProtocolVersion::CURRENT,
+            retry_if: None,
+            bind_addr: None,
+            #[cfg(feature = "tls")]
+            tls: None,
         }
     }
 
@@ -262,11 +821,21 @@ impl Builder<NeedsAddress> {
             host: Some(host),
             port,
             name: self.name,
-            retry_interval: self.retry_interval,
+            backoff: self.backoff,
+            backoff_jitter: self.backoff_jitter,
             retry_count: self.retry_count,
             connection_timeout: self.connection_timeout,
+            handshake_timeout: self.handshake_timeout,
             width: self.width,
             height: self.height,
+            server_error_policy: self.server_error_policy,
+            capabilities: self.capabilities,
+            dialect: self.dialect,
+            protocol_version: self.protocol_version,
+            retry_if: self.retry_if,
+            bind_addr: self.bind_addr,
+            #[cfg(feature = "tls")]
+            tls: self.tls,
         })
     }
 
@@ -307,28 +876,56 @@ impl Builder<NeedsAddress> {
         Ok((server.to_string(), DEFAULT_PORT))
     }
 
-    /// Connect using an existing TCP stream (e.g., from systemd socket activation).
+    /// Connect using an existing stream (e.g., a TCP stream from systemd socket activation).
+    ///
+    /// Any byte stream works, e.g. an in-memory [`tokio::io::duplex`] pipe to a server
+    /// from [`server::Builder::serve_connection`](crate::server::Builder::serve_connection).
     ///
     /// When this function returns, the client state is mostly through the connection handshake.
     ///
     /// # Arguments
     ///
-    /// * `stream` - An established TCP stream to use for the connection
-    pub async fn connect_with_stream(self, stream: TcpStream) -> Result<Client> {
-        // Create a client with the provided stream
-        let mut client = Client {
-            stream,
-            buffer: Vec::with_capacity(4096),
-            width: self.width,
-            height: self.height,
-        };
-
-        let client_name = self.name.as_deref().unwrap_or("schengen-client");
+    /// * `stream` - An established stream to use for the connection
+    pub async fn connect_with_stream<S>(self, stream: S) -> Result<Client>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + Sync + 'static,
+    {
+        self.check_dimensions()?;
+        self.connect_transport(stream).await
+    }
 
-        // Perform the Synergy protocol handshake
-        perform_handshake(&mut client, client_name).await?;
+    /// Connect to a server listening on a Unix domain socket at `path`
+    ///
+    /// See [`server::Builder::listen_on_unix`](crate::server::Builder::listen_on_unix).
+    /// The connection is not retried, and [`Client::run_reconnecting`] has no effect on
+    /// the returned client.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use schengen::client::Builder;
+    ///
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// let client = Builder::new()
+    ///     .name("vm")
+    ///     .connect_unix("/run/schengen.sock")
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(unix)]
+    pub async fn connect_unix(self, path: impl AsRef<std::path::Path>) -> Result<Client> {
+        self.check_dimensions()?;
+        let stream = tokio::net::UnixStream::connect(path)
+            .await
+            .map_err(ClientError::ConnectionFailed)?;
+        self.connect_transport(stream).await
+    }
 
-        Ok(client)
+    /// Set up the transport on `stream` and perform the handshake
+    async fn connect_transport<T: Transport + 'static>(self, stream: T) -> Result<Client> {
+        let stream = self.transport(stream).await?;
+        self.handshake(stream).await
     }
 }
 
@@ -342,6 +939,36 @@ impl Builder<Ready> {
         self
     }
 
+    /// Open a TCP connection to the server, from [`Builder::bind_addr`] if set
+    async fn connect_stream(&self, server_addr: &str) -> std::io::Result<TcpStream> {
+        let Some(bind_addr) = self.bind_addr else {
+            return TcpStream::connect(server_addr).await;
+        };
+
+        let mut last_error = None;
+        for addr in lookup_host(server_addr).await? {
+            if addr.is_ipv4() != bind_addr.is_ipv4() {
+                continue;
+            }
+            let socket = if bind_addr.is_ipv4() {
+                TcpSocket::new_v4()?
+            } else {
+                TcpSocket::new_v6()?
+            };
+            socket.bind(bind_addr)?;
+            match socket.connect(addr).await {
+                Ok(stream) => return Ok(stream),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(last_error.unwrap_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::AddrNotAvailable,
+                format!("No address of {} matches {}", server_addr, bind_addr),
+            )
+        }))
+    }
+
     /// Attempt to connect to the server with retry logic
     async fn try_connect(&self) -> Result<TcpStream> {
         // Safe to unwrap because Ready state guarantees host is set
@@ -360,17 +987,31 @@ impl Builder<Ready> {
                 }
             }
 
-            match TcpStream::connect(&server_addr).await {
+            match self.connect_stream(&server_addr).await {
                 Ok(stream) => {
                     return Ok(stream);
                 }
-                Err(_) => {
+                Err(e) => {
+                    let err = ClientError::ConnectionFailed(e);
+                    if let Some(retry_if) = &self.retry_if {
+                        if !retry_if(&err) {
+                            return Err(err);
+                        }
+                    }
                     if let Some(max_retries) = self.retry_count {
                         if attempt >= max_retries {
                             return Err(ClientError::MaxRetriesExceeded(max_retries));
                         }
                     }
-                    sleep(self.retry_interval).await;
+                    let retry = u32::try_from(attempt - 1).unwrap_or(u32::MAX);
+                    let mut delay = self.backoff.delay(retry);
+                    if self.backoff_jitter {
+                        delay = jitter(delay);
+                    }
+                    if let Some(conn_timeout) = self.connection_timeout {
+                        delay = delay.min(conn_timeout.saturating_sub(start_time.elapsed()));
+                    }
+                    sleep(delay).await;
                 }
             }
         }
@@ -381,161 +1022,138 @@ impl Builder<Ready> {
     ///
     /// When this function returns, the client state is mostly through the connection handshake.
     pub async fn connect(self) -> Result<Client> {
+        self.check_dimensions()?;
+
         // Establish TCP connection with retries
         let stream = self.try_connect().await?;
-
-        // Create a client with the stream
-        let mut client = Client {
-            stream,
-            buffer: Vec::with_capacity(4096),
-            width: self.width,
-            height: self.height,
-        };
-
-        let client_name = self.name.as_deref().unwrap_or("schengen-client");
+        let stream = self.transport(stream).await?;
 
         // Perform the Synergy protocol handshake
-        perform_handshake(&mut client, client_name).await?;
+        let mut client = self.handshake(stream).await?;
 
+        // Keep the settings around for Client::run_reconnecting
+        client.builder = Some(Box::new(self));
         Ok(client)
     }
 }
 
-/// Send client information to the server
-async fn send_client_info(client: &mut Client) -> Result<()> {
-    let client_info = Message::ClientInfo(MessageClientInfo {
-        x: 0,
-        y: 0,
-        width: client.width,
-        height: client.height,
-        current_mouse_x: 0,
-        current_mouse_y: 0,
-        size: 0,
-    });
-
-    client.send_message(client_info).await?;
+/// Record the options of a DSOP
+///
+/// The server's capabilities are answered with our own. Only servers that advertise
+/// [`DsopOption::SchengenCaps`] get a reply, other servers do not expect a DSOP from
+/// the client.
+async fn handle_options(client: &mut Client, msg: &MessageSetOptions) -> Result<()> {
+    client.options.apply(msg);
+    #[cfg(feature = "tracing")]
+    client
+        .span
+        .in_scope(|| tracing::debug!(options = ?msg.options, "options received"));
+    if let Some(caps) = msg.get(DsopOption::SchengenCaps) {
+        client.peer_capabilities = Capabilities(caps);
+        #[cfg(feature = "tracing")]
+        client.span.in_scope(|| {
+            tracing::debug!(
+                capabilities = caps,
+                negotiated = client.negotiated_capabilities().bits(),
+                "capabilities negotiated"
+            )
+        });
+        let reply = MessageSetOptions::from_typed(&[(
+            DsopOption::SchengenCaps,
+            client.capabilities.bits(),
+        )]);
+        client.send_message(Message::SetOptions(reply)).await?;
+    }
     Ok(())
 }
 
 /// Perform the Synergy protocol handshake on a client
+///
+/// Drives a [`Handshake`] with the messages from the server. Options sent with `DSOP`
+/// are also handled like after the handshake.
 async fn perform_handshake(client: &mut Client, client_name: &str) -> Result<()> {
-    // Step 1: Wait for server hello and respond
-    loop {
-        match client.recv_message().await? {
-            Message::HelloBarrier(_hello_msg) => {
-                // Respond with our hello including client name
-                let hello_response = Message::HelloBarrier(MessageHelloBarrier {
-                    major: 1,
-                    minor: 8,
-                    client_name: Some(client_name.to_string()),
-                });
-                client.send_message(hello_response).await?;
-                break;
-            }
-            Message::HelloSynergy(_hello_msg) => {
-                // Respond with Barrier hello including client name
-                let hello_response = Message::HelloBarrier(MessageHelloBarrier {
-                    major: 1,
-                    minor: 8,
-                    client_name: Some(client_name.to_string()),
-                });
-                client.send_message(hello_response).await?;
-                break;
-            }
-            // Shouldn't really happen here anyway but...
-            Message::KeepAlive(_) => {
-                let keepalive_response = Message::KeepAlive(MessageKeepAlive);
-                client.send_message(keepalive_response).await?;
-                continue;
-            }
-            // Shouldn't really happen here anyway but...
-            Message::NoOp(_) => {
-                continue;
-            }
-            msg => {
-                return Err(ClientError::InvalidServerAddress(format!(
-                    "Expected hello from server, got: {:?}",
-                    msg
-                )));
-            }
-        }
-    }
-
-    // Step 2: Wait for QueryInfo and respond with ClientInfo
-    loop {
-        match client.recv_message().await? {
-            Message::QueryInfo(_) => {
-                send_client_info(client).await?;
-                break;
-            }
-            Message::KeepAlive(_) => {
-                let keepalive_response = Message::KeepAlive(MessageKeepAlive);
-                client.send_message(keepalive_response).await?;
-                continue;
-            }
-            Message::NoOp(_) => {
-                continue;
-            }
-            msg => {
-                return Err(ClientError::InvalidServerAddress(format!(
-                    "Expected QueryInfo from server, got: {:?}",
-                    msg
-                )));
-            }
+    let mut handshake = Handshake::client(client_name, client.client_info())
+        .with_dialect(client.dialect)
+        .with_version(client.protocol_version);
+    #[cfg(feature = "tracing")]
+    tracing::debug!("handshake started");
+    while !handshake.is_complete() {
+        let message = client.recv_message().await?;
+        let options = match &message {
+            Message::SetOptions(msg) => Some(msg.clone()),
+            _ => None,
+        };
+        let replies = handshake.on_message(message).map_err(|e| match e {
+            HandshakeError::Rejected(message) => ClientError::HandshakeRejected(
+                HandshakeRejection::from_message(&message)
+                    .expect("handshake rejections are EUNK, EBSY or EICV"),
+            ),
+            e => ClientError::InvalidServerAddress(e.to_string()),
+        })?;
+        // The version decides how the following messages are parsed
+        client.protocol_version = handshake.protocol_version();
+        for reply in replies {
+            client.send_message(reply).await?;
         }
-    }
-
-    // Step 3: Wait for the rest of the handshake messages (LSYN, CIAK, CROP, DSOP)
-    // We need to receive all of these before the handshake is complete
-    let mut received_lsyn = false;
-    let mut received_ciak = false;
-    let mut received_crop = false;
-    let mut received_dsop = false;
-
-    while !received_lsyn || !received_ciak || !received_crop || !received_dsop {
-        match client.recv_message().await? {
-            Message::LegacySynergy(_) => {
-                received_lsyn = true;
-            }
-            Message::InfoAcknowledgment(_) => {
-                received_ciak = true;
-            }
-            Message::ResetOptions(_) => {
-                received_crop = true;
-            }
-            Message::SetOptions(_) => {
-                received_dsop = true;
-            }
-            Message::KeepAlive(_) => {
-                let keepalive_response = Message::KeepAlive(MessageKeepAlive);
-                client.send_message(keepalive_response).await?;
-                continue;
-            }
-            Message::NoOp(_) => {
-                continue;
-            }
-            _ => {
-                // Ignore other messages during handshake
-                continue;
-            }
+        if let Some(options) = options {
+            handle_options(client, &options).await?;
         }
     }
+    client.peer_hello = handshake.peer_hello().cloned();
+    #[cfg(feature = "tracing")]
+    tracing::info!(version = %client.protocol_version, "handshake complete");
 
     Ok(())
 }
 
 /// A client connected to a schengen server
 pub struct Client {
-    stream: TcpStream,
+    stream: BoxedTransport,
     buffer: Vec<u8>,
     width: u16,
     height: u16,
+    server_error_policy: ServerErrorPolicy,
+    /// The last message sent to the server (excluding keepalives), for diagnostics
+    last_sent: Option<Message>,
+    /// The schengen extensions supported by this client
+    capabilities: Capabilities,
+    /// The hello sent in the handshake
+    dialect: Dialect,
+    /// The schengen extensions advertised by the server
+    peer_capabilities: Capabilities,
+    /// The protocol version used with the server, decides how messages are parsed
+    protocol_version: ProtocolVersion,
+    /// The hello sent by the server, always set once the handshake completed
+    peer_hello: Option<Message>,
+    /// The sequence number of the last `CINN`, sent back with clipboard grabs
+    enter_sequence: u32,
+    /// The options set by the server with `DSOP` since the last `CROP`
+    options: ServerOptions,
+    /// When the last data was received from the server
+    last_received: Instant,
+    /// The builder this client was connected with, `None` if it was given a stream
+    builder: Option<Box<Builder<Ready>>>,
+    /// Whether to reconnect when the connection ends, see [`Client::run_reconnecting`]
+    reconnecting: bool,
+    /// Whether [`ClientEvent::Reconnecting`] was returned and the next event reconnects
+    reconnect_pending: bool,
+    /// Whether the server closed the connection, nothing can be sent anymore
+    closed: bool,
+    /// The span of this connection, disabled until the handshake completed
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
 }
 
 impl Client {
     /// Send a protocol message to the server
     async fn send_message(&mut self, message: Message) -> Result<()> {
+        if self.closed {
+            return Err(ClientError::ConnectionClosed);
+        }
         let bytes = message.to_bytes();
+        if !matches!(message, Message::KeepAlive(_)) {
+            self.last_sent = Some(message);
+        }
         self.stream
             .write_all(&bytes)
             .await
@@ -547,19 +1165,328 @@ impl Client {
         Ok(())
     }
 
+    /// The options set by the server
+    ///
+    /// Updated by every `DSOP` and reset by `CROP`, both of which are also reported
+    /// as [`ClientEvent::SetOptions`] and [`ClientEvent::ResetOptions`].
+    pub fn options(&self) -> &ServerOptions {
+        &self.options
+    }
+
+    /// The hello message exactly as the server sent it
+    pub fn peer_hello(&self) -> &Message {
+        self.peer_hello
+            .as_ref()
+            .expect("peer hello is set during the handshake")
+    }
+
+    /// The protocol version used with the server
+    ///
+    /// This is the version from the server's hello, capped at
+    /// [`ProtocolVersion::CURRENT`]. Use it to check for features like horizontal
+    /// scrolling (1.3) or `DKDL` (1.8).
+    pub fn protocol_version(&self) -> ProtocolVersion {
+        self.protocol_version
+    }
+
+    /// The schengen extensions advertised by the server
+    ///
+    /// Empty if the server is not a schengen server or did not advertise any.
+    pub fn peer_capabilities(&self) -> Capabilities {
+        self.peer_capabilities
+    }
+
+    /// The schengen extensions supported by both this client and the server
+    pub fn negotiated_capabilities(&self) -> Capabilities {
+        self.capabilities.intersection(self.peer_capabilities)
+    }
+
+    /// Send this client's screen info to the server
+    ///
+    /// The client answers the server's [`MessageQueryInfo`](crate::protocol::MessageQueryInfo)
+    /// automatically, use this to announce a change without being asked, e.g. after a
+    /// resolution change. The mouse position is reported as the center of the screen.
+    pub async fn send_client_info(&mut self) -> Result<()> {
+        self.send_message(Message::ClientInfo(self.client_info()))
+            .await
+    }
+
+    /// The screen of this client as sent with `DINF`
+    fn client_info(&self) -> MessageClientInfo {
+        MessageClientInfo::for_screen(self.width, self.height)
+    }
+
+    /// Announce that this client's clipboard changed and send the new text contents
+    ///
+    /// This sends the `CCLP` grab followed by the `DCLP` data, split into chunks as
+    /// described in [`ClipboardContents::to_messages`]. The grab carries the sequence
+    /// number of the last [`ClientEvent::CursorEntered`], the clipboard is usually set
+    /// while the cursor is on this screen.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ClientError::ClipboardTooLarge`] without sending anything if the
+    /// server set a clipboard size limit (`CLSZ`) and the data exceeds it.
+    pub async fn set_clipboard(&mut self, id: ClipboardId, contents: String) -> Result<()> {
+        self.set_clipboard_with_policy(id, contents, OversizePolicy::Reject)
+            .await
+    }
+
+    /// Like [`set_clipboard`](Self::set_clipboard), with `policy` deciding what
+    /// happens if the data exceeds the server's clipboard size limit (`CLSZ`)
+    ///
+    /// Without a limit from the server the data is always sent as is.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ClientError::ClipboardTooLarge`] without sending anything if the data
+    /// exceeds the limit and `policy` is [`OversizePolicy::Reject`], or if the limit is
+    /// too small for even an empty clipboard.
+    pub async fn set_clipboard_with_policy(
+        &mut self,
+        id: ClipboardId,
+        mut contents: String,
+        policy: OversizePolicy,
+    ) -> Result<()> {
+        let clipboard =
+            |text: String| ClipboardContents::new(vec![(ClipboardFormat::Text, text.into_bytes())]);
+        if let Some(limit) = self.options.clipboard_size() {
+            let overhead = clipboard(String::new()).to_bytes().len();
+            let size = overhead + contents.len();
+            if size > limit {
+                if policy == OversizePolicy::Reject || overhead > limit {
+                    return Err(ClientError::ClipboardTooLarge { size, limit });
+                }
+                let mut len = limit - overhead;
+                while !contents.is_char_boundary(len) {
+                    len -= 1;
+                }
+                contents.truncate(len);
+            }
+        }
+        let contents = clipboard(contents);
+
+        let mut messages = vec![Message::ClientClipboard(MessageClientClipboard {
+            id: id.into(),
+            sequence: self.enter_sequence,
+        })];
+        messages.extend(
+            contents
+                .to_messages(id, self.enter_sequence)
+                .into_iter()
+                .map(Message::ClipboardData),
+        );
+        self.send_all(&messages).await
+    }
+
+    /// Drag files off this screen to the server
+    ///
+    /// Sends a `DDRG` with the paths, then transfers the contents of each file as a
+    /// `DFTR` sequence split into chunks of at most
+    /// [`FILE_CHUNK_SIZE`](crate::protocol::file_transfer::FILE_CHUNK_SIZE) bytes, see
+    /// [`file_transfer`]. Files are read and sent one chunk at a time, so they are
+    /// never held in memory as a whole.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ClientError::TooManyFiles`] without sending anything for more than
+    /// 65535 paths, and [`ClientError::FileRead`] if a file can't be opened. If reading
+    /// fails later, the transfer of that file stops and the error is returned.
+    pub async fn start_drag(&mut self, paths: &[PathBuf]) -> Result<()> {
+        let info = MessageDragInfo::from_paths(
+            &paths
+                .iter()
+                .map(|path| path.to_string_lossy().into_owned())
+                .collect::<Vec<_>>(),
+        )
+        .map_err(|_| ClientError::TooManyFiles(paths.len()))?;
+
+        let mut files = Vec::with_capacity(paths.len());
+        for path in paths {
+            let file_read = |source| ClientError::FileRead {
+                path: path.clone(),
+                source,
+            };
+            let file = tokio::fs::File::open(path).await.map_err(file_read)?;
+            let size = file.metadata().await.map_err(file_read)?.len();
+            files.push((path, file, size));
+        }
+
+        self.send_message(Message::DragInfo(info)).await?;
+        for (path, file, size) in files {
+            self.send_message(Message::FileTransfer(MessageFileTransfer::data_start(size)))
+                .await?;
+            // Never send more than announced if the file grows while it is read
+            let mut file = file.take(size);
+            loop {
+                let mut chunk = Vec::new();
+                (&mut file)
+                    .take(FILE_CHUNK_SIZE as u64)
+                    .read_to_end(&mut chunk)
+                    .await
+                    .map_err(|source| ClientError::FileRead {
+                        path: path.clone(),
+                        source,
+                    })?;
+                if chunk.is_empty() {
+                    break;
+                }
+                self.send_message(Message::FileTransfer(MessageFileTransfer {
+                    mark: file_transfer::MARK_CHUNK,
+                    data: chunk.into(),
+                }))
+                .await?;
+            }
+            self.send_message(Message::FileTransfer(MessageFileTransfer {
+                mark: file_transfer::MARK_END,
+                data: Vec::new().into(),
+            }))
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Tell the server that `app` put this screen into secure input mode
+    ///
+    /// Sends a `SECN`, reported by the server as `ServerEvent::SecureInput`. Use this
+    /// when an application such as a password prompt grabs the keyboard exclusively
+    /// and input injected by the client no longer arrives.
+    pub async fn notify_secure_input(&mut self, app: &str) -> Result<()> {
+        self.send_message(Message::SecureEncryption(MessageSecureEncryption {
+            data: app.into(),
+        }))
+        .await
+    }
+
     /// Send a raw protocol message to the server (public API)
     ///
     /// This is useful for sending messages that are not automatically handled by the client,
     /// such as clipboard data.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ClientError::ConnectionClosed`] once the server closed the connection,
+    /// i.e. after [`ClientEvent::Close`] or the error reporting the closed connection.
     pub async fn send(&mut self, message: Message) -> Result<()> {
         self.send_message(message).await
     }
 
+    /// Send a batch of protocol messages to the server
+    ///
+    /// All messages are serialized into a single buffer and written with one write
+    /// and a single flush, which avoids per-message overhead for bursts such as
+    /// clipboard chunks. Messages are sent in order.
+    pub async fn send_all(&mut self, messages: &[Message]) -> Result<()> {
+        if self.closed {
+            return Err(ClientError::ConnectionClosed);
+        }
+        let mut bytes = Vec::with_capacity(messages.iter().map(Message::size_hint).sum());
+        for message in messages {
+            message
+                .write_framed(&mut bytes)
+                .expect("writing to a Vec does not fail");
+        }
+        if let Some(message) = messages
+            .iter()
+            .rev()
+            .find(|m| !matches!(m, Message::KeepAlive(_)))
+        {
+            self.last_sent = Some(message.clone());
+        }
+        self.stream
+            .write_all(&bytes)
+            .await
+            .map_err(ClientError::ConnectionFailed)?;
+        self.stream
+            .flush()
+            .await
+            .map_err(ClientError::ConnectionFailed)?;
+        Ok(())
+    }
+
+    /// Close the connection to the server
+    ///
+    /// Sends `CBYE` so the server sees a clean disconnect rather than a dropped
+    /// connection, then closes the socket.
+    pub async fn shutdown(mut self) -> Result<()> {
+        self.send_message(Message::Close(MessageClose)).await?;
+        self.stream
+            .shutdown()
+            .await
+            .map_err(ClientError::ConnectionFailed)
+    }
+
+    /// Reconnect to the server whenever the connection ends
+    ///
+    /// Once the server closes the connection, the connection fails or the server goes
+    /// silent, [`recv_event`](Self::recv_event) returns [`ClientEvent::Reconnecting`].
+    /// The next call connects again with the [`Builder::backoff`] settings and the
+    /// limits of [`Builder::retry_count`] and [`Builder::connection_timeout`], redoes the
+    /// handshake and returns [`ClientEvent::Reconnected`]. The stream from
+    /// [`events`](Self::events) carries on across reconnects.
+    ///
+    /// Only clients from [`Builder::connect`] know where to reconnect to, a client from
+    /// [`Builder::connect_with_stream`] returns errors and [`ClientEvent::Close`] as
+    /// before.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use schengen::client::{Builder, ClientEvent};
+    ///
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// let mut client = Builder::new()
+    ///     .server_addr("synergy-server")?
+    ///     .connect()
+    ///     .await?
+    ///     .run_reconnecting();
+    /// loop {
+    ///     match client.recv_event().await? {
+    ///         ClientEvent::Reconnecting { reason } => println!("Reconnecting: {}", reason),
+    ///         ClientEvent::Reconnected => println!("Back online"),
+    ///         _ => {}
+    ///     }
+    /// }
+    /// # }
+    /// ```
+    pub fn run_reconnecting(mut self) -> Self {
+        self.reconnecting = self.builder.is_some();
+        self
+    }
+
+    /// Connect and do the handshake again with the builder this client was created with
+    async fn reconnect(&mut self) -> Result<()> {
+        let builder = self
+            .builder
+            .take()
+            .expect("only clients with a builder reconnect");
+        let result = async {
+            let stream = builder.try_connect().await?;
+            let stream = builder.transport(stream).await?;
+            builder.handshake(stream).await
+        }
+        .await;
+        match result {
+            Ok(client) => {
+                *self = Client {
+                    builder: Some(builder),
+                    reconnecting: true,
+                    ..client
+                };
+                Ok(())
+            }
+            Err(e) => {
+                self.builder = Some(builder);
+                Err(e)
+            }
+        }
+    }
+
     async fn recv_message(&mut self) -> Result<Message> {
         loop {
             // Try to parse a message from the buffer
             if !self.buffer.is_empty() {
-                match parse_message_with_length(&self.buffer) {
+                match parse_message_with_length_versioned(&self.buffer, self.protocol_version) {
                     Ok((msg, consumed)) => {
                         // Remove consumed bytes from buffer
                         self.buffer.drain(..consumed);
@@ -569,6 +1496,9 @@ impl Client {
                         // Need more data, continue reading
                     }
                     Err(e) => {
+                        #[cfg(feature = "tracing")]
+                        self.span
+                            .in_scope(|| tracing::warn!(error = %e, "failed to parse message"));
                         return Err(ClientError::InvalidServerAddress(format!(
                             "Protocol error: {}",
                             e
@@ -577,15 +1507,27 @@ impl Client {
                 }
             }
 
-            // Read more data from the stream
+            // Read more data from the stream, giving up if the server went silent for
+            // several keepalive intervals
             let mut temp_buf = vec![0u8; 4096];
-            let n = self
-                .stream
-                .read(&mut temp_buf)
-                .await
-                .map_err(ClientError::ConnectionFailed)?;
+            let n = match self.options.heartbeat() {
+                Some(heartbeat) => {
+                    let timeout = heartbeat * HEARTBEATS_UNTIL_DEATH;
+                    let remaining = timeout.saturating_sub(self.last_received.elapsed());
+                    tokio::time::timeout(remaining, self.stream.read(&mut temp_buf))
+                        .await
+                        .map_err(|_| ClientError::KeepAliveTimeout(timeout))?
+                }
+                None => self.stream.read(&mut temp_buf).await,
+            }
+            .map_err(ClientError::ConnectionFailed)?;
+            self.last_received = Instant::now();
 
             if n == 0 {
+                #[cfg(feature = "tracing")]
+                self.span
+                    .in_scope(|| tracing::info!("disconnected, connection closed by server"));
+                self.closed = true;
                 return Err(ClientError::InvalidServerAddress(
                     "Connection closed by server".to_string(),
                 ));
@@ -595,11 +1537,65 @@ impl Client {
         }
     }
 
+    /// A stream of the input received from the server
+    ///
+    /// The stream drives [`recv_event`](Self::recv_event), so protocol messages are still
+    /// handled automatically, and yields the events that are input, see [`InputEvent`].
+    /// It ends when the server closes the connection or the connection fails. Drop the
+    /// stream to use the client again, e.g. to send clipboard data.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use schengen::client::{Builder, InputEvent};
+    ///
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// let mut client = Builder::new().server_addr("localhost")?.connect().await?;
+    /// let mut events = client.events();
+    /// while let Some(event) = events.next().await {
+    ///     if let InputEvent::MouseMove { x, y } = event {
+    ///         println!("Mouse at {},{}", x, y);
+    ///     }
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn events(&mut self) -> InputEvents<'_> {
+        InputEvents {
+            next: Some(InputEvents::recv(self)),
+        }
+    }
+
     /// Receive the next event from the server.
     ///
     /// This automatically handles protocol-level messages like [MessageKeepAlive] internally. Only
     /// returns events that the caller needs to act on.
+    ///
+    /// Returns [`ClientError::KeepAliveTimeout`] if the server sends nothing for three
+    /// keepalive intervals, 3 seconds unless the server set the interval with `HART`.
+    /// Reconnect in this case, the server is gone, or use
+    /// [`run_reconnecting`](Self::run_reconnecting).
     pub async fn recv_event(&mut self) -> Result<ClientEvent> {
+        if !self.reconnecting {
+            return self.recv_server_event().await;
+        }
+        if self.reconnect_pending {
+            self.reconnect().await?;
+            self.reconnect_pending = false;
+            return Ok(ClientEvent::Reconnected);
+        }
+        let reason = match self.recv_server_event().await {
+            Ok(ClientEvent::Close) => "Server closed connection".to_string(),
+            Ok(event) => return Ok(event),
+            Err(e) => e.to_string(),
+        };
+        warn!("Connection to the server ended, reconnecting: {}", reason);
+        self.reconnect_pending = true;
+        Ok(ClientEvent::Reconnecting { reason })
+    }
+
+    /// Receive the next event from the current connection to the server
+    async fn recv_server_event(&mut self) -> Result<ClientEvent> {
         loop {
             match self.recv_message().await? {
                 // Auto-handle keepalives
@@ -611,7 +1607,7 @@ impl Client {
 
                 // Auto-handle QueryInfo
                 Message::QueryInfo(_) => {
-                    send_client_info(self).await?;
+                    self.send_client_info().await?;
                     continue;
                 }
 
@@ -622,6 +1618,7 @@ impl Client {
 
                 // Return events caller cares about
                 Message::CursorEntered(msg) => {
+                    self.enter_sequence = msg.sequence;
                     return Ok(ClientEvent::CursorEntered {
                         x: msg.x,
                         y: msg.y,
@@ -642,6 +1639,15 @@ impl Client {
                     });
                 }
 
+                Message::KeyDownWithLanguage(msg) => {
+                    return Ok(ClientEvent::KeyDownWithLanguage {
+                        key: msg.keyid,
+                        mask: msg.mask,
+                        button: msg.button,
+                        lang: msg.lang.0,
+                    });
+                }
+
                 Message::KeyUp(msg) => {
                     return Ok(ClientEvent::KeyUp {
                         key: msg.keyid,
@@ -689,7 +1695,7 @@ impl Client {
                     return Ok(ClientEvent::ClipboardData {
                         id: msg.id,
                         sequence: msg.sequence,
-                        data: msg.data.0.into_bytes(),
+                        data: msg.data.0,
                     });
                 }
 
@@ -700,10 +1706,12 @@ impl Client {
                 }
 
                 Message::ResetOptions(_) => {
+                    self.options.reset();
                     return Ok(ClientEvent::ResetOptions);
                 }
 
-                Message::SetOptions(_) => {
+                Message::SetOptions(msg) => {
+                    handle_options(self, &msg).await?;
                     return Ok(ClientEvent::SetOptions);
                 }
 
@@ -715,9 +1723,28 @@ impl Client {
                 Message::Close(_) => {
                     // Shutdown the connection before returning the Close event
                     let _ = self.stream.shutdown().await;
+                    #[cfg(feature = "tracing")]
+                    self.span
+                        .in_scope(|| tracing::info!("disconnected, server sent CBYE"));
+                    self.closed = true;
                     return Ok(ClientEvent::Close);
                 }
 
+                Message::ProtocolError(_) => {
+                    warn!(
+                        "Server reported a protocol error, last message sent: {:?}",
+                        self.last_sent
+                    );
+                    match self.server_error_policy {
+                        ServerErrorPolicy::Close => {
+                            let _ = self.stream.shutdown().await;
+                            self.closed = true;
+                            return Err(ClientError::ServerProtocolError);
+                        }
+                        ServerErrorPolicy::Continue => continue,
+                    }
+                }
+
                 // Ignore handshake messages (shouldn't happen after connect)
                 Message::HelloBarrier(_) | Message::HelloSynergy(_) => {
                     continue;
@@ -731,3 +1758,60 @@ impl Client {
         }
     }
 }
+
+type NextEvent<'a> =
+    Pin<Box<dyn Future<Output = (Result<ClientEvent>, &'a mut Client)> + Send + 'a>>;
+
+/// A [`Stream`] of the input received by a [`Client`], see [`Client::events`]
+pub struct InputEvents<'a> {
+    next: Option<NextEvent<'a>>,
+}
+
+impl<'a> InputEvents<'a> {
+    fn recv(client: &'a mut Client) -> NextEvent<'a> {
+        Box::pin(async move {
+            let event = client.recv_event().await;
+            (event, client)
+        })
+    }
+
+    /// Wait for the next input event, `None` once the connection ended
+    ///
+    /// This is the same as `StreamExt::next` without depending on a stream utility
+    /// crate.
+    pub async fn next(&mut self) -> Option<InputEvent> {
+        std::future::poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+    }
+}
+
+impl Stream for InputEvents<'_> {
+    type Item = InputEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<InputEvent>> {
+        loop {
+            let Some(next) = self.next.as_mut() else {
+                return Poll::Ready(None);
+            };
+            let (event, client) = match next.as_mut().poll(cx) {
+                Poll::Ready(result) => result,
+                Poll::Pending => return Poll::Pending,
+            };
+            let event = match event {
+                Ok(ClientEvent::Close) => {
+                    self.next = None;
+                    return Poll::Ready(None);
+                }
+                Ok(event) => event,
+                Err(e) => {
+                    debug!("Input event stream ended: {}", e);
+                    self.next = None;
+                    return Poll::Ready(None);
+                }
+            };
+            self.next = Some(Self::recv(client));
+            if let Some(input) = InputEvent::from_client_event(event) {
+                return Poll::Ready(Some(input));
+            }
+        }
+    }
+}
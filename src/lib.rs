@@ -37,3 +37,6 @@
 pub mod client;
 pub mod protocol;
 pub mod server;
+#[cfg(feature = "tls")]
+pub mod tls;
+mod transport;
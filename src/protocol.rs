@@ -22,7 +22,10 @@
 //! - `CODE` - The protocol code constant for the message type
 //!
 //! You can work with individual message types directly using the trait, or use the
-//! [`Message`] enum for polymorphic message handling.
+//! [`Message`] enum for polymorphic message handling. Every message type converts into
+//! [`Message`] via `From`, e.g. `let msg: Message = MessageMouseMove { x, y }.into();`.
+//! When reading from a stream, [`MessageReader`] buffers incoming data and hands out
+//! complete messages one at a time.
 //!
 //! ## Example Usage
 //!
@@ -90,12 +93,44 @@
 
 use std::error::Error;
 use std::fmt;
+use std::io::{self, Write};
+
+pub mod clipboard;
+pub use clipboard::ClipboardAssembler;
+pub mod file_transfer;
+pub use file_transfer::FileTransferAssembler;
+pub mod handshake;
+pub use handshake::{Handshake, HandshakeError};
+pub mod keys;
+pub use keys::KeyId;
+mod message_ref;
+pub use message_ref::{MessageRef, parse_message_ref};
+pub mod options;
+pub use options::ServerOptions;
+pub mod registry;
+pub use registry::{MessageRegistry, parse_message_with_registry};
+
+#[cfg(feature = "bytes")]
+mod bytes;
+#[cfg(feature = "bytes")]
+pub use self::bytes::{MessageBytes, parse_message_bytes};
 
 /// Errors that can occur during protocol message parsing or serialization
+///
+/// New errors may be added in minor releases.
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum ProtocolError {
     /// Not enough data to parse the message
-    InsufficientData { expected: usize, actual: usize },
+    ///
+    /// `offset` is the position of the value that could not be read and `expected` the
+    /// number of bytes needed to read it, both relative to the start of the data
+    /// passed to the parse function.
+    InsufficientData {
+        offset: usize,
+        expected: usize,
+        actual: usize,
+    },
     /// Unknown message code encountered
     UnknownMessageCode(String),
     /// Invalid UTF-8 string in message data
@@ -104,16 +139,22 @@ pub enum ProtocolError {
     InvalidMessageCode,
     /// Invalid data format or content
     InvalidData(String),
+    /// The length prefix of a message exceeds the allowed maximum
+    FrameTooLarge { len: usize, max: usize },
 }
 
 impl fmt::Display for ProtocolError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            ProtocolError::InsufficientData { expected, actual } => {
+            ProtocolError::InsufficientData {
+                offset,
+                expected,
+                actual,
+            } => {
                 write!(
                     f,
-                    "Insufficient data: expected {} bytes, got {}",
-                    expected, actual
+                    "Insufficient data at offset {}: expected {} bytes, got {}",
+                    offset, expected, actual
                 )
             }
             ProtocolError::UnknownMessageCode(code) => {
@@ -128,12 +169,21 @@ impl fmt::Display for ProtocolError {
             ProtocolError::InvalidData(msg) => {
                 write!(f, "Invalid data: {}", msg)
             }
+            ProtocolError::FrameTooLarge { len, max } => {
+                write!(f, "Message of {} bytes exceeds the maximum of {}", len, max)
+            }
         }
     }
 }
 
 impl Error for ProtocolError {}
 
+/// The default maximum length of a single message, not including the length prefix
+///
+/// This matches the limit used by Deskflow, larger messages (e.g. clipboard data)
+/// are split into chunks by the sender.
+pub const MAX_MESSAGE_LENGTH: usize = 4 * 1024 * 1024;
+
 /// Result type for protocol operations
 pub type Result<T> = std::result::Result<T, ProtocolError>;
 
@@ -142,6 +192,7 @@ fn read_u8(data: &[u8], offset: usize) -> Result<u8> {
     data.get(offset)
         .copied()
         .ok_or(ProtocolError::InsufficientData {
+            offset,
             expected: offset + 1,
             actual: data.len(),
         })
@@ -150,6 +201,7 @@ fn read_u8(data: &[u8], offset: usize) -> Result<u8> {
 fn read_u16(data: &[u8], offset: usize) -> Result<u16> {
     if data.len() < offset + 2 {
         return Err(ProtocolError::InsufficientData {
+            offset,
             expected: offset + 2,
             actual: data.len(),
         });
@@ -160,6 +212,7 @@ fn read_u16(data: &[u8], offset: usize) -> Result<u16> {
 fn read_i16(data: &[u8], offset: usize) -> Result<i16> {
     if data.len() < offset + 2 {
         return Err(ProtocolError::InsufficientData {
+            offset,
             expected: offset + 2,
             actual: data.len(),
         });
@@ -170,6 +223,7 @@ fn read_i16(data: &[u8], offset: usize) -> Result<i16> {
 fn read_u32(data: &[u8], offset: usize) -> Result<u32> {
     if data.len() < offset + 4 {
         return Err(ProtocolError::InsufficientData {
+            offset,
             expected: offset + 4,
             actual: data.len(),
         });
@@ -183,15 +237,38 @@ fn read_u32(data: &[u8], offset: usize) -> Result<u32> {
 }
 
 /// A string with a 4-byte big-endian length prefix
-#[derive(Debug, Clone, PartialEq, Eq)]
+///
+/// The length is the number of bytes of the UTF-8 encoded string, not the number of
+/// characters.
+///
+/// # Example
+///
+/// ```
+/// use schengen::protocol::LengthPrefixedString;
+///
+/// let s = LengthPrefixedString::from("Grüße 🌍");
+/// let bytes = s.to_bytes();
+/// assert_eq!(&bytes[0..4], &[0, 0, 0, 12]);
+///
+/// let (parsed, consumed) = LengthPrefixedString::from_bytes(&bytes, 0).unwrap();
+/// assert_eq!(parsed, s);
+/// assert_eq!(consumed, bytes.len());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", serde(transparent))]
 pub struct LengthPrefixedString(pub String);
 
 impl LengthPrefixedString {
-    /// Read a length-prefixed string from bytes at the given offset
-    /// Returns (LengthPrefixedString, bytes_consumed)
-    fn from_bytes(data: &[u8], offset: usize) -> Result<(Self, usize)> {
+    /// Read a length-prefixed string from `data` at the given offset
+    ///
+    /// Returns the string and the number of bytes consumed, including the 4-byte
+    /// length prefix.
+    pub fn from_bytes(data: &[u8], offset: usize) -> Result<(Self, usize)> {
         if data.len() < offset + 4 {
             return Err(ProtocolError::InsufficientData {
+                offset,
                 expected: offset + 4,
                 actual: data.len(),
             });
@@ -200,9 +277,11 @@ impl LengthPrefixedString {
         let length = read_u32(data, offset)? as usize;
         let string_start = offset + 4;
 
-        if data.len() < string_start + length {
+        // Compare against the remaining data so a hostile length can't overflow
+        if data.len() - string_start < length {
             return Err(ProtocolError::InsufficientData {
-                expected: string_start + length,
+                offset: string_start,
+                expected: string_start.saturating_add(length),
                 actual: data.len(),
             });
         }
@@ -214,12 +293,18 @@ impl LengthPrefixedString {
         Ok((Self(string), 4 + length))
     }
 
-    /// Write a length-prefixed string to bytes
-    fn to_bytes(&self) -> Vec<u8> {
+    /// Write a length-prefixed string into `w`
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
         let string_bytes = self.0.as_bytes();
-        let mut result = Vec::with_capacity(4 + string_bytes.len());
-        result.extend_from_slice(&(string_bytes.len() as u32).to_be_bytes());
-        result.extend_from_slice(string_bytes);
+        w.write_all(&(string_bytes.len() as u32).to_be_bytes())?;
+        w.write_all(string_bytes)
+    }
+
+    /// Write a length-prefixed string to bytes
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut result = Vec::with_capacity(4 + self.0.len());
+        self.write_to(&mut result)
+            .expect("writing to a Vec does not fail");
         result
     }
 }
@@ -242,6 +327,214 @@ impl AsRef<str> for LengthPrefixedString {
     }
 }
 
+/// A byte array with a 4-byte big-endian length prefix
+///
+/// Unlike [`LengthPrefixedString`] the content is not required to be valid UTF-8, this
+/// is used for payloads like clipboard data that may contain binary data.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+pub struct LengthPrefixedBytes(pub Vec<u8>);
+
+impl LengthPrefixedBytes {
+    /// Read length-prefixed bytes from `data` at the given offset
+    ///
+    /// Returns the bytes and the number of bytes consumed, including the 4-byte
+    /// length prefix.
+    pub fn from_bytes(data: &[u8], offset: usize) -> Result<(Self, usize)> {
+        if data.len() < offset + 4 {
+            return Err(ProtocolError::InsufficientData {
+                offset,
+                expected: offset + 4,
+                actual: data.len(),
+            });
+        }
+
+        let length = read_u32(data, offset)? as usize;
+        let start = offset + 4;
+
+        if data.len() - start < length {
+            return Err(ProtocolError::InsufficientData {
+                offset: start,
+                expected: start.saturating_add(length),
+                actual: data.len(),
+            });
+        }
+
+        Ok((Self(data[start..start + length].to_vec()), 4 + length))
+    }
+
+    /// Write length-prefixed bytes into `w`
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(&(self.0.len() as u32).to_be_bytes())?;
+        w.write_all(&self.0)
+    }
+
+    /// Write length-prefixed bytes
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut result = Vec::with_capacity(4 + self.0.len());
+        self.write_to(&mut result)
+            .expect("writing to a Vec does not fail");
+        result
+    }
+}
+
+impl From<Vec<u8>> for LengthPrefixedBytes {
+    fn from(data: Vec<u8>) -> Self {
+        Self(data)
+    }
+}
+
+impl From<&[u8]> for LengthPrefixedBytes {
+    fn from(data: &[u8]) -> Self {
+        Self(data.to_vec())
+    }
+}
+
+impl From<String> for LengthPrefixedBytes {
+    fn from(s: String) -> Self {
+        Self(s.into_bytes())
+    }
+}
+
+impl From<&str> for LengthPrefixedBytes {
+    fn from(s: &str) -> Self {
+        Self(s.as_bytes().to_vec())
+    }
+}
+
+impl AsRef<[u8]> for LengthPrefixedBytes {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// A single modifier key in a [`ModifierMask`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Modifier {
+    Shift,
+    Control,
+    Alt,
+    Meta,
+    Super,
+    AltGr,
+    CapsLock,
+    NumLock,
+    ScrollLock,
+}
+
+impl Modifier {
+    /// All modifiers, in bit order
+    pub const ALL: [Modifier; 9] = [
+        Modifier::Shift,
+        Modifier::Control,
+        Modifier::Alt,
+        Modifier::Meta,
+        Modifier::Super,
+        Modifier::AltGr,
+        Modifier::CapsLock,
+        Modifier::NumLock,
+        Modifier::ScrollLock,
+    ];
+
+    /// The bit for this modifier in the wire format mask
+    pub fn bit(self) -> u16 {
+        match self {
+            Modifier::Shift => ModifierMask::SHIFT.0,
+            Modifier::Control => ModifierMask::CONTROL.0,
+            Modifier::Alt => ModifierMask::ALT.0,
+            Modifier::Meta => ModifierMask::META.0,
+            Modifier::Super => ModifierMask::SUPER.0,
+            Modifier::AltGr => ModifierMask::ALTGR.0,
+            Modifier::CapsLock => ModifierMask::CAPS_LOCK.0,
+            Modifier::NumLock => ModifierMask::NUM_LOCK.0,
+            Modifier::ScrollLock => ModifierMask::SCROLL_LOCK.0,
+        }
+    }
+}
+
+/// The modifier key mask sent with key and cursor-entered messages
+///
+/// The bit layout matches the Synergy/Deskflow `KeyModifierMask`. Bits not known to this
+/// crate are preserved as-is.
+///
+/// # Example
+///
+/// ```
+/// use schengen::protocol::{Modifier, ModifierMask};
+///
+/// let mask = ModifierMask::from(0x0003);
+/// assert!(mask.contains(Modifier::Shift));
+/// assert!(mask.contains(Modifier::Control));
+/// assert!(!mask.contains(Modifier::Alt));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct ModifierMask(pub u16);
+
+impl ModifierMask {
+    pub const SHIFT: ModifierMask = ModifierMask(0x0001);
+    pub const CONTROL: ModifierMask = ModifierMask(0x0002);
+    pub const ALT: ModifierMask = ModifierMask(0x0004);
+    pub const META: ModifierMask = ModifierMask(0x0008);
+    pub const SUPER: ModifierMask = ModifierMask(0x0010);
+    pub const ALTGR: ModifierMask = ModifierMask(0x0020);
+    pub const CAPS_LOCK: ModifierMask = ModifierMask(0x1000);
+    pub const NUM_LOCK: ModifierMask = ModifierMask(0x2000);
+    pub const SCROLL_LOCK: ModifierMask = ModifierMask(0x4000);
+
+    /// A mask with no modifiers set
+    pub fn empty() -> Self {
+        Self(0)
+    }
+
+    /// The raw wire format value
+    pub fn bits(self) -> u16 {
+        self.0
+    }
+
+    /// Whether the given modifier is set
+    pub fn contains(self, modifier: Modifier) -> bool {
+        self.0 & modifier.bit() != 0
+    }
+
+    /// Return a copy of this mask with the given modifier set
+    pub fn with(self, modifier: Modifier) -> Self {
+        Self(self.0 | modifier.bit())
+    }
+
+    /// The known modifiers set in this mask
+    pub fn modifiers(self) -> impl Iterator<Item = Modifier> {
+        Modifier::ALL.into_iter().filter(move |m| self.contains(*m))
+    }
+}
+
+impl From<u16> for ModifierMask {
+    fn from(bits: u16) -> Self {
+        Self(bits)
+    }
+}
+
+impl From<ModifierMask> for u16 {
+    fn from(mask: ModifierMask) -> Self {
+        mask.0
+    }
+}
+
+impl From<Modifier> for ModifierMask {
+    fn from(modifier: Modifier) -> Self {
+        Self(modifier.bit())
+    }
+}
+
+impl std::ops::BitOr for ModifierMask {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
 /// Trait for protocol messages that can be serialized to and deserialized from bytes
 ///
 /// This trait provides a common interface for all Synergy/Deskflow protocol messages.
@@ -275,12 +568,272 @@ pub trait ProtocolMessage: Sized {
     /// Returns the parsed message or an error if the data is malformed or insufficient
     fn from_bytes(data: &[u8]) -> Result<Self>;
 
+    /// Serialize this message into `w` (without the length prefix)
+    ///
+    /// Writes the message code followed by the message data, without allocating.
+    /// With the `bytes` feature, a `BytesMut` can be written to via
+    /// `BufMut::writer`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first error of `w`.
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()>;
+
     /// Serialize this message to bytes (without the length prefix)
     ///
     /// # Returns
     ///
     /// Returns a byte vector containing the message code followed by the message data
-    fn to_bytes(&self) -> Vec<u8>;
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        self.write_to(&mut bytes)
+            .expect("writing to a Vec does not fail");
+        bytes
+    }
+}
+
+/// A mouse button as used in [`MessageMouseButtonDown`] and [`MessageMouseButtonUp`]
+///
+/// # Example
+///
+/// ```
+/// use schengen::protocol::{MessageMouseButtonDown, MouseButton};
+///
+/// let msg = MessageMouseButtonDown { button: MouseButton::Right.to_u8() };
+/// assert_eq!(msg.button_kind(), MouseButton::Right);
+/// assert_eq!(MouseButton::from_u8(8), MouseButton::Extra(8));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MouseButton {
+    /// Button 1
+    Left,
+    /// Button 2
+    Right,
+    /// Button 3
+    Middle,
+    /// Any other button (4 and up), e.g. the back/forward buttons
+    Extra(u8),
+}
+
+impl MouseButton {
+    /// Convert the wire format button number to a MouseButton
+    pub fn from_u8(button: u8) -> Self {
+        match button {
+            1 => Self::Left,
+            2 => Self::Right,
+            3 => Self::Middle,
+            other => Self::Extra(other),
+        }
+    }
+
+    /// The wire format button number
+    pub fn to_u8(self) -> u8 {
+        match self {
+            Self::Left => 1,
+            Self::Right => 2,
+            Self::Middle => 3,
+            Self::Extra(button) => button,
+        }
+    }
+}
+
+impl From<u8> for MouseButton {
+    fn from(button: u8) -> Self {
+        Self::from_u8(button)
+    }
+}
+
+impl From<MouseButton> for u8 {
+    fn from(button: MouseButton) -> Self {
+        button.to_u8()
+    }
+}
+
+/// Implement a `button_kind()` accessor for messages with a raw `button` field
+macro_rules! impl_button_kind {
+    ($($ty:ty),+ $(,)?) => {
+        $(
+            impl $ty {
+                /// The `button` field as a typed [`MouseButton`]
+                pub fn button_kind(&self) -> MouseButton {
+                    MouseButton::from_u8(self.button)
+                }
+            }
+        )+
+    };
+}
+
+impl_button_kind!(MessageMouseButtonDown, MessageMouseButtonUp);
+
+/// Implement a `modifiers()` accessor for messages with a raw `mask` field
+macro_rules! impl_modifiers {
+    ($($ty:ty),+ $(,)?) => {
+        $(
+            impl $ty {
+                /// The `mask` field as a typed [`ModifierMask`]
+                pub fn modifiers(&self) -> ModifierMask {
+                    ModifierMask(self.mask)
+                }
+            }
+        )+
+    };
+}
+
+/// Implement `from_bytes_v1_0` for key messages that lack the `button` field in 1.0
+macro_rules! impl_key_v1_0 {
+    ($($ty:ty),+ $(,)?) => {
+        $(
+            impl $ty {
+                /// Parse the protocol 1.0 form of this message which has no `button` field
+                ///
+                /// The `button` is set to 0. See [`parse_message_versioned`].
+                pub fn from_bytes_v1_0(data: &[u8]) -> Result<Self> {
+                    if data.len() < Self::CODE.len() + 4 {
+                        return Err(ProtocolError::InsufficientData {
+                            offset: Self::CODE.len(),
+                            expected: Self::CODE.len() + 4,
+                            actual: data.len(),
+                        });
+                    }
+                    Ok(Self {
+                        keyid: read_u16(data, Self::CODE.len())?,
+                        mask: read_u16(data, Self::CODE.len() + 2)?,
+                        button: 0,
+                    })
+                }
+
+                /// Write the protocol 1.0 form of this message, omitting the `button`
+                pub fn to_bytes_v1_0(&self) -> Vec<u8> {
+                    let mut bytes = Vec::new();
+                    bytes.extend_from_slice(Self::CODE.as_bytes());
+                    bytes.extend_from_slice(&self.keyid.to_be_bytes());
+                    bytes.extend_from_slice(&self.mask.to_be_bytes());
+                    bytes
+                }
+            }
+        )+
+    };
+}
+
+impl_key_v1_0!(MessageKeyDown, MessageKeyUp);
+
+impl_modifiers!(
+    MessageCursorEntered,
+    MessageKeyDownWithLanguage,
+    MessageKeyDown,
+    MessageKeyRepeat,
+    MessageKeyUp,
+);
+
+/// A protocol version as advertised in the hello messages
+///
+/// Versions are ordered by major, then minor version.
+///
+/// # Example
+///
+/// ```
+/// use schengen::protocol::ProtocolVersion;
+///
+/// assert!(ProtocolVersion::new(1, 0) < ProtocolVersion::new(1, 8));
+/// assert_eq!(ProtocolVersion::CURRENT.to_string(), "1.8");
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ProtocolVersion {
+    pub major: u16,
+    pub minor: u16,
+}
+
+impl ProtocolVersion {
+    /// 1.0, the key messages have no `button` field
+    pub const V1_0: ProtocolVersion = ProtocolVersion::new(1, 0);
+    /// 1.1, adds the `button` field to the key messages
+    pub const V1_1: ProtocolVersion = ProtocolVersion::new(1, 1);
+    /// 1.3, adds horizontal scrolling to the mouse wheel message
+    pub const V1_3: ProtocolVersion = ProtocolVersion::new(1, 3);
+    /// 1.8, adds the key down with language message `DKDL`
+    pub const V1_8: ProtocolVersion = ProtocolVersion::new(1, 8);
+    /// The version implemented by this crate
+    pub const CURRENT: ProtocolVersion = ProtocolVersion::new(1, 8);
+
+    pub const fn new(major: u16, minor: u16) -> Self {
+        Self { major, minor }
+    }
+}
+
+/// Negotiate the protocol version between a server and a client
+///
+/// Both sides must share the same major version, the negotiated version is then the
+/// lower of the two versions. Otherwise the returned error is the `EICV` message the
+/// server sends to reject the client, carrying the server's version.
+///
+/// # Example
+///
+/// ```
+/// use schengen::protocol::{negotiate, ProtocolVersion};
+///
+/// let server = ProtocolVersion::new(1, 8);
+/// assert_eq!(negotiate(server, ProtocolVersion::new(1, 6)), Ok(ProtocolVersion::new(1, 6)));
+/// assert!(negotiate(server, ProtocolVersion::new(2, 0)).is_err());
+/// ```
+pub fn negotiate(
+    server: ProtocolVersion,
+    client: ProtocolVersion,
+) -> std::result::Result<ProtocolVersion, MessageIncompatibleVersion> {
+    if server.major != client.major {
+        return Err(MessageIncompatibleVersion {
+            major_remote: server.major,
+            minor_remote: server.minor,
+        });
+    }
+    Ok(server.min(client))
+}
+
+impl fmt::Display for ProtocolVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+/// The hello sent in the handshake
+///
+/// The hello message is the only difference between the Synergy and Barrier
+/// protocols. Either hello is accepted from a peer regardless of the dialect.
+///
+/// # Example
+///
+/// ```
+/// use schengen::protocol::{Dialect, Message, ProtocolVersion};
+///
+/// let hello = Dialect::Synergy.hello(ProtocolVersion::CURRENT, Some("laptop".into()));
+/// assert!(matches!(hello, Message::HelloSynergy(_)));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Dialect {
+    /// `Barrier` hello, used by Barrier, Input-Leap and Deskflow
+    #[default]
+    Barrier,
+    /// `Synergy` hello, used by the original Synergy
+    Synergy,
+}
+
+impl Dialect {
+    /// The hello in this dialect
+    ///
+    /// Servers send no `client_name`, clients reply with theirs.
+    pub fn hello(self, version: ProtocolVersion, client_name: Option<String>) -> Message {
+        match self {
+            Dialect::Barrier => Message::HelloBarrier(MessageHelloBarrier {
+                major: version.major,
+                minor: version.minor,
+                client_name,
+            }),
+            Dialect::Synergy => Message::HelloSynergy(MessageHelloSynergy {
+                major: version.major,
+                minor: version.minor,
+                client_name,
+            }),
+        }
+    }
 }
 
 // Protocol Messages
@@ -291,7 +844,9 @@ pub trait ProtocolMessage: Sized {
 /// This message is used in two contexts:
 /// 1. Server sends to client without client_name (server hello)
 /// 2. Client responds with client_name included (hello back)
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct MessageHelloBarrier {
     /// Protocol major version number
     pub major: u16,
@@ -307,6 +862,7 @@ impl ProtocolMessage for MessageHelloBarrier {
     fn from_bytes(data: &[u8]) -> Result<Self> {
         if data.len() < Self::CODE.len() + 4 {
             return Err(ProtocolError::InsufficientData {
+                offset: Self::CODE.len(),
                 expected: Self::CODE.len() + 4,
                 actual: data.len(),
             });
@@ -322,6 +878,7 @@ impl ProtocolMessage for MessageHelloBarrier {
             // Read the 4-byte length prefix
             if data.len() < name_length_offset + 4 {
                 return Err(ProtocolError::InsufficientData {
+                    offset: name_length_offset,
                     expected: name_length_offset + 4,
                     actual: data.len(),
                 });
@@ -332,6 +889,7 @@ impl ProtocolMessage for MessageHelloBarrier {
 
             if data.len() < name_start + name_length {
                 return Err(ProtocolError::InsufficientData {
+                    offset: name_start,
                     expected: name_start + name_length,
                     actual: data.len(),
                 });
@@ -352,20 +910,19 @@ impl ProtocolMessage for MessageHelloBarrier {
         })
     }
 
-    fn to_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
-        bytes.extend_from_slice(Self::CODE.as_bytes());
-        bytes.extend_from_slice(&self.major.to_be_bytes());
-        bytes.extend_from_slice(&self.minor.to_be_bytes());
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(Self::CODE.as_bytes())?;
+        w.write_all(&self.major.to_be_bytes())?;
+        w.write_all(&self.minor.to_be_bytes())?;
 
         // Add client name if present (length-prefixed string)
         if let Some(ref name) = self.client_name {
             let name_bytes = name.as_bytes();
-            bytes.extend_from_slice(&(name_bytes.len() as u32).to_be_bytes());
-            bytes.extend_from_slice(name_bytes);
+            w.write_all(&(name_bytes.len() as u32).to_be_bytes())?;
+            w.write_all(name_bytes)?;
         }
 
-        bytes
+        Ok(())
     }
 }
 
@@ -375,7 +932,9 @@ impl ProtocolMessage for MessageHelloBarrier {
 /// This message is used in two contexts:
 /// 1. Server sends to client without client_name (server hello)
 /// 2. Client responds with client_name included (hello back)
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct MessageHelloSynergy {
     /// Protocol major version number
     pub major: u16,
@@ -391,6 +950,7 @@ impl ProtocolMessage for MessageHelloSynergy {
     fn from_bytes(data: &[u8]) -> Result<Self> {
         if data.len() < Self::CODE.len() + 4 {
             return Err(ProtocolError::InsufficientData {
+                offset: Self::CODE.len(),
                 expected: Self::CODE.len() + 4,
                 actual: data.len(),
             });
@@ -406,6 +966,7 @@ impl ProtocolMessage for MessageHelloSynergy {
             // Read the 4-byte length prefix
             if data.len() < name_length_offset + 4 {
                 return Err(ProtocolError::InsufficientData {
+                    offset: name_length_offset,
                     expected: name_length_offset + 4,
                     actual: data.len(),
                 });
@@ -416,6 +977,7 @@ impl ProtocolMessage for MessageHelloSynergy {
 
             if data.len() < name_start + name_length {
                 return Err(ProtocolError::InsufficientData {
+                    offset: name_start,
                     expected: name_start + name_length,
                     actual: data.len(),
                 });
@@ -436,25 +998,26 @@ impl ProtocolMessage for MessageHelloSynergy {
         })
     }
 
-    fn to_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
-        bytes.extend_from_slice(Self::CODE.as_bytes());
-        bytes.extend_from_slice(&self.major.to_be_bytes());
-        bytes.extend_from_slice(&self.minor.to_be_bytes());
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(Self::CODE.as_bytes())?;
+        w.write_all(&self.major.to_be_bytes())?;
+        w.write_all(&self.minor.to_be_bytes())?;
 
         // Add client name if present (length-prefixed string)
         if let Some(ref name) = self.client_name {
             let name_bytes = name.as_bytes();
-            bytes.extend_from_slice(&(name_bytes.len() as u32).to_be_bytes());
-            bytes.extend_from_slice(name_bytes);
+            w.write_all(&(name_bytes.len() as u32).to_be_bytes())?;
+            w.write_all(name_bytes)?;
         }
 
-        bytes
+        Ok(())
     }
 }
 
 /// No operation / keepalive
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct MessageNoOp;
 
 impl ProtocolMessage for MessageNoOp {
@@ -464,13 +1027,15 @@ impl ProtocolMessage for MessageNoOp {
         Ok(Self)
     }
 
-    fn to_bytes(&self) -> Vec<u8> {
-        Self::CODE.as_bytes().to_vec()
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(Self::CODE.as_bytes())
     }
 }
 
 /// Close connection
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct MessageClose;
 
 impl ProtocolMessage for MessageClose {
@@ -480,13 +1045,15 @@ impl ProtocolMessage for MessageClose {
         Ok(Self)
     }
 
-    fn to_bytes(&self) -> Vec<u8> {
-        Self::CODE.as_bytes().to_vec()
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(Self::CODE.as_bytes())
     }
 }
 
 /// Cursor entered screen
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct MessageCursorEntered {
     /// Entry X coordinate - absolute screen position where cursor entered
     pub x: i16,
@@ -498,12 +1065,42 @@ pub struct MessageCursorEntered {
     pub mask: u16,
 }
 
+/// Orders by [`sequence`](Self::sequence), the order in which the server sent the
+/// messages, then by the remaining fields
+///
+/// # Example
+///
+/// ```
+/// use schengen::protocol::MessageCursorEntered;
+///
+/// let first = MessageCursorEntered { x: 100, y: 0, sequence: 1, mask: 0 };
+/// let second = MessageCursorEntered { x: 0, y: 0, sequence: 2, mask: 0 };
+/// assert!(first < second);
+/// ```
+impl Ord for MessageCursorEntered {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.sequence, self.x, self.y, self.mask).cmp(&(
+            other.sequence,
+            other.x,
+            other.y,
+            other.mask,
+        ))
+    }
+}
+
+impl PartialOrd for MessageCursorEntered {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
 impl ProtocolMessage for MessageCursorEntered {
     const CODE: &'static str = "CINN";
 
     fn from_bytes(data: &[u8]) -> Result<Self> {
         if data.len() < Self::CODE.len() + 10 {
             return Err(ProtocolError::InsufficientData {
+                offset: Self::CODE.len(),
                 expected: Self::CODE.len() + 10,
                 actual: data.len(),
             });
@@ -516,19 +1113,20 @@ impl ProtocolMessage for MessageCursorEntered {
         })
     }
 
-    fn to_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
-        bytes.extend_from_slice(Self::CODE.as_bytes());
-        bytes.extend_from_slice(&self.x.to_be_bytes());
-        bytes.extend_from_slice(&self.y.to_be_bytes());
-        bytes.extend_from_slice(&self.sequence.to_be_bytes());
-        bytes.extend_from_slice(&self.mask.to_be_bytes());
-        bytes
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(Self::CODE.as_bytes())?;
+        w.write_all(&self.x.to_be_bytes())?;
+        w.write_all(&self.y.to_be_bytes())?;
+        w.write_all(&self.sequence.to_be_bytes())?;
+        w.write_all(&self.mask.to_be_bytes())?;
+        Ok(())
     }
 }
 
 /// Cursor left screen
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct MessageCursorLeft;
 
 impl ProtocolMessage for MessageCursorLeft {
@@ -538,13 +1136,15 @@ impl ProtocolMessage for MessageCursorLeft {
         Ok(Self)
     }
 
-    fn to_bytes(&self) -> Vec<u8> {
-        Self::CODE.as_bytes().to_vec()
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(Self::CODE.as_bytes())
     }
 }
 
 /// Clipboard from client
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct MessageClientClipboard {
     /// Clipboard identifier (0 = primary clipboard for Ctrl+C/V, 1 = selection clipboard for middle-click on X11)
     pub id: u8,
@@ -558,6 +1158,7 @@ impl ProtocolMessage for MessageClientClipboard {
     fn from_bytes(data: &[u8]) -> Result<Self> {
         if data.len() < Self::CODE.len() + 5 {
             return Err(ProtocolError::InsufficientData {
+                offset: Self::CODE.len(),
                 expected: Self::CODE.len() + 5,
                 actual: data.len(),
             });
@@ -568,17 +1169,18 @@ impl ProtocolMessage for MessageClientClipboard {
         })
     }
 
-    fn to_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
-        bytes.extend_from_slice(Self::CODE.as_bytes());
-        bytes.push(self.id);
-        bytes.extend_from_slice(&self.sequence.to_be_bytes());
-        bytes
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(Self::CODE.as_bytes())?;
+        w.write_all(&[self.id])?;
+        w.write_all(&self.sequence.to_be_bytes())?;
+        Ok(())
     }
 }
 
 /// Screen saver change
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct MessageScreenSaverChange {
     /// Screensaver state (1 = started, 0 = stopped)
     pub state: u8,
@@ -590,6 +1192,7 @@ impl ProtocolMessage for MessageScreenSaverChange {
     fn from_bytes(data: &[u8]) -> Result<Self> {
         if data.len() < Self::CODE.len() + 1 {
             return Err(ProtocolError::InsufficientData {
+                offset: Self::CODE.len(),
                 expected: Self::CODE.len() + 1,
                 actual: data.len(),
             });
@@ -599,16 +1202,17 @@ impl ProtocolMessage for MessageScreenSaverChange {
         })
     }
 
-    fn to_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
-        bytes.extend_from_slice(Self::CODE.as_bytes());
-        bytes.push(self.state);
-        bytes
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(Self::CODE.as_bytes())?;
+        w.write_all(&[self.state])?;
+        Ok(())
     }
 }
 
 /// Reset options
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct MessageResetOptions;
 
 impl ProtocolMessage for MessageResetOptions {
@@ -618,13 +1222,15 @@ impl ProtocolMessage for MessageResetOptions {
         Ok(Self)
     }
 
-    fn to_bytes(&self) -> Vec<u8> {
-        Self::CODE.as_bytes().to_vec()
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(Self::CODE.as_bytes())
     }
 }
 
 /// Info acknowledgment
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct MessageInfoAcknowledgment;
 
 impl ProtocolMessage for MessageInfoAcknowledgment {
@@ -634,13 +1240,15 @@ impl ProtocolMessage for MessageInfoAcknowledgment {
         Ok(Self)
     }
 
-    fn to_bytes(&self) -> Vec<u8> {
-        Self::CODE.as_bytes().to_vec()
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(Self::CODE.as_bytes())
     }
 }
 
 /// Keepalive
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct MessageKeepAlive;
 
 impl ProtocolMessage for MessageKeepAlive {
@@ -650,15 +1258,17 @@ impl ProtocolMessage for MessageKeepAlive {
         Ok(Self)
     }
 
-    fn to_bytes(&self) -> Vec<u8> {
-        Self::CODE.as_bytes().to_vec()
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(Self::CODE.as_bytes())
     }
 }
 
 /// Key down with language
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct MessageKeyDownWithLanguage {
-    /// Virtual key identifier (keysym on Linux/X11, platform-dependent)
+    /// Key identifier, an X11 keysym as described in [`KeyId`]
     pub keyid: u16,
     /// Active modifier keys bitmask
     pub mask: u16,
@@ -674,6 +1284,7 @@ impl ProtocolMessage for MessageKeyDownWithLanguage {
     fn from_bytes(data: &[u8]) -> Result<Self> {
         if data.len() < Self::CODE.len() + 6 {
             return Err(ProtocolError::InsufficientData {
+                offset: Self::CODE.len(),
                 expected: Self::CODE.len() + 6,
                 actual: data.len(),
             });
@@ -687,21 +1298,22 @@ impl ProtocolMessage for MessageKeyDownWithLanguage {
         })
     }
 
-    fn to_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
-        bytes.extend_from_slice(Self::CODE.as_bytes());
-        bytes.extend_from_slice(&self.keyid.to_be_bytes());
-        bytes.extend_from_slice(&self.mask.to_be_bytes());
-        bytes.extend_from_slice(&self.button.to_be_bytes());
-        bytes.extend_from_slice(&self.lang.to_bytes());
-        bytes
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(Self::CODE.as_bytes())?;
+        w.write_all(&self.keyid.to_be_bytes())?;
+        w.write_all(&self.mask.to_be_bytes())?;
+        w.write_all(&self.button.to_be_bytes())?;
+        self.lang.write_to(w)?;
+        Ok(())
     }
 }
 
 /// Key down
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct MessageKeyDown {
-    /// Virtual key identifier (keysym on Linux/X11, platform-dependent)
+    /// Key identifier, an X11 keysym as described in [`KeyId`]
     pub keyid: u16,
     /// Active modifier keys bitmask
     pub mask: u16,
@@ -715,6 +1327,7 @@ impl ProtocolMessage for MessageKeyDown {
     fn from_bytes(data: &[u8]) -> Result<Self> {
         if data.len() < Self::CODE.len() + 6 {
             return Err(ProtocolError::InsufficientData {
+                offset: Self::CODE.len(),
                 expected: Self::CODE.len() + 6,
                 actual: data.len(),
             });
@@ -726,20 +1339,21 @@ impl ProtocolMessage for MessageKeyDown {
         })
     }
 
-    fn to_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
-        bytes.extend_from_slice(Self::CODE.as_bytes());
-        bytes.extend_from_slice(&self.keyid.to_be_bytes());
-        bytes.extend_from_slice(&self.mask.to_be_bytes());
-        bytes.extend_from_slice(&self.button.to_be_bytes());
-        bytes
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(Self::CODE.as_bytes())?;
+        w.write_all(&self.keyid.to_be_bytes())?;
+        w.write_all(&self.mask.to_be_bytes())?;
+        w.write_all(&self.button.to_be_bytes())?;
+        Ok(())
     }
 }
 
 /// Key repeat
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct MessageKeyRepeat {
-    /// Virtual key identifier (keysym on Linux/X11, platform-dependent)
+    /// Key identifier, an X11 keysym as described in [`KeyId`]
     pub keyid: u16,
     /// Active modifier keys bitmask
     pub mask: u16,
@@ -757,6 +1371,7 @@ impl ProtocolMessage for MessageKeyRepeat {
     fn from_bytes(data: &[u8]) -> Result<Self> {
         if data.len() < Self::CODE.len() + 8 {
             return Err(ProtocolError::InsufficientData {
+                offset: Self::CODE.len(),
                 expected: Self::CODE.len() + 8,
                 actual: data.len(),
             });
@@ -771,22 +1386,56 @@ impl ProtocolMessage for MessageKeyRepeat {
         })
     }
 
-    fn to_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(Self::CODE.as_bytes())?;
+        w.write_all(&self.keyid.to_be_bytes())?;
+        w.write_all(&self.mask.to_be_bytes())?;
+        w.write_all(&self.button.to_be_bytes())?;
+        w.write_all(&self.count.to_be_bytes())?;
+        self.lang.write_to(w)?;
+        Ok(())
+    }
+}
+
+impl MessageKeyRepeat {
+    /// Parse the protocol 1.0 form of this message
+    ///
+    /// In 1.0 the message is `keyid`, `mask` and `count` only, the `button` is set to 0
+    /// and the `lang` is empty. See [`parse_message_versioned`].
+    pub fn from_bytes_v1_0(data: &[u8]) -> Result<Self> {
+        if data.len() < Self::CODE.len() + 6 {
+            return Err(ProtocolError::InsufficientData {
+                offset: Self::CODE.len(),
+                expected: Self::CODE.len() + 6,
+                actual: data.len(),
+            });
+        }
+        Ok(Self {
+            keyid: read_u16(data, Self::CODE.len())?,
+            mask: read_u16(data, Self::CODE.len() + 2)?,
+            button: 0,
+            count: read_u16(data, Self::CODE.len() + 4)?,
+            lang: LengthPrefixedString::default(),
+        })
+    }
+
+    /// Write the protocol 1.0 form of this message, omitting the `button` and `lang`
+    pub fn to_bytes_v1_0(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
         bytes.extend_from_slice(Self::CODE.as_bytes());
         bytes.extend_from_slice(&self.keyid.to_be_bytes());
         bytes.extend_from_slice(&self.mask.to_be_bytes());
-        bytes.extend_from_slice(&self.button.to_be_bytes());
         bytes.extend_from_slice(&self.count.to_be_bytes());
-        bytes.extend_from_slice(&self.lang.to_bytes());
         bytes
     }
 }
 
 /// Key up
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct MessageKeyUp {
-    /// Virtual key identifier (keysym on Linux/X11, platform-dependent)
+    /// Key identifier, an X11 keysym as described in [`KeyId`]
     pub keyid: u16,
     /// Active modifier keys bitmask
     pub mask: u16,
@@ -800,6 +1449,7 @@ impl ProtocolMessage for MessageKeyUp {
     fn from_bytes(data: &[u8]) -> Result<Self> {
         if data.len() < Self::CODE.len() + 6 {
             return Err(ProtocolError::InsufficientData {
+                offset: Self::CODE.len(),
                 expected: Self::CODE.len() + 6,
                 actual: data.len(),
             });
@@ -811,18 +1461,19 @@ impl ProtocolMessage for MessageKeyUp {
         })
     }
 
-    fn to_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
-        bytes.extend_from_slice(Self::CODE.as_bytes());
-        bytes.extend_from_slice(&self.keyid.to_be_bytes());
-        bytes.extend_from_slice(&self.mask.to_be_bytes());
-        bytes.extend_from_slice(&self.button.to_be_bytes());
-        bytes
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(Self::CODE.as_bytes())?;
+        w.write_all(&self.keyid.to_be_bytes())?;
+        w.write_all(&self.mask.to_be_bytes())?;
+        w.write_all(&self.button.to_be_bytes())?;
+        Ok(())
     }
 }
 
 /// Mouse button down
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct MessageMouseButtonDown {
     /// Mouse button identifier (1=left, 2=right, 3=middle, 4+=additional)
     pub button: u8,
@@ -834,6 +1485,7 @@ impl ProtocolMessage for MessageMouseButtonDown {
     fn from_bytes(data: &[u8]) -> Result<Self> {
         if data.len() < Self::CODE.len() + 1 {
             return Err(ProtocolError::InsufficientData {
+                offset: Self::CODE.len(),
                 expected: Self::CODE.len() + 1,
                 actual: data.len(),
             });
@@ -843,16 +1495,17 @@ impl ProtocolMessage for MessageMouseButtonDown {
         })
     }
 
-    fn to_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
-        bytes.extend_from_slice(Self::CODE.as_bytes());
-        bytes.push(self.button);
-        bytes
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(Self::CODE.as_bytes())?;
+        w.write_all(&[self.button])?;
+        Ok(())
     }
 }
 
 /// Mouse button up
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct MessageMouseButtonUp {
     /// Mouse button identifier (1=left, 2=right, 3=middle, 4+=additional)
     pub button: u8,
@@ -864,6 +1517,7 @@ impl ProtocolMessage for MessageMouseButtonUp {
     fn from_bytes(data: &[u8]) -> Result<Self> {
         if data.len() < Self::CODE.len() + 1 {
             return Err(ProtocolError::InsufficientData {
+                offset: Self::CODE.len(),
                 expected: Self::CODE.len() + 1,
                 actual: data.len(),
             });
@@ -873,16 +1527,17 @@ impl ProtocolMessage for MessageMouseButtonUp {
         })
     }
 
-    fn to_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
-        bytes.extend_from_slice(Self::CODE.as_bytes());
-        bytes.push(self.button);
-        bytes
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(Self::CODE.as_bytes())?;
+        w.write_all(&[self.button])?;
+        Ok(())
     }
 }
 
 /// Mouse move
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct MessageMouseMove {
     /// Absolute X coordinate on secondary screen
     pub x: i16,
@@ -896,6 +1551,7 @@ impl ProtocolMessage for MessageMouseMove {
     fn from_bytes(data: &[u8]) -> Result<Self> {
         if data.len() < Self::CODE.len() + 4 {
             return Err(ProtocolError::InsufficientData {
+                offset: Self::CODE.len(),
                 expected: Self::CODE.len() + 4,
                 actual: data.len(),
             });
@@ -906,17 +1562,18 @@ impl ProtocolMessage for MessageMouseMove {
         })
     }
 
-    fn to_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
-        bytes.extend_from_slice(Self::CODE.as_bytes());
-        bytes.extend_from_slice(&self.x.to_be_bytes());
-        bytes.extend_from_slice(&self.y.to_be_bytes());
-        bytes
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(Self::CODE.as_bytes())?;
+        w.write_all(&self.x.to_be_bytes())?;
+        w.write_all(&self.y.to_be_bytes())?;
+        Ok(())
     }
 }
 
 /// Mouse relative move
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct MessageMouseRelativeMove {
     /// Horizontal movement delta (signed)
     pub x: i16,
@@ -930,6 +1587,7 @@ impl ProtocolMessage for MessageMouseRelativeMove {
     fn from_bytes(data: &[u8]) -> Result<Self> {
         if data.len() < Self::CODE.len() + 4 {
             return Err(ProtocolError::InsufficientData {
+                offset: Self::CODE.len(),
                 expected: Self::CODE.len() + 4,
                 actual: data.len(),
             });
@@ -940,17 +1598,18 @@ impl ProtocolMessage for MessageMouseRelativeMove {
         })
     }
 
-    fn to_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
-        bytes.extend_from_slice(Self::CODE.as_bytes());
-        bytes.extend_from_slice(&self.x.to_be_bytes());
-        bytes.extend_from_slice(&self.y.to_be_bytes());
-        bytes
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(Self::CODE.as_bytes())?;
+        w.write_all(&self.x.to_be_bytes())?;
+        w.write_all(&self.y.to_be_bytes())?;
+        Ok(())
     }
 }
 
 /// Mouse wheel
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct MessageMouseWheel {
     /// Horizontal scroll delta (+120 = right, -120 = left, typically multiples of 120)
     pub xdelta: i16,
@@ -964,6 +1623,7 @@ impl ProtocolMessage for MessageMouseWheel {
     fn from_bytes(data: &[u8]) -> Result<Self> {
         if data.len() < Self::CODE.len() + 4 {
             return Err(ProtocolError::InsufficientData {
+                offset: Self::CODE.len(),
                 expected: Self::CODE.len() + 4,
                 actual: data.len(),
             });
@@ -974,17 +1634,52 @@ impl ProtocolMessage for MessageMouseWheel {
         })
     }
 
-    fn to_bytes(&self) -> Vec<u8> {
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(Self::CODE.as_bytes())?;
+        w.write_all(&self.xdelta.to_be_bytes())?;
+        w.write_all(&self.ydelta.to_be_bytes())?;
+        Ok(())
+    }
+}
+
+impl MessageMouseWheel {
+    /// Parse the legacy form of this message used before protocol 1.3
+    ///
+    /// Horizontal scrolling was introduced in 1.3, older peers send a single delta
+    /// which is the vertical delta. The `xdelta` is set to 0.
+    /// See [`parse_message_versioned`].
+    pub fn from_bytes_v1_0(data: &[u8]) -> Result<Self> {
+        if data.len() < Self::CODE.len() + 2 {
+            return Err(ProtocolError::InsufficientData {
+                offset: Self::CODE.len(),
+                expected: Self::CODE.len() + 2,
+                actual: data.len(),
+            });
+        }
+        Ok(Self {
+            xdelta: 0,
+            ydelta: read_i16(data, Self::CODE.len())?,
+        })
+    }
+
+    /// Write the legacy form of this message used before protocol 1.3
+    ///
+    /// Only the `ydelta` is sent, the `xdelta` is dropped.
+    pub fn to_bytes_v1_0(&self) -> Vec<u8> {
         let mut bytes = Vec::new();
         bytes.extend_from_slice(Self::CODE.as_bytes());
-        bytes.extend_from_slice(&self.xdelta.to_be_bytes());
         bytes.extend_from_slice(&self.ydelta.to_be_bytes());
         bytes
     }
 }
 
 /// Clipboard data
-#[derive(Debug, Clone, PartialEq, Eq)]
+///
+/// See the [`clipboard`] module for the format of the data and how large clipboards
+/// are split across multiple messages.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct MessageClipboardData {
     /// Clipboard identifier (0 = primary, 1 = selection)
     pub id: u8,
@@ -992,8 +1687,53 @@ pub struct MessageClipboardData {
     pub sequence: u32,
     /// Streaming flags for large data (0=single chunk, 1=first, 2=middle, 3=final) - v1.6+
     pub mark: u8,
-    /// Clipboard content
-    pub data: LengthPrefixedString,
+    /// Clipboard content, not necessarily valid UTF-8
+    pub data: LengthPrefixedBytes,
+}
+
+impl MessageClipboardData {
+    /// The raw clipboard content
+    ///
+    /// Clipboard content is frequently Latin-1 or binary, this never fails.
+    pub fn data_bytes(&self) -> &[u8] {
+        &self.data.0
+    }
+
+    /// The clipboard content as a string
+    ///
+    /// Returns [`ProtocolError::InvalidUtf8`] if the content is not valid UTF-8, use
+    /// [`data_bytes`](Self::data_bytes) for the raw content.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use schengen::protocol::MessageClipboardData;
+    ///
+    /// let msg = MessageClipboardData { id: 0, sequence: 0, mark: 0, data: "hello".into() };
+    /// assert_eq!(msg.data_str().unwrap(), "hello");
+    /// ```
+    pub fn data_str(&self) -> Result<&str> {
+        std::str::from_utf8(&self.data.0).map_err(|_| ProtocolError::InvalidUtf8)
+    }
+
+    /// Parse the data of a [`MARK_SINGLE`](clipboard::MARK_SINGLE) message as
+    /// [`ClipboardContents`](clipboard::ClipboardContents)
+    ///
+    /// Chunked transfers must be reassembled with a [`ClipboardAssembler`] first.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ProtocolError::InvalidData`] if this is a chunk of a larger transfer,
+    /// or an error if the data is not in Synergy's internal clipboard format.
+    pub fn contents(&self) -> Result<clipboard::ClipboardContents> {
+        if self.mark != clipboard::MARK_SINGLE {
+            return Err(ProtocolError::InvalidData(format!(
+                "Clipboard message with mark {} is a chunk of a larger transfer",
+                self.mark
+            )));
+        }
+        clipboard::ClipboardContents::from_bytes(&self.data.0)
+    }
 }
 
 impl ProtocolMessage for MessageClipboardData {
@@ -1002,11 +1742,12 @@ impl ProtocolMessage for MessageClipboardData {
     fn from_bytes(data: &[u8]) -> Result<Self> {
         if data.len() < Self::CODE.len() + 6 {
             return Err(ProtocolError::InsufficientData {
+                offset: Self::CODE.len(),
                 expected: Self::CODE.len() + 6,
                 actual: data.len(),
             });
         }
-        let (clipboard_data, _) = LengthPrefixedString::from_bytes(data, Self::CODE.len() + 6)?;
+        let (clipboard_data, _) = LengthPrefixedBytes::from_bytes(data, Self::CODE.len() + 6)?;
         Ok(Self {
             id: read_u8(data, Self::CODE.len())?,
             sequence: read_u32(data, Self::CODE.len() + 1)?,
@@ -1015,19 +1756,20 @@ impl ProtocolMessage for MessageClipboardData {
         })
     }
 
-    fn to_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
-        bytes.extend_from_slice(Self::CODE.as_bytes());
-        bytes.push(self.id);
-        bytes.extend_from_slice(&self.sequence.to_be_bytes());
-        bytes.push(self.mark);
-        bytes.extend_from_slice(&self.data.to_bytes());
-        bytes
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(Self::CODE.as_bytes())?;
+        w.write_all(&[self.id])?;
+        w.write_all(&self.sequence.to_be_bytes())?;
+        w.write_all(&[self.mark])?;
+        self.data.write_to(w)?;
+        Ok(())
     }
 }
 
 /// Client info
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct MessageClientInfo {
     /// Left edge X coordinate of screen
     pub x: u16,
@@ -1045,12 +1787,37 @@ pub struct MessageClientInfo {
     pub size: u16,
 }
 
+impl MessageClientInfo {
+    /// The info of a screen at the origin with the mouse at its center
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use schengen::protocol::MessageClientInfo;
+    ///
+    /// let info = MessageClientInfo::for_screen(1920, 1080);
+    /// assert_eq!((info.current_mouse_x, info.current_mouse_y), (960, 540));
+    /// ```
+    pub fn for_screen(width: u16, height: u16) -> Self {
+        Self {
+            x: 0,
+            y: 0,
+            width,
+            height,
+            current_mouse_x: width / 2,
+            current_mouse_y: height / 2,
+            size: 0,
+        }
+    }
+}
+
 impl ProtocolMessage for MessageClientInfo {
     const CODE: &'static str = "DINF";
 
     fn from_bytes(data: &[u8]) -> Result<Self> {
         if data.len() < Self::CODE.len() + 14 {
             return Err(ProtocolError::InsufficientData {
+                offset: Self::CODE.len(),
                 expected: Self::CODE.len() + 14,
                 actual: data.len(),
             });
@@ -1066,17 +1833,16 @@ impl ProtocolMessage for MessageClientInfo {
         })
     }
 
-    fn to_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
-        bytes.extend_from_slice(Self::CODE.as_bytes());
-        bytes.extend_from_slice(&self.x.to_be_bytes());
-        bytes.extend_from_slice(&self.y.to_be_bytes());
-        bytes.extend_from_slice(&self.width.to_be_bytes());
-        bytes.extend_from_slice(&self.height.to_be_bytes());
-        bytes.extend_from_slice(&self.current_mouse_x.to_be_bytes());
-        bytes.extend_from_slice(&self.current_mouse_y.to_be_bytes());
-        bytes.extend_from_slice(&self.size.to_be_bytes());
-        bytes
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(Self::CODE.as_bytes())?;
+        w.write_all(&self.x.to_be_bytes())?;
+        w.write_all(&self.y.to_be_bytes())?;
+        w.write_all(&self.width.to_be_bytes())?;
+        w.write_all(&self.height.to_be_bytes())?;
+        w.write_all(&self.current_mouse_x.to_be_bytes())?;
+        w.write_all(&self.current_mouse_y.to_be_bytes())?;
+        w.write_all(&self.size.to_be_bytes())?;
+        Ok(())
     }
 }
 
@@ -1085,6 +1851,8 @@ impl ProtocolMessage for MessageClientInfo {
 /// These options are used in the SetOptions message (DSOP) to configure
 /// various aspects of the Synergy/Barrier protocol behavior.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 #[repr(u32)]
 pub enum DsopOption {
     // Keyboard Modifier Options
@@ -1108,7 +1876,7 @@ pub enum DsopOption {
     ModifierMapForSuper = 0x4D4D4652,
 
     // Screen Switching Options
-    /// SSCM - Screen switch corners
+    /// SSCM - Screen switch corners, see [`CornerMask`]
     ScreenSwitchCorners = 0x5353434D,
     /// SSCS - Screen switch corner size
     ScreenSwitchCornerSize = 0x53534353,
@@ -1144,6 +1912,14 @@ pub enum DsopOption {
     ScreenPreserveFocus = 0x53464F43,
     /// _KFW - Win32 keep foreground
     Win32KeepForeground = 0x5F4B4657,
+    /// SSVR - Screensaver synchronization
+    ScreenSaverSync = 0x53535652,
+
+    // Schengen extensions
+    /// SCAP - Schengen capabilities, see [`Capabilities`]
+    ///
+    /// Not part of the upstream protocol, non-schengen peers never send this option.
+    SchengenCaps = 0x53434150,
 }
 
 impl DsopOption {
@@ -1176,6 +1952,8 @@ impl DsopOption {
             0x58545855 => Some(Self::XTestXineramaUnaware),
             0x53464F43 => Some(Self::ScreenPreserveFocus),
             0x5F4B4657 => Some(Self::Win32KeepForeground),
+            0x53535652 => Some(Self::ScreenSaverSync),
+            0x53434150 => Some(Self::SchengenCaps),
             _ => None,
         }
     }
@@ -1209,10 +1987,150 @@ impl DsopOption {
             Self::XTestXineramaUnaware => "XTXU",
             Self::ScreenPreserveFocus => "SFOC",
             Self::Win32KeepForeground => "_KFW",
+            Self::ScreenSaverSync => "SSVR",
+            Self::SchengenCaps => "SCAP",
         }
     }
 }
 
+/// A bitfield of schengen protocol extensions
+///
+/// Exchanged as the value of [`DsopOption::SchengenCaps`] so that two schengen
+/// endpoints can agree on extensions beyond the upstream protocol. A peer that does
+/// not send the option has no capabilities. The individual bits are defined by the
+/// application.
+///
+/// # Example
+///
+/// ```
+/// use schengen::protocol::Capabilities;
+///
+/// let ours = Capabilities(0b011);
+/// let theirs = Capabilities(0b110);
+/// assert_eq!(ours.intersection(theirs), Capabilities(0b010));
+/// assert!(Capabilities::empty().is_empty());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Capabilities(pub u32);
+
+impl Capabilities {
+    /// No capabilities
+    pub fn empty() -> Self {
+        Self(0)
+    }
+
+    /// The raw wire format value
+    pub fn bits(self) -> u32 {
+        self.0
+    }
+
+    /// Whether no capability is set
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Whether all capabilities in `other` are set in `self`
+    pub fn contains(self, other: Capabilities) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// The capabilities set in both `self` and `other`
+    pub fn intersection(self, other: Capabilities) -> Self {
+        Self(self.0 & other.0)
+    }
+}
+
+impl From<u32> for Capabilities {
+    fn from(bits: u32) -> Self {
+        Self(bits)
+    }
+}
+
+impl From<Capabilities> for u32 {
+    fn from(caps: Capabilities) -> Self {
+        caps.0
+    }
+}
+
+impl std::ops::BitOr for Capabilities {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+/// The screen corners in which switching screens is disabled
+///
+/// Sent as the value of the [`DsopOption::ScreenSwitchCorners`] (`SSCM`) option, the
+/// size of the corners is sent with [`DsopOption::ScreenSwitchCornerSize`] (`SSCS`).
+///
+/// # Example
+///
+/// ```
+/// use schengen::protocol::CornerMask;
+///
+/// let corners = CornerMask::TOP_LEFT | CornerMask::TOP_RIGHT;
+/// assert_eq!(corners.bits(), 0b0011);
+/// assert!(corners.contains(CornerMask::TOP_LEFT));
+/// assert!(!corners.contains(CornerMask::BOTTOM_LEFT));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct CornerMask(pub u32);
+
+impl CornerMask {
+    /// The top left corner
+    pub const TOP_LEFT: Self = Self(1 << 0);
+    /// The top right corner
+    pub const TOP_RIGHT: Self = Self(1 << 1);
+    /// The bottom left corner
+    pub const BOTTOM_LEFT: Self = Self(1 << 2);
+    /// The bottom right corner
+    pub const BOTTOM_RIGHT: Self = Self(1 << 3);
+    /// All four corners
+    pub const ALL: Self = Self(0b1111);
+
+    /// No corners
+    pub fn empty() -> Self {
+        Self(0)
+    }
+
+    /// The raw wire format value
+    pub fn bits(self) -> u32 {
+        self.0
+    }
+
+    /// Whether no corner is set
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Whether all corners in `other` are set in `self`
+    pub fn contains(self, other: CornerMask) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl From<u32> for CornerMask {
+    fn from(bits: u32) -> Self {
+        Self(bits)
+    }
+}
+
+impl From<CornerMask> for u32 {
+    fn from(corners: CornerMask) -> Self {
+        corners.0
+    }
+}
+
+impl std::ops::BitOr for CornerMask {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
 /// Set options
 ///
 /// DSOP message format:
@@ -1221,18 +2139,64 @@ impl DsopOption {
 /// - Key-value pairs, where each pair is:
 ///   - Key (4 bytes BE) - see DsopOption
 ///   - Value (4 bytes BE)
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct MessageSetOptions {
     /// Vector of (key, value) pairs for options
     pub options: Vec<(u32, u32)>,
 }
 
+impl MessageSetOptions {
+    /// Create a DSOP message from typed option keys
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use schengen::protocol::{DsopOption, MessageSetOptions};
+    ///
+    /// let msg = MessageSetOptions::from_typed(&[(DsopOption::Heartbeat, 3000)]);
+    /// assert_eq!(msg.get(DsopOption::Heartbeat), Some(3000));
+    /// ```
+    pub fn from_typed(options: &[(DsopOption, u32)]) -> Self {
+        Self {
+            options: options
+                .iter()
+                .map(|(key, value)| (*key as u32, *value))
+                .collect(),
+        }
+    }
+
+    /// The options with their keys resolved to [`DsopOption`]
+    ///
+    /// Keys not known to this crate are returned as `None` with their value intact,
+    /// use the raw `options` field to access the key itself.
+    pub fn typed(&self) -> Vec<(Option<DsopOption>, u32)> {
+        self.options
+            .iter()
+            .map(|(key, value)| (DsopOption::from_u32(*key), *value))
+            .collect()
+    }
+
+    /// Look up the value of an option, if present
+    ///
+    /// If the option is present multiple times, the last value wins.
+    pub fn get(&self, option: DsopOption) -> Option<u32> {
+        self.options
+            .iter()
+            .rev()
+            .find(|(key, _)| *key == option as u32)
+            .map(|(_, value)| *value)
+    }
+}
+
 impl ProtocolMessage for MessageSetOptions {
     const CODE: &'static str = "DSOP";
 
     fn from_bytes(data: &[u8]) -> Result<Self> {
         if data.len() < Self::CODE.len() + 4 {
             return Err(ProtocolError::InsufficientData {
+                offset: Self::CODE.len(),
                 expected: Self::CODE.len() + 4,
                 actual: data.len(),
             });
@@ -1263,6 +2227,7 @@ impl ProtocolMessage for MessageSetOptions {
         let expected_size = Self::CODE.len() + 4 + (num_pairs * 8);
         if data.len() < expected_size {
             return Err(ProtocolError::InsufficientData {
+                offset,
                 expected: expected_size,
                 actual: data.len(),
             });
@@ -1280,31 +2245,62 @@ impl ProtocolMessage for MessageSetOptions {
         Ok(Self { options })
     }
 
-    fn to_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
-        bytes.extend_from_slice(Self::CODE.as_bytes());
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(Self::CODE.as_bytes())?;
 
         // Write length: 1 (for length itself) + options.len() * 2 (for key-value pairs)
         let length = 1 + (self.options.len() * 2) as u32;
-        bytes.extend_from_slice(&length.to_be_bytes());
+        w.write_all(&length.to_be_bytes())?;
 
         // Write each key-value pair
         for (key, value) in &self.options {
-            bytes.extend_from_slice(&key.to_be_bytes());
-            bytes.extend_from_slice(&value.to_be_bytes());
+            w.write_all(&key.to_be_bytes())?;
+            w.write_all(&value.to_be_bytes())?;
         }
 
-        bytes
+        Ok(())
     }
 }
 
 /// File transfer
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct MessageFileTransfer {
     /// Transfer state (1=DataStart with file size, 2=DataChunk with content, 3=DataEnd)
     pub mark: u8,
     /// Content depending on mark (file size for start, file content for chunk, empty for end)
-    pub data: LengthPrefixedString,
+    ///
+    /// File content is binary, so this is not necessarily valid UTF-8.
+    pub data: LengthPrefixedBytes,
+}
+
+impl MessageFileTransfer {
+    /// Create the first message of a transfer, announcing the file size
+    pub fn data_start(size: u64) -> Self {
+        Self {
+            mark: file_transfer::MARK_START,
+            data: size.to_string().into(),
+        }
+    }
+
+    /// The announced file size if this is the first message of a transfer
+    ///
+    /// Returns `None` if `mark` is not 1 or the payload is not a decimal integer.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use schengen::protocol::MessageFileTransfer;
+    ///
+    /// assert_eq!(MessageFileTransfer::data_start(1024).file_size(), Some(1024));
+    /// ```
+    pub fn file_size(&self) -> Option<u64> {
+        if self.mark != file_transfer::MARK_START {
+            return None;
+        }
+        std::str::from_utf8(&self.data.0).ok()?.parse().ok()
+    }
 }
 
 impl ProtocolMessage for MessageFileTransfer {
@@ -1313,28 +2309,30 @@ impl ProtocolMessage for MessageFileTransfer {
     fn from_bytes(data: &[u8]) -> Result<Self> {
         if data.len() < Self::CODE.len() + 1 {
             return Err(ProtocolError::InsufficientData {
+                offset: Self::CODE.len(),
                 expected: Self::CODE.len() + 1,
                 actual: data.len(),
             });
         }
-        let (transfer_data, _) = LengthPrefixedString::from_bytes(data, Self::CODE.len() + 1)?;
+        let (transfer_data, _) = LengthPrefixedBytes::from_bytes(data, Self::CODE.len() + 1)?;
         Ok(Self {
             mark: read_u8(data, Self::CODE.len())?,
             data: transfer_data,
         })
     }
 
-    fn to_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
-        bytes.extend_from_slice(Self::CODE.as_bytes());
-        bytes.push(self.mark);
-        bytes.extend_from_slice(&self.data.to_bytes());
-        bytes
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(Self::CODE.as_bytes())?;
+        w.write_all(&[self.mark])?;
+        self.data.write_to(w)?;
+        Ok(())
     }
 }
 
 /// Drag info
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct MessageDragInfo {
     /// Number of files being dragged
     pub size: u16,
@@ -1342,12 +2340,73 @@ pub struct MessageDragInfo {
     pub data: LengthPrefixedString,
 }
 
+impl MessageDragInfo {
+    /// Create a drag info message for the given paths, setting `size` accordingly
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ProtocolError::InvalidData`] if there are more paths than fit in the
+    /// 16-bit `size`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use schengen::protocol::MessageDragInfo;
+    ///
+    /// let paths = vec!["/tmp/a file.txt".to_string(), "/tmp/b.txt".to_string()];
+    /// let msg = MessageDragInfo::from_paths(&paths).unwrap();
+    /// assert_eq!(msg.size, 2);
+    /// assert_eq!(msg.paths(), paths);
+    /// ```
+    pub fn from_paths(paths: &[String]) -> Result<Self> {
+        let size = u16::try_from(paths.len()).map_err(|_| {
+            ProtocolError::InvalidData(format!(
+                "DDRG can hold at most {} paths, got {}",
+                u16::MAX,
+                paths.len()
+            ))
+        })?;
+        Ok(Self {
+            size,
+            data: paths.join("\0").into(),
+        })
+    }
+
+    /// The dragged file paths
+    ///
+    /// Splits `data` on the null separator, empty entries (e.g. from a trailing
+    /// separator) are skipped.
+    pub fn paths(&self) -> Vec<String> {
+        self.data
+            .0
+            .split('\0')
+            .filter(|p| !p.is_empty())
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// Check that `size` matches the number of paths in `data`
+    ///
+    /// Peers are known to be sloppy with the count, so parsing doesn't check it.
+    pub fn validate(&self) -> Result<()> {
+        let count = self.paths().len();
+        if count != self.size as usize {
+            return Err(ProtocolError::InvalidData(format!(
+                "DDRG announces {} files but contains {} paths",
+                self.size, count
+            )));
+        }
+        Ok(())
+    }
+}
+
 impl ProtocolMessage for MessageDragInfo {
     const CODE: &'static str = "DDRG";
 
     fn from_bytes(data: &[u8]) -> Result<Self> {
         if data.len() < Self::CODE.len() + 2 {
             return Err(ProtocolError::InsufficientData {
+                offset: Self::CODE.len(),
                 expected: Self::CODE.len() + 2,
                 actual: data.len(),
             });
@@ -1359,17 +2418,18 @@ impl ProtocolMessage for MessageDragInfo {
         })
     }
 
-    fn to_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
-        bytes.extend_from_slice(Self::CODE.as_bytes());
-        bytes.extend_from_slice(&self.size.to_be_bytes());
-        bytes.extend_from_slice(&self.data.to_bytes());
-        bytes
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(Self::CODE.as_bytes())?;
+        w.write_all(&self.size.to_be_bytes())?;
+        self.data.write_to(w)?;
+        Ok(())
     }
 }
 
 /// Secure encryption
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct MessageSecureEncryption {
     /// Application name requesting secure input (macOS feature)
     pub data: LengthPrefixedString,
@@ -1383,21 +2443,55 @@ impl ProtocolMessage for MessageSecureEncryption {
         Ok(Self { data: secure_data })
     }
 
-    fn to_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
-        bytes.extend_from_slice(Self::CODE.as_bytes());
-        bytes.extend_from_slice(&self.data.to_bytes());
-        bytes
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(Self::CODE.as_bytes())?;
+        self.data.write_to(w)?;
+        Ok(())
     }
 }
 
 /// Legacy synergy
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct MessageLegacySynergy {
     /// Comma-separated list of language codes (ISO 639-1)
     pub data: LengthPrefixedString,
 }
 
+impl MessageLegacySynergy {
+    /// Create a message listing the given language codes
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use schengen::protocol::MessageLegacySynergy;
+    ///
+    /// let msg = MessageLegacySynergy::from_languages(&["en", "de"]);
+    /// assert_eq!(msg.data.0, "en,de");
+    /// assert_eq!(msg.languages(), ["en", "de"]);
+    /// ```
+    pub fn from_languages(languages: &[&str]) -> Self {
+        Self {
+            data: languages.join(",").into(),
+        }
+    }
+
+    /// The listed language codes
+    ///
+    /// Splits `data` on commas and trims whitespace around each code, empty entries
+    /// are skipped.
+    pub fn languages(&self) -> Vec<String> {
+        self.data
+            .0
+            .split(',')
+            .map(str::trim)
+            .filter(|l| !l.is_empty())
+            .map(str::to_string)
+            .collect()
+    }
+}
+
 impl ProtocolMessage for MessageLegacySynergy {
     const CODE: &'static str = "LSYN";
 
@@ -1406,16 +2500,17 @@ impl ProtocolMessage for MessageLegacySynergy {
         Ok(Self { data: legacy_data })
     }
 
-    fn to_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
-        bytes.extend_from_slice(Self::CODE.as_bytes());
-        bytes.extend_from_slice(&self.data.to_bytes());
-        bytes
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(Self::CODE.as_bytes())?;
+        self.data.write_to(w)?;
+        Ok(())
     }
 }
 
 /// Query info
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct MessageQueryInfo;
 
 impl ProtocolMessage for MessageQueryInfo {
@@ -1425,13 +2520,15 @@ impl ProtocolMessage for MessageQueryInfo {
         Ok(Self)
     }
 
-    fn to_bytes(&self) -> Vec<u8> {
-        Self::CODE.as_bytes().to_vec()
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(Self::CODE.as_bytes())
     }
 }
 
 /// Incompatible version error
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct MessageIncompatibleVersion {
     /// Primary's major version number
     pub major_remote: u16,
@@ -1445,6 +2542,7 @@ impl ProtocolMessage for MessageIncompatibleVersion {
     fn from_bytes(data: &[u8]) -> Result<Self> {
         if data.len() < Self::CODE.len() + 4 {
             return Err(ProtocolError::InsufficientData {
+                offset: Self::CODE.len(),
                 expected: Self::CODE.len() + 4,
                 actual: data.len(),
             });
@@ -1455,17 +2553,18 @@ impl ProtocolMessage for MessageIncompatibleVersion {
         })
     }
 
-    fn to_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
-        bytes.extend_from_slice(Self::CODE.as_bytes());
-        bytes.extend_from_slice(&self.major_remote.to_be_bytes());
-        bytes.extend_from_slice(&self.minor_remote.to_be_bytes());
-        bytes
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(Self::CODE.as_bytes())?;
+        w.write_all(&self.major_remote.to_be_bytes())?;
+        w.write_all(&self.minor_remote.to_be_bytes())?;
+        Ok(())
     }
 }
 
 /// Server busy error
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct MessageServerBusy;
 
 impl ProtocolMessage for MessageServerBusy {
@@ -1475,13 +2574,15 @@ impl ProtocolMessage for MessageServerBusy {
         Ok(Self)
     }
 
-    fn to_bytes(&self) -> Vec<u8> {
-        Self::CODE.as_bytes().to_vec()
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(Self::CODE.as_bytes())
     }
 }
 
 /// Unknown client error
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct MessageUnknownClient;
 
 impl ProtocolMessage for MessageUnknownClient {
@@ -1491,13 +2592,15 @@ impl ProtocolMessage for MessageUnknownClient {
         Ok(Self)
     }
 
-    fn to_bytes(&self) -> Vec<u8> {
-        Self::CODE.as_bytes().to_vec()
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(Self::CODE.as_bytes())
     }
 }
 
 /// Protocol error
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct MessageProtocolError;
 
 impl ProtocolMessage for MessageProtocolError {
@@ -1507,13 +2610,19 @@ impl ProtocolMessage for MessageProtocolError {
         Ok(Self)
     }
 
-    fn to_bytes(&self) -> Vec<u8> {
-        Self::CODE.as_bytes().to_vec()
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(Self::CODE.as_bytes())
     }
 }
 
 /// Main protocol message enum representing all possible messages
-#[derive(Debug, Clone, PartialEq, Eq)]
+///
+/// New messages may be added in minor releases. Code that forwards messages it
+/// doesn't handle can do so with [`Message::relay_bytes`] in a `_` arm.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[non_exhaustive]
 pub enum Message {
     HelloBarrier(MessageHelloBarrier),
     HelloSynergy(MessageHelloSynergy),
@@ -1547,13 +2656,22 @@ pub enum Message {
     ServerBusy(MessageServerBusy),
     UnknownClient(MessageUnknownClient),
     ProtocolError(MessageProtocolError),
+    /// A message with a code this crate does not know about
+    ///
+    /// Never returned by [`parse_message`], see [`parse_message_preserving_unknown`]
+    /// and [`parse_message_with_registry`].
+    /// `payload` is everything after the 4-byte code.
+    Unknown {
+        code: [u8; 4],
+        payload: Vec<u8>,
+    },
 }
 
 impl Message {
     /// Converts this message to bytes with a 4-byte length prefix
     ///
-    /// This method wraps the individual message's `ProtocolMessage::to_bytes()`
-    /// implementation and adds the required 4-byte length prefix.
+    /// Allocates a buffer of [`Message::size_hint`] bytes and fills it with
+    /// [`Message::write_framed`].
     ///
     /// # Example
     ///
@@ -1567,47 +2685,613 @@ impl Message {
     /// assert_eq!(&bytes[4..8], b"CALV");
     /// ```
     pub fn to_bytes(&self) -> Vec<u8> {
+        let mut result = Vec::with_capacity(self.size_hint());
+        self.write_framed(&mut result)
+            .expect("writing to a Vec does not fail");
+        result
+    }
+
+    /// Serialize this message into `w` with a 4-byte length prefix
+    ///
+    /// The allocation-free counterpart of [`Message::to_bytes`], e.g. to write many
+    /// messages into one reused buffer. With the `bytes` feature, a `BytesMut` can be
+    /// written to via `BufMut::writer`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first error of `w`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use schengen::protocol::{Message, MessageMouseMove};
+    ///
+    /// let msg = Message::MouseMove(MessageMouseMove { x: 100, y: 200 });
+    /// let mut buffer = Vec::new();
+    /// msg.write_framed(&mut buffer).unwrap();
+    /// assert_eq!(buffer, msg.to_bytes());
+    /// ```
+    pub fn write_framed<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(&((self.size_hint() - 4) as u32).to_be_bytes())?;
+        match self {
+            Message::HelloBarrier(m) => m.write_to(w),
+            Message::HelloSynergy(m) => m.write_to(w),
+            Message::NoOp(m) => m.write_to(w),
+            Message::Close(m) => m.write_to(w),
+            Message::CursorEntered(m) => m.write_to(w),
+            Message::CursorLeft(m) => m.write_to(w),
+            Message::ClientClipboard(m) => m.write_to(w),
+            Message::ScreenSaverChange(m) => m.write_to(w),
+            Message::ResetOptions(m) => m.write_to(w),
+            Message::InfoAcknowledgment(m) => m.write_to(w),
+            Message::KeepAlive(m) => m.write_to(w),
+            Message::KeyDownWithLanguage(m) => m.write_to(w),
+            Message::KeyDown(m) => m.write_to(w),
+            Message::KeyRepeat(m) => m.write_to(w),
+            Message::KeyUp(m) => m.write_to(w),
+            Message::MouseButtonDown(m) => m.write_to(w),
+            Message::MouseButtonUp(m) => m.write_to(w),
+            Message::MouseMove(m) => m.write_to(w),
+            Message::MouseRelativeMove(m) => m.write_to(w),
+            Message::MouseWheel(m) => m.write_to(w),
+            Message::ClipboardData(m) => m.write_to(w),
+            Message::ClientInfo(m) => m.write_to(w),
+            Message::SetOptions(m) => m.write_to(w),
+            Message::FileTransfer(m) => m.write_to(w),
+            Message::DragInfo(m) => m.write_to(w),
+            Message::SecureEncryption(m) => m.write_to(w),
+            Message::LegacySynergy(m) => m.write_to(w),
+            Message::QueryInfo(m) => m.write_to(w),
+            Message::IncompatibleVersion(m) => m.write_to(w),
+            Message::ServerBusy(m) => m.write_to(w),
+            Message::UnknownClient(m) => m.write_to(w),
+            Message::ProtocolError(m) => m.write_to(w),
+            Message::Unknown { code, payload } => {
+                w.write_all(code)?;
+                w.write_all(payload)
+            }
+        }
+    }
+
+    /// The length of [`Message::to_bytes`], including the 4-byte length prefix
+    ///
+    /// Computed from the fields without serializing the message, e.g. to size a write
+    /// buffer up front.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use schengen::protocol::{Message, MessageSecureEncryption};
+    ///
+    /// let msg = Message::SecureEncryption(MessageSecureEncryption {
+    ///     data: "Terminal".into(),
+    /// });
+    /// assert_eq!(msg.size_hint(), 4 + 4 + 4 + 8);
+    /// assert_eq!(msg.size_hint(), msg.to_bytes().len());
+    /// ```
+    pub fn size_hint(&self) -> usize {
+        let hello = |name: &Option<String>| 4 + name.as_ref().map_or(0, |n| 4 + n.len());
+        let payload = match self {
+            Message::HelloBarrier(m) => hello(&m.client_name),
+            Message::HelloSynergy(m) => hello(&m.client_name),
+            Message::NoOp(_)
+            | Message::Close(_)
+            | Message::CursorLeft(_)
+            | Message::ResetOptions(_)
+            | Message::InfoAcknowledgment(_)
+            | Message::KeepAlive(_)
+            | Message::QueryInfo(_)
+            | Message::ServerBusy(_)
+            | Message::UnknownClient(_)
+            | Message::ProtocolError(_) => 0,
+            Message::CursorEntered(_) => 10,
+            Message::ClientClipboard(_) => 5,
+            Message::ScreenSaverChange(_) => 1,
+            Message::KeyDownWithLanguage(m) => 6 + 4 + m.lang.0.len(),
+            Message::KeyDown(_) | Message::KeyUp(_) => 6,
+            Message::KeyRepeat(m) => 8 + 4 + m.lang.0.len(),
+            Message::MouseButtonDown(_) | Message::MouseButtonUp(_) => 1,
+            Message::MouseMove(_) | Message::MouseRelativeMove(_) | Message::MouseWheel(_) => 4,
+            Message::ClipboardData(m) => 6 + 4 + m.data.0.len(),
+            Message::ClientInfo(_) => 14,
+            Message::SetOptions(m) => 4 + m.options.len() * 8,
+            Message::FileTransfer(m) => 1 + 4 + m.data.0.len(),
+            Message::DragInfo(m) => 2 + 4 + m.data.0.len(),
+            Message::SecureEncryption(m) => 4 + m.data.0.len(),
+            Message::LegacySynergy(m) => 4 + m.data.0.len(),
+            Message::IncompatibleVersion(_) => 4,
+            Message::Unknown { payload, .. } => payload.len(),
+        };
+        4 + self.code().len() + payload
+    }
+
+    /// The frame to forward this message to another peer, including the length prefix
+    ///
+    /// For [`Message::Unknown`], as returned by [`parse_message_preserving_unknown`],
+    /// this is the frame the message was parsed from, byte for byte. Every other
+    /// variant is written in its current format like [`Message::to_bytes`]: bytes a
+    /// newer peer appended after the fields this crate knows are not kept by the
+    /// parsers and are lost. A proxy that must forward such messages unchanged should
+    /// forward the frame it received and only use the parsed message to inspect it.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use schengen::protocol::{parse_message_preserving_unknown, Message};
+    ///
+    /// # fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// let frame = [0, 0, 0, 5, b'Z', b'Z', b'Z', b'Z', 1];
+    /// let msg = parse_message_preserving_unknown(&frame[4..])?;
+    /// let forwarded = match &msg {
+    ///     Message::KeepAlive(_) => None,
+    ///     other => Some(other.relay_bytes()),
+    /// };
+    /// assert_eq!(forwarded.as_deref(), Some(&frame[..]));
+    /// # Ok(())
+    /// # }
+    /// # example().unwrap();
+    /// ```
+    pub fn relay_bytes(&self) -> Vec<u8> {
+        self.to_bytes()
+    }
+
+    /// Converts this message to bytes with a 4-byte length prefix for a peer that
+    /// negotiated `version`
+    ///
+    /// Messages whose format changed between protocol versions are written in the
+    /// form understood by `version`, the counterpart of [`parse_message_versioned`]:
+    /// - Before 1.1, `DKDN`, `DKUP` and `DKRP` have no `button` field
+    /// - Before 1.3, `DMWM` only has the vertical delta
+    ///
+    /// Messages that `version` does not know about are downgraded to the nearest
+    /// supported message:
+    /// - Before 1.8, `DKDL` is sent as `DKDN` without the language
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use schengen::protocol::{Message, MessageMouseWheel, ProtocolVersion};
+    ///
+    /// let msg = Message::MouseWheel(MessageMouseWheel { xdelta: 0, ydelta: 120 });
+    /// let bytes = msg.to_bytes_versioned(ProtocolVersion::new(1, 2));
+    /// assert_eq!(bytes, [0, 0, 0, 6, b'D', b'M', b'W', b'M', 0, 120]);
+    /// ```
+    pub fn to_bytes_versioned(&self, version: ProtocolVersion) -> Vec<u8> {
         let data = match self {
-            Message::HelloBarrier(m) => m.to_bytes(),
-            Message::HelloSynergy(m) => m.to_bytes(),
-            Message::NoOp(m) => m.to_bytes(),
-            Message::Close(m) => m.to_bytes(),
-            Message::CursorEntered(m) => m.to_bytes(),
-            Message::CursorLeft(m) => m.to_bytes(),
-            Message::ClientClipboard(m) => m.to_bytes(),
-            Message::ScreenSaverChange(m) => m.to_bytes(),
-            Message::ResetOptions(m) => m.to_bytes(),
-            Message::InfoAcknowledgment(m) => m.to_bytes(),
-            Message::KeepAlive(m) => m.to_bytes(),
-            Message::KeyDownWithLanguage(m) => m.to_bytes(),
-            Message::KeyDown(m) => m.to_bytes(),
-            Message::KeyRepeat(m) => m.to_bytes(),
-            Message::KeyUp(m) => m.to_bytes(),
-            Message::MouseButtonDown(m) => m.to_bytes(),
-            Message::MouseButtonUp(m) => m.to_bytes(),
-            Message::MouseMove(m) => m.to_bytes(),
-            Message::MouseRelativeMove(m) => m.to_bytes(),
-            Message::MouseWheel(m) => m.to_bytes(),
-            Message::ClipboardData(m) => m.to_bytes(),
-            Message::ClientInfo(m) => m.to_bytes(),
-            Message::SetOptions(m) => m.to_bytes(),
-            Message::FileTransfer(m) => m.to_bytes(),
-            Message::DragInfo(m) => m.to_bytes(),
-            Message::SecureEncryption(m) => m.to_bytes(),
-            Message::LegacySynergy(m) => m.to_bytes(),
-            Message::QueryInfo(m) => m.to_bytes(),
-            Message::IncompatibleVersion(m) => m.to_bytes(),
-            Message::ServerBusy(m) => m.to_bytes(),
-            Message::UnknownClient(m) => m.to_bytes(),
-            Message::ProtocolError(m) => m.to_bytes(),
+            Message::KeyDown(m) if version < ProtocolVersion::V1_1 => m.to_bytes_v1_0(),
+            Message::KeyRepeat(m) if version < ProtocolVersion::V1_1 => m.to_bytes_v1_0(),
+            Message::KeyUp(m) if version < ProtocolVersion::V1_1 => m.to_bytes_v1_0(),
+            Message::MouseWheel(m) if version < ProtocolVersion::V1_3 => m.to_bytes_v1_0(),
+            Message::KeyDownWithLanguage(m) if version < ProtocolVersion::V1_8 => {
+                let key_down = Message::KeyDown(MessageKeyDown {
+                    keyid: m.keyid,
+                    mask: m.mask,
+                    button: m.button,
+                });
+                return key_down.to_bytes_versioned(version);
+            }
+            _ => return self.to_bytes(),
         };
 
-        // Prepend the length
         let mut result = Vec::with_capacity(4 + data.len());
         result.extend_from_slice(&(data.len() as u32).to_be_bytes());
         result.extend_from_slice(&data);
         result
     }
+
+    /// Returns the protocol code of the wrapped message
+    ///
+    /// This is the [`ProtocolMessage::CODE`] of the message type, i.e. the 4-character prefix
+    /// (or the 7-character prefix for the Hello messages). For [`Message::Unknown`] this
+    /// is `"????"`, see [`Message::raw_code`] for its actual code.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use schengen::protocol::{Message, MessageKeepAlive};
+    ///
+    /// let msg = Message::KeepAlive(MessageKeepAlive);
+    /// assert_eq!(msg.code(), "CALV");
+    /// ```
+    pub fn code(&self) -> &'static str {
+        match self {
+            Message::HelloBarrier(_) => MessageHelloBarrier::CODE,
+            Message::HelloSynergy(_) => MessageHelloSynergy::CODE,
+            Message::NoOp(_) => MessageNoOp::CODE,
+            Message::Close(_) => MessageClose::CODE,
+            Message::CursorEntered(_) => MessageCursorEntered::CODE,
+            Message::CursorLeft(_) => MessageCursorLeft::CODE,
+            Message::ClientClipboard(_) => MessageClientClipboard::CODE,
+            Message::ScreenSaverChange(_) => MessageScreenSaverChange::CODE,
+            Message::ResetOptions(_) => MessageResetOptions::CODE,
+            Message::InfoAcknowledgment(_) => MessageInfoAcknowledgment::CODE,
+            Message::KeepAlive(_) => MessageKeepAlive::CODE,
+            Message::KeyDownWithLanguage(_) => MessageKeyDownWithLanguage::CODE,
+            Message::KeyDown(_) => MessageKeyDown::CODE,
+            Message::KeyRepeat(_) => MessageKeyRepeat::CODE,
+            Message::KeyUp(_) => MessageKeyUp::CODE,
+            Message::MouseButtonDown(_) => MessageMouseButtonDown::CODE,
+            Message::MouseButtonUp(_) => MessageMouseButtonUp::CODE,
+            Message::MouseMove(_) => MessageMouseMove::CODE,
+            Message::MouseRelativeMove(_) => MessageMouseRelativeMove::CODE,
+            Message::MouseWheel(_) => MessageMouseWheel::CODE,
+            Message::ClipboardData(_) => MessageClipboardData::CODE,
+            Message::ClientInfo(_) => MessageClientInfo::CODE,
+            Message::SetOptions(_) => MessageSetOptions::CODE,
+            Message::FileTransfer(_) => MessageFileTransfer::CODE,
+            Message::DragInfo(_) => MessageDragInfo::CODE,
+            Message::SecureEncryption(_) => MessageSecureEncryption::CODE,
+            Message::LegacySynergy(_) => MessageLegacySynergy::CODE,
+            Message::QueryInfo(_) => MessageQueryInfo::CODE,
+            Message::IncompatibleVersion(_) => MessageIncompatibleVersion::CODE,
+            Message::ServerBusy(_) => MessageServerBusy::CODE,
+            Message::UnknownClient(_) => MessageUnknownClient::CODE,
+            Message::ProtocolError(_) => MessageProtocolError::CODE,
+            Message::Unknown { .. } => "????",
+        }
+    }
+
+    /// Returns the 4-byte protocol code of the message as sent on the wire
+    ///
+    /// Unlike [`Message::code`] this is the received code for [`Message::Unknown`]. The
+    /// Hello messages have a 7-byte code, this is its first 4 bytes.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use schengen::protocol::Message;
+    ///
+    /// let msg = Message::Unknown {
+    ///     code: *b"ZZZZ",
+    ///     payload: vec![],
+    /// };
+    /// assert_eq!(msg.code(), "????");
+    /// assert_eq!(&msg.raw_code(), b"ZZZZ");
+    /// ```
+    pub fn raw_code(&self) -> [u8; 4] {
+        match self {
+            Message::Unknown { code, .. } => *code,
+            _ => {
+                let code = self.code().as_bytes();
+                [code[0], code[1], code[2], code[3]]
+            }
+        }
+    }
+
+    /// Returns true if this is one of the error messages (`EICV`, `EBSY`, `EUNK` or `EBAD`)
+    pub fn is_error(&self) -> bool {
+        matches!(
+            self,
+            Message::IncompatibleVersion(_)
+                | Message::ServerBusy(_)
+                | Message::UnknownClient(_)
+                | Message::ProtocolError(_)
+        )
+    }
+
+    /// Returns true if this is keyboard or mouse input (`DKDN`, `DKDL`, `DKRP`, `DKUP`,
+    /// `DMDN`, `DMUP`, `DMMV`, `DMRM` or `DMWM`)
+    pub fn is_input(&self) -> bool {
+        matches!(
+            self,
+            Message::KeyDown(_)
+                | Message::KeyDownWithLanguage(_)
+                | Message::KeyRepeat(_)
+                | Message::KeyUp(_)
+                | Message::MouseButtonDown(_)
+                | Message::MouseButtonUp(_)
+                | Message::MouseMove(_)
+                | Message::MouseRelativeMove(_)
+                | Message::MouseWheel(_)
+        )
+    }
+
+    /// Write a compact, single-line, human-readable form of this message
+    ///
+    /// This writes directly into `w` without allocating, useful for printing a stream
+    /// of messages into a reused buffer. The [`Display`](fmt::Display) implementation
+    /// uses the same format. Variable-length payloads like clipboard data are
+    /// summarized by their length.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use schengen::protocol::{Message, MessageMouseMove};
+    ///
+    /// let mut out = String::new();
+    /// Message::MouseMove(MessageMouseMove { x: 10, y: 20 }).write_pretty(&mut out).unwrap();
+    /// assert_eq!(out, "DMMV x=10 y=20");
+    /// ```
+    pub fn write_pretty(&self, w: &mut impl fmt::Write) -> fmt::Result {
+        match self {
+            Message::Unknown { code, .. } => write!(w, "{}", String::from_utf8_lossy(code))?,
+            _ => w.write_str(self.code())?,
+        }
+        match self {
+            Message::HelloBarrier(MessageHelloBarrier {
+                major,
+                minor,
+                client_name,
+            })
+            | Message::HelloSynergy(MessageHelloSynergy {
+                major,
+                minor,
+                client_name,
+            }) => {
+                write!(w, " version={}.{}", major, minor)?;
+                if let Some(name) = client_name {
+                    write!(w, " name={:?}", name)?;
+                }
+                Ok(())
+            }
+            Message::CursorEntered(m) => write!(
+                w,
+                " x={} y={} seq={} mask=0x{:04x}",
+                m.x, m.y, m.sequence, m.mask
+            ),
+            Message::ClientClipboard(m) => write!(w, " id={} seq={}", m.id, m.sequence),
+            Message::ScreenSaverChange(m) => write!(w, " state={}", m.state),
+            Message::KeyDownWithLanguage(m) => write!(
+                w,
+                " key=0x{:04x} mask=0x{:04x} button=0x{:04x} lang={:?}",
+                m.keyid, m.mask, m.button, m.lang.0
+            ),
+            Message::KeyDown(MessageKeyDown {
+                keyid,
+                mask,
+                button,
+            })
+            | Message::KeyUp(MessageKeyUp {
+                keyid,
+                mask,
+                button,
+            }) => write!(
+                w,
+                " key=0x{:04x} mask=0x{:04x} button=0x{:04x}",
+                keyid, mask, button
+            ),
+            Message::KeyRepeat(m) => write!(
+                w,
+                " key=0x{:04x} mask=0x{:04x} button=0x{:04x} count={} lang={:?}",
+                m.keyid, m.mask, m.button, m.count, m.lang.0
+            ),
+            Message::MouseButtonDown(MessageMouseButtonDown { button })
+            | Message::MouseButtonUp(MessageMouseButtonUp { button }) => {
+                write!(w, " button={}", button)
+            }
+            Message::MouseMove(m) => write!(w, " x={} y={}", m.x, m.y),
+            Message::MouseRelativeMove(m) => write!(w, " dx={} dy={}", m.x, m.y),
+            Message::MouseWheel(m) => write!(w, " x={} y={}", m.xdelta, m.ydelta),
+            Message::ClipboardData(m) => write!(
+                w,
+                " id={} seq={} mark={} len={}",
+                m.id,
+                m.sequence,
+                m.mark,
+                m.data.0.len()
+            ),
+            Message::ClientInfo(m) => write!(
+                w,
+                " pos={},{} size={}x{} mouse={},{}",
+                m.x, m.y, m.width, m.height, m.current_mouse_x, m.current_mouse_y
+            ),
+            Message::SetOptions(m) => {
+                for (key, value) in &m.options {
+                    match DsopOption::from_u32(*key) {
+                        Some(option) => write!(w, " {}={}", option.code(), value)?,
+                        None => write!(w, " 0x{:08x}={}", key, value)?,
+                    }
+                }
+                Ok(())
+            }
+            Message::FileTransfer(m) => write!(w, " mark={} len={}", m.mark, m.data.0.len()),
+            Message::DragInfo(m) => write!(w, " count={} len={}", m.size, m.data.0.len()),
+            Message::SecureEncryption(m) => write!(w, " {:?}", m.data.0),
+            Message::LegacySynergy(m) => write!(w, " {:?}", m.data.0),
+            Message::IncompatibleVersion(m) => {
+                write!(w, " version={}.{}", m.major_remote, m.minor_remote)
+            }
+            Message::Unknown { payload, .. } => write!(w, " len={}", payload.len()),
+            Message::NoOp(_)
+            | Message::Close(_)
+            | Message::CursorLeft(_)
+            | Message::ResetOptions(_)
+            | Message::InfoAcknowledgment(_)
+            | Message::KeepAlive(_)
+            | Message::QueryInfo(_)
+            | Message::ServerBusy(_)
+            | Message::UnknownClient(_)
+            | Message::ProtocolError(_) => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write_pretty(f)
+    }
+}
+
+/// Parse a message without the length prefix, see [`parse_message`]
+///
+/// # Example
+///
+/// ```
+/// use schengen::protocol::{Message, MessageMouseMove, ProtocolError};
+///
+/// # fn example() -> Result<(), ProtocolError> {
+/// let data = [b'D', b'M', b'M', b'V', 0x00, 0x0a, 0x00, 0x14];
+/// let msg = Message::try_from(&data[..])?;
+/// assert_eq!(msg, Message::MouseMove(MessageMouseMove { x: 10, y: 20 }));
+///
+/// // Or directly as the expected message
+/// let mouse_move = MessageMouseMove::try_from(&data[..])?;
+/// assert_eq!((mouse_move.x, mouse_move.y), (10, 20));
+/// # Ok(())
+/// # }
+/// # example().unwrap();
+/// ```
+impl TryFrom<&[u8]> for Message {
+    type Error = ProtocolError;
+
+    fn try_from(data: &[u8]) -> Result<Self> {
+        parse_message(data)
+    }
+}
+
+// Implements From<MessageFoo> for Message and TryFrom<&[u8]> for MessageFoo for each
+// (variant, struct) pair
+macro_rules! impl_from_message {
+    ($($variant:ident => $ty:ty),+ $(,)?) => {
+        $(
+            impl From<$ty> for Message {
+                fn from(msg: $ty) -> Self {
+                    Message::$variant(msg)
+                }
+            }
+
+            impl TryFrom<&[u8]> for $ty {
+                type Error = ProtocolError;
+
+                fn try_from(data: &[u8]) -> Result<Self> {
+                    <$ty as ProtocolMessage>::from_bytes(data)
+                }
+            }
+        )+
+    };
+}
+
+impl_from_message! {
+    HelloBarrier => MessageHelloBarrier,
+    HelloSynergy => MessageHelloSynergy,
+    NoOp => MessageNoOp,
+    Close => MessageClose,
+    CursorEntered => MessageCursorEntered,
+    CursorLeft => MessageCursorLeft,
+    ClientClipboard => MessageClientClipboard,
+    ScreenSaverChange => MessageScreenSaverChange,
+    ResetOptions => MessageResetOptions,
+    InfoAcknowledgment => MessageInfoAcknowledgment,
+    KeepAlive => MessageKeepAlive,
+    KeyDownWithLanguage => MessageKeyDownWithLanguage,
+    KeyDown => MessageKeyDown,
+    KeyRepeat => MessageKeyRepeat,
+    KeyUp => MessageKeyUp,
+    MouseButtonDown => MessageMouseButtonDown,
+    MouseButtonUp => MessageMouseButtonUp,
+    MouseMove => MessageMouseMove,
+    MouseRelativeMove => MessageMouseRelativeMove,
+    MouseWheel => MessageMouseWheel,
+    ClipboardData => MessageClipboardData,
+    ClientInfo => MessageClientInfo,
+    SetOptions => MessageSetOptions,
+    FileTransfer => MessageFileTransfer,
+    DragInfo => MessageDragInfo,
+    SecureEncryption => MessageSecureEncryption,
+    LegacySynergy => MessageLegacySynergy,
+    QueryInfo => MessageQueryInfo,
+    IncompatibleVersion => MessageIncompatibleVersion,
+    ServerBusy => MessageServerBusy,
+    UnknownClient => MessageUnknownClient,
+    ProtocolError => MessageProtocolError,
+}
+
+// Every message code known to this crate
+const MESSAGE_CODES: [&str; 32] = [
+    MessageHelloBarrier::CODE,
+    MessageHelloSynergy::CODE,
+    MessageNoOp::CODE,
+    MessageClose::CODE,
+    MessageCursorEntered::CODE,
+    MessageCursorLeft::CODE,
+    MessageClientClipboard::CODE,
+    MessageScreenSaverChange::CODE,
+    MessageResetOptions::CODE,
+    MessageInfoAcknowledgment::CODE,
+    MessageKeepAlive::CODE,
+    MessageKeyDownWithLanguage::CODE,
+    MessageKeyDown::CODE,
+    MessageKeyRepeat::CODE,
+    MessageKeyUp::CODE,
+    MessageMouseButtonDown::CODE,
+    MessageMouseButtonUp::CODE,
+    MessageMouseMove::CODE,
+    MessageMouseRelativeMove::CODE,
+    MessageMouseWheel::CODE,
+    MessageClipboardData::CODE,
+    MessageClientInfo::CODE,
+    MessageSetOptions::CODE,
+    MessageFileTransfer::CODE,
+    MessageDragInfo::CODE,
+    MessageSecureEncryption::CODE,
+    MessageLegacySynergy::CODE,
+    MessageQueryInfo::CODE,
+    MessageIncompatibleVersion::CODE,
+    MessageServerBusy::CODE,
+    MessageUnknownClient::CODE,
+    MessageProtocolError::CODE,
+];
+
+/// Extract the message code from message data (without the length prefix)
+///
+/// This does not parse the message. The code is the 7-character `"Barrier"` or
+/// `"Synergy"` for the Hello messages and the first 4 bytes otherwise. Returns `None`
+/// if `data` is too short or the code is not valid UTF-8.
+///
+/// # Example
+///
+/// ```
+/// use schengen::protocol::{code_for, is_known_code};
+///
+/// assert_eq!(code_for(b"CALV"), Some("CALV"));
+/// assert_eq!(code_for(b"Barrier\x00\x01\x00\x08"), Some("Barrier"));
+/// assert!(!is_known_code(code_for(b"ZZZZ").unwrap()));
+/// ```
+pub fn code_for(data: &[u8]) -> Option<&str> {
+    if data.len() >= 7 && (&data[0..7] == b"Barrier" || &data[0..7] == b"Synergy") {
+        return std::str::from_utf8(&data[0..7]).ok();
+    }
+    std::str::from_utf8(data.get(0..4)?).ok()
+}
+
+/// Returns true if `code` is the code of a message known to this crate
+///
+/// Use this with [`code_for`] to decide whether a message can be parsed, e.g. to forward
+/// unknown messages verbatim.
+pub fn is_known_code(code: &str) -> bool {
+    MESSAGE_CODES.contains(&code)
+}
+
+/// The length of the payload after the code of a message with a fixed size
+///
+/// Returns `None` for messages that contain strings or lists and for unknown codes.
+/// Messages whose format changed between protocol versions have the size of the
+/// current version, see [`parse_message_versioned`]. Tools can use this to check
+/// captured frames before parsing them.
+///
+/// # Example
+///
+/// ```
+/// use schengen::protocol::min_payload_len;
+///
+/// assert_eq!(min_payload_len("CINN"), Some(10));
+/// assert_eq!(min_payload_len("CALV"), Some(0));
+/// assert_eq!(min_payload_len("DCLP"), None);
+/// ```
+pub fn min_payload_len(code: &str) -> Option<usize> {
+    let len = match code {
+        "CNOP" | "CBYE" | "COUT" | "CROP" | "CIAK" | "CALV" | "QINF" | "EBSY" | "EUNK" | "EBAD" => {
+            0
+        }
+        "CSEC" | "DMDN" | "DMUP" => 1,
+        "DMMV" | "DMRM" | "DMWM" | "EICV" => 4,
+        "CCLP" => 5,
+        "DKDN" | "DKUP" => 6,
+        "CINN" => 10,
+        "DINF" => 14,
+        _ => return None,
+    };
+    Some(len)
 }
 
 /// Parse a protocol message from bytes (without the length prefix)
@@ -1638,79 +3322,82 @@ impl Message {
 pub fn parse_message(data: &[u8]) -> Result<Message> {
     if data.len() < 4 {
         return Err(ProtocolError::InsufficientData {
+            offset: 0,
             expected: 4,
             actual: data.len(),
         });
     }
 
-    // Try to read the message code
-    let code = std::str::from_utf8(&data[0..4]).map_err(|_| ProtocolError::InvalidMessageCode)?;
+    // Dispatch on the raw code bytes, this avoids UTF-8 validation for the
+    // fixed 4-byte codes which make up all messages after the handshake
+    let code: [u8; 4] = [data[0], data[1], data[2], data[3]];
 
-    match code {
-        "CNOP" => Ok(Message::NoOp(MessageNoOp::from_bytes(data)?)),
-        "CBYE" => Ok(Message::Close(MessageClose::from_bytes(data)?)),
-        "CINN" => Ok(Message::CursorEntered(MessageCursorEntered::from_bytes(
+    match &code {
+        b"CNOP" => Ok(Message::NoOp(MessageNoOp::from_bytes(data)?)),
+        b"CBYE" => Ok(Message::Close(MessageClose::from_bytes(data)?)),
+        b"CINN" => Ok(Message::CursorEntered(MessageCursorEntered::from_bytes(
             data,
         )?)),
-        "COUT" => Ok(Message::CursorLeft(MessageCursorLeft::from_bytes(data)?)),
-        "CCLP" => Ok(Message::ClientClipboard(
+        b"COUT" => Ok(Message::CursorLeft(MessageCursorLeft::from_bytes(data)?)),
+        b"CCLP" => Ok(Message::ClientClipboard(
             MessageClientClipboard::from_bytes(data)?,
         )),
-        "CSEC" => Ok(Message::ScreenSaverChange(
+        b"CSEC" => Ok(Message::ScreenSaverChange(
             MessageScreenSaverChange::from_bytes(data)?,
         )),
-        "CROP" => Ok(Message::ResetOptions(MessageResetOptions::from_bytes(
+        b"CROP" => Ok(Message::ResetOptions(MessageResetOptions::from_bytes(
             data,
         )?)),
-        "CIAK" => Ok(Message::InfoAcknowledgment(
+        b"CIAK" => Ok(Message::InfoAcknowledgment(
             MessageInfoAcknowledgment::from_bytes(data)?,
         )),
-        "CALV" => Ok(Message::KeepAlive(MessageKeepAlive::from_bytes(data)?)),
-        "DKDL" => Ok(Message::KeyDownWithLanguage(
+        b"CALV" => Ok(Message::KeepAlive(MessageKeepAlive::from_bytes(data)?)),
+        b"DKDL" => Ok(Message::KeyDownWithLanguage(
             MessageKeyDownWithLanguage::from_bytes(data)?,
         )),
-        "DKDN" => Ok(Message::KeyDown(MessageKeyDown::from_bytes(data)?)),
-        "DKRP" => Ok(Message::KeyRepeat(MessageKeyRepeat::from_bytes(data)?)),
-        "DKUP" => Ok(Message::KeyUp(MessageKeyUp::from_bytes(data)?)),
-        "DMDN" => Ok(Message::MouseButtonDown(
+        b"DKDN" => Ok(Message::KeyDown(MessageKeyDown::from_bytes(data)?)),
+        b"DKRP" => Ok(Message::KeyRepeat(MessageKeyRepeat::from_bytes(data)?)),
+        b"DKUP" => Ok(Message::KeyUp(MessageKeyUp::from_bytes(data)?)),
+        b"DMDN" => Ok(Message::MouseButtonDown(
             MessageMouseButtonDown::from_bytes(data)?,
         )),
-        "DMUP" => Ok(Message::MouseButtonUp(MessageMouseButtonUp::from_bytes(
+        b"DMUP" => Ok(Message::MouseButtonUp(MessageMouseButtonUp::from_bytes(
             data,
         )?)),
-        "DMMV" => Ok(Message::MouseMove(MessageMouseMove::from_bytes(data)?)),
-        "DMRM" => Ok(Message::MouseRelativeMove(
+        b"DMMV" => Ok(Message::MouseMove(MessageMouseMove::from_bytes(data)?)),
+        b"DMRM" => Ok(Message::MouseRelativeMove(
             MessageMouseRelativeMove::from_bytes(data)?,
         )),
-        "DMWM" => Ok(Message::MouseWheel(MessageMouseWheel::from_bytes(data)?)),
-        "DCLP" => Ok(Message::ClipboardData(MessageClipboardData::from_bytes(
+        b"DMWM" => Ok(Message::MouseWheel(MessageMouseWheel::from_bytes(data)?)),
+        b"DCLP" => Ok(Message::ClipboardData(MessageClipboardData::from_bytes(
             data,
         )?)),
-        "DINF" => Ok(Message::ClientInfo(MessageClientInfo::from_bytes(data)?)),
-        "DSOP" => Ok(Message::SetOptions(MessageSetOptions::from_bytes(data)?)),
-        "DFTR" => Ok(Message::FileTransfer(MessageFileTransfer::from_bytes(
+        b"DINF" => Ok(Message::ClientInfo(MessageClientInfo::from_bytes(data)?)),
+        b"DSOP" => Ok(Message::SetOptions(MessageSetOptions::from_bytes(data)?)),
+        b"DFTR" => Ok(Message::FileTransfer(MessageFileTransfer::from_bytes(
             data,
         )?)),
-        "DDRG" => Ok(Message::DragInfo(MessageDragInfo::from_bytes(data)?)),
-        "SECN" => Ok(Message::SecureEncryption(
+        b"DDRG" => Ok(Message::DragInfo(MessageDragInfo::from_bytes(data)?)),
+        b"SECN" => Ok(Message::SecureEncryption(
             MessageSecureEncryption::from_bytes(data)?,
         )),
-        "LSYN" => Ok(Message::LegacySynergy(MessageLegacySynergy::from_bytes(
+        b"LSYN" => Ok(Message::LegacySynergy(MessageLegacySynergy::from_bytes(
             data,
         )?)),
-        "QINF" => Ok(Message::QueryInfo(MessageQueryInfo::from_bytes(data)?)),
-        "EICV" => Ok(Message::IncompatibleVersion(
+        b"QINF" => Ok(Message::QueryInfo(MessageQueryInfo::from_bytes(data)?)),
+        b"EICV" => Ok(Message::IncompatibleVersion(
             MessageIncompatibleVersion::from_bytes(data)?,
         )),
-        "EBSY" => Ok(Message::ServerBusy(MessageServerBusy::from_bytes(data)?)),
-        "EUNK" => Ok(Message::UnknownClient(MessageUnknownClient::from_bytes(
+        b"EBSY" => Ok(Message::ServerBusy(MessageServerBusy::from_bytes(data)?)),
+        b"EUNK" => Ok(Message::UnknownClient(MessageUnknownClient::from_bytes(
             data,
         )?)),
-        "EBAD" => Ok(Message::ProtocolError(MessageProtocolError::from_bytes(
+        b"EBAD" => Ok(Message::ProtocolError(MessageProtocolError::from_bytes(
             data,
         )?)),
         _ => {
             // Check for Hello messages which have longer codes
+            let code = std::str::from_utf8(&code).map_err(|_| ProtocolError::InvalidMessageCode)?;
             if data.len() >= 7 && &data[0..7] == b"Barrier" {
                 Ok(Message::HelloBarrier(MessageHelloBarrier::from_bytes(
                     data,
@@ -1726,6 +3413,85 @@ pub fn parse_message(data: &[u8]) -> Result<Message> {
     }
 }
 
+/// Parse a protocol message from bytes (without the length prefix), keeping messages
+/// with unknown codes
+///
+/// Behaves like [`parse_message`] but returns messages whose code this crate does not
+/// know as [`Message::Unknown`] instead of failing with
+/// [`ProtocolError::UnknownMessageCode`]. [`Message::to_bytes`] reproduces them byte
+/// for byte, so a proxy can forward message types added in later protocol versions.
+///
+/// # Example
+///
+/// ```
+/// use schengen::protocol::{parse_message_preserving_unknown, Message};
+///
+/// # fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let msg = parse_message_preserving_unknown(b"ZZZZ\x01\x02")?;
+/// assert_eq!(
+///     msg,
+///     Message::Unknown {
+///         code: *b"ZZZZ",
+///         payload: vec![1, 2],
+///     }
+/// );
+/// # Ok(())
+/// # }
+/// # example().unwrap();
+/// ```
+pub fn parse_message_preserving_unknown(data: &[u8]) -> Result<Message> {
+    match parse_message(data) {
+        Err(ProtocolError::UnknownMessageCode(_)) | Err(ProtocolError::InvalidMessageCode) => {
+            Ok(Message::Unknown {
+                code: [data[0], data[1], data[2], data[3]],
+                payload: data[4..].to_vec(),
+            })
+        }
+        result => result,
+    }
+}
+
+/// Parse a protocol message from bytes (without the length prefix) sent by a peer
+/// that negotiated `version`
+///
+/// Behaves like [`parse_message`] but decodes the older form of messages whose
+/// format changed between protocol versions:
+/// - Before 1.1, `DKDN`, `DKUP` and `DKRP` have no `button` field, which is set to 0
+/// - Before 1.3, `DMWM` only has the vertical delta, `xdelta` is set to 0
+///
+/// # Example
+///
+/// ```
+/// use schengen::protocol::{parse_message_versioned, Message, ProtocolVersion};
+///
+/// # fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let data = [b'D', b'K', b'D', b'N', 0x00, 0x61, 0x00, 0x00];
+/// match parse_message_versioned(&data, ProtocolVersion::V1_0)? {
+///     Message::KeyDown(msg) => assert_eq!((msg.keyid, msg.button), (0x61, 0)),
+///     _ => unreachable!(),
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub fn parse_message_versioned(data: &[u8], version: ProtocolVersion) -> Result<Message> {
+    if version < ProtocolVersion::V1_1 {
+        match data.get(0..4) {
+            Some(b"DKDN") => return Ok(Message::KeyDown(MessageKeyDown::from_bytes_v1_0(data)?)),
+            Some(b"DKUP") => return Ok(Message::KeyUp(MessageKeyUp::from_bytes_v1_0(data)?)),
+            Some(b"DKRP") => {
+                return Ok(Message::KeyRepeat(MessageKeyRepeat::from_bytes_v1_0(data)?));
+            }
+            _ => {}
+        }
+    }
+    if version < ProtocolVersion::V1_3 && data.get(0..4) == Some(b"DMWM") {
+        return Ok(Message::MouseWheel(MessageMouseWheel::from_bytes_v1_0(
+            data,
+        )?));
+    }
+    parse_message(data)
+}
+
 /// Parse a complete message including the 4-byte length prefix
 ///
 /// # Arguments
@@ -1735,7 +3501,9 @@ pub fn parse_message(data: &[u8]) -> Result<Message> {
 /// # Returns
 ///
 /// Returns a tuple of (parsed message, bytes consumed) or an error if parsing fails
-/// or if there is insufficient data
+/// or if there is insufficient data. Messages longer than [`MAX_MESSAGE_LENGTH`] are
+/// rejected with [`ProtocolError::FrameTooLarge`], use [`parse_message_with_limit`]
+/// for a different limit.
 ///
 /// # Example
 ///
@@ -1755,53 +3523,348 @@ pub fn parse_message(data: &[u8]) -> Result<Message> {
 /// # }
 /// ```
 pub fn parse_message_with_length(data: &[u8]) -> Result<(Message, usize)> {
+    parse_message_with_limit(data, MAX_MESSAGE_LENGTH)
+}
+
+/// Parse a complete message including the 4-byte length prefix, with a custom size limit
+///
+/// Behaves like [`parse_message_with_length`] but rejects any message whose length
+/// prefix exceeds `max_frame_len` with [`ProtocolError::FrameTooLarge`]. The check
+/// happens as soon as the length prefix is available, before waiting for the rest
+/// of the message, so a peer cannot make the caller buffer an arbitrary amount of data.
+///
+/// # Example
+///
+/// ```
+/// use schengen::protocol::{parse_message_with_limit, ProtocolError};
+///
+/// let data = [0xff, 0xff, 0xff, 0xff];
+/// assert!(matches!(
+///     parse_message_with_limit(&data, 1024),
+///     Err(ProtocolError::FrameTooLarge { max: 1024, .. })
+/// ));
+/// ```
+pub fn parse_message_with_limit(data: &[u8], max_frame_len: usize) -> Result<(Message, usize)> {
+    parse_frame(data, max_frame_len, ProtocolVersion::CURRENT)
+}
+
+/// Parse a complete message including the 4-byte length prefix sent by a peer that
+/// negotiated `version`
+///
+/// Behaves like [`parse_message_with_length`] but decodes the message body with
+/// [`parse_message_versioned`].
+pub fn parse_message_with_length_versioned(
+    data: &[u8],
+    version: ProtocolVersion,
+) -> Result<(Message, usize)> {
+    parse_frame(data, MAX_MESSAGE_LENGTH, version)
+}
+
+/// Parse a complete message including the 4-byte length prefix, reporting the length
+/// of malformed messages
+///
+/// Behaves like [`parse_message_with_length`], but if the length prefix is valid and
+/// the whole message is available, the number of bytes it takes up is returned even
+/// if the message itself fails to parse. Tools like proxies and loggers can skip the
+/// bad message and continue with the next one. If the length prefix is incomplete or
+/// too large, the consumed length is 0.
+///
+/// # Example
+///
+/// ```
+/// use schengen::protocol::{parse_message_with_length_lenient, Message};
+///
+/// // A mouse move that is missing its y coordinate, then a keepalive
+/// let data = [0, 0, 0, 6, b'D', b'M', b'M', b'V', 0, 1, 0, 0, 0, 4, b'C', b'A', b'L', b'V'];
+/// let (result, consumed) = parse_message_with_length_lenient(&data);
+/// assert!(result.is_err());
+/// assert_eq!(consumed, 10);
+///
+/// let (result, _) = parse_message_with_length_lenient(&data[consumed..]);
+/// assert!(matches!(result, Ok(Message::KeepAlive(_))));
+/// ```
+pub fn parse_message_with_length_lenient(data: &[u8]) -> (Result<Message>, usize) {
+    match frame_size(data, MAX_MESSAGE_LENGTH) {
+        Ok(total_size) => (parse_message(&data[4..total_size]), total_size),
+        Err(e) => (Err(e), 0),
+    }
+}
+
+/// Split off the length prefix and parse the message body
+fn parse_frame(
+    data: &[u8],
+    max_frame_len: usize,
+    version: ProtocolVersion,
+) -> Result<(Message, usize)> {
+    let total_size = frame_size(data, max_frame_len)?;
+    let msg = parse_message_versioned(&data[4..total_size], version)?;
+    Ok((msg, total_size))
+}
+
+/// The size of the complete message at the start of `data` including the length
+/// prefix
+fn frame_size(data: &[u8], max_frame_len: usize) -> Result<usize> {
     if data.len() < 4 {
         return Err(ProtocolError::InsufficientData {
+            offset: 0,
             expected: 4,
             actual: data.len(),
         });
     }
 
     let length = read_u32(data, 0)? as usize;
+    if length > max_frame_len {
+        return Err(ProtocolError::FrameTooLarge {
+            len: length,
+            max: max_frame_len,
+        });
+    }
     let total_size = 4 + length;
 
     if data.len() < total_size {
         return Err(ProtocolError::InsufficientData {
+            offset: 4,
             expected: total_size,
             actual: data.len(),
         });
     }
 
-    let msg = parse_message(&data[4..total_size])?;
-    Ok((msg, total_size))
+    Ok(total_size)
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn test_hello_barrier() {
-        // Test server hello (no client name)
-        let msg_server = MessageHelloBarrier {
-            major: 1,
-            minor: 8,
-            client_name: None,
+/// Parse all complete length-prefixed messages in `data`
+///
+/// Returns the parsed messages and the number of bytes they used. A partial message at
+/// the end of `data` is left unparsed and not included in the consumed count, the caller
+/// keeps those bytes until the rest of the message arrives. See [`MessageReader`] for an
+/// incremental parser that keeps the buffer itself.
+///
+/// # Errors
+///
+/// Returns an error if any complete message fails to parse, or if a length prefix
+/// exceeds [`MAX_MESSAGE_LENGTH`].
+///
+/// # Example
+///
+/// ```
+/// use schengen::protocol::parse_all;
+///
+/// # fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// // Two keepalives and the first two bytes of a third message
+/// let data = [0, 0, 0, 4, b'C', b'A', b'L', b'V', 0, 0, 0, 4, b'C', b'A', b'L', b'V', 0, 0];
+/// let (messages, consumed) = parse_all(&data)?;
+/// assert_eq!(messages.len(), 2);
+/// assert_eq!(consumed, 16);
+/// # Ok(())
+/// # }
+/// ```
+pub fn parse_all(data: &[u8]) -> Result<(Vec<Message>, usize)> {
+    let mut messages = Vec::new();
+    let mut offset = 0;
+    while offset < data.len() {
+        // Only an incomplete frame ends the loop, a complete frame whose body is too
+        // short is an error like any other invalid message
+        let total_size = match frame_size(&data[offset..], MAX_MESSAGE_LENGTH) {
+            Ok(total_size) => total_size,
+            Err(ProtocolError::InsufficientData { .. }) => break,
+            Err(e) => return Err(e),
         };
-        let bytes = msg_server.to_bytes();
-        assert_eq!(&bytes[0..7], b"Barrier");
-        assert_eq!(&bytes[7..9], &1u16.to_be_bytes());
-        assert_eq!(&bytes[9..11], &8u16.to_be_bytes());
-        assert_eq!(bytes.len(), 11);
-
-        let parsed = MessageHelloBarrier::from_bytes(&bytes).unwrap();
-        assert_eq!(parsed, msg_server);
+        messages.push(parse_message(&data[offset + 4..offset + total_size])?);
+        offset += total_size;
+    }
+    Ok((messages, offset))
+}
 
-        // Test client hello (with client name)
-        let msg_client = MessageHelloBarrier {
-            major: 1,
-            minor: 8,
-            client_name: Some("testclient".to_string()),
+/// Iterate over the length-prefixed messages in `data`
+///
+/// See [`MessageIter`].
+///
+/// # Example
+///
+/// ```
+/// use schengen::protocol::{parse_iter, Message};
+///
+/// // A keepalive and the first two bytes of a second message
+/// let data = [0, 0, 0, 4, b'C', b'A', b'L', b'V', 0, 0];
+/// let mut iter = parse_iter(&data);
+/// assert!(matches!(iter.next(), Some(Ok(Message::KeepAlive(_)))));
+/// assert!(iter.next().is_none());
+/// assert_eq!(iter.remaining(), &[0, 0]);
+/// ```
+pub fn parse_iter(data: &[u8]) -> MessageIter<'_> {
+    MessageIter {
+        data,
+        failed: false,
+    }
+}
+
+/// Iterator over the length-prefixed messages in a byte slice, see [`parse_iter`]
+///
+/// Yields one result per frame and stops at the first incomplete frame, which is left in
+/// [`remaining`](MessageIter::remaining). A frame that fails to parse yields an error and
+/// iteration continues with the next frame. A length prefix exceeding
+/// [`MAX_MESSAGE_LENGTH`] yields an error and ends the iteration since the following
+/// frames cannot be located.
+#[derive(Debug, Clone)]
+pub struct MessageIter<'a> {
+    data: &'a [u8],
+    failed: bool,
+}
+
+impl<'a> MessageIter<'a> {
+    /// The bytes that have not been parsed yet
+    pub fn remaining(&self) -> &'a [u8] {
+        self.data
+    }
+}
+
+impl Iterator for MessageIter<'_> {
+    type Item = Result<Message>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed || self.data.len() < 4 {
+            return None;
+        }
+
+        let length = read_u32(self.data, 0).ok()? as usize;
+        if length > MAX_MESSAGE_LENGTH {
+            self.failed = true;
+            return Some(Err(ProtocolError::FrameTooLarge {
+                len: length,
+                max: MAX_MESSAGE_LENGTH,
+            }));
+        }
+        if self.data.len() - 4 < length {
+            return None;
+        }
+
+        let (frame, rest) = self.data.split_at(4 + length);
+        self.data = rest;
+        Some(parse_message(&frame[4..]))
+    }
+}
+
+/// Incremental message parser for a stream of length-prefixed messages
+///
+/// Feed incoming bytes with [`MessageReader::push`] in whatever chunks they arrive,
+/// then call [`MessageReader::next`] until it returns `Ok(None)` to drain all complete
+/// messages. Partial messages stay buffered until the rest of the data is pushed.
+///
+/// # Example
+///
+/// ```
+/// use schengen::protocol::{Message, MessageReader};
+///
+/// # fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let mut reader = MessageReader::new();
+///
+/// // A keepalive split across two reads
+/// reader.push(&[0, 0, 0, 4, b'C', b'A']);
+/// assert!(reader.next()?.is_none());
+///
+/// reader.push(b"LV");
+/// assert!(matches!(reader.next()?, Some(Message::KeepAlive(_))));
+/// assert!(reader.next()?.is_none());
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct MessageReader {
+    buffer: Vec<u8>,
+    /// The start of the unconsumed data in `buffer`, consumed messages are only
+    /// removed from the buffer when new data is pushed
+    start: usize,
+    max_frame_len: usize,
+}
+
+impl Default for MessageReader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MessageReader {
+    /// Create a new reader with an empty buffer
+    ///
+    /// Messages longer than [`MAX_MESSAGE_LENGTH`] are rejected.
+    pub fn new() -> Self {
+        Self::with_limit(MAX_MESSAGE_LENGTH)
+    }
+
+    /// Create a new reader that rejects messages longer than `max_frame_len`
+    pub fn with_limit(max_frame_len: usize) -> Self {
+        Self {
+            buffer: Vec::new(),
+            start: 0,
+            max_frame_len,
+        }
+    }
+
+    /// Append received data to the internal buffer
+    pub fn push(&mut self, data: &[u8]) {
+        // Moving the unconsumed data only once at least as much was consumed keeps the
+        // cost linear in the data pushed
+        if self.start > 0 && self.start >= self.buffer.len() - self.start {
+            self.buffer.drain(..self.start);
+            self.start = 0;
+        }
+        self.buffer.extend_from_slice(data);
+    }
+
+    /// Take the next complete message out of the buffer
+    ///
+    /// Returns `Ok(None)` if the buffer does not yet contain a complete message.
+    /// If a complete frame fails to parse, the frame is discarded and the error is
+    /// returned, so the caller may choose to continue with the next message.
+    ///
+    /// A length prefix above the limit returns [`ProtocolError::FrameTooLarge`]. The
+    /// stream cannot be resynchronized after this and the connection should be closed.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<Option<Message>> {
+        let data = &self.buffer[self.start..];
+        let total_size = match frame_size(data, self.max_frame_len) {
+            Ok(total_size) => total_size,
+            Err(ProtocolError::InsufficientData { .. }) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        let result = parse_message(&data[4..total_size]);
+        self.start += total_size;
+        result.map(Some)
+    }
+
+    /// The number of buffered bytes not yet consumed as a message
+    pub fn buffered_len(&self) -> usize {
+        self.buffer.len() - self.start
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hello_barrier() {
+        // Test server hello (no client name)
+        let msg_server = MessageHelloBarrier {
+            major: 1,
+            minor: 8,
+            client_name: None,
+        };
+        let bytes = msg_server.to_bytes();
+        assert_eq!(&bytes[0..7], b"Barrier");
+        assert_eq!(&bytes[7..9], &1u16.to_be_bytes());
+        assert_eq!(&bytes[9..11], &8u16.to_be_bytes());
+        assert_eq!(bytes.len(), 11);
+
+        let parsed = MessageHelloBarrier::from_bytes(&bytes).unwrap();
+        assert_eq!(parsed, msg_server);
+
+        // Test client hello (with client name)
+        let msg_client = MessageHelloBarrier {
+            major: 1,
+            minor: 8,
+            client_name: Some("testclient".to_string()),
         };
         let bytes = msg_client.to_bytes();
         assert_eq!(&bytes[0..7], b"Barrier");
@@ -2353,6 +4416,16 @@ mod tests {
         let parsed = MessageFileTransfer::from_bytes(&bytes).unwrap();
         assert_eq!(parsed, msg_chunk);
 
+        // Binary content is not valid UTF-8
+        let msg_binary = MessageFileTransfer {
+            mark: 2,
+            data: vec![0x89, b'P', b'N', b'G', 0xff, 0x00].into(),
+        };
+        let bytes = msg_binary.to_bytes();
+        let parsed = MessageFileTransfer::from_bytes(&bytes).unwrap();
+        assert_eq!(parsed, msg_binary);
+        assert_eq!(parsed.file_size(), None);
+
         // Data end
         let msg_end = MessageFileTransfer {
             mark: 3,
@@ -2429,6 +4502,27 @@ mod tests {
         assert_eq!(parsed, msg_single);
     }
 
+    #[test]
+    fn test_lsyn_languages() {
+        let empty = MessageLegacySynergy::from_languages(&[]);
+        assert_eq!(empty.data.0, "");
+        assert!(empty.languages().is_empty());
+
+        let single = MessageLegacySynergy::from_languages(&["en"]);
+        assert_eq!(single.data.0, "en");
+        assert_eq!(single.languages(), ["en"]);
+
+        let multi = MessageLegacySynergy::from_languages(&["en", "de", "fr"]);
+        assert_eq!(multi.data.0, "en,de,fr");
+        assert_eq!(multi.languages(), ["en", "de", "fr"]);
+
+        // Whitespace and empty entries are dropped
+        let spaced = MessageLegacySynergy {
+            data: " en , de,,fr ,".into(),
+        };
+        assert_eq!(spaced.languages(), ["en", "de", "fr"]);
+    }
+
     #[test]
     fn test_qinf() {
         let msg = MessageQueryInfo;
@@ -2505,10 +4599,1189 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn test_hash_mouse_moves() {
+        use std::collections::HashSet;
+
+        let moves = [(10, 20), (10, 20), (11, 20), (10, 20), (10, 21)];
+        let unique: HashSet<MessageMouseMove> = moves
+            .iter()
+            .map(|&(x, y)| MessageMouseMove { x, y })
+            .collect();
+        assert_eq!(unique.len(), 3);
+        assert!(unique.contains(&MessageMouseMove { x: 11, y: 20 }));
+
+        // Whole messages hash too, including those with strings
+        let messages: HashSet<Message> = sample_messages().into_iter().collect();
+        assert_eq!(messages.len(), sample_messages().len());
+    }
+
+    #[test]
+    fn test_cursor_entered_order() {
+        let entered = |x, sequence| MessageCursorEntered {
+            x,
+            y: 0,
+            sequence,
+            mask: 0,
+        };
+        let mut messages = vec![entered(0, 3), entered(5, 1), entered(1, 2), entered(0, 1)];
+        messages.sort();
+        assert_eq!(
+            messages,
+            [entered(0, 1), entered(5, 1), entered(1, 2), entered(0, 3)]
+        );
+    }
+
+    #[test]
+    fn test_relay_bytes() {
+        let mut messages = sample_messages();
+        messages.push(Message::Unknown {
+            code: *b"ZZZZ",
+            payload: vec![1, 2, 3],
+        });
+        for msg in messages {
+            let frame = msg.to_bytes();
+            let parsed = parse_message_preserving_unknown(&frame[4..]).unwrap();
+            assert_eq!(parsed.relay_bytes(), frame, "{:?}", msg);
+        }
+
+        // A trailing byte is kept for unknown messages but not after known fields
+        let unknown = [0, 0, 0, 6, b'Z', b'Z', b'Z', b'Z', 1, 0xff];
+        let parsed = parse_message_preserving_unknown(&unknown[4..]).unwrap();
+        assert_eq!(parsed.relay_bytes(), unknown);
+        let mouse_move = [0, 0, 0, 9, b'D', b'M', b'M', b'V', 0, 1, 0, 2, 0xff];
+        let parsed = parse_message_preserving_unknown(&mouse_move[4..]).unwrap();
+        assert_eq!(parsed, Message::MouseMove(MessageMouseMove { x: 1, y: 2 }));
+        assert_eq!(
+            parsed.relay_bytes(),
+            [0, 0, 0, 8, b'D', b'M', b'M', b'V', 0, 1, 0, 2]
+        );
+    }
+
     #[test]
     fn test_unknown_message() {
         let data = b"XXXX";
         let result = parse_message(data);
         assert!(matches!(result, Err(ProtocolError::UnknownMessageCode(_))));
     }
+
+    #[test]
+    fn test_parse_message_preserving_unknown() {
+        let frame = [0, 0, 0, 7, b'Z', b'Z', b'Z', b'Z', 0xde, 0xad, 0xbe];
+        let msg = parse_message_preserving_unknown(&frame[4..]).unwrap();
+        assert_eq!(
+            msg,
+            Message::Unknown {
+                code: *b"ZZZZ",
+                payload: vec![0xde, 0xad, 0xbe],
+            }
+        );
+        assert_eq!(msg.to_bytes(), frame);
+
+        // Known codes are parsed as usual, malformed ones still fail
+        assert_eq!(
+            parse_message_preserving_unknown(b"CALV"),
+            Ok(Message::KeepAlive(MessageKeepAlive))
+        );
+        assert!(parse_message_preserving_unknown(b"DMMV\x00").is_err());
+    }
+
+    #[test]
+    fn test_message_code() {
+        assert_eq!(Message::KeepAlive(MessageKeepAlive).code(), "CALV");
+        assert_eq!(
+            Message::HelloBarrier(MessageHelloBarrier {
+                major: 1,
+                minor: 8,
+                client_name: None,
+            })
+            .code(),
+            "Barrier"
+        );
+        assert_eq!(
+            Message::MouseMove(MessageMouseMove { x: 1, y: 2 }).code(),
+            "DMMV"
+        );
+
+        // The code must match the prefix written by to_bytes
+        let msg = Message::SetOptions(MessageSetOptions { options: vec![] });
+        assert_eq!(&msg.to_bytes()[4..8], msg.code().as_bytes());
+    }
+
+    #[test]
+    fn test_message_raw_code() {
+        assert_eq!(&Message::KeepAlive(MessageKeepAlive).raw_code(), b"CALV");
+        assert_eq!(
+            &Message::HelloSynergy(MessageHelloSynergy {
+                major: 1,
+                minor: 6,
+                client_name: None,
+            })
+            .raw_code(),
+            b"Syne"
+        );
+
+        // Unknown codes are only available raw, even if they are not valid UTF-8
+        let msg = Message::Unknown {
+            code: [0xff, b'Z', b'Z', b'Z'],
+            payload: vec![],
+        };
+        assert_eq!(msg.code(), "????");
+        assert_eq!(msg.raw_code(), [0xff, b'Z', b'Z', b'Z']);
+        assert_eq!(&msg.to_bytes()[4..8], &msg.raw_code());
+    }
+
+    /// One instance of every message type, built via `From<Message*> for Message`
+    fn sample_messages() -> Vec<Message> {
+        vec![
+            MessageHelloBarrier {
+                major: 1,
+                minor: 8,
+                client_name: Some("client".to_string()),
+            }
+            .into(),
+            MessageHelloSynergy {
+                major: 1,
+                minor: 6,
+                client_name: None,
+            }
+            .into(),
+            MessageNoOp.into(),
+            MessageClose.into(),
+            MessageCursorEntered {
+                x: 10,
+                y: 20,
+                sequence: 3,
+                mask: 0,
+            }
+            .into(),
+            MessageCursorLeft.into(),
+            MessageClientClipboard { id: 0, sequence: 1 }.into(),
+            MessageScreenSaverChange { state: 1 }.into(),
+            MessageResetOptions.into(),
+            MessageInfoAcknowledgment.into(),
+            MessageKeepAlive.into(),
+            MessageKeyDownWithLanguage {
+                keyid: 0x61,
+                mask: 0,
+                button: 0x26,
+                lang: "en".into(),
+            }
+            .into(),
+            MessageKeyDown {
+                keyid: 0x61,
+                mask: 0,
+                button: 0x26,
+            }
+            .into(),
+            MessageKeyRepeat {
+                keyid: 0x61,
+                mask: 0,
+                button: 0x26,
+                count: 2,
+                lang: "en".into(),
+            }
+            .into(),
+            MessageKeyUp {
+                keyid: 0x61,
+                mask: 0,
+                button: 0x26,
+            }
+            .into(),
+            MessageMouseButtonDown { button: 1 }.into(),
+            MessageMouseButtonUp { button: 1 }.into(),
+            MessageMouseMove { x: 100, y: 200 }.into(),
+            MessageMouseRelativeMove { x: -1, y: 1 }.into(),
+            MessageMouseWheel {
+                xdelta: 0,
+                ydelta: 120,
+            }
+            .into(),
+            MessageClipboardData {
+                id: 0,
+                sequence: 1,
+                mark: 0,
+                data: "text".into(),
+            }
+            .into(),
+            MessageClientInfo {
+                x: 0,
+                y: 0,
+                width: 1920,
+                height: 1080,
+                current_mouse_x: 960,
+                current_mouse_y: 540,
+                size: 0,
+            }
+            .into(),
+            MessageSetOptions {
+                options: vec![(DsopOption::Heartbeat as u32, 3000)],
+            }
+            .into(),
+            MessageFileTransfer {
+                mark: 1,
+                data: "42".into(),
+            }
+            .into(),
+            MessageDragInfo {
+                size: 1,
+                data: "/tmp/file".into(),
+            }
+            .into(),
+            MessageSecureEncryption {
+                data: "Terminal".into(),
+            }
+            .into(),
+            MessageLegacySynergy { data: "en".into() }.into(),
+            MessageQueryInfo.into(),
+            MessageIncompatibleVersion {
+                major_remote: 1,
+                minor_remote: 8,
+            }
+            .into(),
+            MessageServerBusy.into(),
+            MessageUnknownClient.into(),
+            MessageProtocolError.into(),
+        ]
+    }
+
+    #[test]
+    fn test_message_from_structs() {
+        for msg in sample_messages() {
+            let (parsed, consumed) = parse_message_with_length(&msg.to_bytes()).unwrap();
+            assert_eq!(consumed, msg.to_bytes().len());
+            assert_eq!(parsed, msg);
+        }
+    }
+
+    #[test]
+    fn test_size_hint() {
+        let mut messages = sample_messages();
+        messages.extend([
+            MessageHelloSynergy {
+                major: 1,
+                minor: 8,
+                client_name: None,
+            }
+            .into(),
+            MessageKeyDownWithLanguage {
+                keyid: 0xe9,
+                mask: 0,
+                button: 0x1a,
+                lang: "fr".into(),
+            }
+            .into(),
+            MessageSetOptions { options: vec![] }.into(),
+            MessageClipboardData {
+                id: 1,
+                sequence: 7,
+                mark: 2,
+                data: vec![0xff; 300].into(),
+            }
+            .into(),
+            MessageSecureEncryption {
+                data: "Schlüsselbund".into(),
+            }
+            .into(),
+            Message::Unknown {
+                code: *b"ZZZZ",
+                payload: vec![1, 2, 3],
+            },
+        ]);
+        for msg in messages {
+            assert_eq!(msg.size_hint(), msg.to_bytes().len(), "{:?}", msg);
+        }
+    }
+
+    #[test]
+    fn test_write_framed() {
+        // Messages written back to back into one buffer match their to_bytes
+        let messages = sample_messages();
+        let mut buffer = Vec::new();
+        for msg in &messages {
+            msg.write_framed(&mut buffer).unwrap();
+        }
+        let expected: Vec<u8> = messages.iter().flat_map(Message::to_bytes).collect();
+        assert_eq!(buffer, expected);
+
+        let msg = MessageClipboardData {
+            id: 0,
+            sequence: 1,
+            mark: 2,
+            data: "text".into(),
+        };
+        let mut buffer = Vec::new();
+        msg.write_to(&mut buffer).unwrap();
+        assert_eq!(buffer, msg.to_bytes());
+
+        // Errors of the writer are passed on
+        let mut short = [0u8; 8];
+        let result = Message::from(msg).write_framed(&mut &mut short[..]);
+        assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::WriteZero);
+    }
+
+    #[test]
+    fn test_parse_message_dispatch_every_code() {
+        let messages = sample_messages();
+        let mut codes: Vec<&str> = messages.iter().map(|m| m.code()).collect();
+        codes.sort();
+        codes.dedup();
+        assert_eq!(codes.len(), 32, "every message type must be covered");
+
+        for msg in messages {
+            let bytes = msg.to_bytes();
+            // Skip the length prefix, parse_message takes the code and payload only
+            let parsed = parse_message(&bytes[4..]).unwrap();
+            assert_eq!(parsed.code(), msg.code());
+            assert_eq!(parsed, msg);
+        }
+
+        // Unknown but valid codes and invalid UTF-8 keep their distinct errors
+        assert!(matches!(
+            parse_message(b"ZZZZ"),
+            Err(ProtocolError::UnknownMessageCode(code)) if code == "ZZZZ"
+        ));
+        assert!(matches!(
+            parse_message(&[0xff, 0xfe, 0xfd, 0xfc]),
+            Err(ProtocolError::InvalidMessageCode)
+        ));
+        assert!(matches!(
+            parse_message(b"Barr"),
+            Err(ProtocolError::UnknownMessageCode(_))
+        ));
+    }
+
+    #[test]
+    fn test_message_is_error() {
+        assert!(
+            Message::IncompatibleVersion(MessageIncompatibleVersion {
+                major_remote: 1,
+                minor_remote: 6,
+            })
+            .is_error()
+        );
+        assert!(Message::ServerBusy(MessageServerBusy).is_error());
+        assert!(Message::UnknownClient(MessageUnknownClient).is_error());
+        assert!(Message::ProtocolError(MessageProtocolError).is_error());
+
+        assert!(!Message::KeepAlive(MessageKeepAlive).is_error());
+        assert!(!Message::Close(MessageClose).is_error());
+    }
+
+    #[test]
+    fn test_message_is_input() {
+        let input: Vec<Message> = sample_messages()
+            .into_iter()
+            .filter(Message::is_input)
+            .collect();
+        // Every key and mouse message of the samples, nothing else
+        assert_eq!(input.len(), 9);
+        assert!(
+            input
+                .iter()
+                .all(|m| m.code().starts_with("DK") || m.code().starts_with("DM"))
+        );
+    }
+
+    #[test]
+    fn test_message_reader_partial_writes() {
+        let bytes = Message::MouseMove(MessageMouseMove { x: 100, y: 200 }).to_bytes();
+        let mut reader = MessageReader::new();
+
+        // Feed one byte at a time, only the last byte completes the message
+        for (i, byte) in bytes.iter().enumerate() {
+            assert!(reader.next().unwrap().is_none());
+            reader.push(&[*byte]);
+            if i < bytes.len() - 1 {
+                assert!(reader.next().unwrap().is_none());
+                assert_eq!(reader.buffered_len(), i + 1);
+            }
+        }
+        assert_eq!(
+            reader.next().unwrap(),
+            Some(Message::MouseMove(MessageMouseMove { x: 100, y: 200 }))
+        );
+        assert!(reader.next().unwrap().is_none());
+        assert_eq!(reader.buffered_len(), 0);
+    }
+
+    #[test]
+    fn test_message_reader_back_to_back() {
+        let messages = sample_messages();
+        let mut stream: Vec<u8> = messages.iter().flat_map(|m| m.to_bytes()).collect();
+        // Trailing partial message
+        let trailing = Message::KeepAlive(MessageKeepAlive).to_bytes();
+        stream.extend_from_slice(&trailing[..6]);
+
+        let mut reader = MessageReader::new();
+        let mut parsed = Vec::new();
+        // Interleave pushes of uneven chunk sizes with draining
+        for chunk in stream.chunks(7) {
+            reader.push(chunk);
+            while let Some(msg) = reader.next().unwrap() {
+                parsed.push(msg);
+            }
+        }
+        assert_eq!(parsed, messages);
+        assert_eq!(reader.buffered_len(), 6);
+
+        reader.push(&trailing[6..]);
+        assert_eq!(
+            reader.next().unwrap(),
+            Some(Message::KeepAlive(MessageKeepAlive))
+        );
+        assert!(reader.next().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_message_reader_compacts_consumed_data() {
+        let frame = Message::KeepAlive(MessageKeepAlive).to_bytes();
+        let mut reader = MessageReader::new();
+
+        // A burst of small frames in one push, consumed one at a time
+        let burst: Vec<u8> = frame
+            .iter()
+            .copied()
+            .cycle()
+            .take(frame.len() * 1000)
+            .collect();
+        reader.push(&burst);
+        for _ in 0..999 {
+            assert!(reader.next().unwrap().is_some());
+        }
+        assert_eq!(reader.buffered_len(), frame.len());
+
+        // The consumed frames are dropped on the next push
+        reader.push(&frame);
+        assert_eq!(reader.buffer.len(), 2 * frame.len());
+        assert!(reader.next().unwrap().is_some());
+        assert!(reader.next().unwrap().is_some());
+        assert!(reader.next().unwrap().is_none());
+        assert_eq!(reader.buffered_len(), 0);
+    }
+
+    #[test]
+    fn test_message_reader_parse_error() {
+        let mut reader = MessageReader::new();
+        // A complete frame with an unknown code, followed by a valid message
+        reader.push(&[0, 0, 0, 4, b'Z', b'Z', b'Z', b'Z']);
+        reader.push(&Message::KeepAlive(MessageKeepAlive).to_bytes());
+
+        assert!(matches!(
+            reader.next(),
+            Err(ProtocolError::UnknownMessageCode(_))
+        ));
+        assert_eq!(
+            reader.next().unwrap(),
+            Some(Message::KeepAlive(MessageKeepAlive))
+        );
+
+        // A complete frame that is too short for its message type is an error,
+        // not a request for more data
+        reader.push(&[0, 0, 0, 6, b'D', b'M', b'M', b'V', 0, 1]);
+        assert!(matches!(
+            reader.next(),
+            Err(ProtocolError::InsufficientData { .. })
+        ));
+        assert!(reader.next().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_frame_too_large() {
+        // Hostile length prefix with only a few bytes following it
+        let data = [0xff, 0xff, 0xff, 0xff, b'C', b'A', b'L', b'V'];
+        assert_eq!(
+            parse_message_with_length(&data),
+            Err(ProtocolError::FrameTooLarge {
+                len: 0xffff_ffff,
+                max: MAX_MESSAGE_LENGTH,
+            })
+        );
+        assert_eq!(
+            parse_message_with_limit(&data, 16),
+            Err(ProtocolError::FrameTooLarge {
+                len: 0xffff_ffff,
+                max: 16,
+            })
+        );
+
+        // A message at the limit still parses
+        let keepalive = Message::KeepAlive(MessageKeepAlive).to_bytes();
+        assert!(parse_message_with_limit(&keepalive, 4).is_ok());
+        assert!(matches!(
+            parse_message_with_limit(&keepalive, 3),
+            Err(ProtocolError::FrameTooLarge { len: 4, max: 3 })
+        ));
+
+        let mut reader = MessageReader::new();
+        reader.push(&data);
+        assert!(matches!(
+            reader.next(),
+            Err(ProtocolError::FrameTooLarge { .. })
+        ));
+    }
+
+    #[test]
+    fn test_length_prefixed_string_hostile_length() {
+        // DSOP-style string with a length prefix far beyond the available data
+        let data = [b'L', b'S', b'Y', b'N', 0xff, 0xff, 0xff, 0xff, b'e', b'n'];
+        assert!(matches!(
+            parse_message(&data),
+            Err(ProtocolError::InsufficientData { .. })
+        ));
+    }
+
+    #[test]
+    fn test_set_options_typed() {
+        let msg = MessageSetOptions::from_typed(&[
+            (DsopOption::Heartbeat, 3000),
+            (DsopOption::ScreenPreserveFocus, 1),
+        ]);
+        assert_eq!(msg.options, vec![(0x48415254, 3000), (0x53464F43, 1)]);
+        assert_eq!(msg.get(DsopOption::Heartbeat), Some(3000));
+        assert_eq!(msg.get(DsopOption::ScreenPreserveFocus), Some(1));
+        assert_eq!(msg.get(DsopOption::ClipboardSharing), None);
+        assert_eq!(
+            msg.typed(),
+            vec![
+                (Some(DsopOption::Heartbeat), 3000),
+                (Some(DsopOption::ScreenPreserveFocus), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_key_messages_v1_0() {
+        // 1.1+ form with the 6-byte payload including the button
+        let data = [b'D', b'K', b'D', b'N', 0x00, 0x61, 0x00, 0x01, 0x00, 0x26];
+        let expected = Message::KeyDown(MessageKeyDown {
+            keyid: 0x61,
+            mask: 0x01,
+            button: 0x26,
+        });
+        assert_eq!(parse_message(&data).unwrap(), expected);
+        assert_eq!(
+            parse_message_versioned(&data, ProtocolVersion::CURRENT).unwrap(),
+            expected
+        );
+
+        // 1.0 form with the 4-byte payload, rejected by the current parser
+        let data = [b'D', b'K', b'D', b'N', 0x00, 0x61, 0x00, 0x01];
+        assert!(matches!(
+            parse_message(&data),
+            Err(ProtocolError::InsufficientData { .. })
+        ));
+        assert_eq!(
+            parse_message_versioned(&data, ProtocolVersion::V1_0).unwrap(),
+            Message::KeyDown(MessageKeyDown {
+                keyid: 0x61,
+                mask: 0x01,
+                button: 0,
+            })
+        );
+
+        let data = [b'D', b'K', b'U', b'P', 0x00, 0x61, 0x00, 0x01];
+        assert_eq!(
+            parse_message_versioned(&data, ProtocolVersion::V1_0).unwrap(),
+            Message::KeyUp(MessageKeyUp {
+                keyid: 0x61,
+                mask: 0x01,
+                button: 0,
+            })
+        );
+
+        let data = [b'D', b'K', b'R', b'P', 0x00, 0x61, 0x00, 0x01, 0x00, 0x03];
+        assert_eq!(
+            parse_message_versioned(&data, ProtocolVersion::V1_0).unwrap(),
+            Message::KeyRepeat(MessageKeyRepeat {
+                keyid: 0x61,
+                mask: 0x01,
+                button: 0,
+                count: 3,
+                lang: "".into(),
+            })
+        );
+
+        // Other messages are unaffected by the version
+        let mut framed = vec![0, 0, 0, 4];
+        framed.extend_from_slice(b"CALV");
+        assert_eq!(
+            parse_message_with_length_versioned(&framed, ProtocolVersion::V1_0).unwrap(),
+            (Message::KeepAlive(MessageKeepAlive), 8)
+        );
+    }
+
+    #[test]
+    fn test_mouse_wheel_legacy() {
+        let msg = Message::MouseWheel(MessageMouseWheel {
+            xdelta: 0,
+            ydelta: -120,
+        });
+
+        // 1.3+ form with both deltas
+        let bytes = msg.to_bytes_versioned(ProtocolVersion::V1_3);
+        assert_eq!(bytes, msg.to_bytes());
+        assert_eq!(&bytes[4..], b"DMWM\x00\x00\xff\x88");
+        assert_eq!(
+            parse_message_with_length_versioned(&bytes, ProtocolVersion::V1_3).unwrap(),
+            (msg.clone(), 12)
+        );
+
+        // Legacy form with the single vertical delta
+        let bytes = msg.to_bytes_versioned(ProtocolVersion::new(1, 2));
+        assert_eq!(&bytes[4..], b"DMWM\xff\x88");
+        assert!(parse_message(&bytes[4..]).is_err());
+        assert_eq!(
+            parse_message_with_length_versioned(&bytes, ProtocolVersion::new(1, 2)).unwrap(),
+            (msg, 10)
+        );
+
+        // The horizontal delta can't be represented in the legacy form
+        let msg = MessageMouseWheel {
+            xdelta: 120,
+            ydelta: 0,
+        };
+        assert_eq!(
+            MessageMouseWheel::from_bytes_v1_0(&msg.to_bytes_v1_0()).unwrap(),
+            MessageMouseWheel {
+                xdelta: 0,
+                ydelta: 0
+            }
+        );
+    }
+
+    #[test]
+    fn test_negotiate_version() {
+        let server = ProtocolVersion::new(1, 6);
+
+        // Equal versions
+        assert_eq!(negotiate(server, server), Ok(server));
+
+        // Client older than the server
+        assert_eq!(
+            negotiate(server, ProtocolVersion::new(1, 3)),
+            Ok(ProtocolVersion::new(1, 3))
+        );
+
+        // Client newer than the server
+        assert_eq!(negotiate(server, ProtocolVersion::new(1, 8)), Ok(server));
+
+        // Different major versions are incompatible
+        let expected = MessageIncompatibleVersion {
+            major_remote: 1,
+            minor_remote: 6,
+        };
+        assert_eq!(
+            negotiate(server, ProtocolVersion::new(2, 0)),
+            Err(expected.clone())
+        );
+        assert_eq!(negotiate(server, ProtocolVersion::new(0, 9)), Err(expected));
+    }
+
+    #[test]
+    fn test_key_down_with_language_downgrade() {
+        let msg = Message::KeyDownWithLanguage(MessageKeyDownWithLanguage {
+            keyid: 0x61,
+            mask: 0,
+            button: 0x26,
+            lang: "de".into(),
+        });
+        assert_eq!(
+            msg.to_bytes_versioned(ProtocolVersion::V1_8),
+            msg.to_bytes()
+        );
+
+        let key_down = Message::KeyDown(MessageKeyDown {
+            keyid: 0x61,
+            mask: 0,
+            button: 0x26,
+        });
+        assert_eq!(
+            msg.to_bytes_versioned(ProtocolVersion::new(1, 6)),
+            key_down.to_bytes()
+        );
+        assert_eq!(
+            msg.to_bytes_versioned(ProtocolVersion::V1_0),
+            key_down.to_bytes_versioned(ProtocolVersion::V1_0)
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_json_roundtrip() {
+        let msg = Message::ClientInfo(MessageClientInfo {
+            x: 0,
+            y: 0,
+            width: 1920,
+            height: 1080,
+            current_mouse_x: 960,
+            current_mouse_y: 540,
+            size: 0,
+        });
+        let json = serde_json::to_string(&msg).unwrap();
+        assert_eq!(
+            json,
+            r#"{"ClientInfo":{"x":0,"y":0,"width":1920,"height":1080,"current_mouse_x":960,"current_mouse_y":540,"size":0}}"#
+        );
+        assert_eq!(serde_json::from_str::<Message>(&json).unwrap(), msg);
+
+        // Length-prefixed strings serialize as plain strings
+        let msg = Message::LegacySynergy(MessageLegacySynergy { data: "en".into() });
+        let json = serde_json::to_string(&msg).unwrap();
+        assert_eq!(json, r#"{"LegacySynergy":{"data":"en"}}"#);
+        assert_eq!(serde_json::from_str::<Message>(&json).unwrap(), msg);
+    }
+
+    #[cfg(feature = "arbitrary")]
+    proptest::proptest! {
+        #[test]
+        fn test_arbitrary_roundtrip(seed in proptest::collection::vec(proptest::prelude::any::<u8>(), 0..256)) {
+            use arbitrary::{Arbitrary, Unstructured};
+
+            let msg = Message::arbitrary(&mut Unstructured::new(&seed)).unwrap();
+            let bytes = msg.to_bytes();
+            let parsed = parse_message_with_registry(&bytes[4..], &MessageRegistry::new());
+            if let Message::Unknown { .. } = msg {
+                // An arbitrary code may collide with a built-in one
+                proptest::prop_assume!(matches!(parsed, Ok(Message::Unknown { .. })));
+            }
+            proptest::prop_assert_eq!(parsed.unwrap(), msg);
+        }
+    }
+
+    #[test]
+    fn test_parse_all() {
+        let messages = vec![
+            Message::MouseMove(MessageMouseMove { x: 1, y: 2 }),
+            Message::KeepAlive(MessageKeepAlive),
+            Message::LegacySynergy(MessageLegacySynergy { data: "en".into() }),
+        ];
+        let mut data: Vec<u8> = messages.iter().flat_map(|m| m.to_bytes()).collect();
+        let complete_len = data.len();
+        data.extend_from_slice(&[0, 0]);
+
+        let (parsed, consumed) = parse_all(&data).unwrap();
+        assert_eq!(parsed, messages);
+        assert_eq!(consumed, complete_len);
+
+        assert_eq!(parse_all(&[]).unwrap(), (vec![], 0));
+
+        // A complete but invalid message is an error
+        let data = [0, 0, 0, 4, b'X', b'X', b'X', b'X'];
+        assert!(parse_all(&data).is_err());
+
+        // So is a complete frame whose body is too short, even if more data follows
+        let mut data = vec![0, 0, 0, 6, b'D', b'M', b'M', b'V', 0, 1];
+        data.extend_from_slice(&Message::KeepAlive(MessageKeepAlive).to_bytes());
+        assert!(matches!(
+            parse_all(&data),
+            Err(ProtocolError::InsufficientData { .. })
+        ));
+    }
+
+    #[test]
+    fn test_parse_message_with_length_lenient() {
+        let first = Message::MouseMove(MessageMouseMove { x: 1, y: 2 });
+        let last = Message::KeepAlive(MessageKeepAlive);
+        let mut data = first.to_bytes();
+        // A DMMV whose body is two bytes short
+        data.extend_from_slice(&[0, 0, 0, 6, b'D', b'M', b'M', b'V', 0, 1]);
+        data.extend_from_slice(&last.to_bytes());
+
+        let mut offset = 0;
+        let mut results = Vec::new();
+        while offset < data.len() {
+            let (result, consumed) = parse_message_with_length_lenient(&data[offset..]);
+            assert!(consumed > 0);
+            results.push(result);
+            offset += consumed;
+        }
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0], Ok(first));
+        assert!(results[1].is_err());
+        assert_eq!(results[2], Ok(last));
+
+        // Nothing is consumed without a complete frame
+        let (result, consumed) = parse_message_with_length_lenient(&[0, 0, 0, 4, b'C']);
+        assert!(matches!(
+            result,
+            Err(ProtocolError::InsufficientData { .. })
+        ));
+        assert_eq!(consumed, 0);
+    }
+
+    #[test]
+    fn test_client_info_for_screen() {
+        assert_eq!(
+            MessageClientInfo::for_screen(1920, 1080),
+            MessageClientInfo {
+                x: 0,
+                y: 0,
+                width: 1920,
+                height: 1080,
+                current_mouse_x: 960,
+                current_mouse_y: 540,
+                size: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_min_payload_len() {
+        let fixed = Message::CursorEntered(MessageCursorEntered {
+            x: 1,
+            y: 2,
+            sequence: 3,
+            mask: 4,
+        });
+        assert_eq!(
+            min_payload_len(fixed.code()),
+            Some(fixed.to_bytes().len() - 4 - fixed.code().len())
+        );
+        assert_eq!(min_payload_len("DMMV"), Some(4));
+        assert_eq!(min_payload_len("CALV"), Some(0));
+
+        assert_eq!(min_payload_len("DSOP"), None);
+        assert_eq!(min_payload_len("Barrier"), None);
+        assert_eq!(min_payload_len("ZZZZ"), None);
+    }
+
+    #[test]
+    fn test_parse_iter() {
+        let messages = vec![
+            Message::MouseMove(MessageMouseMove { x: 1, y: 2 }),
+            Message::KeepAlive(MessageKeepAlive),
+            Message::LegacySynergy(MessageLegacySynergy { data: "en".into() }),
+        ];
+        let data: Vec<u8> = messages.iter().flat_map(|m| m.to_bytes()).collect();
+
+        let mut iter = parse_iter(&data);
+        let parsed: Vec<Message> = iter.by_ref().map(|m| m.unwrap()).collect();
+        assert_eq!(parsed, messages);
+        assert!(iter.remaining().is_empty());
+
+        assert_eq!(parse_iter(&[]).count(), 0);
+    }
+
+    #[test]
+    fn test_parse_iter_incomplete_tail() {
+        let mut data = Message::KeepAlive(MessageKeepAlive).to_bytes();
+        // An invalid but complete frame is skipped
+        data.extend_from_slice(&[0, 0, 0, 4, b'X', b'X', b'X', b'X']);
+        let tail = Message::MouseMove(MessageMouseMove { x: 1, y: 2 }).to_bytes();
+        data.extend_from_slice(&tail[..6]);
+
+        let mut iter = parse_iter(&data);
+        assert_eq!(
+            iter.next().unwrap().unwrap(),
+            Message::KeepAlive(MessageKeepAlive)
+        );
+        assert!(matches!(
+            iter.next(),
+            Some(Err(ProtocolError::UnknownMessageCode(_)))
+        ));
+        assert!(iter.next().is_none());
+        assert_eq!(iter.remaining(), &tail[..6]);
+
+        // An oversized frame ends the iteration
+        let mut iter = parse_iter(&[0xff, 0xff, 0xff, 0xff, 0, 0]);
+        assert!(matches!(
+            iter.next(),
+            Some(Err(ProtocolError::FrameTooLarge { .. }))
+        ));
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn test_code_for() {
+        let hello = Message::HelloBarrier(MessageHelloBarrier {
+            major: 1,
+            minor: 8,
+            client_name: Some("client".to_string()),
+        });
+        let bytes = hello.to_bytes();
+        let code = code_for(&bytes[4..]).unwrap();
+        assert_eq!(code, "Barrier");
+        assert!(is_known_code(code));
+
+        assert_eq!(code_for(b"CALV"), Some("CALV"));
+        assert!(is_known_code("CALV"));
+
+        assert_eq!(code_for(b"ZZZZ\x00\x01"), Some("ZZZZ"));
+        assert!(!is_known_code("ZZZZ"));
+
+        assert_eq!(code_for(b"CAL"), None);
+        assert_eq!(code_for(b"\xff\xffAB"), None);
+
+        for msg in sample_messages() {
+            assert_eq!(code_for(&msg.to_bytes()[4..]), Some(msg.code()));
+            assert!(is_known_code(msg.code()));
+        }
+    }
+
+    #[test]
+    fn test_clipboard_data_invalid_utf8() {
+        // "café" in Latin-1
+        let msg = Message::ClipboardData(MessageClipboardData {
+            id: 0,
+            sequence: 1,
+            mark: 0,
+            data: vec![b'c', b'a', b'f', 0xe9].into(),
+        });
+        let parsed = parse_message(&msg.to_bytes()[4..]).unwrap();
+        assert_eq!(parsed, msg);
+
+        let Message::ClipboardData(data) = parsed else {
+            panic!("Expected ClipboardData");
+        };
+        assert_eq!(data.data_bytes(), b"caf\xe9");
+        assert_eq!(data.data_str(), Err(ProtocolError::InvalidUtf8));
+    }
+
+    #[test]
+    fn test_insufficient_data_offset() {
+        // DCLP whose data claims 100 bytes but carries 2
+        let data = b"DCLP\x00\x00\x00\x00\x01\x00\x00\x00\x00\x64ab";
+        let err = parse_message(data).unwrap_err();
+        assert_eq!(
+            err,
+            ProtocolError::InsufficientData {
+                offset: 14,
+                expected: 114,
+                actual: 16,
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "Insufficient data at offset 14: expected 114 bytes, got 16"
+        );
+
+        // Truncated DSOP value
+        let data = b"DSOP\x00\x00\x00\x03CLPS\x00\x00";
+        assert!(matches!(
+            parse_message(data),
+            Err(ProtocolError::InsufficientData { offset: 8, .. })
+        ));
+
+        let err = LengthPrefixedString::from_bytes(b"xx\x00\x00", 2).unwrap_err();
+        assert!(matches!(
+            err,
+            ProtocolError::InsufficientData { offset: 2, .. }
+        ));
+    }
+
+    #[test]
+    fn test_schengen_caps_option() {
+        assert_eq!(DsopOption::SchengenCaps.code(), "SCAP");
+        assert_eq!(
+            DsopOption::from_u32(u32::from_be_bytes(*b"SCAP")),
+            Some(DsopOption::SchengenCaps)
+        );
+
+        let caps = Capabilities(0b0101) | Capabilities(0b0010);
+        assert_eq!(caps.bits(), 0b0111);
+        assert!(caps.contains(Capabilities(0b0011)));
+        assert!(!caps.contains(Capabilities(0b1000)));
+        assert_eq!(
+            caps.intersection(Capabilities(0b1100)),
+            Capabilities(0b0100)
+        );
+    }
+
+    #[test]
+    fn test_set_options_unknown_keys_roundtrip() {
+        // "ABCD" is not a known option
+        let unknown = u32::from_be_bytes(*b"ABCD");
+        let msg = MessageSetOptions {
+            options: vec![
+                (DsopOption::Heartbeat as u32, 5000),
+                (unknown, 42),
+                (DsopOption::Heartbeat as u32, 2000),
+            ],
+        };
+
+        let parsed = MessageSetOptions::from_bytes(&msg.to_bytes()).unwrap();
+        assert_eq!(parsed, msg);
+        assert_eq!(
+            parsed.typed(),
+            vec![
+                (Some(DsopOption::Heartbeat), 5000),
+                (None, 42),
+                (Some(DsopOption::Heartbeat), 2000),
+            ]
+        );
+        // Later values override earlier ones
+        assert_eq!(parsed.get(DsopOption::Heartbeat), Some(2000));
+    }
+
+    #[test]
+    fn test_modifier_mask() {
+        let msg = MessageKeyDown {
+            keyid: 0x61,
+            mask: 0x2003,
+            button: 0x26,
+        };
+        let mask = msg.modifiers();
+        assert!(mask.contains(Modifier::Shift));
+        assert!(mask.contains(Modifier::Control));
+        assert!(mask.contains(Modifier::NumLock));
+        assert!(!mask.contains(Modifier::Alt));
+        assert!(!mask.contains(Modifier::CapsLock));
+        assert_eq!(
+            mask.modifiers().collect::<Vec<_>>(),
+            vec![Modifier::Shift, Modifier::Control, Modifier::NumLock]
+        );
+
+        let built = ModifierMask::empty()
+            .with(Modifier::Alt)
+            .with(Modifier::ScrollLock);
+        assert_eq!(built.bits(), 0x4004);
+        assert_eq!(built, ModifierMask::ALT | ModifierMask::SCROLL_LOCK);
+
+        // Every modifier has a distinct bit
+        let all = Modifier::ALL
+            .iter()
+            .fold(ModifierMask::empty(), |m, b| m.with(*b));
+        assert_eq!(all.bits().count_ones() as usize, Modifier::ALL.len());
+
+        // Unknown bits are preserved
+        let unknown = ModifierMask::from(0x8041);
+        assert_eq!(u16::from(unknown), 0x8041);
+        assert_eq!(
+            unknown.modifiers().collect::<Vec<_>>(),
+            vec![Modifier::Shift]
+        );
+
+        let entered = MessageCursorEntered {
+            x: 0,
+            y: 0,
+            sequence: 0,
+            mask: 0x1000,
+        };
+        assert!(entered.modifiers().contains(Modifier::CapsLock));
+    }
+
+    #[test]
+    fn test_mouse_button() {
+        assert_eq!(MouseButton::from_u8(1), MouseButton::Left);
+        assert_eq!(MouseButton::from_u8(2), MouseButton::Right);
+        assert_eq!(MouseButton::from_u8(3), MouseButton::Middle);
+        assert_eq!(MouseButton::from_u8(8), MouseButton::Extra(8));
+
+        for button in 1..=10u8 {
+            assert_eq!(MouseButton::from_u8(button).to_u8(), button);
+        }
+
+        assert_eq!(
+            MessageMouseButtonDown { button: 3 }.button_kind(),
+            MouseButton::Middle
+        );
+        assert_eq!(
+            MessageMouseButtonUp { button: 8 }.button_kind(),
+            MouseButton::Extra(8)
+        );
+        assert_eq!(u8::from(MouseButton::Left), 1);
+    }
+
+    #[test]
+    fn test_drag_info_paths() {
+        // No paths
+        let msg = MessageDragInfo::from_paths(&[]).unwrap();
+        assert_eq!(msg.size, 0);
+        assert!(msg.paths().is_empty());
+        assert!(msg.validate().is_ok());
+
+        // One path
+        let msg = MessageDragInfo::from_paths(&["/tmp/file".to_string()]).unwrap();
+        assert_eq!(msg.size, 1);
+        assert_eq!(msg.data.0, "/tmp/file");
+        assert_eq!(msg.paths(), vec!["/tmp/file".to_string()]);
+
+        // Several paths, including spaces
+        let paths = vec![
+            "/home/user/My Documents/report final.pdf".to_string(),
+            "/tmp/a".to_string(),
+            "C:\\Program Files\\app.exe".to_string(),
+        ];
+        let msg = MessageDragInfo::from_paths(&paths).unwrap();
+        assert_eq!(msg.size, 3);
+        let parsed = MessageDragInfo::from_bytes(&msg.to_bytes()).unwrap();
+        assert_eq!(parsed.paths(), paths);
+        assert!(parsed.validate().is_ok());
+
+        // A trailing separator doesn't add an empty path
+        let msg = MessageDragInfo {
+            size: 2,
+            data: "/a\0/b\0".into(),
+        };
+        assert_eq!(msg.paths(), vec!["/a".to_string(), "/b".to_string()]);
+        assert!(msg.validate().is_ok());
+
+        // Count mismatch is reported by validate() but doesn't fail the parse
+        let msg = MessageDragInfo {
+            size: 5,
+            data: "/a\0/b".into(),
+        };
+        assert!(matches!(msg.validate(), Err(ProtocolError::InvalidData(_))));
+        assert_eq!(MessageDragInfo::from_bytes(&msg.to_bytes()).unwrap(), msg);
+
+        // The count has to fit in 16 bits
+        let paths = vec!["/a".to_string(); u16::MAX as usize];
+        assert_eq!(MessageDragInfo::from_paths(&paths).unwrap().size, u16::MAX);
+        let paths = vec!["/a".to_string(); u16::MAX as usize + 1];
+        assert!(matches!(
+            MessageDragInfo::from_paths(&paths),
+            Err(ProtocolError::InvalidData(_))
+        ));
+    }
+
+    #[test]
+    fn test_message_write_pretty() {
+        let messages: Vec<Message> = vec![
+            MessageKeepAlive.into(),
+            MessageMouseMove { x: 100, y: -5 }.into(),
+            MessageKeyDown {
+                keyid: 0x61,
+                mask: 0x0001,
+                button: 0x26,
+            }
+            .into(),
+            MessageHelloBarrier {
+                major: 1,
+                minor: 8,
+                client_name: Some("laptop".to_string()),
+            }
+            .into(),
+            MessageClipboardData {
+                id: 0,
+                sequence: 3,
+                mark: 2,
+                data: vec![0u8; 42].into(),
+            }
+            .into(),
+            MessageSetOptions {
+                options: vec![(DsopOption::Heartbeat as u32, 3000), (0x41424344, 1)],
+            }
+            .into(),
+        ];
+
+        // All messages go into the same reused buffer
+        let mut out = String::new();
+        for msg in &messages {
+            msg.write_pretty(&mut out).unwrap();
+            out.push('\n');
+        }
+        assert_eq!(
+            out,
+            "CALV\n\
+             DMMV x=100 y=-5\n\
+             DKDN key=0x0061 mask=0x0001 button=0x0026\n\
+             Barrier version=1.8 name=\"laptop\"\n\
+             DCLP id=0 seq=3 mark=2 len=42\n\
+             DSOP HART=3000 0x41424344=1\n"
+        );
+
+        // Display uses the same format
+        assert_eq!(messages[1].to_string(), "DMMV x=100 y=-5");
+    }
 }
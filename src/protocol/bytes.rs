@@ -0,0 +1,328 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Zero-copy parsing from [`Bytes`], available with the `bytes` feature
+
+use ::bytes::Bytes;
+
+use super::{
+    MAX_MESSAGE_LENGTH, Message, MessageClipboardData, MessageDragInfo, MessageFileTransfer,
+    MessageKeyDownWithLanguage, MessageKeyRepeat, MessageLegacySynergy, MessageRef,
+    MessageSecureEncryption, ProtocolError, Result, frame_size, parse_message_ref,
+};
+
+/// A message parsed by [`parse_message_bytes`]
+///
+/// Messages with a string or byte payload keep it as a [`Bytes`] slice of the input
+/// buffer, sharing its allocation. String payloads are checked to be valid UTF-8 while
+/// parsing. All other messages are parsed into an owned [`Message`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum MessageBytes {
+    /// Key press with language, see [`MessageKeyDownWithLanguage`]
+    KeyDownWithLanguage {
+        keyid: u16,
+        mask: u16,
+        button: u16,
+        lang: Bytes,
+    },
+    /// Key auto-repeat, see [`MessageKeyRepeat`]
+    KeyRepeat {
+        keyid: u16,
+        mask: u16,
+        button: u16,
+        count: u16,
+        lang: Bytes,
+    },
+    /// Clipboard data, see [`MessageClipboardData`]
+    ClipboardData {
+        id: u8,
+        sequence: u32,
+        mark: u8,
+        data: Bytes,
+    },
+    /// File transfer, see [`MessageFileTransfer`]
+    FileTransfer { mark: u8, data: Bytes },
+    /// Drag info, see [`MessageDragInfo`]
+    DragInfo { size: u16, data: Bytes },
+    /// Secure input notification, see [`MessageSecureEncryption`]
+    SecureEncryption { data: Bytes },
+    /// Language list, see [`MessageLegacySynergy`]
+    LegacySynergy { data: Bytes },
+    /// Any other message
+    Other(Message),
+}
+
+impl MessageBytes {
+    /// Convert into an owned [`Message`], copying the payload
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ProtocolError::InvalidUtf8`] if a string payload is not valid UTF-8,
+    /// which can only happen for a message that was not created by
+    /// [`parse_message_bytes`].
+    pub fn into_message(self) -> Result<Message> {
+        match self {
+            MessageBytes::KeyDownWithLanguage {
+                keyid,
+                mask,
+                button,
+                lang,
+            } => Ok(Message::KeyDownWithLanguage(MessageKeyDownWithLanguage {
+                keyid,
+                mask,
+                button,
+                lang: string(&lang)?.into(),
+            })),
+            MessageBytes::KeyRepeat {
+                keyid,
+                mask,
+                button,
+                count,
+                lang,
+            } => Ok(Message::KeyRepeat(MessageKeyRepeat {
+                keyid,
+                mask,
+                button,
+                count,
+                lang: string(&lang)?.into(),
+            })),
+            MessageBytes::ClipboardData {
+                id,
+                sequence,
+                mark,
+                data,
+            } => Ok(Message::ClipboardData(MessageClipboardData {
+                id,
+                sequence,
+                mark,
+                data: data.to_vec().into(),
+            })),
+            MessageBytes::FileTransfer { mark, data } => {
+                Ok(Message::FileTransfer(MessageFileTransfer {
+                    mark,
+                    data: data.to_vec().into(),
+                }))
+            }
+            MessageBytes::DragInfo { size, data } => Ok(Message::DragInfo(MessageDragInfo {
+                size,
+                data: string(&data)?.into(),
+            })),
+            MessageBytes::SecureEncryption { data } => {
+                Ok(Message::SecureEncryption(MessageSecureEncryption {
+                    data: string(&data)?.into(),
+                }))
+            }
+            MessageBytes::LegacySynergy { data } => {
+                Ok(Message::LegacySynergy(MessageLegacySynergy {
+                    data: string(&data)?.into(),
+                }))
+            }
+            MessageBytes::Other(message) => Ok(message),
+        }
+    }
+}
+
+/// The string in a payload
+fn string(data: &[u8]) -> Result<&str> {
+    std::str::from_utf8(data).map_err(|_| ProtocolError::InvalidUtf8)
+}
+
+/// Parse a complete message including the 4-byte length prefix from a [`Bytes`] buffer
+///
+/// Behaves like [`parse_message_with_length`](super::parse_message_with_length) but
+/// string and byte payloads are returned as [`Bytes`] slices of `buf` instead of being
+/// copied.
+///
+/// # Example
+///
+/// ```
+/// use bytes::Bytes;
+/// use schengen::protocol::{parse_message_bytes, MessageBytes};
+///
+/// # fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let buf = Bytes::from_static(&[0, 0, 0, 4, b'C', b'A', b'L', b'V']);
+/// let (msg, consumed) = parse_message_bytes(&buf)?;
+/// assert_eq!(consumed, 8);
+/// assert!(matches!(msg, MessageBytes::Other(_)));
+/// # Ok(())
+/// # }
+/// ```
+pub fn parse_message_bytes(buf: &Bytes) -> Result<(MessageBytes, usize)> {
+    let total_size = frame_size(buf, MAX_MESSAGE_LENGTH)?;
+    let frame = buf.slice(4..total_size);
+    let msg = match parse_message_ref(&frame)? {
+        MessageRef::KeyDownWithLanguage {
+            keyid,
+            mask,
+            button,
+            lang,
+        } => MessageBytes::KeyDownWithLanguage {
+            keyid,
+            mask,
+            button,
+            lang: frame.slice_ref(lang.as_bytes()),
+        },
+        MessageRef::KeyRepeat {
+            keyid,
+            mask,
+            button,
+            count,
+            lang,
+        } => MessageBytes::KeyRepeat {
+            keyid,
+            mask,
+            button,
+            count,
+            lang: frame.slice_ref(lang.as_bytes()),
+        },
+        MessageRef::ClipboardData {
+            id,
+            sequence,
+            mark,
+            data,
+        } => MessageBytes::ClipboardData {
+            id,
+            sequence,
+            mark,
+            data: frame.slice_ref(data),
+        },
+        MessageRef::FileTransfer { mark, data } => MessageBytes::FileTransfer {
+            mark,
+            data: frame.slice_ref(data),
+        },
+        MessageRef::DragInfo { size, data } => MessageBytes::DragInfo {
+            size,
+            data: frame.slice_ref(data.as_bytes()),
+        },
+        MessageRef::SecureEncryption { data } => MessageBytes::SecureEncryption {
+            data: frame.slice_ref(data.as_bytes()),
+        },
+        MessageRef::LegacySynergy { data } => MessageBytes::LegacySynergy {
+            data: frame.slice_ref(data.as_bytes()),
+        },
+        MessageRef::Other(message) => MessageBytes::Other(message),
+    };
+    Ok((msg, total_size))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::MessageKeepAlive;
+
+    #[test]
+    fn test_parse_message_bytes_shares_allocation() {
+        let msg = Message::ClipboardData(MessageClipboardData {
+            id: 0,
+            sequence: 1,
+            mark: 2,
+            data: vec![0xffu8; 1024].into(),
+        });
+        let mut wire = msg.to_bytes();
+        wire.extend_from_slice(&Message::KeepAlive(MessageKeepAlive).to_bytes());
+        let buf = Bytes::from(wire);
+
+        let (parsed, consumed) = parse_message_bytes(&buf).unwrap();
+        let data = match &parsed {
+            MessageBytes::ClipboardData {
+                id: 0,
+                sequence: 1,
+                mark: 2,
+                data,
+            } => data.clone(),
+            other => panic!("Unexpected message {:?}", other),
+        };
+
+        // The payload points into the original buffer
+        let start = buf.as_ptr() as usize;
+        let end = start + buf.len();
+        let ptr = data.as_ptr() as usize;
+        assert!(ptr >= start && ptr + data.len() <= end);
+        assert_eq!(data.len(), 1024);
+        assert_eq!(parsed.into_message().unwrap(), msg);
+
+        let rest = buf.slice(consumed..);
+        let (parsed, _) = parse_message_bytes(&rest).unwrap();
+        assert_eq!(
+            parsed,
+            MessageBytes::Other(Message::KeepAlive(MessageKeepAlive))
+        );
+    }
+
+    #[test]
+    fn test_parse_message_bytes_matches_parse_message() {
+        let messages = [
+            Message::KeyDownWithLanguage(MessageKeyDownWithLanguage {
+                keyid: 0xe9,
+                mask: 0,
+                button: 0x1a,
+                lang: "fr".into(),
+            }),
+            Message::KeyRepeat(MessageKeyRepeat {
+                keyid: 0x61,
+                mask: 0,
+                button: 0x26,
+                count: 2,
+                lang: "en".into(),
+            }),
+            Message::FileTransfer(MessageFileTransfer {
+                mark: 2,
+                data: vec![0x89, b'P', b'N', b'G'].into(),
+            }),
+            Message::DragInfo(MessageDragInfo::from_paths(&["/tmp/a".to_string()]).unwrap()),
+            Message::SecureEncryption(MessageSecureEncryption {
+                data: "Terminal".into(),
+            }),
+            Message::LegacySynergy(MessageLegacySynergy::from_languages(&["en", "de"])),
+            // An empty payload at the end of the frame
+            Message::SecureEncryption(MessageSecureEncryption { data: "".into() }),
+        ];
+        for msg in messages {
+            let buf = Bytes::from(msg.to_bytes());
+            let (parsed, consumed) = parse_message_bytes(&buf).unwrap();
+            assert_eq!(consumed, buf.len());
+            assert!(!matches!(parsed, MessageBytes::Other(_)), "{:?}", msg);
+            assert_eq!(parsed.into_message().unwrap(), msg);
+        }
+
+        // String payloads share the allocation too
+        let buf = Bytes::from(
+            Message::SecureEncryption(MessageSecureEncryption {
+                data: "Terminal".into(),
+            })
+            .to_bytes(),
+        );
+        let Ok((MessageBytes::SecureEncryption { data }, _)) = parse_message_bytes(&buf) else {
+            panic!("Expected SecureEncryption");
+        };
+        assert_eq!(data.as_ptr(), buf[12..].as_ptr());
+    }
+
+    #[test]
+    fn test_parse_message_bytes_errors() {
+        let buf = Bytes::from_static(&[0, 0, 0, 10, b'D', b'C', b'L', b'P']);
+        assert!(matches!(
+            parse_message_bytes(&buf),
+            Err(ProtocolError::InsufficientData { .. })
+        ));
+
+        let buf = Bytes::from_static(&[0xff, 0xff, 0xff, 0xff]);
+        assert!(matches!(
+            parse_message_bytes(&buf),
+            Err(ProtocolError::FrameTooLarge { .. })
+        ));
+
+        // Invalid UTF-8 in a string payload
+        let buf =
+            Bytes::from_static(&[0, 0, 0, 10, b'L', b'S', b'Y', b'N', 0, 0, 0, 2, 0xff, 0xfe]);
+        assert_eq!(parse_message_bytes(&buf), Err(ProtocolError::InvalidUtf8));
+
+        // Payload length beyond the frame
+        let buf = Bytes::from_static(&[
+            0, 0, 0, 14, b'D', b'C', b'L', b'P', 0, 0, 0, 0, 1, 0, 0, 0, 0, 9,
+        ]);
+        assert!(matches!(
+            parse_message_bytes(&buf),
+            Err(ProtocolError::InsufficientData { .. })
+        ));
+    }
+}
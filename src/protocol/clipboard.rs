@@ -0,0 +1,495 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! # Clipboard Data
+//!
+//! The data sent in a [`MessageClipboardData`] is not the raw clipboard content but
+//! Synergy's internal clipboard format which can hold multiple formats at once:
+//! - Number of formats (4 bytes BE)
+//! - For each format:
+//!   - Format identifier (4 bytes BE) - see [`ClipboardFormat`]
+//!   - Length of the data (4 bytes BE)
+//!   - The data
+//!
+//! Large clipboards are split across multiple [`MessageClipboardData`] messages, see
+//! [`ClipboardContents::to_messages`] for sending and [`ClipboardAssembler`] for receiving.
+
+use std::collections::HashMap;
+
+use super::{MessageClipboardData, ProtocolError, Result, read_u32};
+
+/// The maximum amount of clipboard data sent in a single [`MessageClipboardData`]
+///
+/// This matches the chunk size used by Deskflow.
+pub const CLIPBOARD_CHUNK_SIZE: usize = 512 * 1024;
+
+/// [`MessageClipboardData::mark`] for a message containing the complete data
+pub const MARK_SINGLE: u8 = 0;
+/// [`MessageClipboardData::mark`] for the first message, the data is the total size in decimal
+pub const MARK_FIRST: u8 = 1;
+/// [`MessageClipboardData::mark`] for a message carrying a chunk of the data
+pub const MARK_MIDDLE: u8 = 2;
+/// [`MessageClipboardData::mark`] for the final message, the data is empty
+pub const MARK_FINAL: u8 = 3;
+
+/// Identifies one of the clipboards
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum ClipboardId {
+    /// The regular clipboard (Ctrl+C/Ctrl+V)
+    Clipboard = 0,
+    /// The X11 primary selection (middle-click paste)
+    Selection = 1,
+}
+
+impl ClipboardId {
+    /// Convert the wire format identifier to a ClipboardId
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Self::Clipboard),
+            1 => Some(Self::Selection),
+            _ => None,
+        }
+    }
+}
+
+impl From<ClipboardId> for u8 {
+    fn from(id: ClipboardId) -> Self {
+        id as u8
+    }
+}
+
+/// The format of one entry in [`ClipboardContents`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u32)]
+pub enum ClipboardFormat {
+    /// UTF-8 text
+    Text = 0,
+    /// HTML markup, UTF-8
+    Html = 1,
+    /// Bitmap image data
+    Bitmap = 2,
+}
+
+impl ClipboardFormat {
+    /// Convert the wire format identifier to a ClipboardFormat
+    pub fn from_u32(value: u32) -> Option<Self> {
+        match value {
+            0 => Some(Self::Text),
+            1 => Some(Self::Html),
+            2 => Some(Self::Bitmap),
+            _ => None,
+        }
+    }
+}
+
+/// The contents of a clipboard, one entry per available format
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ClipboardContents {
+    /// The available formats and their data
+    pub formats: Vec<(ClipboardFormat, Vec<u8>)>,
+}
+
+impl ClipboardContents {
+    /// Create clipboard contents from a list of formats
+    pub fn new(formats: Vec<(ClipboardFormat, Vec<u8>)>) -> Self {
+        Self { formats }
+    }
+
+    /// The data for `format`, if available
+    pub fn get(&self, format: ClipboardFormat) -> Option<&[u8]> {
+        self.formats
+            .iter()
+            .find(|(f, _)| *f == format)
+            .map(|(_, data)| data.as_slice())
+    }
+
+    /// Parse clipboard contents from Synergy's internal clipboard format
+    ///
+    /// Entries with a format unknown to this crate are skipped.
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        let count = read_u32(data, 0)?;
+        let mut offset = 4;
+        let mut formats = Vec::new();
+        for _ in 0..count {
+            let format = read_u32(data, offset)?;
+            let length = read_u32(data, offset + 4)? as usize;
+            offset += 8;
+            if data.len() - offset < length {
+                return Err(ProtocolError::InsufficientData {
+                    offset,
+                    expected: offset.saturating_add(length),
+                    actual: data.len(),
+                });
+            }
+            if let Some(format) = ClipboardFormat::from_u32(format) {
+                formats.push((format, data[offset..offset + length].to_vec()));
+            }
+            offset += length;
+        }
+        Ok(Self { formats })
+    }
+
+    /// Serialize the clipboard contents into Synergy's internal clipboard format
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&(self.formats.len() as u32).to_be_bytes());
+        for (format, data) in &self.formats {
+            bytes.extend_from_slice(&(*format as u32).to_be_bytes());
+            bytes.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            bytes.extend_from_slice(data);
+        }
+        bytes
+    }
+
+    /// Serialize the contents into a single [`MARK_SINGLE`] message
+    ///
+    /// This is the format used before Synergy 1.6, see
+    /// [`to_messages`](Self::to_messages) for large clipboards.
+    pub fn to_message(&self, id: ClipboardId, sequence: u32) -> MessageClipboardData {
+        MessageClipboardData {
+            id: id.into(),
+            sequence,
+            mark: MARK_SINGLE,
+            data: self.to_bytes().into(),
+        }
+    }
+
+    /// Split the serialized contents into the sequence of messages to send
+    ///
+    /// This produces the sequence used by Synergy 1.6 and later: a [`MARK_FIRST`] message
+    /// with the total size, one [`MARK_MIDDLE`] message per chunk of at most
+    /// [`CLIPBOARD_CHUNK_SIZE`] bytes, then an empty [`MARK_FINAL`] message.
+    pub fn to_messages(&self, id: ClipboardId, sequence: u32) -> Vec<MessageClipboardData> {
+        let data = self.to_bytes();
+        let message = |mark, data: Vec<u8>| MessageClipboardData {
+            id: id.into(),
+            sequence,
+            mark,
+            data: data.into(),
+        };
+
+        let mut messages = vec![message(MARK_FIRST, data.len().to_string().into_bytes())];
+        for chunk in data.chunks(CLIPBOARD_CHUNK_SIZE) {
+            messages.push(message(MARK_MIDDLE, chunk.to_vec()));
+        }
+        messages.push(message(MARK_FINAL, Vec::new()));
+        messages
+    }
+}
+
+/// Reassembles clipboard data split across multiple [`MessageClipboardData`] messages
+///
+/// Feed every received [`MessageClipboardData`] to [`ClipboardAssembler::push`], it returns
+/// the complete data once the final chunk of a clipboard arrived. Transfers for different
+/// clipboard ids may be interleaved.
+///
+/// # Example
+///
+/// ```
+/// use schengen::protocol::ClipboardAssembler;
+/// use schengen::protocol::clipboard::{ClipboardContents, ClipboardFormat, ClipboardId};
+///
+/// # fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let contents = ClipboardContents::new(vec![(ClipboardFormat::Text, b"hello".to_vec())]);
+/// let mut assembler = ClipboardAssembler::new();
+/// let mut complete = None;
+/// for msg in contents.to_messages(ClipboardId::Clipboard, 0) {
+///     complete = assembler.push(&msg)?;
+/// }
+/// assert_eq!(ClipboardContents::from_bytes(&complete.unwrap())?, contents);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Default)]
+pub struct ClipboardAssembler {
+    /// In-progress transfers by clipboard id: (announced size, data so far)
+    pending: HashMap<u8, (usize, Vec<u8>)>,
+    /// The largest clipboard accepted, `None` for no limit
+    max_size: Option<usize>,
+}
+
+impl ClipboardAssembler {
+    /// Create a new assembler with no transfers in progress
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a new assembler that rejects clipboards larger than `max_size` bytes
+    ///
+    /// The limit is checked against the size announced in the first chunk, so nothing
+    /// is buffered for a clipboard that is too large.
+    pub fn with_max_size(max_size: usize) -> Self {
+        Self {
+            pending: HashMap::new(),
+            max_size: Some(max_size),
+        }
+    }
+
+    /// The error for a clipboard of `size` bytes if it exceeds the maximum size
+    fn check_size(&self, id: u8, size: usize) -> Result<()> {
+        match self.max_size {
+            Some(max_size) if size > max_size => Err(ProtocolError::InvalidData(format!(
+                "Clipboard {} of {} bytes exceeds the limit of {} bytes",
+                id, size, max_size
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Add a received message
+    ///
+    /// Returns the complete clipboard data once the last message of a transfer has been
+    /// pushed and `None` while the transfer is still in progress. A [`MARK_SINGLE`]
+    /// message is complete on its own. A new [`MARK_FIRST`] message for a clipboard
+    /// discards any unfinished transfer for that clipboard.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ProtocolError::InvalidData`] if a middle or final chunk arrives without
+    /// a preceding first chunk, if the first chunk doesn't contain a valid size or
+    /// announces more than the maximum size, if the received data exceeds or falls
+    /// short of the announced size or if the mark is unknown. The in-progress transfer
+    /// for the clipboard is discarded on error.
+    pub fn push(&mut self, msg: &MessageClipboardData) -> Result<Option<Vec<u8>>> {
+        match msg.mark {
+            MARK_SINGLE => {
+                self.check_size(msg.id, msg.data.0.len())?;
+                Ok(Some(msg.data.0.clone()))
+            }
+            MARK_FIRST => {
+                let size = std::str::from_utf8(&msg.data.0)
+                    .ok()
+                    .and_then(|s| s.parse::<usize>().ok())
+                    .ok_or_else(|| {
+                        ProtocolError::InvalidData(format!(
+                            "Invalid clipboard size in first chunk for clipboard {}",
+                            msg.id
+                        ))
+                    })?;
+                self.pending.remove(&msg.id);
+                self.check_size(msg.id, size)?;
+                self.pending.insert(msg.id, (size, Vec::new()));
+                Ok(None)
+            }
+            MARK_MIDDLE => {
+                let (size, data) = self.pending.get_mut(&msg.id).ok_or_else(|| {
+                    ProtocolError::InvalidData(format!(
+                        "Clipboard chunk for clipboard {} without a first chunk",
+                        msg.id
+                    ))
+                })?;
+                if msg.data.0.len() > *size - data.len() {
+                    let err = ProtocolError::InvalidData(format!(
+                        "Clipboard {} exceeds the announced size of {} bytes",
+                        msg.id, size
+                    ));
+                    self.pending.remove(&msg.id);
+                    return Err(err);
+                }
+                data.extend_from_slice(&msg.data.0);
+                Ok(None)
+            }
+            MARK_FINAL => {
+                let (size, data) = self.pending.remove(&msg.id).ok_or_else(|| {
+                    ProtocolError::InvalidData(format!(
+                        "Final clipboard chunk for clipboard {} without a first chunk",
+                        msg.id
+                    ))
+                })?;
+                if data.len() != size {
+                    return Err(ProtocolError::InvalidData(format!(
+                        "Clipboard {} announced {} bytes but received {}",
+                        msg.id,
+                        size,
+                        data.len()
+                    )));
+                }
+                Ok(Some(data))
+            }
+            mark => Err(ProtocolError::InvalidData(format!(
+                "Unknown clipboard chunk mark {}",
+                mark
+            ))),
+        }
+    }
+
+    /// Whether a transfer for the given clipboard id is in progress
+    pub fn is_pending(&self, id: u8) -> bool {
+        self.pending.contains_key(&id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{Message, parse_message};
+
+    #[test]
+    fn test_clipboard_contents_roundtrip() {
+        let contents = ClipboardContents::new(vec![
+            (ClipboardFormat::Text, b"hello".to_vec()),
+            (ClipboardFormat::Html, b"<b>hello</b>".to_vec()),
+        ]);
+        let bytes = contents.to_bytes();
+        assert_eq!(&bytes[0..4], &[0, 0, 0, 2]);
+        assert_eq!(&bytes[4..12], &[0, 0, 0, 0, 0, 0, 0, 5]);
+        assert_eq!(&bytes[12..17], b"hello");
+        assert_eq!(ClipboardContents::from_bytes(&bytes).unwrap(), contents);
+
+        // Unknown formats are skipped
+        let mut unknown = vec![0, 0, 0, 2];
+        unknown.extend_from_slice(&[0, 0, 0, 9, 0, 0, 0, 1, 0xff]);
+        unknown.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 2, b'h', b'i']);
+        assert_eq!(
+            ClipboardContents::from_bytes(&unknown).unwrap().formats,
+            vec![(ClipboardFormat::Text, b"hi".to_vec())]
+        );
+
+        // Truncated data
+        assert!(matches!(
+            ClipboardContents::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(ProtocolError::InsufficientData { .. })
+        ));
+    }
+
+    #[test]
+    fn test_clipboard_message_contents() {
+        let contents = ClipboardContents::new(vec![
+            (ClipboardFormat::Text, b"hello".to_vec()),
+            (ClipboardFormat::Html, b"<b>hello</b>".to_vec()),
+        ]);
+        let msg = Message::ClipboardData(contents.to_message(ClipboardId::Clipboard, 3));
+
+        let Message::ClipboardData(parsed) = parse_message(&msg.to_bytes()[4..]).unwrap() else {
+            panic!("Expected ClipboardData");
+        };
+        assert_eq!(parsed.mark, MARK_SINGLE);
+        let parsed = parsed.contents().unwrap();
+        assert_eq!(parsed, contents);
+        assert_eq!(parsed.get(ClipboardFormat::Text), Some(&b"hello"[..]));
+        assert_eq!(
+            parsed.get(ClipboardFormat::Html),
+            Some(&b"<b>hello</b>"[..])
+        );
+        assert_eq!(parsed.get(ClipboardFormat::Bitmap), None);
+
+        // Chunks must be reassembled first
+        let first = &contents.to_messages(ClipboardId::Clipboard, 3)[0];
+        assert!(matches!(
+            first.contents(),
+            Err(ProtocolError::InvalidData(_))
+        ));
+    }
+
+    #[test]
+    fn test_clipboard_to_messages() {
+        let contents = ClipboardContents::new(vec![(
+            ClipboardFormat::Bitmap,
+            vec![0xab; CLIPBOARD_CHUNK_SIZE + 10],
+        )]);
+        let messages = contents.to_messages(ClipboardId::Selection, 7);
+        let marks: Vec<u8> = messages.iter().map(|m| m.mark).collect();
+        assert_eq!(
+            marks,
+            vec![MARK_FIRST, MARK_MIDDLE, MARK_MIDDLE, MARK_FINAL]
+        );
+        assert!(messages.iter().all(|m| m.id == 1 && m.sequence == 7));
+
+        let total = contents.to_bytes();
+        assert_eq!(messages[0].data.0, total.len().to_string().into_bytes());
+        let joined: Vec<u8> = messages[1..3]
+            .iter()
+            .flat_map(|m| m.data.0.clone())
+            .collect();
+        assert_eq!(joined, total);
+        assert!(messages[3].data.0.is_empty());
+    }
+
+    #[test]
+    fn test_clipboard_assembler() {
+        let chunk = |id, mark, data: &[u8]| MessageClipboardData {
+            id,
+            sequence: 0,
+            mark,
+            data: data.into(),
+        };
+
+        let mut assembler = ClipboardAssembler::new();
+        assert_eq!(assembler.push(&chunk(0, MARK_FIRST, b"11")).unwrap(), None);
+        assert!(assembler.is_pending(0));
+        assert_eq!(
+            assembler.push(&chunk(0, MARK_MIDDLE, b"hello ")).unwrap(),
+            None
+        );
+        // A transfer for the other clipboard doesn't interfere
+        assert_eq!(
+            assembler.push(&chunk(1, MARK_SINGLE, b"single")).unwrap(),
+            Some(b"single".to_vec())
+        );
+        assert_eq!(
+            assembler.push(&chunk(0, MARK_MIDDLE, b"world")).unwrap(),
+            None
+        );
+        assert_eq!(
+            assembler.push(&chunk(0, MARK_FINAL, b"")).unwrap(),
+            Some(b"hello world".to_vec())
+        );
+        assert!(!assembler.is_pending(0));
+
+        // Chunks without a first chunk
+        assert!(matches!(
+            assembler.push(&chunk(0, MARK_MIDDLE, b"data")),
+            Err(ProtocolError::InvalidData(_))
+        ));
+        assert!(matches!(
+            assembler.push(&chunk(0, MARK_FINAL, b"")),
+            Err(ProtocolError::InvalidData(_))
+        ));
+
+        // Size mismatch
+        assembler.push(&chunk(0, MARK_FIRST, b"100")).unwrap();
+        assembler.push(&chunk(0, MARK_MIDDLE, b"short")).unwrap();
+        assert!(matches!(
+            assembler.push(&chunk(0, MARK_FINAL, b"")),
+            Err(ProtocolError::InvalidData(_))
+        ));
+
+        // Too much data is detected as soon as it arrives
+        assembler.push(&chunk(0, MARK_FIRST, b"4")).unwrap();
+        assembler.push(&chunk(0, MARK_MIDDLE, b"four")).unwrap();
+        assert!(matches!(
+            assembler.push(&chunk(0, MARK_MIDDLE, b"!")),
+            Err(ProtocolError::InvalidData(_))
+        ));
+        assert!(!assembler.is_pending(0));
+
+        // Invalid size and mark
+        assert!(assembler.push(&chunk(0, MARK_FIRST, b"abc")).is_err());
+        assert!(assembler.push(&chunk(0, 7, b"")).is_err());
+    }
+
+    #[test]
+    fn test_clipboard_assembler_max_size() {
+        let chunk = |mark, data: &[u8]| MessageClipboardData {
+            id: 0,
+            sequence: 0,
+            mark,
+            data: data.into(),
+        };
+
+        let mut assembler = ClipboardAssembler::with_max_size(4);
+        assembler.push(&chunk(MARK_FIRST, b"4")).unwrap();
+        assembler.push(&chunk(MARK_MIDDLE, b"four")).unwrap();
+        assert_eq!(
+            assembler.push(&chunk(MARK_FINAL, b"")).unwrap(),
+            Some(b"four".to_vec())
+        );
+        assert!(assembler.push(&chunk(MARK_SINGLE, b"five!")).is_err());
+
+        // An oversized first chunk also discards the transfer in progress
+        assembler.push(&chunk(MARK_FIRST, b"2")).unwrap();
+        assert!(assembler.push(&chunk(MARK_FIRST, b"4294967296")).is_err());
+        assert!(!assembler.is_pending(0));
+        assert!(assembler.push(&chunk(MARK_MIDDLE, b"data")).is_err());
+    }
+}
@@ -0,0 +1,297 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! # File Transfer
+//!
+//! A file is sent as a sequence of [`MessageFileTransfer`] messages:
+//! - A [`MARK_START`] message with the file size as decimal string, see
+//!   [`MessageFileTransfer::data_start`]
+//! - Any number of [`MARK_CHUNK`] messages with the file content
+//! - A [`MARK_END`] message with an empty payload
+//!
+//! Use [`to_messages`] to split a file into this sequence and
+//! [`FileTransferAssembler`] to collect the chunks into the complete file.
+
+use super::{MessageFileTransfer, ProtocolError, Result};
+
+/// [`MessageFileTransfer::mark`] for the first message, the data is the file size
+pub const MARK_START: u8 = 1;
+/// [`MessageFileTransfer::mark`] for a message carrying a chunk of the file
+pub const MARK_CHUNK: u8 = 2;
+/// [`MessageFileTransfer::mark`] for the final message, the data is empty
+pub const MARK_END: u8 = 3;
+
+/// The maximum size of the file data in a single [`MARK_CHUNK`] message
+///
+/// This matches the chunk size used by Deskflow.
+pub const FILE_CHUNK_SIZE: usize = 512 * 1024;
+
+/// Split a file into the messages that transfer it
+///
+/// Produces a [`MARK_START`] message with the size, one [`MARK_CHUNK`] message per
+/// chunk of at most `chunk_size` bytes and an empty [`MARK_END`] message.
+///
+/// # Example
+///
+/// ```
+/// use schengen::protocol::file_transfer::{MARK_CHUNK, to_messages};
+///
+/// let messages = to_messages(b"hello world", 4);
+/// assert_eq!(messages.len(), 5);
+/// assert_eq!(messages[1].mark, MARK_CHUNK);
+/// assert_eq!(messages[1].data.0, b"hell");
+/// ```
+pub fn to_messages(data: &[u8], chunk_size: usize) -> Vec<MessageFileTransfer> {
+    let mut messages = vec![MessageFileTransfer::data_start(data.len() as u64)];
+    messages.extend(
+        data.chunks(chunk_size.max(1))
+            .map(|chunk| MessageFileTransfer {
+                mark: MARK_CHUNK,
+                data: chunk.into(),
+            }),
+    );
+    messages.push(MessageFileTransfer {
+        mark: MARK_END,
+        data: Vec::new().into(),
+    });
+    messages
+}
+
+/// Reassembles a file sent across multiple [`MessageFileTransfer`] messages
+///
+/// # Example
+///
+/// ```
+/// use schengen::protocol::{FileTransferAssembler, MessageFileTransfer};
+///
+/// # fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let mut assembler = FileTransferAssembler::new();
+/// assembler.push(&MessageFileTransfer::data_start(5))?;
+/// assembler.push(&MessageFileTransfer { mark: 2, data: "hello".into() })?;
+/// let file = assembler.push(&MessageFileTransfer { mark: 3, data: "".into() })?;
+/// assert_eq!(file, Some(b"hello".to_vec()));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Default)]
+pub struct FileTransferAssembler {
+    /// The in-progress transfer: (announced size, data so far)
+    pending: Option<(u64, Vec<u8>)>,
+    /// The largest file accepted, `None` for no limit
+    max_size: Option<u64>,
+}
+
+impl FileTransferAssembler {
+    /// Create a new assembler with no transfer in progress
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a new assembler that rejects files larger than `max_size` bytes
+    ///
+    /// The limit is checked against the size announced in the start message, so
+    /// nothing is buffered for a file that is too large.
+    pub fn with_max_size(max_size: u64) -> Self {
+        Self {
+            pending: None,
+            max_size: Some(max_size),
+        }
+    }
+
+    /// Add a received message
+    ///
+    /// Returns the complete file once the end message has been pushed and `None` while
+    /// the transfer is still in progress. A new start message discards any unfinished
+    /// transfer.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ProtocolError::InvalidData`] if a chunk or end message arrives without a
+    /// preceding start message, if the start message doesn't contain a valid size or
+    /// announces more than the maximum size, if the received data exceeds or falls
+    /// short of the announced size or if the mark is unknown. The in-progress transfer
+    /// is discarded on error.
+    pub fn push(&mut self, msg: &MessageFileTransfer) -> Result<Option<Vec<u8>>> {
+        match msg.mark {
+            MARK_START => {
+                let size = msg.file_size().ok_or_else(|| {
+                    ProtocolError::InvalidData(format!(
+                        "Invalid file size {:?}",
+                        String::from_utf8_lossy(&msg.data.0)
+                    ))
+                })?;
+                self.pending = None;
+                if let Some(max_size) = self.max_size.filter(|&max_size| size > max_size) {
+                    return Err(ProtocolError::InvalidData(format!(
+                        "File of {} bytes exceeds the limit of {} bytes",
+                        size, max_size
+                    )));
+                }
+                self.pending = Some((size, Vec::new()));
+                Ok(None)
+            }
+            MARK_CHUNK => {
+                let (size, data) = self.pending.as_mut().ok_or_else(|| {
+                    ProtocolError::InvalidData("File chunk without a start message".to_string())
+                })?;
+                data.extend_from_slice(&msg.data.0);
+                if data.len() as u64 > *size {
+                    let err = ProtocolError::InvalidData(format!(
+                        "File transfer exceeds the announced size of {} bytes",
+                        size
+                    ));
+                    self.pending = None;
+                    return Err(err);
+                }
+                Ok(None)
+            }
+            MARK_END => {
+                let (size, data) = self.pending.take().ok_or_else(|| {
+                    ProtocolError::InvalidData("File end without a start message".to_string())
+                })?;
+                if data.len() as u64 != size {
+                    return Err(ProtocolError::InvalidData(format!(
+                        "File transfer announced {} bytes but received {}",
+                        size,
+                        data.len()
+                    )));
+                }
+                Ok(Some(data))
+            }
+            mark => Err(ProtocolError::InvalidData(format!(
+                "Unknown file transfer mark {}",
+                mark
+            ))),
+        }
+    }
+
+    /// Whether a transfer is in progress
+    pub fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(mark: u8, data: &str) -> MessageFileTransfer {
+        MessageFileTransfer {
+            mark,
+            data: data.into(),
+        }
+    }
+
+    #[test]
+    fn test_file_size() {
+        assert_eq!(MessageFileTransfer::data_start(0).file_size(), Some(0));
+        assert_eq!(chunk(MARK_START, "123456").file_size(), Some(123456));
+        assert_eq!(chunk(MARK_START, "12a").file_size(), None);
+        assert_eq!(chunk(MARK_CHUNK, "123").file_size(), None);
+        assert_eq!(chunk(MARK_END, "").file_size(), None);
+    }
+
+    #[test]
+    fn test_file_transfer_assembler() {
+        let mut assembler = FileTransferAssembler::new();
+        assert_eq!(
+            assembler
+                .push(&MessageFileTransfer::data_start(16))
+                .unwrap(),
+            None
+        );
+        assert!(assembler.is_pending());
+        assert_eq!(assembler.push(&chunk(MARK_CHUNK, "first ")).unwrap(), None);
+        assert_eq!(assembler.push(&chunk(MARK_CHUNK, "second")).unwrap(), None);
+        assert_eq!(assembler.push(&chunk(MARK_CHUNK, "!!!!")).unwrap(), None);
+        assert_eq!(
+            assembler.push(&chunk(MARK_END, "")).unwrap(),
+            Some(b"first second!!!!".to_vec())
+        );
+        assert!(!assembler.is_pending());
+
+        // Chunks without a start
+        assert!(assembler.push(&chunk(MARK_CHUNK, "data")).is_err());
+        assert!(assembler.push(&chunk(MARK_END, "")).is_err());
+
+        // Too little data
+        assembler
+            .push(&MessageFileTransfer::data_start(10))
+            .unwrap();
+        assembler.push(&chunk(MARK_CHUNK, "short")).unwrap();
+        assert!(assembler.push(&chunk(MARK_END, "")).is_err());
+
+        // Too much data is detected as soon as it arrives
+        assembler.push(&MessageFileTransfer::data_start(2)).unwrap();
+        assert!(assembler.push(&chunk(MARK_CHUNK, "too long")).is_err());
+        assert!(!assembler.is_pending());
+
+        // Binary content is collected unchanged
+        let binary = [0x89, b'P', b'N', b'G', 0xff, 0x00];
+        assembler.push(&MessageFileTransfer::data_start(6)).unwrap();
+        assembler
+            .push(&MessageFileTransfer {
+                mark: MARK_CHUNK,
+                data: binary.as_slice().into(),
+            })
+            .unwrap();
+        assert_eq!(
+            assembler.push(&chunk(MARK_END, "")).unwrap(),
+            Some(binary.to_vec())
+        );
+
+        // Invalid size and mark
+        assert!(assembler.push(&chunk(MARK_START, "abc")).is_err());
+        assert!(assembler.push(&chunk(9, "")).is_err());
+    }
+
+    #[test]
+    fn test_file_transfer_assembler_max_size() {
+        let mut assembler = FileTransferAssembler::with_max_size(4);
+        assembler.push(&MessageFileTransfer::data_start(4)).unwrap();
+        assembler.push(&chunk(MARK_CHUNK, "four")).unwrap();
+        assert_eq!(
+            assembler.push(&chunk(MARK_END, "")).unwrap(),
+            Some(b"four".to_vec())
+        );
+
+        // An oversized start also discards the transfer in progress
+        assembler.push(&MessageFileTransfer::data_start(2)).unwrap();
+        assert!(assembler.push(&MessageFileTransfer::data_start(5)).is_err());
+        assert!(!assembler.is_pending());
+        assert!(assembler.push(&chunk(MARK_CHUNK, "fives")).is_err());
+    }
+
+    #[test]
+    fn test_to_messages() {
+        let marks = |messages: &[MessageFileTransfer]| -> Vec<u8> {
+            messages.iter().map(|m| m.mark).collect()
+        };
+
+        let messages = to_messages(b"", FILE_CHUNK_SIZE);
+        assert_eq!(marks(&messages), [MARK_START, MARK_END]);
+        assert_eq!(messages[0].file_size(), Some(0));
+
+        // Chunks are split on byte boundaries, even inside a UTF-8 character
+        let data = "aäöü".as_bytes();
+        let messages = to_messages(data, 2);
+        assert_eq!(
+            marks(&messages),
+            [
+                MARK_START, MARK_CHUNK, MARK_CHUNK, MARK_CHUNK, MARK_CHUNK, MARK_END
+            ]
+        );
+        let chunks: Vec<&[u8]> = messages.iter().map(|m| m.data.0.as_slice()).collect();
+        assert_eq!(
+            chunks[1..5],
+            [&data[0..2], &data[2..4], &data[4..6], &data[6..]]
+        );
+
+        let mut assembler = FileTransferAssembler::new();
+        let mut file = None;
+        let binary: Vec<u8> = (0..=255).collect();
+        for msg in to_messages(&binary, 100) {
+            file = assembler.push(&msg).unwrap();
+        }
+        assert_eq!(file, Some(binary));
+    }
+}
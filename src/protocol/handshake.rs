@@ -0,0 +1,579 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! # Handshake
+//!
+//! The handshake between a server and a client, without any I/O. The exchange is:
+//!
+//! | Server | | Client |
+//! |--------|---|--------|
+//! | hello | → | |
+//! | | ← | hello with the client name |
+//! | `QINF` | → | |
+//! | | ← | `DINF` |
+//! | `LSYN`, `CIAK`, `CROP`, `DSOP` | → | |
+//!
+//! Keepalives are answered at any point. A server may reject the client instead with
+//! `EICV`, `EUNK` or `EBSY`.
+//!
+//! A [`Handshake`] is driven by feeding it each parsed message with
+//! [`Handshake::on_message`] and sending the returned replies, until
+//! [`Handshake::is_complete`].
+//!
+//! # Example
+//!
+//! ```
+//! use schengen::protocol::{Handshake, MessageClientInfo};
+//!
+//! # fn example() -> Result<(), Box<dyn std::error::Error>> {
+//! let info = MessageClientInfo {
+//!     x: 0,
+//!     y: 0,
+//!     width: 1920,
+//!     height: 1080,
+//!     current_mouse_x: 0,
+//!     current_mouse_y: 0,
+//!     size: 0,
+//! };
+//! let mut server = Handshake::server(vec![]);
+//! let mut client = Handshake::client("laptop", info);
+//!
+//! // Pass the messages back and forth until neither side has anything to say
+//! let mut to_client = server.start();
+//! while !to_client.is_empty() {
+//!     let mut to_server = Vec::new();
+//!     for message in to_client {
+//!         to_server.extend(client.on_message(message)?);
+//!     }
+//!     to_client = Vec::new();
+//!     for message in to_server {
+//!         to_client.extend(server.on_message(message)?);
+//!     }
+//! }
+//! assert!(server.is_complete() && client.is_complete());
+//! assert_eq!(server.peer_name(), Some("laptop"));
+//! # Ok(())
+//! # }
+//! ```
+
+use std::error::Error;
+use std::fmt;
+
+use super::{
+    Dialect, Message, MessageClientInfo, MessageIncompatibleVersion, MessageInfoAcknowledgment,
+    MessageKeepAlive, MessageLegacySynergy, MessageQueryInfo, MessageResetOptions,
+    MessageSetOptions, ProtocolVersion, negotiate,
+};
+
+/// Errors that end a [`Handshake`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HandshakeError {
+    /// The peer sent a message that doesn't fit at this point of the handshake
+    UnexpectedMessage {
+        expected: &'static str,
+        got: Box<Message>,
+    },
+    /// The client's hello has no client name
+    MissingClientName,
+    /// The client's protocol version is not supported by the server
+    ///
+    /// The server should send `reply` to the client before closing the connection.
+    IncompatibleVersion {
+        version: ProtocolVersion,
+        reply: MessageIncompatibleVersion,
+    },
+    /// The server rejected the client with `EICV`, `EUNK` or `EBSY`
+    Rejected(Box<Message>),
+}
+
+impl fmt::Display for HandshakeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HandshakeError::UnexpectedMessage { expected, got } => {
+                write!(f, "Expected {}, got: {:?}", expected, got)
+            }
+            HandshakeError::MissingClientName => write!(f, "Client hello missing client name"),
+            HandshakeError::IncompatibleVersion { version, .. } => {
+                write!(f, "Incompatible client protocol version {}", version)
+            }
+            HandshakeError::Rejected(message) => {
+                write!(f, "Rejected by the server: {:?}", message)
+            }
+        }
+    }
+}
+
+impl Error for HandshakeError {}
+
+type Result<T> = std::result::Result<T, HandshakeError>;
+
+/// Where a [`Handshake`] is at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Waiting for the peer's hello
+    Hello,
+    /// Waiting for `QINF` (client) or `DINF` (server)
+    Info,
+    /// The client waits for the remaining messages from the server
+    Finish {
+        lsyn: bool,
+        ciak: bool,
+        crop: bool,
+        dsop: bool,
+    },
+    Complete,
+}
+
+/// The side of the connection, with the data only that side needs
+#[derive(Debug, Clone)]
+enum Role {
+    Client {
+        name: String,
+        info: MessageClientInfo,
+    },
+    Server {
+        options: Vec<(u32, u32)>,
+    },
+}
+
+/// The handshake of one side of a connection, without any I/O
+///
+/// See the [module documentation](self) for the message exchange and an example.
+#[derive(Debug, Clone)]
+pub struct Handshake {
+    role: Role,
+    dialect: Dialect,
+    /// The version advertised in our hello
+    version: ProtocolVersion,
+    progress: Progress,
+}
+
+/// What a [`Handshake`] learned about the peer so far
+#[derive(Debug, Clone)]
+struct Progress {
+    state: State,
+    protocol_version: ProtocolVersion,
+    peer_hello: Option<Message>,
+    peer_name: Option<String>,
+    client_info: Option<MessageClientInfo>,
+}
+
+impl Handshake {
+    /// The handshake of a client called `name`, answering `QINF` with `info`
+    pub fn client(name: &str, info: MessageClientInfo) -> Self {
+        Self::new(Role::Client {
+            name: name.to_string(),
+            info,
+        })
+    }
+
+    /// The handshake of a server, sending `options` with `DSOP` to complete it
+    pub fn server(options: Vec<(u32, u32)>) -> Self {
+        Self::new(Role::Server { options })
+    }
+
+    fn new(role: Role) -> Self {
+        Handshake {
+            role,
+            dialect: Dialect::default(),
+            version: ProtocolVersion::CURRENT,
+            progress: Progress {
+                state: State::Hello,
+                protocol_version: ProtocolVersion::CURRENT,
+                peer_hello: None,
+                peer_name: None,
+                client_info: None,
+            },
+        }
+    }
+
+    /// Send the hello in `dialect` instead of the default Barrier hello
+    ///
+    /// The peer's hello is accepted in either dialect.
+    pub fn with_dialect(mut self, dialect: Dialect) -> Self {
+        self.dialect = dialect;
+        self
+    }
+
+    /// Advertise `version` in the hello instead of [`ProtocolVersion::CURRENT`]
+    ///
+    /// The version used with the peer is negotiated against `version` as usual, e.g.
+    /// to test against a peer as if this side only spoke an older version.
+    pub fn with_version(mut self, version: ProtocolVersion) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// The messages to send before anything was received
+    ///
+    /// This is the hello for a server and nothing for a client.
+    pub fn start(&mut self) -> Vec<Message> {
+        match self.role {
+            // Server doesn't send its name
+            Role::Server { .. } => vec![self.dialect.hello(self.version, None)],
+            Role::Client { .. } => Vec::new(),
+        }
+    }
+
+    /// Process a message from the peer, returns the replies to send in order
+    ///
+    /// Messages received after the handshake completed are ignored.
+    pub fn on_message(&mut self, message: Message) -> Result<Vec<Message>> {
+        match message {
+            Message::KeepAlive(_) if !self.is_complete() => {
+                return Ok(vec![Message::KeepAlive(MessageKeepAlive)]);
+            }
+            Message::NoOp(_) => return Ok(Vec::new()),
+            _ => {}
+        }
+        match &self.role {
+            Role::Client { name, info } => {
+                self.progress
+                    .on_client_message(name, info, self.dialect, self.version, message)
+            }
+            Role::Server { options } => {
+                self.progress
+                    .on_server_message(options, self.version, message)
+            }
+        }
+    }
+
+    /// Whether the handshake is complete
+    pub fn is_complete(&self) -> bool {
+        self.progress.state == State::Complete
+    }
+
+    /// The protocol version to use with the peer, known once the hello was received
+    pub fn protocol_version(&self) -> ProtocolVersion {
+        self.progress.protocol_version
+    }
+
+    /// The hello exactly as the peer sent it
+    pub fn peer_hello(&self) -> Option<&Message> {
+        self.progress.peer_hello.as_ref()
+    }
+
+    /// The name of the client, as received by a server
+    pub fn peer_name(&self) -> Option<&str> {
+        self.progress.peer_name.as_deref()
+    }
+
+    /// The screen of the client, as received by a server with `DINF`
+    pub fn client_info(&self) -> Option<&MessageClientInfo> {
+        self.progress.client_info.as_ref()
+    }
+}
+
+impl Progress {
+    /// A message received by a client
+    fn on_client_message(
+        &mut self,
+        name: &str,
+        info: &MessageClientInfo,
+        dialect: Dialect,
+        own_version: ProtocolVersion,
+        message: Message,
+    ) -> Result<Vec<Message>> {
+        if matches!(
+            message,
+            Message::UnknownClient(_) | Message::ServerBusy(_) | Message::IncompatibleVersion(_)
+        ) {
+            return Err(HandshakeError::Rejected(Box::new(message)));
+        }
+
+        match self.state {
+            State::Hello => {
+                let (major, minor) = match &message {
+                    Message::HelloBarrier(hello) => (hello.major, hello.minor),
+                    Message::HelloSynergy(hello) => (hello.major, hello.minor),
+                    _ => return Err(unexpected("hello from server", message)),
+                };
+                self.protocol_version = ProtocolVersion::new(major, minor).min(own_version);
+                self.peer_hello = Some(message);
+                self.state = State::Info;
+                // Respond with a hello including the client name
+                Ok(vec![dialect.hello(own_version, Some(name.to_string()))])
+            }
+            State::Info => match message {
+                Message::QueryInfo(_) => {
+                    self.state = State::Finish {
+                        lsyn: false,
+                        ciak: false,
+                        crop: false,
+                        dsop: false,
+                    };
+                    Ok(vec![Message::ClientInfo(info.clone())])
+                }
+                message => Err(unexpected("QueryInfo from server", message)),
+            },
+            State::Finish {
+                mut lsyn,
+                mut ciak,
+                mut crop,
+                mut dsop,
+            } => {
+                match message {
+                    Message::LegacySynergy(_) => lsyn = true,
+                    Message::InfoAcknowledgment(_) => ciak = true,
+                    Message::ResetOptions(_) => crop = true,
+                    Message::SetOptions(_) => dsop = true,
+                    // Ignore other messages during the handshake
+                    _ => {}
+                }
+                self.state = if lsyn && ciak && crop && dsop {
+                    State::Complete
+                } else {
+                    State::Finish {
+                        lsyn,
+                        ciak,
+                        crop,
+                        dsop,
+                    }
+                };
+                Ok(Vec::new())
+            }
+            State::Complete => Ok(Vec::new()),
+        }
+    }
+
+    /// A message received by a server
+    fn on_server_message(
+        &mut self,
+        options: &[(u32, u32)],
+        own_version: ProtocolVersion,
+        message: Message,
+    ) -> Result<Vec<Message>> {
+        match self.state {
+            State::Hello => {
+                let (major, minor, name) = match &message {
+                    Message::HelloBarrier(hello) => (hello.major, hello.minor, &hello.client_name),
+                    Message::HelloSynergy(hello) => (hello.major, hello.minor, &hello.client_name),
+                    _ => return Err(unexpected("hello from client", message)),
+                };
+                let name = name.clone().ok_or(HandshakeError::MissingClientName)?;
+                let version = ProtocolVersion::new(major, minor);
+                // Reject clients we can't talk to with EICV
+                self.protocol_version = negotiate(own_version, version)
+                    .map_err(|reply| HandshakeError::IncompatibleVersion { version, reply })?;
+                self.peer_name = Some(name);
+                self.peer_hello = Some(message);
+                self.state = State::Info;
+                Ok(vec![Message::QueryInfo(MessageQueryInfo)])
+            }
+            State::Info => match message {
+                Message::ClientInfo(info) => {
+                    self.client_info = Some(info);
+                    self.state = State::Complete;
+                    Ok(vec![
+                        Message::LegacySynergy(MessageLegacySynergy { data: "en".into() }),
+                        Message::InfoAcknowledgment(MessageInfoAcknowledgment),
+                        Message::ResetOptions(MessageResetOptions),
+                        // The configured options, may be empty
+                        Message::SetOptions(MessageSetOptions {
+                            options: options.to_vec(),
+                        }),
+                    ])
+                }
+                // Ignore other messages during the handshake
+                _ => Ok(Vec::new()),
+            },
+            State::Finish { .. } | State::Complete => Ok(Vec::new()),
+        }
+    }
+}
+
+fn unexpected(expected: &'static str, got: Message) -> HandshakeError {
+    HandshakeError::UnexpectedMessage {
+        expected,
+        got: Box::new(got),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{
+        DsopOption, MessageHelloBarrier, MessageHelloSynergy, MessageServerBusy,
+        MessageUnknownClient,
+    };
+
+    fn info() -> MessageClientInfo {
+        MessageClientInfo {
+            x: 0,
+            y: 0,
+            width: 2560,
+            height: 1440,
+            current_mouse_x: 10,
+            current_mouse_y: 20,
+            size: 0,
+        }
+    }
+
+    #[test]
+    fn test_handshake_full_sequence() {
+        let options = vec![(DsopOption::Heartbeat as u32, 1000)];
+        let mut server = Handshake::server(options.clone());
+        let mut client = Handshake::client("laptop", info());
+
+        // Server hello
+        let hello = server.start();
+        assert!(matches!(
+            hello.as_slice(),
+            [Message::HelloBarrier(MessageHelloBarrier {
+                client_name: None,
+                ..
+            })]
+        ));
+        assert!(client.start().is_empty());
+
+        // Client hello with its name
+        let replies = client.on_message(hello[0].clone()).unwrap();
+        assert_eq!(
+            replies,
+            vec![Message::HelloBarrier(MessageHelloBarrier {
+                major: 1,
+                minor: 8,
+                client_name: Some("laptop".to_string()),
+            })]
+        );
+        assert_eq!(client.peer_hello(), Some(&hello[0]));
+
+        // QINF
+        let replies = server.on_message(replies[0].clone()).unwrap();
+        assert_eq!(replies, vec![Message::QueryInfo(MessageQueryInfo)]);
+        assert_eq!(server.peer_name(), Some("laptop"));
+        assert!(!server.is_complete());
+
+        // DINF
+        let replies = client.on_message(replies[0].clone()).unwrap();
+        assert_eq!(replies, vec![Message::ClientInfo(info())]);
+
+        // LSYN, CIAK, CROP and DSOP complete the handshake
+        let replies = server.on_message(replies[0].clone()).unwrap();
+        assert!(server.is_complete());
+        assert_eq!(server.client_info(), Some(&info()));
+        assert_eq!(replies.len(), 4);
+        assert_eq!(
+            replies[3],
+            Message::SetOptions(MessageSetOptions { options })
+        );
+
+        for (i, message) in replies.into_iter().enumerate() {
+            assert!(!client.is_complete(), "Complete after {} messages", i);
+            assert!(client.on_message(message).unwrap().is_empty());
+        }
+        assert!(client.is_complete());
+        assert_eq!(client.protocol_version(), ProtocolVersion::CURRENT);
+        assert_eq!(server.protocol_version(), ProtocolVersion::CURRENT);
+    }
+
+    #[test]
+    fn test_handshake_keepalive_and_older_version() {
+        let mut server = Handshake::server(vec![]);
+        server.start();
+        assert_eq!(
+            server
+                .on_message(Message::KeepAlive(MessageKeepAlive))
+                .unwrap(),
+            vec![Message::KeepAlive(MessageKeepAlive)]
+        );
+        server
+            .on_message(Message::HelloSynergy(MessageHelloSynergy {
+                major: 1,
+                minor: 6,
+                client_name: Some("old".to_string()),
+            }))
+            .unwrap();
+        assert_eq!(server.protocol_version(), ProtocolVersion::new(1, 6));
+
+        let mut client = Handshake::client("laptop", info());
+        client
+            .on_message(Message::HelloSynergy(MessageHelloSynergy {
+                major: 1,
+                minor: 6,
+                client_name: None,
+            }))
+            .unwrap();
+        assert_eq!(client.protocol_version(), ProtocolVersion::new(1, 6));
+    }
+
+    #[test]
+    fn test_handshake_with_version() {
+        let v1_6 = ProtocolVersion::new(1, 6);
+        let mut server = Handshake::server(vec![]).with_version(v1_6);
+        let hello = server.start();
+        assert!(matches!(
+            hello.as_slice(),
+            [Message::HelloBarrier(MessageHelloBarrier {
+                major: 1,
+                minor: 6,
+                ..
+            })]
+        ));
+
+        // The client advertises 1.6 and negotiates down to it from 1.8
+        let mut client = Handshake::client("laptop", info()).with_version(v1_6);
+        let replies = client
+            .on_message(Dialect::Barrier.hello(ProtocolVersion::CURRENT, None))
+            .unwrap();
+        assert!(matches!(
+            replies.as_slice(),
+            [Message::HelloBarrier(MessageHelloBarrier {
+                major: 1,
+                minor: 6,
+                ..
+            })]
+        ));
+        assert_eq!(client.protocol_version(), v1_6);
+
+        server.on_message(replies[0].clone()).unwrap();
+        assert_eq!(server.protocol_version(), v1_6);
+    }
+
+    #[test]
+    fn test_handshake_errors() {
+        let mut server = Handshake::server(vec![]);
+        assert_eq!(
+            server.on_message(Message::HelloBarrier(MessageHelloBarrier {
+                major: 1,
+                minor: 8,
+                client_name: None,
+            })),
+            Err(HandshakeError::MissingClientName)
+        );
+
+        let mut server = Handshake::server(vec![]);
+        match server.on_message(Message::HelloBarrier(MessageHelloBarrier {
+            major: 2,
+            minor: 0,
+            client_name: Some("future".to_string()),
+        })) {
+            Err(HandshakeError::IncompatibleVersion { version, reply }) => {
+                assert_eq!(version, ProtocolVersion::new(2, 0));
+                assert_eq!(reply.major_remote, 1);
+            }
+            other => panic!("Unexpected result {:?}", other),
+        }
+
+        let mut server = Handshake::server(vec![]);
+        assert!(matches!(
+            server.on_message(Message::QueryInfo(MessageQueryInfo)),
+            Err(HandshakeError::UnexpectedMessage { .. })
+        ));
+
+        let mut client = Handshake::client("laptop", info());
+        assert!(matches!(
+            client.on_message(Message::UnknownClient(MessageUnknownClient)),
+            Err(HandshakeError::Rejected(_))
+        ));
+        let mut client = Handshake::client("laptop", info());
+        assert!(matches!(
+            client.on_message(Message::ServerBusy(MessageServerBusy)),
+            Err(HandshakeError::Rejected(_))
+        ));
+        let mut client = Handshake::client("laptop", info());
+        assert!(matches!(
+            client.on_message(Message::ResetOptions(MessageResetOptions)),
+            Err(HandshakeError::UnexpectedMessage { .. })
+        ));
+    }
+}
@@ -0,0 +1,123 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! # Key Identifiers
+//!
+//! The `keyid` of key messages such as [`MessageKeyDown`](super::MessageKeyDown) is
+//! a [`KeyId`]. Key ids follow the X11 keysym values as Synergy uses them: printable
+//! characters are their Unicode code point, the X11 function keys in the `0xFF00`
+//! range are moved to `0xEF00` so every key fits into 16 bits.
+
+/// A key identifier as sent in the `keyid` of key messages
+///
+/// # Example
+///
+/// ```
+/// use schengen::protocol::keys::KeyId;
+///
+/// assert_eq!(KeyId::from_char('a'), Some(KeyId(0x61)));
+/// assert_eq!(KeyId::from_char('\n'), Some(KeyId::RETURN));
+/// assert_eq!(u16::from(KeyId::ESCAPE), 0xEF1B);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct KeyId(pub u16);
+
+impl KeyId {
+    pub const BACKSPACE: Self = Self(0xEF08);
+    pub const TAB: Self = Self(0xEF09);
+    pub const RETURN: Self = Self(0xEF0D);
+    pub const PAUSE: Self = Self(0xEF13);
+    pub const SCROLL_LOCK: Self = Self(0xEF14);
+    pub const ESCAPE: Self = Self(0xEF1B);
+    pub const DELETE: Self = Self(0xEFFF);
+    pub const SPACE: Self = Self(0x0020);
+
+    pub const HOME: Self = Self(0xEF50);
+    pub const LEFT: Self = Self(0xEF51);
+    pub const UP: Self = Self(0xEF52);
+    pub const RIGHT: Self = Self(0xEF53);
+    pub const DOWN: Self = Self(0xEF54);
+    pub const PAGE_UP: Self = Self(0xEF55);
+    pub const PAGE_DOWN: Self = Self(0xEF56);
+    pub const END: Self = Self(0xEF57);
+    pub const INSERT: Self = Self(0xEF63);
+
+    pub const F1: Self = Self(0xEFBE);
+    pub const F2: Self = Self(0xEFBF);
+    pub const F3: Self = Self(0xEFC0);
+    pub const F4: Self = Self(0xEFC1);
+    pub const F5: Self = Self(0xEFC2);
+    pub const F6: Self = Self(0xEFC3);
+    pub const F7: Self = Self(0xEFC4);
+    pub const F8: Self = Self(0xEFC5);
+    pub const F9: Self = Self(0xEFC6);
+    pub const F10: Self = Self(0xEFC7);
+    pub const F11: Self = Self(0xEFC8);
+    pub const F12: Self = Self(0xEFC9);
+
+    pub const SHIFT_L: Self = Self(0xEFE1);
+    pub const SHIFT_R: Self = Self(0xEFE2);
+    pub const CONTROL_L: Self = Self(0xEFE3);
+    pub const CONTROL_R: Self = Self(0xEFE4);
+    pub const CAPS_LOCK: Self = Self(0xEFE5);
+    pub const ALT_L: Self = Self(0xEFE9);
+    pub const ALT_R: Self = Self(0xEFEA);
+    pub const SUPER_L: Self = Self(0xEFEB);
+    pub const SUPER_R: Self = Self(0xEFEC);
+
+    /// The key that types `c`
+    ///
+    /// Letters, digits and other characters map to their code point, `\n` and `\t`
+    /// to [`RETURN`](Self::RETURN) and [`TAB`](Self::TAB). Returns `None` for
+    /// characters outside the Basic Multilingual Plane, which don't fit into a key id.
+    pub fn from_char(c: char) -> Option<Self> {
+        match c {
+            '\n' => Some(Self::RETURN),
+            '\t' => Some(Self::TAB),
+            c => u16::try_from(u32::from(c)).ok().map(Self),
+        }
+    }
+}
+
+impl From<u16> for KeyId {
+    fn from(keyid: u16) -> Self {
+        Self(keyid)
+    }
+}
+
+impl From<KeyId> for u16 {
+    fn from(key: KeyId) -> Self {
+        key.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{Message, MessageKeyDown, ProtocolMessage, parse_message};
+
+    #[test]
+    fn test_from_char() {
+        assert_eq!(KeyId::from_char('A'), Some(KeyId(0x41)));
+        assert_eq!(KeyId::from_char('z'), Some(KeyId(0x7A)));
+        assert_eq!(KeyId::from_char('0'), Some(KeyId(0x30)));
+        assert_eq!(KeyId::from_char(' '), Some(KeyId::SPACE));
+        assert_eq!(KeyId::from_char('ß'), Some(KeyId(0xDF)));
+        assert_eq!(KeyId::from_char('\t'), Some(KeyId::TAB));
+        assert_eq!(KeyId::from_char('🦀'), None);
+    }
+
+    #[test]
+    fn test_key_down_return() {
+        let msg = MessageKeyDown {
+            keyid: KeyId::RETURN.into(),
+            mask: 0,
+            button: 36,
+        };
+        let bytes = msg.to_bytes();
+        assert_eq!(bytes, b"DKDN\xEF\x0D\x00\x00\x00\x24");
+        let Ok(Message::KeyDown(parsed)) = parse_message(&bytes) else {
+            panic!("Expected DKDN");
+        };
+        assert_eq!(KeyId::from(parsed.keyid), KeyId::RETURN);
+    }
+}
@@ -0,0 +1,272 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Parsing that borrows string and byte payloads from the input buffer
+
+use super::{
+    Message, MessageClipboardData, MessageDragInfo, MessageFileTransfer,
+    MessageKeyDownWithLanguage, MessageKeyRepeat, MessageLegacySynergy, MessageSecureEncryption,
+    ProtocolError, ProtocolMessage, Result, parse_message, read_u8, read_u16, read_u32,
+};
+
+/// A message parsed by [`parse_message_ref`]
+///
+/// Messages with a string or byte payload borrow it from the input buffer. All other
+/// messages are parsed into an owned [`Message`], which for the fixed-size input
+/// messages such as `DMMV` does not allocate either.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum MessageRef<'a> {
+    /// Key press with language, see [`MessageKeyDownWithLanguage`]
+    KeyDownWithLanguage {
+        keyid: u16,
+        mask: u16,
+        button: u16,
+        lang: &'a str,
+    },
+    /// Key auto-repeat, see [`MessageKeyRepeat`]
+    KeyRepeat {
+        keyid: u16,
+        mask: u16,
+        button: u16,
+        count: u16,
+        lang: &'a str,
+    },
+    /// Clipboard data, see [`MessageClipboardData`]
+    ClipboardData {
+        id: u8,
+        sequence: u32,
+        mark: u8,
+        data: &'a [u8],
+    },
+    /// File transfer, see [`MessageFileTransfer`]
+    FileTransfer { mark: u8, data: &'a [u8] },
+    /// Drag info, see [`MessageDragInfo`]
+    DragInfo { size: u16, data: &'a str },
+    /// Secure input notification, see [`MessageSecureEncryption`]
+    SecureEncryption { data: &'a str },
+    /// Language list, see [`MessageLegacySynergy`]
+    LegacySynergy { data: &'a str },
+    /// Any other message
+    Other(Message),
+}
+
+impl MessageRef<'_> {
+    /// Convert into an owned [`Message`], copying the payload
+    pub fn to_owned(&self) -> Message {
+        match *self {
+            MessageRef::KeyDownWithLanguage {
+                keyid,
+                mask,
+                button,
+                lang,
+            } => Message::KeyDownWithLanguage(MessageKeyDownWithLanguage {
+                keyid,
+                mask,
+                button,
+                lang: lang.into(),
+            }),
+            MessageRef::KeyRepeat {
+                keyid,
+                mask,
+                button,
+                count,
+                lang,
+            } => Message::KeyRepeat(MessageKeyRepeat {
+                keyid,
+                mask,
+                button,
+                count,
+                lang: lang.into(),
+            }),
+            MessageRef::ClipboardData {
+                id,
+                sequence,
+                mark,
+                data,
+            } => Message::ClipboardData(MessageClipboardData {
+                id,
+                sequence,
+                mark,
+                data: data.into(),
+            }),
+            MessageRef::FileTransfer { mark, data } => Message::FileTransfer(MessageFileTransfer {
+                mark,
+                data: data.into(),
+            }),
+            MessageRef::DragInfo { size, data } => Message::DragInfo(MessageDragInfo {
+                size,
+                data: data.into(),
+            }),
+            MessageRef::SecureEncryption { data } => {
+                Message::SecureEncryption(MessageSecureEncryption { data: data.into() })
+            }
+            MessageRef::LegacySynergy { data } => {
+                Message::LegacySynergy(MessageLegacySynergy { data: data.into() })
+            }
+            MessageRef::Other(ref message) => message.clone(),
+        }
+    }
+}
+
+impl From<MessageRef<'_>> for Message {
+    fn from(message: MessageRef<'_>) -> Self {
+        match message {
+            MessageRef::Other(message) => message,
+            borrowed => borrowed.to_owned(),
+        }
+    }
+}
+
+/// Borrow a length-prefixed payload at `offset` from `data`
+fn payload(data: &[u8], offset: usize) -> Result<&[u8]> {
+    let length = read_u32(data, offset)? as usize;
+    let start = offset + 4;
+    if data.len() - start < length {
+        return Err(ProtocolError::InsufficientData {
+            offset: start,
+            expected: start.saturating_add(length),
+            actual: data.len(),
+        });
+    }
+    Ok(&data[start..start + length])
+}
+
+/// Borrow a length-prefixed UTF-8 string at `offset` from `data`
+fn string(data: &[u8], offset: usize) -> Result<&str> {
+    std::str::from_utf8(payload(data, offset)?).map_err(|_| ProtocolError::InvalidUtf8)
+}
+
+/// Parse a message from bytes (without the length prefix), borrowing its payload
+///
+/// Behaves like [`parse_message`] but string and byte payloads are returned as
+/// slices of `data` instead of being copied. Use [`MessageRef::to_owned`] to keep a
+/// message beyond the lifetime of the buffer.
+///
+/// # Example
+///
+/// ```
+/// use schengen::protocol::{parse_message_ref, Message, MessageRef, MessageSecureEncryption};
+///
+/// # fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let msg = Message::SecureEncryption(MessageSecureEncryption {
+///     data: "Terminal".into(),
+/// });
+/// let bytes = msg.to_bytes();
+/// let parsed = parse_message_ref(&bytes[4..])?;
+/// assert_eq!(parsed, MessageRef::SecureEncryption { data: "Terminal" });
+/// assert_eq!(parsed.to_owned(), msg);
+/// # Ok(())
+/// # }
+/// # example().unwrap();
+/// ```
+pub fn parse_message_ref(data: &[u8]) -> Result<MessageRef<'_>> {
+    let code_len = MessageKeyDownWithLanguage::CODE.len();
+    let msg = match data.get(0..4) {
+        Some(b"DKDL") => MessageRef::KeyDownWithLanguage {
+            keyid: read_u16(data, code_len)?,
+            mask: read_u16(data, code_len + 2)?,
+            button: read_u16(data, code_len + 4)?,
+            lang: string(data, code_len + 6)?,
+        },
+        Some(b"DKRP") => MessageRef::KeyRepeat {
+            keyid: read_u16(data, code_len)?,
+            mask: read_u16(data, code_len + 2)?,
+            button: read_u16(data, code_len + 4)?,
+            count: read_u16(data, code_len + 6)?,
+            lang: string(data, code_len + 8)?,
+        },
+        Some(b"DCLP") => MessageRef::ClipboardData {
+            id: read_u8(data, code_len)?,
+            sequence: read_u32(data, code_len + 1)?,
+            mark: read_u8(data, code_len + 5)?,
+            data: payload(data, code_len + 6)?,
+        },
+        Some(b"DFTR") => MessageRef::FileTransfer {
+            mark: read_u8(data, code_len)?,
+            data: payload(data, code_len + 1)?,
+        },
+        Some(b"DDRG") => MessageRef::DragInfo {
+            size: read_u16(data, code_len)?,
+            data: string(data, code_len + 2)?,
+        },
+        Some(b"SECN") => MessageRef::SecureEncryption {
+            data: string(data, code_len)?,
+        },
+        Some(b"LSYN") => MessageRef::LegacySynergy {
+            data: string(data, code_len)?,
+        },
+        _ => MessageRef::Other(parse_message(data)?),
+    };
+    Ok(msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::MessageMouseMove;
+
+    #[test]
+    fn test_parse_message_ref_matches_parse_message() {
+        let messages = [
+            Message::KeyDownWithLanguage(MessageKeyDownWithLanguage {
+                keyid: 0xe9,
+                mask: 0,
+                button: 0x1a,
+                lang: "fr".into(),
+            }),
+            Message::KeyRepeat(MessageKeyRepeat {
+                keyid: 0x61,
+                mask: 0,
+                button: 0x26,
+                count: 2,
+                lang: "en".into(),
+            }),
+            Message::ClipboardData(MessageClipboardData {
+                id: 0,
+                sequence: 1,
+                mark: 2,
+                data: vec![0xff, 0, 1].into(),
+            }),
+            Message::FileTransfer(MessageFileTransfer {
+                mark: 2,
+                data: vec![0x89, b'P', b'N', b'G'].into(),
+            }),
+            Message::DragInfo(MessageDragInfo::from_paths(&["/tmp/a".to_string()]).unwrap()),
+            Message::SecureEncryption(MessageSecureEncryption {
+                data: "Terminal".into(),
+            }),
+            Message::LegacySynergy(MessageLegacySynergy::from_languages(&["en", "de"])),
+            Message::MouseMove(MessageMouseMove { x: 100, y: 200 }),
+        ];
+        for msg in messages {
+            let bytes = msg.to_bytes();
+            let parsed = parse_message_ref(&bytes[4..]).unwrap();
+            assert_eq!(parsed.to_owned(), msg);
+            assert_eq!(Message::from(parsed), parse_message(&bytes[4..]).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_parse_message_ref_borrows_payload() {
+        let msg = Message::SecureEncryption(MessageSecureEncryption {
+            data: "Terminal".into(),
+        });
+        let bytes = msg.to_bytes();
+        let MessageRef::SecureEncryption { data } = parse_message_ref(&bytes[4..]).unwrap() else {
+            panic!("Expected SecureEncryption");
+        };
+        assert_eq!(data.as_ptr(), bytes[12..].as_ptr());
+    }
+
+    #[test]
+    fn test_parse_message_ref_errors() {
+        // Truncated payload and invalid UTF-8 fail like the owned parse
+        let truncated = b"SECN\x00\x00\x00\x05abc";
+        assert_eq!(
+            parse_message_ref(truncated),
+            Err(parse_message(truncated).unwrap_err())
+        );
+        let invalid = b"LSYN\x00\x00\x00\x02\xff\xfe";
+        assert_eq!(parse_message_ref(invalid), Err(ProtocolError::InvalidUtf8));
+        assert!(parse_message_ref(b"CA").is_err());
+    }
+}
@@ -0,0 +1,270 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! # Server options
+//!
+//! The options a server sets on a client with `DSOP`, see [`ServerOptions`].
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use super::{DsopOption, MessageSetOptions};
+
+/// The keepalive interval if the server doesn't set one with `HART`, as in Deskflow
+const DEFAULT_HEARTBEAT: Duration = Duration::from_secs(3);
+
+/// The options set by a server
+///
+/// A snapshot of all `DSOP` options received since the last `CROP`, with later
+/// values replacing earlier ones. Options that were never set have their upstream
+/// default.
+///
+/// # Example
+///
+/// ```
+/// use schengen::protocol::{DsopOption, MessageSetOptions, ServerOptions};
+/// use std::time::Duration;
+///
+/// let msg = MessageSetOptions::from_typed(&[
+///     (DsopOption::Heartbeat, 5000),
+///     (DsopOption::ClipboardSharingSize, 1024),
+/// ]);
+/// let options = ServerOptions::from_message(&msg);
+/// assert_eq!(options.heartbeat(), Some(Duration::from_secs(5)));
+/// assert_eq!(options.clipboard_size(), Some(1024 * 1024));
+/// assert!(!options.relative_mouse_moves());
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ServerOptions {
+    options: BTreeMap<u32, u32>,
+}
+
+impl ServerOptions {
+    /// The options set by a single `DSOP`
+    pub fn from_message(msg: &MessageSetOptions) -> Self {
+        let mut options = Self::default();
+        options.apply(msg);
+        options
+    }
+
+    /// Record the options of a `DSOP`, replacing earlier values of the same options
+    pub fn apply(&mut self, msg: &MessageSetOptions) {
+        self.options.extend(msg.options.iter().copied());
+    }
+
+    /// Forget all options, as for a `CROP`
+    ///
+    /// All options have their upstream default afterwards.
+    pub fn reset(&mut self) {
+        self.options.clear();
+    }
+
+    /// The `DSOP` that changes these options into `other`
+    ///
+    /// Contains the options of `other` that are not set to the same value here. A
+    /// `DSOP` can't unset an option, so options only set here are left out. To drop
+    /// them, send `CROP` and the full options of `other` instead.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use schengen::protocol::{DsopOption, MessageSetOptions, ServerOptions};
+    ///
+    /// let old = ServerOptions::from_message(&MessageSetOptions::from_typed(&[
+    ///     (DsopOption::Heartbeat, 5000),
+    ///     (DsopOption::ClipboardSharing, 1),
+    /// ]));
+    /// let new = ServerOptions::from_message(&MessageSetOptions::from_typed(&[
+    ///     (DsopOption::Heartbeat, 5000),
+    ///     (DsopOption::ClipboardSharing, 0),
+    /// ]));
+    /// let update = old.diff(&new);
+    /// assert_eq!(update.get(DsopOption::ClipboardSharing), Some(0));
+    /// assert_eq!(update.get(DsopOption::Heartbeat), None);
+    /// ```
+    pub fn diff(&self, other: &ServerOptions) -> MessageSetOptions {
+        MessageSetOptions {
+            options: other
+                .options
+                .iter()
+                .filter(|&(key, value)| self.options.get(key) != Some(value))
+                .map(|(&key, &value)| (key, value))
+                .collect(),
+        }
+    }
+
+    /// The raw value of an option, if the server set it
+    pub fn get(&self, option: DsopOption) -> Option<u32> {
+        self.options.get(&(option as u32)).copied()
+    }
+
+    /// The keepalive interval set with `HART`, `None` if keepalives are disabled
+    ///
+    /// `HART` is the interval in milliseconds, 0 disables keepalives. Without `HART`
+    /// the interval is 3 seconds.
+    pub fn heartbeat(&self) -> Option<Duration> {
+        match self.get(DsopOption::Heartbeat) {
+            None => Some(DEFAULT_HEARTBEAT),
+            Some(0) => None,
+            Some(ms) => Some(Duration::from_millis(ms.into())),
+        }
+    }
+
+    /// Whether clipboards are shared
+    ///
+    /// `CLPS` is 1 if clipboards are shared and 0 if not. Without `CLPS` clipboards are
+    /// shared.
+    pub fn clipboard_sharing(&self) -> bool {
+        self.get(DsopOption::ClipboardSharing) != Some(0)
+    }
+
+    /// The maximum clipboard size in bytes set with `CLSZ`, if any
+    ///
+    /// `CLSZ` is the size in KiB.
+    pub fn clipboard_size(&self) -> Option<usize> {
+        self.get(DsopOption::ClipboardSharingSize)
+            .map(|kib| kib as usize * 1024)
+    }
+
+    /// Whether the server sends relative mouse moves
+    ///
+    /// `MDLT` is 1 if mouse moves are sent as `DMRM` and 0 if they are sent as `DMMV`,
+    /// the default.
+    pub fn relative_mouse_moves(&self) -> bool {
+        self.flag(DsopOption::RelativeMouseMoves)
+    }
+
+    /// Whether the client's screensaver follows the server's
+    ///
+    /// `SSVR` is 1 if the client should start and stop its screensaver when the server
+    /// sends `CSEC` and 0 if it should ignore `CSEC`. Without `SSVR` the screensaver is
+    /// synchronized.
+    pub fn screensaver_sync(&self) -> bool {
+        self.get(DsopOption::ScreenSaverSync) != Some(0)
+    }
+
+    /// Whether Shift must be held to switch screens
+    ///
+    /// `SSNS` is nonzero if Shift is required, the default is not to require it.
+    pub fn switch_needs_shift(&self) -> bool {
+        self.flag(DsopOption::ScreenSwitchNeedsShift)
+    }
+
+    /// Whether Control must be held to switch screens, encoded like
+    /// [`switch_needs_shift`](Self::switch_needs_shift) with `SSNC`
+    pub fn switch_needs_control(&self) -> bool {
+        self.flag(DsopOption::ScreenSwitchNeedsControl)
+    }
+
+    /// Whether Alt must be held to switch screens, encoded like
+    /// [`switch_needs_shift`](Self::switch_needs_shift) with `SSNA`
+    pub fn switch_needs_alt(&self) -> bool {
+        self.flag(DsopOption::ScreenSwitchNeedsAlt)
+    }
+
+    /// Whether an option that is off by default is set to a nonzero value
+    fn flag(&self, option: DsopOption) -> bool {
+        self.get(option).is_some_and(|value| value != 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{Message, parse_message};
+
+    #[test]
+    fn test_defaults() {
+        let options = ServerOptions::default();
+        assert_eq!(options.heartbeat(), Some(DEFAULT_HEARTBEAT));
+        assert!(options.clipboard_sharing());
+        assert_eq!(options.clipboard_size(), None);
+        assert!(!options.relative_mouse_moves());
+        assert!(options.screensaver_sync());
+        assert!(!options.switch_needs_shift());
+        assert!(!options.switch_needs_control());
+        assert!(!options.switch_needs_alt());
+    }
+
+    #[test]
+    fn test_parse_options() {
+        // DSOP as sent by a server with a 5s heartbeat, clipboard sharing off,
+        // relative moves and Shift required to switch screens
+        let mut data = b"DSOP".to_vec();
+        data.extend_from_slice(&13u32.to_be_bytes());
+        for (key, value) in [
+            (b"HART", 5000u32),
+            (b"CLPS", 0),
+            (b"CLSZ", 3072),
+            (b"MDLT", 1),
+            (b"SSNS", 1),
+            (b"SSVR", 0),
+        ] {
+            data.extend_from_slice(key);
+            data.extend_from_slice(&value.to_be_bytes());
+        }
+        let Ok(Message::SetOptions(msg)) = parse_message(&data) else {
+            panic!("Expected DSOP");
+        };
+
+        let options = ServerOptions::from_message(&msg);
+        assert_eq!(options.heartbeat(), Some(Duration::from_secs(5)));
+        assert!(!options.clipboard_sharing());
+        assert_eq!(options.clipboard_size(), Some(3 * 1024 * 1024));
+        assert!(options.relative_mouse_moves());
+        assert!(options.switch_needs_shift());
+        assert!(!options.switch_needs_control());
+        assert!(!options.screensaver_sync());
+
+        // Zero turns the flags off again and disables keepalives
+        let mut options = options;
+        options.apply(&MessageSetOptions::from_typed(&[
+            (DsopOption::Heartbeat, 0),
+            (DsopOption::RelativeMouseMoves, 0),
+            (DsopOption::ScreenSwitchNeedsShift, 0),
+        ]));
+        assert_eq!(options.heartbeat(), None);
+        assert!(!options.relative_mouse_moves());
+        assert!(!options.switch_needs_shift());
+    }
+
+    #[test]
+    fn test_apply_reset_diff() {
+        let first = MessageSetOptions::from_typed(&[
+            (DsopOption::Heartbeat, 5000),
+            (DsopOption::ClipboardSharing, 1),
+            (DsopOption::ScreenSwitchNeedsShift, 1),
+        ]);
+        let second = MessageSetOptions::from_typed(&[
+            (DsopOption::ClipboardSharing, 0),
+            (DsopOption::RelativeMouseMoves, 1),
+        ]);
+        let mut options = ServerOptions::from_message(&first);
+        let before = options.clone();
+        options.apply(&second);
+        assert_eq!(options.heartbeat(), Some(Duration::from_secs(5)));
+        assert!(!options.clipboard_sharing());
+        assert!(options.relative_mouse_moves());
+        assert!(options.switch_needs_shift());
+
+        // Only the changed options are sent, and applying them gets the same result
+        let update = before.diff(&options);
+        assert_eq!(
+            update.options,
+            vec![
+                (DsopOption::ClipboardSharing as u32, 0),
+                (DsopOption::RelativeMouseMoves as u32, 1),
+            ]
+        );
+        let mut updated = before.clone();
+        updated.apply(&update);
+        assert_eq!(updated, options);
+        assert!(options.diff(&options).options.is_empty());
+
+        // Options only set on the left can't be unset with a DSOP
+        assert!(options.diff(&ServerOptions::default()).options.is_empty());
+
+        options.reset();
+        assert_eq!(options, ServerOptions::default());
+        assert_eq!(options.heartbeat(), Some(DEFAULT_HEARTBEAT));
+    }
+}
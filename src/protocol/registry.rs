@@ -0,0 +1,145 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! # Custom Message Codes
+//!
+//! A [`MessageRegistry`] maps 4-byte codes that this crate does not know about to
+//! user-provided parse functions, useful to experiment with new messages without
+//! changing the crate. See [`parse_message_with_registry`].
+
+use std::collections::HashMap;
+use std::fmt;
+
+use super::{Message, ProtocolError, Result, parse_message};
+
+type ParseFn = Box<dyn Fn(&[u8]) -> Result<Message> + Send + Sync>;
+
+/// A set of parse functions for custom message codes
+///
+/// # Example
+///
+/// ```
+/// use schengen::protocol::{Message, MessageMouseMove, MessageRegistry, parse_message_with_registry};
+///
+/// # fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let mut registry = MessageRegistry::new();
+/// registry.register(b"XPOS", |_data| Ok(Message::MouseMove(MessageMouseMove { x: 0, y: 0 })));
+///
+/// let msg = parse_message_with_registry(b"XPOS", &registry)?;
+/// assert_eq!(msg, Message::MouseMove(MessageMouseMove { x: 0, y: 0 }));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Default)]
+pub struct MessageRegistry {
+    handlers: HashMap<[u8; 4], ParseFn>,
+}
+
+impl MessageRegistry {
+    /// Create an empty registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Install `parse_fn` as the handler for `code`, replacing any previous handler
+    ///
+    /// `parse_fn` receives the message without the length prefix, starting with the
+    /// code. Handlers for codes built into this crate are never called.
+    pub fn register<F>(&mut self, code: &[u8; 4], parse_fn: F)
+    where
+        F: Fn(&[u8]) -> Result<Message> + Send + Sync + 'static,
+    {
+        self.handlers.insert(*code, Box::new(parse_fn));
+    }
+
+    /// Returns true if a handler is registered for `code`
+    pub fn contains(&self, code: &[u8; 4]) -> bool {
+        self.handlers.contains_key(code)
+    }
+}
+
+impl fmt::Debug for MessageRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let codes: Vec<String> = self
+            .handlers
+            .keys()
+            .map(|code| String::from_utf8_lossy(code).into_owned())
+            .collect();
+        f.debug_struct("MessageRegistry")
+            .field("codes", &codes)
+            .finish()
+    }
+}
+
+/// Parse a protocol message from bytes (without the length prefix), falling back to
+/// `registry` for unknown codes
+///
+/// The built-in codes are tried first, see [`parse_message`]. A code that is not
+/// built in is passed to its handler in `registry`, if there is none the message is
+/// returned as [`Message::Unknown`].
+pub fn parse_message_with_registry(data: &[u8], registry: &MessageRegistry) -> Result<Message> {
+    match parse_message(data) {
+        Err(ProtocolError::UnknownMessageCode(_)) | Err(ProtocolError::InvalidMessageCode) => {
+            let code: [u8; 4] = [data[0], data[1], data[2], data[3]];
+            match registry.handlers.get(&code) {
+                Some(parse_fn) => parse_fn(data),
+                None => Ok(Message::Unknown {
+                    code,
+                    payload: data[4..].to_vec(),
+                }),
+            }
+        }
+        result => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{MessageKeepAlive, MessageMouseMove, read_i16};
+
+    fn test_registry() -> MessageRegistry {
+        let mut registry = MessageRegistry::new();
+        registry.register(b"TEST", |data| {
+            Ok(Message::MouseMove(MessageMouseMove {
+                x: read_i16(data, 4)?,
+                y: read_i16(data, 6)?,
+            }))
+        });
+        registry
+    }
+
+    #[test]
+    fn test_registry_custom_code() {
+        let registry = test_registry();
+        assert!(registry.contains(b"TEST"));
+
+        let msg = parse_message_with_registry(b"TEST\x00\x0a\x00\x14", &registry).unwrap();
+        assert_eq!(msg, Message::MouseMove(MessageMouseMove { x: 10, y: 20 }));
+
+        // Errors from the handler are returned as-is
+        assert!(matches!(
+            parse_message_with_registry(b"TEST\x00", &registry),
+            Err(ProtocolError::InsufficientData { .. })
+        ));
+    }
+
+    #[test]
+    fn test_registry_builtin_and_unknown() {
+        let registry = test_registry();
+
+        let msg = parse_message_with_registry(b"CALV", &registry).unwrap();
+        assert_eq!(msg, Message::KeepAlive(MessageKeepAlive));
+
+        let msg = parse_message_with_registry(b"ZZZZ\x01\x02", &registry).unwrap();
+        assert_eq!(
+            msg,
+            Message::Unknown {
+                code: *b"ZZZZ",
+                payload: vec![1, 2],
+            }
+        );
+        assert_eq!(&msg.raw_code(), b"ZZZZ");
+        assert_eq!(msg.to_string(), "ZZZZ len=2");
+        assert_eq!(msg.to_bytes(), b"\x00\x00\x00\x06ZZZZ\x01\x02");
+    }
+}
@@ -63,7 +63,7 @@
 //!     // Main event loop - processes all events from the server
 //!     loop {
 //!         match server.recv_event().await {
-//!             Ok(ServerEvent::ClientConnected { client_id, name, width, height }) => {
+//!             Ok(ServerEvent::ClientConnected { client_id, name, width, height, .. }) => {
 //!                 println!("✓ Client '{}' ({:?}) connected ({}x{})", name, client_id, width, height);
 //!
 //!                 // Get list of all connected clients
@@ -75,8 +75,8 @@
 //!                 println!();
 //!             }
 //!
-//!             Ok(ServerEvent::ClientDisconnected { client_id, name }) => {
-//!                 println!("✗ Client '{}' ({:?}) disconnected\n", name, client_id);
+//!             Ok(ServerEvent::ClientDisconnected { client_id, name, reason }) => {
+//!                 println!("✗ Client '{}' ({:?}) disconnected: {:?}\n", name, client_id, reason);
 //!             }
 //!
 //!             Ok(ServerEvent::ClipboardData { client_id, data, .. }) => {
@@ -84,15 +84,48 @@
 //!                          client_id, data.len());
 //!             }
 //!
+//!             Ok(ServerEvent::ClipboardReceived { name, contents, .. }) => {
+//!                 println!("📋 Clipboard from '{}' with {} format(s)",
+//!                          name, contents.formats.len());
+//!             }
+//!
+//!             Ok(ServerEvent::ClipboardChanged { name, id, .. }) => {
+//!                 println!("📋 '{}' took over the {:?} clipboard", name, id);
+//!             }
+//!
+//!             Ok(ServerEvent::ClipboardTransferAborted { name, error, .. }) => {
+//!                 println!("📋 Clipboard from '{}' was dropped: {}", name, error);
+//!             }
+//!
+//!             Ok(ServerEvent::FileReceived { name, path, bytes, .. }) => {
+//!                 println!("📁 '{}' dropped {:?}: {} bytes", name, path, bytes.len());
+//!             }
+//!
+//!             Ok(ServerEvent::FileTransferAborted { name, error, .. }) => {
+//!                 println!("📁 File transfer from '{}' failed: {}", name, error);
+//!             }
+//!
+//!             Ok(ServerEvent::SecureInput { name, app_name, .. }) => {
+//!                 println!("🔒 '{}' is in secure input mode for {}", name, app_name);
+//!             }
+//!
 //!             Ok(ServerEvent::ScreenSaverChanged { client_id, active }) => {
 //!                 let state = if active { "activated" } else { "deactivated" };
 //!                 println!("💤 Screen saver {} on {:?}", state, client_id);
 //!             }
 //!
+//!             Ok(ServerEvent::Idle | ServerEvent::Active) => {
+//!                 // Only reported with Builder::idle_timeout
+//!             }
+//!
 //!             Ok(ServerEvent::ClientInfoUpdated { .. }) => {
 //!                 // Client sent updated info (screen dimensions, etc.)
 //!             }
 //!
+//!             Ok(ServerEvent::HandshakeFailed { addr, error }) => {
+//!                 println!("✗ Connection from {} failed: {}\n", addr, error);
+//!             }
+//!
 //!             Err(e) => {
 //!                 eprintln!("Error: {}", e);
 //!                 break;
@@ -136,22 +169,62 @@
 //! # }
 //! ```
 
-use log::debug;
-use std::collections::HashMap;
-use std::sync::Arc;
+use futures_core::Stream;
+use log::{debug, warn};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Weak};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
 use thiserror::Error;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::{TcpListener, TcpStream};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpListener;
 use tokio::sync::RwLock;
+#[cfg(feature = "tracing")]
+use tracing::Instrument;
 
+use crate::protocol::clipboard::{
+    self, ClipboardAssembler, ClipboardContents, ClipboardFormat, ClipboardId,
+};
+use crate::protocol::file_transfer::{self, FileTransferAssembler};
 use crate::protocol::{
-    Message, MessageHelloBarrier, MessageInfoAcknowledgment, MessageKeepAlive,
-    MessageLegacySynergy, MessageQueryInfo, MessageResetOptions, MessageSetOptions, ProtocolError,
-    parse_message_with_length,
+    Capabilities, CornerMask, Dialect, DsopOption, Handshake, HandshakeError, KeyId, Message,
+    MessageClipboardData, MessageFileTransfer, MessageHelloBarrier, MessageHelloSynergy,
+    MessageKeepAlive, MessageKeyDown, MessageSetOptions, ProtocolError, ProtocolVersion,
+    parse_message_with_length, parse_message_with_length_versioned,
 };
+use crate::transport::{BoxedTransport, Listener};
+
+pub mod layout;
+
+use layout::{Crossing, LayoutReport};
 
 const DEFAULT_PORT: u16 = 24801;
 
+/// The keepalive interval clients assume unless told otherwise with `HART`, as in Deskflow
+const DEFAULT_HEARTBEAT: Duration = Duration::from_secs(3);
+
+/// The number of keepalive intervals without any message before a client is
+/// considered gone, as in Deskflow
+const HEARTBEATS_UNTIL_DEATH: u32 = 3;
+
+/// The largest file accepted from a client unless set with [`Builder::max_file_size`]
+const DEFAULT_MAX_FILE_SIZE: u64 = 64 * 1024 * 1024;
+
+/// The largest clipboard accepted from a client unless set with
+/// [`Builder::max_clipboard_size`]
+const DEFAULT_MAX_CLIPBOARD_SIZE: usize = 64 * 1024 * 1024;
+
+/// How long a clipboard grab waits for its data unless set with
+/// [`Builder::clipboard_grab_timeout`]
+const DEFAULT_CLIPBOARD_GRAB_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long a new connection may take for the TLS and protocol handshakes unless set
+/// with [`Builder::handshake_timeout`]
+const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
 /// Unique identifier for a connected client
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct ClientId(u64);
@@ -180,6 +253,8 @@ pub struct Client {
     pub width: u16,
     /// Screen height in pixels
     pub height: u16,
+    capabilities: Capabilities,
+    hello: Message,
 }
 
 impl Client {
@@ -192,6 +267,61 @@ impl Client {
     pub fn name(&self) -> &str {
         &self.name
     }
+
+    /// The hello message exactly as the client sent it, including its advertised name
+    /// and protocol version
+    pub fn peer_hello(&self) -> &Message {
+        &self.hello
+    }
+
+    /// The schengen extensions advertised by the client
+    ///
+    /// Empty if the client is not a schengen client, or if the server did not advertise
+    /// any capabilities with [`Builder::capabilities`].
+    pub fn peer_capabilities(&self) -> Capabilities {
+        self.capabilities
+    }
+}
+
+/// Details of a connected client, as returned by [`Server::clients`]
+///
+/// A snapshot for e.g. a status UI, it is not updated after [`Server::clients`]
+/// returned.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectedClient {
+    client: Client,
+    /// The address the client connected from
+    pub addr: SocketAddr,
+    /// Where the client sits in the layout, relative to `relative_to` if set or the
+    /// server otherwise
+    pub position: Position,
+    /// The client this client is positioned relative to, see [`ClientBuilder::relative_to`]
+    pub relative_to: Option<String>,
+    /// The screen size last reported by the client with `DINF`, `None` if it reported
+    /// an empty screen
+    pub dimensions: Option<(u16, u16)>,
+    /// When the client completed the handshake
+    pub connected_at: Instant,
+    /// The keyboard languages (ISO 639-1) the client last listed with `LSYN`, empty if
+    /// it never sent one
+    pub languages: Vec<String>,
+}
+
+impl ConnectedClient {
+    /// Get the client's unique ID
+    pub fn id(&self) -> ClientId {
+        self.client.id
+    }
+
+    /// Get the client's name
+    pub fn name(&self) -> &str {
+        &self.client.name
+    }
+
+    /// The client as it completed the handshake, with its hello and capabilities
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
 }
 
 /// Configuration for a client that can connect to the server
@@ -202,6 +332,7 @@ pub struct NewClient {
     name: String,
     position: Position,
     relative_to: Option<String>,
+    edge_range: (u8, u8),
 }
 
 impl NewClient {
@@ -249,6 +380,7 @@ pub struct ClientBuilder<S: client_builder_state::State = client_builder_state::
     name: String,
     position: Option<Position>,
     relative_to: Option<String>,
+    edge_range: (u8, u8),
 }
 
 mod client_builder_state {
@@ -271,6 +403,7 @@ impl ClientBuilder<client_builder_state::NeedsPosition> {
             name: name.to_string(),
             position: None,
             relative_to: None,
+            edge_range: (0, 100),
         }
     }
 
@@ -283,6 +416,7 @@ impl ClientBuilder<client_builder_state::NeedsPosition> {
             name: self.name,
             position: Some(position),
             relative_to: None,
+            edge_range: (0, 100),
         }
     }
 }
@@ -323,6 +457,41 @@ impl ClientBuilder<client_builder_state::Ready> {
         self
     }
 
+    /// Place this client along part of the edge it is next to
+    ///
+    /// `start` and `end` are percentages along the edge of the server or of the client
+    /// in [`relative_to`](Self::relative_to), from the top for the left and right edges
+    /// and from the left for the top and bottom edges. Several clients can share an
+    /// edge if their ranges don't overlap, the cursor then enters the client whose
+    /// range it crosses. The default is the whole edge, `0` to `100`.
+    ///
+    /// ```
+    /// # use schengen::server::{ClientBuilder, Position};
+    /// // Two screens stacked on the left of the server
+    /// let top = ClientBuilder::new("top")
+    ///     .position(Position::Left)
+    ///     .edge_range(0, 50)
+    ///     .build();
+    /// let bottom = ClientBuilder::new("bottom")
+    ///     .position(Position::Left)
+    ///     .edge_range(50, 100)
+    ///     .build();
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if `start` is not less than `end` or `end` is greater than 100.
+    pub fn edge_range(mut self, start: u8, end: u8) -> Self {
+        if start >= end || end > 100 {
+            panic!(
+                "Invalid edge range {}-{}% for client '{}'",
+                start, end, self.name
+            );
+        }
+        self.edge_range = (start, end);
+        self
+    }
+
     /// Build the client configuration
     ///
     /// Only available after position has been set.
@@ -333,6 +502,7 @@ impl ClientBuilder<client_builder_state::Ready> {
                 .position
                 .expect("position should be set in Ready state"),
             relative_to: self.relative_to,
+            edge_range: self.edge_range,
         }
     }
 }
@@ -344,12 +514,24 @@ pub enum ServerEvent {
     ClientConnected {
         client_id: ClientId,
         name: String,
+        /// The address the client connected from
+        addr: SocketAddr,
         width: u16,
         height: u16,
     },
 
+    /// A connection was accepted but the handshake with it failed
+    ///
+    /// The connection is closed, e.g. because the client is not configured or speaks
+    /// an incompatible protocol version.
+    HandshakeFailed { addr: SocketAddr, error: String },
+
     /// A client disconnected
-    ClientDisconnected { client_id: ClientId, name: String },
+    ClientDisconnected {
+        client_id: ClientId,
+        name: String,
+        reason: DisconnectReason,
+    },
 
     /// Clipboard data received from a client
     ClipboardData {
@@ -359,6 +541,75 @@ pub enum ServerEvent {
         data: Vec<u8>,
     },
 
+    /// A client's clipboard was received completely
+    ///
+    /// Emitted after the [`ServerEvent::ClipboardData`] of the final chunk once the
+    /// clipboard data has been reassembled and parsed.
+    ClipboardReceived {
+        client_id: ClientId,
+        name: String,
+        id: ClipboardId,
+        contents: ClipboardContents,
+    },
+
+    /// A client took over a clipboard and its contents were received
+    ///
+    /// Emitted after the [`ServerEvent::ClipboardReceived`] of the data that follows a
+    /// clipboard grab (`CCLP`). The protocol has no message to request the data of a
+    /// grab, clients send it right away or only when the cursor leaves their screen.
+    /// A grab whose data doesn't arrive within [`Builder::clipboard_grab_timeout`] is
+    /// dropped without an event.
+    ClipboardChanged {
+        client_id: ClientId,
+        name: String,
+        id: ClipboardId,
+        contents: ClipboardContents,
+    },
+
+    /// A clipboard transfer from a client failed
+    ///
+    /// Emitted if the chunks of a clipboard are invalid or the clipboard exceeds
+    /// [`Builder::max_clipboard_size`]. The rest of the transfer is ignored.
+    ClipboardTransferAborted {
+        client_id: ClientId,
+        name: String,
+        id: u8,
+        error: String,
+    },
+
+    /// A client dropped a file on this server
+    ///
+    /// Assembled from the `DFTR` transfer following a `DDRG`. `path` is the file's
+    /// path on the client as announced in the `DDRG`, `None` if the client sent the
+    /// file without one.
+    FileReceived {
+        client_id: ClientId,
+        name: String,
+        path: Option<String>,
+        bytes: Vec<u8>,
+    },
+
+    /// A file transfer from a client failed
+    ///
+    /// Emitted if a transfer is invalid, exceeds [`Builder::max_file_size`] or is
+    /// interrupted by the next transfer. The rest of the transfer is ignored.
+    FileTransferAborted {
+        client_id: ClientId,
+        name: String,
+        path: Option<String>,
+        error: String,
+    },
+
+    /// A client entered secure input mode
+    ///
+    /// Sent by the client with `SECN` when an application such as a password prompt
+    /// blocks keyboard input. `app_name` is the application as named by the client.
+    SecureInput {
+        client_id: ClientId,
+        name: String,
+        app_name: String,
+    },
+
     /// Screen saver state changed on a client
     ScreenSaverChanged { client_id: ClientId, active: bool },
 
@@ -368,6 +619,47 @@ pub enum ServerEvent {
         width: u16,
         height: u16,
     },
+
+    /// No keyboard or mouse input was sent to any client for the
+    /// [`Builder::idle_timeout`]
+    Idle,
+
+    /// Input was sent to a client again after [`ServerEvent::Idle`]
+    Active,
+}
+
+/// Why a client was disconnected, see [`ServerEvent::ClientDisconnected`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisconnectReason {
+    /// The client closed the connection with `CBYE`
+    ClientClosed,
+    /// The connection was lost, the client sent invalid data or it sent nothing for
+    /// three keepalive intervals, see [`Builder::heartbeat`]
+    ConnectionLost,
+    /// Disconnected via [`Server::disconnect_client`], the client was sent `CBYE`
+    Kicked,
+    /// Disconnected via [`Server::disconnect_client`] or [`Server::remove_client`], the
+    /// client was sent `EUNK` to signal that it is no longer allowed to connect
+    Revoked,
+}
+
+/// A key event injected with [`Server::inject_key`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyEvent {
+    /// A key press, sent as `DKDN`
+    Down { keyid: u16, mask: u16, button: u16 },
+    /// A key press in the given keyboard language, sent as `DKDL`
+    ///
+    /// Sent as `DKDN` without the language to clients before protocol 1.8 or if
+    /// [`Builder::forward_language`] is disabled.
+    DownWithLanguage {
+        keyid: u16,
+        mask: u16,
+        button: u16,
+        lang: String,
+    },
+    /// A key release, sent as `DKUP`
+    Up { keyid: u16, mask: u16, button: u16 },
 }
 
 #[derive(Debug, Error)]
@@ -406,6 +698,24 @@ pub enum ServerError {
 
     #[error("Client '{0}' referenced in relative_to does not exist")]
     RelativeClientNotFound(String),
+
+    #[error("A client named '{0}' is already configured")]
+    DuplicateName(String),
+
+    #[error("Client '{client}' is still referenced in relative_to by client '{referenced_by}'")]
+    ClientStillReferenced {
+        client: String,
+        referenced_by: String,
+    },
+
+    #[error("Client protocol version {0} is incompatible with this server")]
+    IncompatibleVersion(ProtocolVersion),
+
+    #[error("The cursor is not on a client's screen")]
+    NoActiveClient,
+
+    #[error("The cursor can't reach the screens of clients {0:?}")]
+    UnreachableClients(Vec<String>),
 }
 
 pub type Result<T> = std::result::Result<T, ServerError>;
@@ -413,21 +723,218 @@ pub type Result<T> = std::result::Result<T, ServerError>;
 /// Internal configuration for a single client
 #[derive(Debug, Clone)]
 struct ClientConfig {
-    #[allow(dead_code)]
     position: Position,
+    relative_to: Option<String>,
+    /// The part of the edge the client is on, in percent
+    edge_range: (u8, u8),
 }
 
-/// A connected client
-struct ConnectedClient {
+impl ClientConfig {
+    /// Where the client is placed in the layout
+    fn placement(&self) -> layout::Placement<'_> {
+        layout::Placement {
+            position: self.position,
+            relative_to: self.relative_to.as_deref(),
+            edge_range: self.edge_range,
+        }
+    }
+}
+
+/// A screen by name, `None` for the primary screen, with its placement
+fn screen_placement<'a>(
+    name: &'a Option<String>,
+    configs: &'a HashMap<String, ClientConfig>,
+) -> (Option<&'a str>, Option<layout::Placement<'a>>) {
+    let config = name.as_ref().and_then(|name| configs.get(name));
+    (name.as_deref(), config.map(ClientConfig::placement))
+}
+
+/// A duration in milliseconds as sent in a `DSOP` option
+fn delay_millis(duration: Duration) -> u32 {
+    duration.as_millis().try_into().unwrap_or(u32::MAX)
+}
+
+/// Check that `client` can be added next to the `existing` (name, position, relative_to,
+/// edge_range) clients without conflicts
+fn check_position<'a, I>(existing: I, client: &NewClient) -> Result<()>
+where
+    I: Iterator<Item = (&'a str, Position, Option<&'a str>, (u8, u8))> + Clone,
+{
+    // Names identify clients in the protocol
+    if existing.clone().any(|(name, _, _, _)| name == client.name) {
+        return Err(ServerError::DuplicateName(client.name.clone()));
+    }
+
+    if let Some(relative_to) = &client.relative_to {
+        // Check if the referenced client exists
+        if !existing.clone().any(|(name, _, _, _)| name == relative_to) {
+            return Err(ServerError::RelativeClientNotFound(relative_to.clone()));
+        }
+    }
+
+    // Clients on the same side of the server or of the target client can't overlap
+    let (start, end) = client.edge_range;
+    if let Some((existing_client, _, _, _)) =
+        existing
+            .clone()
+            .find(|(_, position, other, (other_start, other_end))| {
+                *other == client.relative_to.as_deref()
+                    && *position == client.position
+                    && start < *other_end
+                    && *other_start < end
+            })
+    {
+        return Err(match &client.relative_to {
+            Some(relative_to) => ServerError::RelativePositionOccupied {
+                position: client.position,
+                relative_to: relative_to.clone(),
+                existing_client: existing_client.to_string(),
+            },
+            None => ServerError::ServerPositionOccupied {
+                position: client.position,
+                existing_client: existing_client.to_string(),
+            },
+        });
+    }
+    Ok(())
+}
+
+/// The sequence numbers of `CINN`
+///
+/// The number increases every time the cursor enters a client. Clients send the
+/// number of the last `CINN` they received back with their clipboard grabs (`CCLP`)
+/// and data (`DCLP`), anything with an older number was sent before the latest
+/// switch and is stale.
+///
+/// The number wraps around after `u32::MAX`, numbers are compared by their distance
+/// as in RFC 1982 so that the first numbers after the wrap are newer than those
+/// before it.
+#[derive(Debug, Default)]
+struct SequenceCounter(std::sync::atomic::AtomicU32);
+
+impl SequenceCounter {
+    /// Assign the sequence number of a new `CINN`
+    fn next(&self) -> u32 {
+        let previous = self
+            .0
+            .fetch_update(
+                std::sync::atomic::Ordering::SeqCst,
+                std::sync::atomic::Ordering::SeqCst,
+                |current| Some(current.wrapping_add(1)),
+            )
+            .unwrap();
+        previous.wrapping_add(1)
+    }
+
+    /// Record the sequence number of a `CINN` sent with a caller-chosen number
+    ///
+    /// The number becomes the latest one even if it is lower, the caller may have
+    /// wrapped it around or restarted its own count.
+    fn record(&self, sequence: u32) {
+        self.0.store(sequence, std::sync::atomic::Ordering::SeqCst);
+    }
+
+    /// Whether a sequence number sent by a client predates the latest `CINN`
+    fn is_stale(&self, sequence: u32) -> bool {
+        Self::precedes(sequence, self.0.load(std::sync::atomic::Ordering::SeqCst))
+    }
+
+    /// Whether sequence number `a` is older than `b`
+    fn precedes(a: u32, b: u32) -> bool {
+        (b.wrapping_sub(a) as i32) > 0
+    }
+}
+
+/// The connection to a client, as stored by the server
+struct ClientConnection {
     client: Client,
-    stream: TcpStream,
+    /// The address the client connected from
+    addr: SocketAddr,
+    /// When the handshake completed
+    connected_at: Instant,
+    stream: BoxedTransport,
     buffer: Vec<u8>,
+    /// Sequence of the primary clipboards last sent to this client
+    clipboard_sequences: HashMap<ClipboardId, u32>,
+    /// Reassembles the clipboard data sent by this client
+    clipboard_assembler: ClipboardAssembler,
+    /// Clipboard ids whose failed transfer is being ignored until the next one starts
+    discarding_clipboards: HashSet<u8>,
+    /// Clipboards this client grabbed whose data hasn't arrived yet, with the time of
+    /// the grab
+    pending_grabs: HashMap<ClipboardId, Instant>,
+    /// Reassembles the files dropped by this client
+    file_assembler: FileTransferAssembler,
+    /// The paths of the last `DDRG` whose files haven't been transferred yet
+    dropped_paths: VecDeque<String>,
+    /// Whether the rest of a failed file transfer is being ignored
+    discarding_file: bool,
+    /// The languages from the client's last `LSYN`
+    languages: Vec<String>,
+    /// The protocol version negotiated with this client
+    protocol_version: ProtocolVersion,
+    /// The cursor position last reported by the client in `DINF`
+    cursor: (u16, u16),
+    /// The cursor position last sent to the client with `CINN`, `DMMV` or `DMRM`
+    pointer: (i16, i16),
+    /// Whether `DKDL` is sent as is, see [`Builder::forward_language`]
+    forward_language: bool,
+    /// The keepalive interval of this client, `None` if disabled
+    heartbeat: Option<Duration>,
+    /// When the last data was received from the client
+    last_received: Instant,
+    /// The span of this connection, the parent of its events and background tasks
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
 }
 
-impl ConnectedClient {
+impl ClientConnection {
     /// Send a protocol message to this client
+    ///
+    /// Messages are encoded for the negotiated protocol version, see
+    /// [`Message::to_bytes_versioned`].
     async fn send_message(&mut self, message: Message) -> Result<()> {
-        let bytes = message.to_bytes();
+        let bytes = self.encode(&message);
+        match &message {
+            Message::CursorEntered(m) => self.pointer = (m.x, m.y),
+            Message::MouseMove(m) => self.pointer = (m.x, m.y),
+            Message::MouseRelativeMove(m) => {
+                self.pointer = (
+                    self.pointer.0.saturating_add(m.x),
+                    self.pointer.1.saturating_add(m.y),
+                )
+            }
+            _ => {}
+        }
+        self.stream
+            .write_all(&bytes)
+            .await
+            .map_err(|e| ServerError::ConnectionError(e.to_string()))?;
+        self.stream
+            .flush()
+            .await
+            .map_err(|e| ServerError::ConnectionError(e.to_string()))?;
+        Ok(())
+    }
+
+    /// Encode a message for this client
+    fn encode(&self, message: &Message) -> Vec<u8> {
+        match message {
+            Message::KeyDownWithLanguage(m) if !self.forward_language => {
+                Message::KeyDown(MessageKeyDown {
+                    keyid: m.keyid,
+                    mask: m.mask,
+                    button: m.button,
+                })
+                .to_bytes_versioned(self.protocol_version)
+            }
+            message => message.to_bytes_versioned(self.protocol_version),
+        }
+    }
+
+    /// Send a batch of protocol messages to this client with a single write and flush
+    async fn send_all(&mut self, messages: &[Message]) -> Result<()> {
+        let bytes: Vec<u8> = messages.iter().flat_map(|m| self.encode(m)).collect();
         self.stream
             .write_all(&bytes)
             .await
@@ -444,7 +951,7 @@ impl ConnectedClient {
         loop {
             // Try to parse a message from the buffer
             if !self.buffer.is_empty() {
-                match parse_message_with_length(&self.buffer) {
+                match parse_message_with_length_versioned(&self.buffer, self.protocol_version) {
                     Ok((msg, consumed)) => {
                         // Remove consumed bytes from buffer
                         self.buffer.drain(..consumed);
@@ -454,6 +961,9 @@ impl ConnectedClient {
                         // Need more data, continue reading
                     }
                     Err(e) => {
+                        #[cfg(feature = "tracing")]
+                        self.span
+                            .in_scope(|| tracing::warn!(error = %e, "failed to parse message"));
                         return Err(ServerError::ProtocolError(e));
                     }
                 }
@@ -461,42 +971,39 @@ impl ConnectedClient {
 
             // Since we're expected to be integrated/combined with some other event loop
             // we have a 1ms timeout here so the caller can go back to what it's doing.
+            // Reads are cancel safe, data is never lost when the timeout hits.
             let mut temp_buf = vec![0u8; 4096];
             match tokio::time::timeout(
                 tokio::time::Duration::from_millis(1),
-                self.stream.readable(),
+                self.stream.read(&mut temp_buf),
             )
             .await
             {
-                Ok(Ok(())) => {
-                    // Socket is readable, try non-blocking read
-                    match self.stream.try_read(&mut temp_buf) {
-                        Ok(0) => {
-                            // Connection closed
-                            return Err(ServerError::ConnectionError(
-                                "Connection closed by client".to_string(),
-                            ));
-                        }
-                        Ok(n) => {
-                            self.buffer.extend_from_slice(&temp_buf[..n]);
-                        }
-                        Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                            // No data available right now, loop again
-                            continue;
-                        }
-                        Err(e) => {
-                            return Err(ServerError::ConnectionError(e.to_string()));
-                        }
-                    }
+                Ok(Ok(0)) => {
+                    // Connection closed
+                    return Err(ServerError::ConnectionError(
+                        "Connection closed by client".to_string(),
+                    ));
+                }
+                Ok(Ok(n)) => {
+                    self.last_received = Instant::now();
+                    self.buffer.extend_from_slice(&temp_buf[..n]);
                 }
                 Ok(Err(e)) => {
-                    return Err(ServerError::ConnectionError(format!(
-                        "Error waiting for readable: {}",
-                        e
-                    )));
+                    return Err(ServerError::ConnectionError(e.to_string()));
                 }
                 Err(_) => {
-                    // Timeout - no data available
+                    // Timeout - no data available, give up on clients that went
+                    // silent for several keepalive intervals
+                    if let Some(heartbeat) = self.heartbeat {
+                        let timeout = heartbeat * HEARTBEATS_UNTIL_DEATH;
+                        if self.last_received.elapsed() >= timeout {
+                            return Err(ServerError::ConnectionError(format!(
+                                "No keepalive from client within {:?}",
+                                timeout
+                            )));
+                        }
+                    }
                     return Ok(None);
                 }
             }
@@ -504,9 +1011,43 @@ impl ConnectedClient {
     }
 }
 
+/// The decision of an [`Builder::on_accept`] callback
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcceptDecision {
+    /// Continue with the handshake
+    Accept,
+    /// Close the connection without a handshake
+    Reject,
+}
+
+/// Callback invoked for each accepted connection, see [`Builder::on_accept`]
+type AcceptCallback = Arc<dyn Fn(&SocketAddr) -> AcceptDecision + Send + Sync>;
+
 pub struct Builder {
     port: u16,
+    dimensions: (u16, u16),
     clients: Vec<NewClient>,
+    preserve_focus: bool,
+    forward_language: bool,
+    max_file_size: u64,
+    max_clipboard_size: usize,
+    clipboard_grab_timeout: Duration,
+    handshake_timeout: Duration,
+    idle_timeout: Option<Duration>,
+    switch_corners: CornerMask,
+    switch_corner_size: u16,
+    lock_to_screen: bool,
+    relative_mouse_moves: bool,
+    dialect: Dialect,
+    protocol_version: ProtocolVersion,
+    switch_delay: Option<Duration>,
+    switch_double_tap: Option<Duration>,
+    heartbeat: Duration,
+    capabilities: Capabilities,
+    on_accept: Option<AcceptCallback>,
+    peer_name_hint: Option<String>,
+    #[cfg(feature = "tls")]
+    tls: Option<crate::tls::TlsIdentity>,
 }
 
 impl Builder {
@@ -514,104 +1055,425 @@ impl Builder {
     pub fn new() -> Self {
         Builder {
             port: DEFAULT_PORT,
+            dimensions: (1920, 1080),
             clients: Vec::new(),
+            preserve_focus: false,
+            forward_language: true,
+            max_file_size: DEFAULT_MAX_FILE_SIZE,
+            max_clipboard_size: DEFAULT_MAX_CLIPBOARD_SIZE,
+            clipboard_grab_timeout: DEFAULT_CLIPBOARD_GRAB_TIMEOUT,
+            handshake_timeout: DEFAULT_HANDSHAKE_TIMEOUT,
+            idle_timeout: None,
+            switch_corners: CornerMask::empty(),
+            switch_corner_size: 0,
+            lock_to_screen: false,
+            relative_mouse_moves: false,
+            dialect: Dialect::default(),
+            protocol_version: ProtocolVersion::CURRENT,
+            switch_delay: None,
+            switch_double_tap: None,
+            heartbeat: DEFAULT_HEARTBEAT,
+            capabilities: Capabilities::empty(),
+            on_accept: None,
+            peer_name_hint: None,
+            #[cfg(feature = "tls")]
+            tls: None,
         }
     }
 
+    /// Set the dimensions of the primary screen
+    ///
+    /// These are used to detect when the cursor leaves the primary screen, see
+    /// [`Server::update_cursor`]. The default is 1920x1080.
+    pub fn dimensions(mut self, width: u16, height: u16) -> Self {
+        self.dimensions = (width, height);
+        self
+    }
+
+    /// Forward the keyboard language of key presses to clients
+    ///
+    /// If enabled, key presses with a language, e.g. from [`KeyEvent::DownWithLanguage`]
+    /// or [`Server::send_text`], are sent as `DKDL` to clients that negotiated protocol
+    /// 1.8 or later so they type the characters of that language. If disabled, they
+    /// are always sent as `DKDN` without the language, with the client's own layout
+    /// deciding the characters.
+    ///
+    /// Defaults to `true`.
+    pub fn forward_language(mut self, forward: bool) -> Self {
+        self.forward_language = forward;
+        self
+    }
+
     /// Set the port to listen on
     pub fn port(mut self, port: u16) -> Self {
         self.port = port;
         self
     }
 
-    /// Add a client that is allowed to connect
-    ///
-    /// Use [`ClientBuilder`] to create a client configuration.
+    /// Ask clients to preserve the focused window when the cursor leaves their screen
     ///
-    /// # Validation
+    /// If enabled, the [`DsopOption::ScreenPreserveFocus`] (`SFOC`) option is sent to
+    /// every client as part of the handshake. There is no separate message for this
+    /// in the protocol, the switch-away itself is the regular `COUT` sent by
+    /// [`Server::send_cursor_left`]. A client that received `SFOC` with a nonzero value
+    /// is expected to keep the currently focused window focused when handling `COUT`
+    /// instead of deactivating it (e.g. by not moving focus to a hidden window).
     ///
-    /// This method validates that:
-    /// - For clients positioned relative to the server (no `relative_to`), only one client
-    ///   can occupy each edge (Left, Right, Above, Below)
-    /// - For clients positioned relative to another client (`relative_to` set), only one
-    ///   client can be on each side of the target client
-    /// - The client referenced in `relative_to` must have been added previously
+    /// Defaults to `false`.
+    pub fn preserve_focus(mut self, preserve: bool) -> Self {
+        self.preserve_focus = preserve;
+        self
+    }
+
+    /// Disable switching screens in the given corners
     ///
-    /// # Errors
+    /// The cursor doesn't cross onto another screen while it is within
+    /// [`switch_corner_size`](Self::switch_corner_size) pixels of one of these corners,
+    /// see [`Server::update_cursor`]. The corners are also sent to every client with
+    /// the [`DsopOption::ScreenSwitchCorners`] (`SSCM`) option as part of the handshake.
     ///
-    /// Returns an error if:
-    /// - The position is already occupied on the server
-    /// - The position is already occupied relative to the target client
-    /// - The target client in `relative_to` doesn't exist
+    /// Defaults to no corners.
     ///
     /// # Example
     ///
     /// ```no_run
-    /// use schengen::server::{Builder, ClientBuilder, Position};
+    /// use schengen::protocol::CornerMask;
+    /// use schengen::server::Builder;
     ///
     /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
-    /// let laptop = ClientBuilder::new("laptop")
-    ///     .position(Position::Left)
-    ///     .build();
-    ///
-    /// let monitor = ClientBuilder::new("monitor")
-    ///     .position(Position::Below)
-    ///     .relative_to(&laptop)
-    ///     .build();
-    ///
     /// let server = Builder::new()
-    ///     .add_client(laptop)?
-    ///     .add_client(monitor)?
+    ///     .switch_corners(CornerMask::TOP_LEFT | CornerMask::TOP_RIGHT)
+    ///     .switch_corner_size(20)
     ///     .listen()
     ///     .await?;
     /// # Ok(())
     /// # }
     /// ```
-    pub fn add_client(mut self, client: NewClient) -> Result<Self> {
-        // Validate the client's position
-        if let Some(relative_to) = &client.relative_to {
-            // Check if the referenced client exists
-            if !self.clients.iter().any(|c| &c.name == relative_to) {
-                return Err(ServerError::RelativeClientNotFound(relative_to.clone()));
-            }
+    pub fn switch_corners(mut self, corners: CornerMask) -> Self {
+        self.switch_corners = corners;
+        self
+    }
 
-            // Check if this position is already occupied relative to the target client
-            if let Some(existing) = self.clients.iter().find(|c| {
-                c.relative_to.as_ref() == Some(relative_to) && c.position == client.position
-            }) {
-                return Err(ServerError::RelativePositionOccupied {
-                    position: client.position,
-                    relative_to: relative_to.clone(),
-                    existing_client: existing.name.clone(),
-                });
-            }
-        } else {
-            // Client is positioned relative to the server
-            // Check if this position is already occupied on the server
-            if let Some(existing) = self
-                .clients
-                .iter()
-                .find(|c| c.relative_to.is_none() && c.position == client.position)
-            {
-                return Err(ServerError::ServerPositionOccupied {
-                    position: client.position,
-                    existing_client: existing.name.clone(),
-                });
-            }
-        }
+    /// Set the size in pixels of the corners set with
+    /// [`switch_corners`](Self::switch_corners)
+    ///
+    /// The size is sent to every client with the
+    /// [`DsopOption::ScreenSwitchCornerSize`] (`SSCS`) option as part of the handshake.
+    ///
+    /// Defaults to 0, which disables the corners.
+    pub fn switch_corner_size(mut self, pixels: u16) -> Self {
+        self.switch_corner_size = pixels;
+        self
+    }
 
-        self.clients.push(client);
-        Ok(self)
+    /// Only switch screens after the cursor stayed at the edge for `delay`
+    ///
+    /// This prevents accidental switches when the cursor brushes against an edge, see
+    /// [`Server::update_cursor`]. The delay is also sent to every client with the
+    /// [`DsopOption::ScreenSwitchDelay`] (`SSWT`) option in milliseconds as part of the
+    /// handshake. If [`switch_double_tap`](Self::switch_double_tap) is set as well,
+    /// either condition switches.
+    ///
+    /// Defaults to switching immediately.
+    pub fn switch_delay(mut self, delay: Duration) -> Self {
+        self.switch_delay = Some(delay);
+        self
     }
 
-    /// Build and start the server
+    /// Set the interval at which keepalives (`CALV`) are sent to each client
     ///
-    /// This will bind to 0.0.0.0 (IPv4) or :: (IPv6) on the configured port
-    /// and start accepting connections.
-    /// When this function returns, the server is ready to accept client connections.
-    pub async fn listen(self) -> Result<Server> {
-        // Try IPv6 first (which accepts IPv4 on most systems), fall back to IPv4
-        let listener = match TcpListener::bind(("::".to_string(), self.port)).await {
+    /// Clients that send nothing for three intervals are disconnected with
+    /// [`DisconnectReason::ConnectionLost`]. An interval other than the default is
+    /// sent to every client with the [`DsopOption::Heartbeat`] (`HART`) option in
+    /// milliseconds as part of the handshake, so clients expect keepalives at the same
+    /// rate. A zero interval disables keepalives and the detection of dead clients.
+    ///
+    /// Defaults to 3 seconds.
+    pub fn heartbeat(mut self, interval: Duration) -> Self {
+        self.heartbeat = interval;
+        self
+    }
+
+    /// Only switch screens if the cursor hits the edge twice within `window`
+    ///
+    /// The cursor has to move away from the edge between the two taps, see
+    /// [`Server::update_cursor`]. The window is also sent to every client with the
+    /// [`DsopOption::ScreenSwitchTwoTap`] (`SSTT`) option in milliseconds as part of
+    /// the handshake.
+    ///
+    /// Defaults to switching immediately.
+    pub fn switch_double_tap(mut self, window: Duration) -> Self {
+        self.switch_double_tap = Some(window);
+        self
+    }
+
+    /// Start with the cursor locked to the screen it is on
+    ///
+    /// See [`Server::set_lock_to_screen`]. If enabled, the
+    /// [`DsopOption::DefaultLockToScreenState`] (`LTSS`) option is sent to every client
+    /// as part of the handshake.
+    ///
+    /// Defaults to `false`.
+    pub fn default_lock_to_screen(mut self, locked: bool) -> Self {
+        self.lock_to_screen = locked;
+        self
+    }
+
+    /// Start with relative mouse moves enabled
+    ///
+    /// See [`Server::set_relative_mouse_moves`]. If enabled, the
+    /// [`DsopOption::RelativeMouseMoves`] (`MDLT`) option is sent to every client as
+    /// part of the handshake.
+    ///
+    /// Defaults to `false`.
+    pub fn relative_mouse_moves(mut self, relative: bool) -> Self {
+        self.relative_mouse_moves = relative;
+        self
+    }
+
+    /// Set the largest file accepted from a client, in bytes
+    ///
+    /// A file transfer announcing a larger size is reported as
+    /// [`ServerEvent::FileTransferAborted`] without buffering any of it.
+    ///
+    /// Defaults to 64 MiB.
+    pub fn max_file_size(mut self, bytes: u64) -> Self {
+        self.max_file_size = bytes;
+        self
+    }
+
+    /// Set the largest clipboard accepted from a client, in bytes
+    ///
+    /// A clipboard transfer announcing a larger size is reported as
+    /// [`ServerEvent::ClipboardTransferAborted`] without buffering any of it.
+    ///
+    /// Defaults to 64 MiB.
+    pub fn max_clipboard_size(mut self, bytes: usize) -> Self {
+        self.max_clipboard_size = bytes;
+        self
+    }
+
+    /// Set how long a clipboard grab (`CCLP`) from a client waits for its data
+    ///
+    /// Data arriving within `timeout` of the grab is reported as
+    /// [`ServerEvent::ClipboardChanged`]. A grab whose data doesn't arrive in time is
+    /// dropped, later data is only reported as [`ServerEvent::ClipboardReceived`].
+    ///
+    /// Defaults to 30 seconds.
+    pub fn clipboard_grab_timeout(mut self, timeout: Duration) -> Self {
+        self.clipboard_grab_timeout = timeout;
+        self
+    }
+
+    /// Set how long a new connection may take to complete its handshake
+    ///
+    /// This covers the TLS handshake, if enabled, and the protocol handshake. A
+    /// connection that doesn't complete both within `timeout` is closed and reported
+    /// as [`ServerEvent::HandshakeFailed`]. Other clients are not served while a
+    /// handshake is in progress, so this bounds how long a silent peer can stall them.
+    ///
+    /// Defaults to 10 seconds.
+    pub fn handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.handshake_timeout = timeout;
+        self
+    }
+
+    /// Report when no input was sent to clients for `timeout`
+    ///
+    /// Once no keyboard or mouse message was sent to any client for `timeout`, since
+    /// the server started or since the last input, [`Server::recv_event`] returns
+    /// [`ServerEvent::Idle`]. The next input is reported as [`ServerEvent::Active`].
+    ///
+    /// Defaults to no idle detection.
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Set the hello sent to clients in the handshake
+    ///
+    /// Clients are accepted with either hello regardless of this setting.
+    ///
+    /// Defaults to [`Dialect::Barrier`], which Barrier, Input-Leap and Deskflow
+    /// clients expect. Use [`Dialect::Synergy`] for the original Synergy client.
+    pub fn hello_dialect(mut self, dialect: Dialect) -> Self {
+        self.dialect = dialect;
+        self
+    }
+
+    /// Set the protocol version advertised in the hello to clients
+    ///
+    /// Defaults to [`ProtocolVersion::CURRENT`]. The version used with each client is
+    /// still negotiated, the older of this and the client's version, and clients
+    /// with a different major version are rejected. This is mostly useful to test
+    /// clients against an older server.
+    pub fn protocol_version(mut self, version: ProtocolVersion) -> Self {
+        self.protocol_version = version;
+        self
+    }
+
+    /// Set the schengen extensions supported by this server
+    ///
+    /// If nonempty, the capabilities are sent to every client with the
+    /// [`DsopOption::SchengenCaps`] option as part of the handshake. Schengen clients reply
+    /// with their own capabilities, see [`Client::peer_capabilities`] and
+    /// [`Server::negotiated_capabilities`]. Other clients ignore the option.
+    ///
+    /// Defaults to [`Capabilities::empty()`].
+    pub fn capabilities(mut self, capabilities: Capabilities) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    /// Require clients to connect with TLS, presenting `identity`
+    ///
+    /// The TLS handshake is performed before the Synergy handshake, clients connecting
+    /// without TLS fail the handshake. See the [`tls`](crate::tls) module.
+    #[cfg(feature = "tls")]
+    pub fn tls(mut self, identity: crate::tls::TlsIdentity) -> Self {
+        self.tls = Some(identity);
+        self
+    }
+
+    /// Set a callback invoked with the peer address of every accepted connection
+    ///
+    /// The callback runs immediately after the TCP connection is accepted, before any
+    /// protocol bytes are exchanged. This is the place for connection-level policy like
+    /// address filtering, rate limiting or logging. If the callback returns
+    /// [`AcceptDecision::Reject`] the connection is closed without a handshake.
+    ///
+    /// The callback runs on the task calling [`Server::recv_event`] and should not block.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use schengen::server::{AcceptDecision, Builder};
+    ///
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// let server = Builder::new()
+    ///     .on_accept(|addr| {
+    ///         if addr.ip().is_loopback() {
+    ///             AcceptDecision::Accept
+    ///         } else {
+    ///             AcceptDecision::Reject
+    ///         }
+    ///     })
+    ///     .listen()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn on_accept<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&SocketAddr) -> AcceptDecision + Send + Sync + 'static,
+    {
+        self.on_accept = Some(Arc::new(callback));
+        self
+    }
+
+    /// Add a client that is allowed to connect
+    ///
+    /// Use [`ClientBuilder`] to create a client configuration.
+    ///
+    /// # Validation
+    ///
+    /// This method validates that:
+    /// - For clients positioned relative to the server (no `relative_to`), only one client
+    ///   can occupy each edge (Left, Right, Above, Below)
+    /// - For clients positioned relative to another client (`relative_to` set), only one
+    ///   client can be on each side of the target client
+    /// - The client referenced in `relative_to` must have been added previously
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if:
+    /// - A client with the same name was already added
+    /// - The position is already occupied on the server
+    /// - The position is already occupied relative to the target client
+    /// - The target client in `relative_to` doesn't exist
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use schengen::server::{Builder, ClientBuilder, Position};
+    ///
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// let laptop = ClientBuilder::new("laptop")
+    ///     .position(Position::Left)
+    ///     .build();
+    ///
+    /// let monitor = ClientBuilder::new("monitor")
+    ///     .position(Position::Below)
+    ///     .relative_to(&laptop)
+    ///     .build();
+    ///
+    /// let server = Builder::new()
+    ///     .add_client(laptop)?
+    ///     .add_client(monitor)?
+    ///     .listen()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn add_client(mut self, client: NewClient) -> Result<Self> {
+        check_position(
+            self.clients.iter().map(|c| {
+                (
+                    c.name.as_str(),
+                    c.position,
+                    c.relative_to.as_deref(),
+                    c.edge_range,
+                )
+            }),
+            &client,
+        )?;
+        self.clients.push(client);
+        Ok(self)
+    }
+
+    /// Check that the cursor can reach every configured client
+    ///
+    /// Clients are always placed next to the screen they are positioned relative to,
+    /// but a client whose cell in the layout is taken, e.g. one to the right of a
+    /// client on the left of the primary screen, ends up hidden behind the screen
+    /// already there. Returns the neighbors of every screen, see [`LayoutReport`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ServerError::UnreachableClients`] with the clients the cursor can't
+    /// reach from the primary screen by crossing edges.
+    pub fn validate(&self) -> Result<LayoutReport> {
+        let placements: Vec<_> = self
+            .clients
+            .iter()
+            .map(|client| {
+                (
+                    client.name.as_str(),
+                    layout::Placement {
+                        position: client.position,
+                        relative_to: client.relative_to.as_deref(),
+                        edge_range: client.edge_range,
+                    },
+                )
+            })
+            .collect();
+        let report = LayoutReport::new(&placements);
+        if !report.unreachable().is_empty() {
+            return Err(ServerError::UnreachableClients(
+                report.unreachable().to_vec(),
+            ));
+        }
+        Ok(report)
+    }
+
+    /// Build and start the server
+    ///
+    /// This will bind to 0.0.0.0 (IPv4) or :: (IPv6) on the configured port
+    /// and start accepting connections.
+    /// When this function returns, the server is ready to accept client connections.
+    pub async fn listen(self) -> Result<Server> {
+        // Try IPv6 first (which accepts IPv4 on most systems), fall back to IPv4
+        let listener = match TcpListener::bind(("::".to_string(), self.port)).await {
             Ok(listener) => listener,
             Err(_) => {
                 // IPv6 failed, try IPv4
@@ -619,6 +1481,74 @@ impl Builder {
             }
         };
 
+        self.build_server_with_listener(Listener::Tcp(listener))
+    }
+
+    /// Build and start the server on a Unix domain socket at `path`
+    ///
+    /// For server and clients on the same host, e.g. with clients in VMs or containers
+    /// that share the socket, without going through TCP. Clients connect with
+    /// [`client::Builder::connect_unix`](crate::client::Builder::connect_unix). Unix
+    /// socket peers have no IP address, they are reported with the unspecified address
+    /// `0.0.0.0:0` in [`ServerEvent::ClientConnected`] and elsewhere.
+    ///
+    /// The socket file is not removed when the server is dropped.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use schengen::server::{Builder, ClientBuilder, Position};
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// let server = Builder::new()
+    ///     .add_client(ClientBuilder::new("vm").position(Position::Right).build())?
+    ///     .listen_on_unix("/run/schengen.sock")
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(unix)]
+    pub async fn listen_on_unix(self, path: impl AsRef<std::path::Path>) -> Result<Server> {
+        let listener = tokio::net::UnixListener::bind(path)?;
+        self.build_server_with_listener(Listener::Unix(listener))
+    }
+
+    /// Build a server for the single client connected over `stream`
+    ///
+    /// The server runs the full session over any byte stream, e.g. an in-memory
+    /// [`tokio::io::duplex`] pipe for deterministic tests or a transport this crate
+    /// doesn't provide. The connection is accepted by [`Server::recv_event`] like a
+    /// TCP connection, with the unspecified address `0.0.0.0:0` as peer address. No
+    /// further connections are accepted.
+    ///
+    /// `peer_name_hint` is the name of the client if its hello does not contain one.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use schengen::server::{Builder, ClientBuilder, Position};
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// let (server_end, client_end) = tokio::io::duplex(64 * 1024);
+    /// let server = Builder::new()
+    ///     .add_client(ClientBuilder::new("laptop").position(Position::Left).build())?
+    ///     .serve_connection(server_end, None)
+    ///     .await?;
+    /// let client = schengen::client::Builder::new()
+    ///     .name("laptop")
+    ///     .connect_with_stream(client_end);
+    /// let (event, client) = tokio::join!(server.recv_event(), client);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn serve_connection<S>(
+        mut self,
+        stream: S,
+        peer_name_hint: Option<String>,
+    ) -> Result<Server>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + Sync + 'static,
+    {
+        self.peer_name_hint = peer_name_hint;
+        let listener = Listener::Stream(std::sync::Mutex::new(Some(Box::new(stream))));
         self.build_server_with_listener(listener)
     }
 
@@ -631,11 +1561,11 @@ impl Builder {
     ///
     /// * `listener` - An already-bound TCP listener (e.g., from systemd socket activation)
     pub async fn listen_on_stream(self, listener: TcpListener) -> Result<Server> {
-        self.build_server_with_listener(listener)
+        self.build_server_with_listener(Listener::Tcp(listener))
     }
 
     /// Internal helper to build a server with a given listener
-    fn build_server_with_listener(self, listener: TcpListener) -> Result<Server> {
+    fn build_server_with_listener(self, listener: Listener) -> Result<Server> {
         // Build the allowed clients map
         let mut allowed_clients = HashMap::new();
         for client in self.clients {
@@ -643,25 +1573,83 @@ impl Builder {
                 client.name.clone(),
                 ClientConfig {
                     position: client.position,
+                    relative_to: client.relative_to.clone(),
+                    edge_range: client.edge_range,
                 },
             );
         }
 
+        // Options sent to each client with DSOP during the handshake
+        let mut options = Vec::new();
+        if self.preserve_focus {
+            options.push((DsopOption::ScreenPreserveFocus as u32, 1));
+        }
+        if !self.switch_corners.is_empty() {
+            options.push((
+                DsopOption::ScreenSwitchCorners as u32,
+                self.switch_corners.bits(),
+            ));
+        }
+        if self.lock_to_screen {
+            options.push((DsopOption::DefaultLockToScreenState as u32, 1));
+        }
+        if let Some(delay) = self.switch_delay {
+            options.push((DsopOption::ScreenSwitchDelay as u32, delay_millis(delay)));
+        }
+        if let Some(window) = self.switch_double_tap {
+            options.push((DsopOption::ScreenSwitchTwoTap as u32, delay_millis(window)));
+        }
+        if self.heartbeat != DEFAULT_HEARTBEAT {
+            options.push((DsopOption::Heartbeat as u32, delay_millis(self.heartbeat)));
+        }
+        if self.switch_corner_size != 0 {
+            options.push((
+                DsopOption::ScreenSwitchCornerSize as u32,
+                self.switch_corner_size.into(),
+            ));
+        }
+        if !self.capabilities.is_empty() {
+            options.push((DsopOption::SchengenCaps as u32, self.capabilities.bits()));
+        }
+
         let server = Server {
             listener,
-            allowed_clients: Arc::new(allowed_clients),
+            dimensions: self.dimensions,
+            switch_corners: self.switch_corners,
+            switch_corner_size: self.switch_corner_size,
+            lock_to_screen: std::sync::atomic::AtomicBool::new(self.lock_to_screen),
+            relative_mouse_moves: std::sync::atomic::AtomicBool::new(self.relative_mouse_moves),
+            dialect: self.dialect,
+            protocol_version: self.protocol_version,
+            switch_delay: self.switch_delay,
+            switch_double_tap: self.switch_double_tap,
+            heartbeat: (!self.heartbeat.is_zero()).then_some(self.heartbeat),
+            edge_state: std::sync::Mutex::new(layout::EdgeState::default()),
+            options: Arc::new(options),
+            capabilities: self.capabilities,
+            on_accept: self.on_accept,
+            peer_name_hint: self.peer_name_hint,
+            forward_language: self.forward_language,
+            max_file_size: self.max_file_size,
+            max_clipboard_size: self.max_clipboard_size,
+            clipboard_grab_timeout: self.clipboard_grab_timeout,
+            handshake_timeout: self.handshake_timeout,
+            idle_timeout: self.idle_timeout,
+            last_input: std::sync::Mutex::new(Instant::now()),
+            idle: std::sync::atomic::AtomicBool::new(false),
+            #[cfg(feature = "tls")]
+            tls: self.tls,
+            allowed_clients: std::sync::RwLock::new(allowed_clients),
             connected_clients: Arc::new(RwLock::new(HashMap::new())),
             next_client_id: Arc::new(std::sync::atomic::AtomicU64::new(1)),
+            active_screen: std::sync::Mutex::new(None),
+            clipboards: std::sync::Mutex::new(HashMap::new()),
+            next_clipboard_sequence: std::sync::atomic::AtomicU32::new(1),
+            enter_sequence: SequenceCounter::default(),
+            pending_events: std::sync::Mutex::new(VecDeque::new()),
+            pending_notify: tokio::sync::Notify::new(),
         };
 
-        // Spawn a background task to send periodic keepalives (KeepAlive messages) to all clients
-        let connected_clients = Arc::clone(&server.connected_clients);
-        debug!("Spawning keepalive sender task");
-        tokio::spawn(async move {
-            debug!("Keepalive sender task started");
-            Server::keepalive_sender(connected_clients).await;
-        });
-
         Ok(server)
     }
 }
@@ -677,31 +1665,202 @@ impl Default for Builder {
 /// The server manages multiple client connections and handles the Synergy protocol
 /// handshake and message routing.
 pub struct Server {
-    listener: TcpListener,
-    allowed_clients: Arc<HashMap<String, ClientConfig>>,
-    connected_clients: Arc<RwLock<HashMap<ClientId, Arc<RwLock<ConnectedClient>>>>>,
+    listener: Listener,
+    /// Dimensions of the primary screen
+    dimensions: (u16, u16),
+    /// Corners in which the cursor doesn't switch screens, see [`Builder::switch_corners`]
+    switch_corners: CornerMask,
+    switch_corner_size: u16,
+    /// Whether edge crossings are suppressed, see [`Server::set_lock_to_screen`]
+    lock_to_screen: std::sync::atomic::AtomicBool,
+    /// Whether mouse moves are sent as `DMRM`, see [`Server::set_relative_mouse_moves`]
+    relative_mouse_moves: std::sync::atomic::AtomicBool,
+    /// The hello sent to clients, see [`Builder::hello_dialect`]
+    dialect: Dialect,
+    /// The version advertised in the hello, see [`Builder::protocol_version`]
+    protocol_version: ProtocolVersion,
+    /// See [`Builder::switch_delay`] and [`Builder::switch_double_tap`]
+    switch_delay: Option<Duration>,
+    switch_double_tap: Option<Duration>,
+    /// The keepalive interval, `None` if disabled, see [`Builder::heartbeat`]
+    heartbeat: Option<Duration>,
+    /// Tracks the cursor at the edges for the switch delay and double tap
+    edge_state: std::sync::Mutex<layout::EdgeState>,
+    /// The configured layout, see [`Server::add_client`] and [`Server::remove_client`]
+    allowed_clients: std::sync::RwLock<HashMap<String, ClientConfig>>,
+    /// Options sent to each client in the handshake DSOP
+    options: Arc<Vec<(u32, u32)>>,
+    /// The schengen extensions supported by this server
+    capabilities: Capabilities,
+    /// Connection policy callback, see [`Builder::on_accept`]
+    on_accept: Option<AcceptCallback>,
+    /// The client name if a hello has none, see [`Builder::serve_connection`]
+    peer_name_hint: Option<String>,
+    /// See [`Builder::forward_language`]
+    forward_language: bool,
+    /// See [`Builder::max_file_size`]
+    max_file_size: u64,
+    /// See [`Builder::max_clipboard_size`]
+    max_clipboard_size: usize,
+    /// See [`Builder::clipboard_grab_timeout`]
+    clipboard_grab_timeout: Duration,
+    /// See [`Builder::handshake_timeout`]
+    handshake_timeout: Duration,
+    /// See [`Builder::idle_timeout`]
+    idle_timeout: Option<Duration>,
+    /// When input was last sent to a client, or the server started
+    last_input: std::sync::Mutex<Instant>,
+    /// Whether [`ServerEvent::Idle`] was reported and no input was sent since
+    idle: std::sync::atomic::AtomicBool,
+    /// The identity presented to clients if TLS is enabled, see [`Builder::tls`]
+    #[cfg(feature = "tls")]
+    tls: Option<crate::tls::TlsIdentity>,
+    connected_clients: Arc<RwLock<HashMap<ClientId, Arc<RwLock<ClientConnection>>>>>,
     /// Counter for generating unique client IDs
     next_client_id: Arc<std::sync::atomic::AtomicU64>,
+    /// Name of the client the cursor is currently on, `None` for the primary screen
+    active_screen: std::sync::Mutex<Option<String>>,
+    /// The primary screen's clipboards and their sequence numbers
+    clipboards: std::sync::Mutex<HashMap<ClipboardId, (u32, ClipboardContents)>>,
+    /// Counter for the clipboard sequence numbers
+    next_clipboard_sequence: std::sync::atomic::AtomicU32,
+    /// The sequence numbers of `CINN` sent by the server
+    enter_sequence: SequenceCounter,
+    /// Events generated outside of [`Server::recv_event`], returned by its next call
+    pending_events: std::sync::Mutex<VecDeque<ServerEvent>>,
+    /// Wakes up [`Server::recv_event`] when an event is queued in `pending_events`
+    pending_notify: tokio::sync::Notify,
 }
 
 impl Server {
+    /// The connection of a specific client by ID (internal helper)
+    async fn client_connection(
+        &self,
+        client_id: ClientId,
+    ) -> Result<Arc<RwLock<ClientConnection>>> {
+        self.connected_clients
+            .read()
+            .await
+            .get(&client_id)
+            .cloned()
+            .ok_or_else(|| {
+                ServerError::ConnectionError(format!(
+                    "Client with ID {:?} not connected",
+                    client_id
+                ))
+            })
+    }
+
     /// Send a message to a specific client by ID
     async fn send_to(&self, client_id: ClientId, message: Message) -> Result<()> {
-        let client = {
-            let clients = self.connected_clients.read().await;
-            clients
-                .get(&client_id)
-                .ok_or_else(|| {
-                    ServerError::ConnectionError(format!(
-                        "Client with ID {:?} not connected",
-                        client_id
-                    ))
-                })?
-                .clone()
-        };
+        let client = self.client_connection(client_id).await?;
 
+        let is_input = message.is_input();
         let mut client_lock = client.write().await;
-        client_lock.send_message(message).await
+        client_lock.send_message(message).await?;
+        if is_input {
+            self.input_sent();
+        }
+        Ok(())
+    }
+
+    /// Record that input was sent to a client, for [`Builder::idle_timeout`] (internal
+    /// helper)
+    fn input_sent(&self) {
+        if self.idle_timeout.is_none() {
+            return;
+        }
+        *self.last_input.lock().unwrap() = Instant::now();
+        if self.idle.swap(false, std::sync::atomic::Ordering::SeqCst) {
+            self.pending_events
+                .lock()
+                .unwrap()
+                .push_back(ServerEvent::Active);
+            self.pending_notify.notify_one();
+        }
+    }
+
+    /// When [`ServerEvent::Idle`] is due, `None` if it was reported or is disabled
+    fn idle_deadline(&self) -> Option<Instant> {
+        let timeout = self.idle_timeout?;
+        if self.idle.load(std::sync::atomic::Ordering::SeqCst) {
+            return None;
+        }
+        Some(*self.last_input.lock().unwrap() + timeout)
+    }
+
+    /// Send a message to every connected client (internal helper)
+    ///
+    /// Returns the first error, see [`Server::broadcast`].
+    async fn send_to_all(&self, message: Message) -> Result<()> {
+        self.broadcast(message)
+            .await
+            .into_iter()
+            .try_for_each(|(_, result)| result)
+    }
+
+    /// Send a message to every connected client
+    ///
+    /// The message is sent to all clients concurrently, encoded for each client's
+    /// protocol version. Returns the result for each client. A client that can't be
+    /// sent to is disconnected and reported with a
+    /// [`ServerEvent::ClientDisconnected`] by the next [`Server::recv_event`].
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use schengen::server::Server;
+    /// use schengen::protocol::{Message, MessageResetOptions};
+    ///
+    /// # async fn example(server: &Server) {
+    /// for (client_id, result) in server.broadcast(Message::ResetOptions(MessageResetOptions)).await {
+    ///     if let Err(e) = result {
+    ///         eprintln!("Failed to reset the options of {:?}: {}", client_id, e);
+    ///     }
+    /// }
+    /// # }
+    /// ```
+    pub async fn broadcast(&self, message: Message) -> Vec<(ClientId, Result<()>)> {
+        let clients: Vec<_> = self
+            .connected_clients
+            .read()
+            .await
+            .iter()
+            .map(|(client_id, client)| (*client_id, Arc::clone(client)))
+            .collect();
+
+        let mut sends = tokio::task::JoinSet::new();
+        for (client_id, client) in clients {
+            let message = message.clone();
+            sends.spawn(async move {
+                let mut client = client.write().await;
+                let result = client.send_message(message).await;
+                (client_id, client.client.name().to_string(), result)
+            });
+        }
+
+        let mut results = Vec::new();
+        while let Some(sent) = sends.join_next().await {
+            let (client_id, name, result) = sent.expect("sending to a client doesn't panic");
+            if let Err(e) = &result {
+                warn!("Failed to send to client '{}', disconnecting: {}", name, e);
+                if self.forget_client(client_id).await {
+                    self.pending_events.lock().unwrap().push_back(
+                        ServerEvent::ClientDisconnected {
+                            client_id,
+                            name,
+                            reason: DisconnectReason::ConnectionLost,
+                        },
+                    );
+                    self.pending_notify.notify_one();
+                }
+            }
+            results.push((client_id, result));
+        }
+        if message.is_input() && results.iter().any(|(_, result)| result.is_ok()) {
+            self.input_sent();
+        }
+        results
     }
 
     /// Query client information
@@ -716,12 +1875,319 @@ impl Server {
         .await
     }
 
-    /// Remove a client from all tracking structures (internal helper)
+    /// Look up a connected client by name (internal helper)
+    async fn client_by_name(
+        &self,
+        name: &str,
+    ) -> Result<(ClientId, Arc<RwLock<ClientConnection>>)> {
+        let clients = self.connected_clients.read().await;
+        for (id, client) in clients.iter() {
+            if client.read().await.client.name() == name {
+                return Ok((*id, Arc::clone(client)));
+            }
+        }
+        Err(ServerError::ConnectionError(format!(
+            "Client '{}' not connected",
+            name
+        )))
+    }
+
+    /// Look up the client the cursor is on (internal helper)
+    async fn active_client_id(&self) -> Result<ClientId> {
+        let active = self.active_screen.lock().unwrap().clone();
+        let name = active.ok_or(ServerError::NoActiveClient)?;
+        let (client_id, _) = self.client_by_name(&name).await?;
+        Ok(client_id)
+    }
+
+    /// Look up the name of a connected client (internal helper)
+    async fn client_name(&self, client_id: ClientId) -> Option<String> {
+        let client = self
+            .connected_clients
+            .read()
+            .await
+            .get(&client_id)
+            .cloned()?;
+        let name = client.read().await.client.name().to_string();
+        Some(name)
+    }
+
+    /// Compute the grid cell of every configured screen (internal helper)
+    ///
+    /// The primary screen is at `(0, 0)` and labeled `None`, x grows to the right and
+    /// y grows downwards. Clients positioned relative to another client are placed
+    /// next to that client's cell.
+    fn layout_grid(&self) -> Vec<(Option<String>, i32, i32)> {
+        let allowed = self.allowed_clients.read().unwrap();
+        let placements = allowed
+            .iter()
+            .map(|(name, config)| (name.as_str(), config.placement()))
+            .collect();
+        let cells = layout::grid_cells(&placements);
+
+        let mut grid = vec![(None, 0, 0)];
+        for (name, (x, y)) in cells {
+            grid.push((Some(name.to_string()), x, y));
+        }
+        grid
+    }
+
+    /// Render the configured layout as an ASCII grid
+    ///
+    /// The primary (server) screen is labeled `server` and each configured client is
+    /// placed in its direction relative to the server or the client it is positioned
+    /// relative to. The screen the cursor is currently on is marked with a `*`.
+    /// This is intended for debugging and status displays.
+    ///
+    /// ```text
+    /// [laptop ] [server*] [desktop]
+    /// [tablet ]
+    /// ```
+    pub fn render_layout_ascii(&self) -> String {
+        let active = self.active_screen.lock().unwrap().clone();
+        let mut grid = self.layout_grid();
+        // Stable output regardless of HashMap order
+        grid.sort_by(|a, b| (a.2, a.1, &a.0).cmp(&(b.2, b.1, &b.0)));
+
+        let labels: Vec<(String, i32, i32)> = grid
+            .iter()
+            .map(|(name, x, y)| {
+                let is_active = *name == active;
+                let marker = if is_active { "*" } else { "" };
+                let label = name.as_deref().unwrap_or("server");
+                (format!("{}{}", label, marker), *x, *y)
+            })
+            .collect();
+
+        let width = labels.iter().map(|(l, _, _)| l.len()).max().unwrap_or(0);
+        let min_x = labels.iter().map(|(_, x, _)| *x).min().unwrap_or(0);
+        let max_x = labels.iter().map(|(_, x, _)| *x).max().unwrap_or(0);
+        let min_y = labels.iter().map(|(_, _, y)| *y).min().unwrap_or(0);
+        let max_y = labels.iter().map(|(_, _, y)| *y).max().unwrap_or(0);
+
+        let mut lines = Vec::new();
+        for y in min_y..=max_y {
+            let cells: Vec<String> = (min_x..=max_x)
+                .map(|x| {
+                    let names: Vec<&str> = labels
+                        .iter()
+                        .filter(|(_, lx, ly)| *lx == x && *ly == y)
+                        .map(|(l, _, _)| l.as_str())
+                        .collect();
+                    if names.is_empty() {
+                        " ".repeat(width + 2)
+                    } else {
+                        format!("[{:<width$}]", names.join("/"), width = width)
+                    }
+                })
+                .collect();
+            lines.push(cells.join(" ").trim_end().to_string());
+        }
+        lines.join("\n")
+    }
+
+    /// Remove a client from all tracking structures (internal helper)
+    ///
+    /// This should be called when a client disconnects, either gracefully or due to an error.
+    /// If the cursor is on the client's screen it returns to the primary screen.
+    ///
+    /// Returns false if the client was already removed.
+    async fn forget_client(&self, client_id: ClientId) -> bool {
+        // Remove from connected clients
+        let removed = self.connected_clients.write().await.remove(&client_id);
+        let Some(client) = removed else {
+            return false;
+        };
+
+        let client = client.read().await;
+        #[cfg(feature = "tracing")]
+        client
+            .span
+            .in_scope(|| tracing::info!("client disconnected"));
+        let name = client.client.name().to_string();
+        let mut active = self.active_screen.lock().unwrap();
+        if active.as_deref() == Some(name.as_str()) {
+            *active = None;
+        }
+        true
+    }
+
+    /// Add a client to the layout of the running server
+    ///
+    /// The client is validated against the current layout the same way as by
+    /// [`Builder::add_client`] and may connect once this returns.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use schengen::server::{ClientBuilder, Position, Server};
+    /// # async fn example(server: &Server) -> Result<(), Box<dyn std::error::Error>> {
+    /// let tablet = ClientBuilder::new("tablet")
+    ///     .position(Position::Above)
+    ///     .build();
+    /// server.add_client(tablet)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`Builder::add_client`] if the name is already taken,
+    /// the position is already occupied or the client in `relative_to` doesn't exist.
+    pub fn add_client(&self, client: NewClient) -> Result<()> {
+        let mut allowed = self.allowed_clients.write().unwrap();
+        check_position(
+            allowed.iter().map(|(name, config)| {
+                (
+                    name.as_str(),
+                    config.position,
+                    config.relative_to.as_deref(),
+                    config.edge_range,
+                )
+            }),
+            &client,
+        )?;
+        allowed.insert(
+            client.name,
+            ClientConfig {
+                position: client.position,
+                relative_to: client.relative_to,
+                edge_range: client.edge_range,
+            },
+        );
+        Ok(())
+    }
+
+    /// Remove a client from the layout of the running server
+    ///
+    /// The client may no longer connect. If it is connected it is disconnected as by
+    /// [`Server::disconnect_client`] with [`DisconnectReason::Revoked`], which returns
+    /// the cursor to the primary screen if it was on the client's screen.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ServerError::UnknownClient`] if no client with this name is configured
+    /// and [`ServerError::ClientStillReferenced`] if another client is positioned
+    /// relative to it.
+    pub async fn remove_client(&self, name: &str) -> Result<()> {
+        {
+            let mut allowed = self.allowed_clients.write().unwrap();
+            if !allowed.contains_key(name) {
+                return Err(ServerError::UnknownClient(name.to_string()));
+            }
+            if let Some((other, _)) = allowed
+                .iter()
+                .find(|(_, config)| config.relative_to.as_deref() == Some(name))
+            {
+                return Err(ServerError::ClientStillReferenced {
+                    client: name.to_string(),
+                    referenced_by: other.clone(),
+                });
+            }
+            allowed.remove(name);
+        }
+
+        if self.client_by_name(name).await.is_ok() {
+            self.disconnect_client(name, DisconnectReason::Revoked)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Forcibly disconnect a connected client
+    ///
+    /// If the cursor is on the client's screen, the client is sent `COUT` and the cursor
+    /// returns to the primary screen. The client is then sent `CBYE`, or `EUNK` if the
+    /// reason is [`DisconnectReason::Revoked`], and its connection is closed. The next
+    /// call to [`Server::recv_event`] returns [`ServerEvent::ClientDisconnected`] with
+    /// the given reason.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use schengen::server::{DisconnectReason, Server};
+    /// # async fn example(server: &Server) -> Result<(), Box<dyn std::error::Error>> {
+    /// server.disconnect_client("laptop", DisconnectReason::Revoked).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ServerError::ConnectionError`] if no client with this name is connected.
+    pub async fn disconnect_client(&self, name: &str, reason: DisconnectReason) -> Result<()> {
+        let (client_id, client) = self.client_by_name(name).await?;
+
+        let is_active = self.active_screen.lock().unwrap().as_deref() == Some(name);
+        let mut messages = Vec::new();
+        if is_active {
+            messages.push(Message::CursorLeft(crate::protocol::MessageCursorLeft));
+        }
+        messages.push(match reason {
+            DisconnectReason::Revoked => {
+                Message::UnknownClient(crate::protocol::MessageUnknownClient)
+            }
+            _ => Message::Close(crate::protocol::MessageClose),
+        });
+
+        {
+            let mut client_lock = client.write().await;
+            // The client is going away regardless, a failed write only means it's gone already
+            if let Err(e) = client_lock.send_all(&messages).await {
+                debug!("Failed to notify client '{}' of disconnect: {}", name, e);
+            }
+            let _ = client_lock.stream.shutdown().await;
+        }
+
+        if self.forget_client(client_id).await {
+            self.pending_events
+                .lock()
+                .unwrap()
+                .push_back(ServerEvent::ClientDisconnected {
+                    client_id,
+                    name: name.to_string(),
+                    reason,
+                });
+            self.pending_notify.notify_one();
+        }
+        Ok(())
+    }
+
+    /// Close all client connections and stop the server
     ///
-    /// This should be called when a client disconnects, either gracefully or due to an error.
-    async fn remove_client(&self, client_id: ClientId) {
-        // Remove from connected clients
-        self.connected_clients.write().await.remove(&client_id);
+    /// Every connected client is sent `CBYE` and its connection is closed once the
+    /// message is flushed. The listener is closed when the server is dropped at the end
+    /// of this call.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use schengen::server::Server;
+    /// # async fn example(server: Server) -> Result<(), Box<dyn std::error::Error>> {
+    /// server.shutdown().await;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn shutdown(self) {
+        let clients: Vec<Arc<RwLock<ClientConnection>>> = {
+            let mut clients = self.connected_clients.write().await;
+            clients.drain().map(|(_, client)| client).collect()
+        };
+        for client in clients {
+            let mut client_lock = client.write().await;
+            // A failed write only means the client is gone already
+            if let Err(e) = client_lock
+                .send_message(Message::Close(crate::protocol::MessageClose))
+                .await
+            {
+                debug!(
+                    "Failed to send close to client '{}': {}",
+                    client_lock.client.name(),
+                    e
+                );
+            }
+            let _ = client_lock.stream.shutdown().await;
+        }
     }
 
     /// Receive the next event from the server
@@ -739,8 +2205,12 @@ impl Server {
     /// This method will block until an event is available.
     pub async fn recv_event(&self) -> Result<ServerEvent> {
         loop {
+            if let Some(event) = self.pending_events.lock().unwrap().pop_front() {
+                return Ok(event);
+            }
+
             // Get a snapshot of connected clients
-            let client_list: Vec<Arc<RwLock<ConnectedClient>>> = {
+            let client_list: Vec<Arc<RwLock<ClientConnection>>> = {
                 let clients = self.connected_clients.read().await;
                 clients.values().cloned().collect()
             };
@@ -767,11 +2237,13 @@ impl Server {
                                 drop(client_lock);
                                 let client_id = client_info.id();
                                 let client_name = client_info.name().to_string();
-                                self.remove_client(client_id).await;
-                                return Ok(ServerEvent::ClientDisconnected {
-                                    client_id,
-                                    name: client_name,
-                                });
+                                if self.forget_client(client_id).await {
+                                    return Ok(ServerEvent::ClientDisconnected {
+                                        client_id,
+                                        name: client_name,
+                                        reason: DisconnectReason::ConnectionLost,
+                                    });
+                                }
                             }
                         }
                     }
@@ -779,21 +2251,117 @@ impl Server {
             }
 
             // Use select to handle both new connections and messages from existing clients
+            let idle_deadline = self.idle_deadline();
             tokio::select! {
+                // Return queued events
+                _ = self.pending_notify.notified() => {
+                    continue;
+                }
+
+                // Report the idle timeout, unless input was sent in the meantime
+                _ = async {
+                    match idle_deadline {
+                        Some(deadline) => tokio::time::sleep_until(deadline.into()).await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    if self.idle_deadline().is_some_and(|deadline| deadline <= Instant::now()) {
+                        self.idle.store(true, std::sync::atomic::Ordering::SeqCst);
+                        return Ok(ServerEvent::Idle);
+                    }
+                    continue;
+                }
+
                 // Accept new client connections
                 accept_result = self.listener.accept() => {
                     match accept_result {
-                        Ok((stream, _addr)) => {
+                        Ok((stream, addr)) => {
+                            if let Some(on_accept) = &self.on_accept {
+                                if on_accept(&addr) == AcceptDecision::Reject {
+                                    debug!("Rejected connection from {}", addr);
+                                    drop(stream);
+                                    continue;
+                                }
+                            }
+
                             // Generate a unique client ID
                             let client_id = ClientId(
                                 self.next_client_id
                                     .fetch_add(1, std::sync::atomic::Ordering::SeqCst),
                             );
 
-                            match perform_server_handshake(client_id, stream, &self.allowed_clients).await {
-                                Ok(connected_client) => {
+                            let deadline =
+                                tokio::time::Instant::now() + self.handshake_timeout;
+                            let stream =
+                                match tokio::time::timeout_at(deadline, self.transport(stream))
+                                    .await
+                                {
+                                    Ok(Ok(stream)) => stream,
+                                    Ok(Err(e)) => {
+                                        debug!(
+                                            "Failed to set up connection from {}: {}",
+                                            addr, e
+                                        );
+                                        continue;
+                                    }
+                                    Err(_) => {
+                                        debug!("TLS handshake with {} timed out", addr);
+                                        return Ok(ServerEvent::HandshakeFailed {
+                                            addr,
+                                            error: "TLS handshake timed out".to_string(),
+                                        });
+                                    }
+                                };
+
+                            let handshake = Handshake::server(self.handshake_options())
+                                .with_dialect(self.dialect)
+                                .with_version(self.protocol_version);
+                            let handshake = perform_server_handshake(
+                                client_id,
+                                addr,
+                                stream,
+                                &self.allowed_clients,
+                                handshake,
+                                self.heartbeat,
+                                self.peer_name_hint.as_deref(),
+                            );
+                            #[cfg(feature = "tracing")]
+                            let handshake = handshake.instrument(tracing::info_span!(
+                                "handshake",
+                                %addr,
+                                client = tracing::field::Empty,
+                            ));
+                            let handshake = tokio::time::timeout_at(deadline, handshake)
+                                .await
+                                .unwrap_or_else(|_| {
+                                    Err(ServerError::ConnectionError(
+                                        "handshake timed out".to_string(),
+                                    ))
+                                });
+                            match handshake {
+                                Ok(mut connected_client) => {
+                                    connected_client.forward_language = self.forward_language;
+                                    connected_client.clipboard_assembler =
+                                        ClipboardAssembler::with_max_size(
+                                            self.max_clipboard_size,
+                                        );
+                                    connected_client.file_assembler =
+                                        FileTransferAssembler::with_max_size(self.max_file_size);
                                     let client = connected_client.client.clone();
                                     let connected = Arc::new(RwLock::new(connected_client));
+                                    #[cfg(feature = "tracing")]
+                                    let span = connected.read().await.span.clone();
+                                    #[cfg(feature = "tracing")]
+                                    span.in_scope(|| tracing::info!("client connected"));
+                                    if let Some(heartbeat) = self.heartbeat {
+                                        let keepalive = Self::keepalive_sender(
+                                            Arc::downgrade(&connected),
+                                            heartbeat,
+                                        );
+                                        #[cfg(feature = "tracing")]
+                                        let keepalive = keepalive.instrument(span);
+                                        tokio::spawn(keepalive);
+                                    }
 
                                     self.connected_clients
                                         .write()
@@ -803,13 +2371,19 @@ impl Server {
                                     return Ok(ServerEvent::ClientConnected {
                                         client_id,
                                         name: client.name,
+                                        addr,
                                         width: client.width,
                                         height: client.height,
                                     });
                                 }
-                                Err(_e) => {
-                                    // Handshake failed, continue to accept other clients
-                                    continue;
+                                Err(e) => {
+                                    debug!("Handshake with {} failed: {}", addr, e);
+                                    #[cfg(feature = "tracing")]
+                                    tracing::warn!(%addr, error = %e, "handshake failed");
+                                    return Ok(ServerEvent::HandshakeFailed {
+                                        addr,
+                                        error: e.to_string(),
+                                    });
                                 }
                             }
                         }
@@ -830,11 +2404,13 @@ impl Server {
                         Some(Err(client)) => {
                             let client_id = client.id();
                             let client_name = client.name().to_string();
-                            self.remove_client(client_id).await;
-                            return Ok(ServerEvent::ClientDisconnected {
-                                client_id,
-                                name: client_name,
-                            });
+                            if self.forget_client(client_id).await {
+                                return Ok(ServerEvent::ClientDisconnected {
+                                    client_id,
+                                    name: client_name,
+                                    reason: DisconnectReason::ConnectionLost,
+                                });
+                            }
                         }
                         None => {
                             // No messages available, continue
@@ -845,6 +2421,32 @@ impl Server {
         }
     }
 
+    /// A [`Stream`] of the server's events
+    ///
+    /// The stream is driven by [`Server::recv_event`] and ends if it returns an error.
+    /// Calling [`Server::recv_event`] while the stream is in use splits the events
+    /// between both.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use schengen::server::{Server, ServerEvent};
+    /// # async fn example(server: &Server) {
+    /// let mut events = server.events();
+    /// while let Some(event) = events.next().await {
+    ///     if let ServerEvent::ClientConnected { name, addr, .. } = event {
+    ///         println!("{} connected from {}", name, addr);
+    ///     }
+    /// }
+    /// # }
+    /// ```
+    pub fn events(&self) -> ServerEvents<'_> {
+        ServerEvents {
+            server: self,
+            next: Some(Box::pin(self.recv_event())),
+        }
+    }
+
     /// Helper to receive a message from any client (internal)
     ///
     /// Returns either:
@@ -852,7 +2454,7 @@ impl Server {
     /// - Some(Err(client)) - Client disconnected with error
     /// - None - No messages available from any client
     async fn recv_from_any_client(
-        clients: Vec<Arc<RwLock<ConnectedClient>>>,
+        clients: Vec<Arc<RwLock<ClientConnection>>>,
     ) -> Option<std::result::Result<(Client, Message), Client>> {
         for client in clients {
             let mut client_lock = client.write().await;
@@ -870,6 +2472,15 @@ impl Server {
         None
     }
 
+    /// Set up the transport on an accepted stream, performing the TLS handshake if enabled
+    async fn transport(&self, stream: BoxedTransport) -> std::io::Result<BoxedTransport> {
+        #[cfg(feature = "tls")]
+        if let Some(tls) = &self.tls {
+            return tls.accept(stream).await;
+        }
+        Ok(stream)
+    }
+
     /// Handle a message from a client
     /// Returns Some(event) if the caller should handle this, None if it was auto-handled
     async fn handle_client_message(
@@ -905,6 +2516,7 @@ impl Server {
                     let mut c = client.write().await;
                     c.client.width = info.width;
                     c.client.height = info.height;
+                    c.cursor = (info.current_mouse_x, info.current_mouse_y);
                 }
                 drop(clients);
 
@@ -915,18 +2527,78 @@ impl Server {
                 }))
             }
 
-            Message::ClientClipboard(msg) => Ok(Some(ServerEvent::ClipboardData {
-                client_id,
-                id: msg.id,
-                sequence: msg.sequence,
-                data: vec![], // Actual data comes in ClipboardData message
-            })),
+            // Grabs and data from before the latest CINN are stale
+            Message::ClientClipboard(msg) if self.enter_sequence.is_stale(msg.sequence) => {
+                debug!(
+                    "Ignoring stale clipboard grab from '{}' with sequence {}",
+                    client_name, msg.sequence
+                );
+                Ok(None)
+            }
+            Message::ClipboardData(msg) if self.enter_sequence.is_stale(msg.sequence) => {
+                debug!(
+                    "Ignoring stale clipboard data from '{}' with sequence {}",
+                    client_name, msg.sequence
+                );
+                Ok(None)
+            }
+
+            Message::ClientClipboard(msg) => {
+                if let Some(id) = ClipboardId::from_u8(msg.id) {
+                    let clients = self.connected_clients.read().await;
+                    if let Some(client) = clients.get(&client_id) {
+                        let timeout = self.clipboard_grab_timeout;
+                        let pending_grabs = &mut client.write().await.pending_grabs;
+                        pending_grabs.retain(|_, grabbed_at| grabbed_at.elapsed() <= timeout);
+                        pending_grabs.insert(id, Instant::now());
+                    }
+                }
+                Ok(Some(ServerEvent::ClipboardData {
+                    client_id,
+                    id: msg.id,
+                    sequence: msg.sequence,
+                    data: vec![], // Actual data comes in ClipboardData message
+                }))
+            }
+
+            Message::ClipboardData(msg) => {
+                self.assemble_clipboard(client, &msg).await;
+                Ok(Some(ServerEvent::ClipboardData {
+                    client_id,
+                    id: msg.id,
+                    sequence: msg.sequence,
+                    data: msg.data.0,
+                }))
+            }
+
+            Message::DragInfo(msg) => {
+                if let Err(e) = msg.validate() {
+                    warn!("Drag info from client '{}': {}", client_name, e);
+                }
+                let clients = self.connected_clients.read().await;
+                if let Some(client) = clients.get(&client_id) {
+                    client.write().await.dropped_paths = msg.paths().into();
+                }
+                Ok(None)
+            }
+
+            Message::FileTransfer(msg) => {
+                self.assemble_file(client, &msg).await;
+                Ok(None)
+            }
+
+            Message::LegacySynergy(msg) => {
+                let clients = self.connected_clients.read().await;
+                if let Some(client) = clients.get(&client_id) {
+                    client.write().await.languages = msg.languages();
+                }
+                Ok(None)
+            }
 
-            Message::ClipboardData(msg) => Ok(Some(ServerEvent::ClipboardData {
+            Message::SecureEncryption(msg) => Ok(Some(ServerEvent::SecureInput {
                 client_id,
-                id: msg.id,
-                sequence: msg.sequence,
-                data: msg.data.0.into_bytes(),
+                name: client_name.to_string(),
+                app_name: msg.data.0,
             })),
 
             Message::ScreenSaverChange(msg) => Ok(Some(ServerEvent::ScreenSaverChanged {
@@ -936,50 +2608,680 @@ impl Server {
 
             Message::InfoAcknowledgment(_) => Ok(None),
 
+            // Schengen clients reply to our capabilities with their own
+            Message::SetOptions(msg) => {
+                if let Some(caps) = msg.get(DsopOption::SchengenCaps) {
+                    debug!("Client '{}' capabilities: {:#x}", client_name, caps);
+                    let clients = self.connected_clients.read().await;
+                    if let Some(client) = clients.get(&client_id) {
+                        let mut client = client.write().await;
+                        client.client.capabilities = Capabilities(caps);
+                        #[cfg(feature = "tracing")]
+                        client.span.in_scope(|| {
+                            tracing::debug!(
+                                capabilities = caps,
+                                negotiated =
+                                    self.capabilities.intersection(Capabilities(caps)).bits(),
+                                "capabilities negotiated"
+                            )
+                        });
+                    }
+                }
+                Ok(None)
+            }
+
             Message::Close(_) => {
-                self.remove_client(client_id).await;
+                if !self.forget_client(client_id).await {
+                    return Ok(None);
+                }
                 Ok(Some(ServerEvent::ClientDisconnected {
                     client_id,
                     name: client_name.to_string(),
+                    reason: DisconnectReason::ClientClosed,
                 }))
             }
 
-            // Ignore other messages
-            _ => Ok(None),
+            // Ignore other messages
+            _ => Ok(None),
+        }
+    }
+
+    /// The schengen extensions supported by both this server and the given client
+    ///
+    /// Returns `None` if the client is not connected.
+    pub async fn negotiated_capabilities(&self, client_id: ClientId) -> Option<Capabilities> {
+        let clients = self.connected_clients.read().await;
+        let client = clients.get(&client_id)?;
+        let caps = client.read().await.client.capabilities;
+        Some(self.capabilities.intersection(caps))
+    }
+
+    /// Feed a clipboard chunk from a client to its assembler (internal helper)
+    ///
+    /// Once a clipboard is complete a [`ServerEvent::ClipboardReceived`] is queued,
+    /// followed by a [`ServerEvent::ClipboardChanged`] if the client grabbed it before.
+    /// The first error of a transfer queues a [`ServerEvent::ClipboardTransferAborted`].
+    async fn assemble_clipboard(&self, client: &Client, msg: &MessageClipboardData) {
+        let connected = self
+            .connected_clients
+            .read()
+            .await
+            .get(&client.id())
+            .cloned();
+        let Some(connected) = connected else {
+            return;
+        };
+
+        let pushed = {
+            let mut connected = connected.write().await;
+            if matches!(msg.mark, clipboard::MARK_SINGLE | clipboard::MARK_FIRST) {
+                connected.discarding_clipboards.remove(&msg.id);
+            }
+            match connected.clipboard_assembler.push(msg) {
+                // The rest of a transfer that already failed
+                Err(_) if connected.discarding_clipboards.contains(&msg.id) => return,
+                Err(e) => {
+                    connected.discarding_clipboards.insert(msg.id);
+                    Err(e)
+                }
+                pushed => pushed,
+            }
+        };
+        let data = match pushed {
+            Ok(Some(data)) => data,
+            Ok(None) => return,
+            Err(e) => {
+                warn!(
+                    "Invalid clipboard data from client '{}': {}",
+                    client.name(),
+                    e
+                );
+                self.pending_events.lock().unwrap().push_back(
+                    ServerEvent::ClipboardTransferAborted {
+                        client_id: client.id(),
+                        name: client.name().to_string(),
+                        id: msg.id,
+                        error: e.to_string(),
+                    },
+                );
+                self.pending_notify.notify_one();
+                return;
+            }
+        };
+        let Some(id) = ClipboardId::from_u8(msg.id) else {
+            warn!(
+                "Unknown clipboard id {} from client '{}'",
+                msg.id,
+                client.name()
+            );
+            return;
+        };
+        match ClipboardContents::from_bytes(&data) {
+            Ok(contents) => {
+                let grabbed = connected
+                    .write()
+                    .await
+                    .pending_grabs
+                    .remove(&id)
+                    .is_some_and(|grabbed_at| grabbed_at.elapsed() <= self.clipboard_grab_timeout);
+                let mut events = self.pending_events.lock().unwrap();
+                events.push_back(ServerEvent::ClipboardReceived {
+                    client_id: client.id(),
+                    name: client.name().to_string(),
+                    id,
+                    contents: contents.clone(),
+                });
+                if grabbed {
+                    events.push_back(ServerEvent::ClipboardChanged {
+                        client_id: client.id(),
+                        name: client.name().to_string(),
+                        id,
+                        contents,
+                    });
+                }
+                drop(events);
+                self.pending_notify.notify_one();
+            }
+            Err(e) => warn!("Invalid clipboard from client '{}': {}", client.name(), e),
+        }
+    }
+
+    /// Feed a file chunk from a client to its assembler (internal helper)
+    ///
+    /// Queues a [`ServerEvent::FileReceived`] once a file is complete and a
+    /// [`ServerEvent::FileTransferAborted`] for the first error of a transfer.
+    async fn assemble_file(&self, client: &Client, msg: &MessageFileTransfer) {
+        let connected = self
+            .connected_clients
+            .read()
+            .await
+            .get(&client.id())
+            .cloned();
+        let Some(connected) = connected else {
+            return;
+        };
+
+        let aborted = |path, error| ServerEvent::FileTransferAborted {
+            client_id: client.id(),
+            name: client.name().to_string(),
+            path,
+            error,
+        };
+        let mut events = Vec::new();
+        let mut connected = connected.write().await;
+        if msg.mark == file_transfer::MARK_START {
+            if connected.file_assembler.is_pending() {
+                let path = connected.dropped_paths.pop_front();
+                events.push(aborted(path, "Interrupted by the next file".to_string()));
+            }
+            connected.discarding_file = false;
+        }
+        match connected.file_assembler.push(msg) {
+            Ok(Some(bytes)) => events.push(ServerEvent::FileReceived {
+                client_id: client.id(),
+                name: client.name().to_string(),
+                path: connected.dropped_paths.pop_front(),
+                bytes,
+            }),
+            Ok(None) => {}
+            // The rest of a transfer that already failed
+            Err(_) if connected.discarding_file => {}
+            Err(e) => {
+                warn!(
+                    "Invalid file transfer from client '{}': {}",
+                    client.name(),
+                    e
+                );
+                connected.discarding_file = true;
+                let path = connected.dropped_paths.pop_front();
+                events.push(aborted(path, e.to_string()));
+            }
+        }
+        drop(connected);
+
+        if !events.is_empty() {
+            self.pending_events.lock().unwrap().extend(events);
+            self.pending_notify.notify_one();
+        }
+    }
+
+    /// The number of connected clients
+    ///
+    /// Cheaper than [`Server::clients`] as nothing is copied. Clients that were just
+    /// removed from the layout are counted until they are disconnected.
+    pub async fn client_count(&self) -> usize {
+        self.connected_clients.read().await.len()
+    }
+
+    /// Whether a client with the given name is connected, e.g. before
+    /// [`Server::switch_to`]
+    pub async fn is_client_connected(&self, name: &str) -> bool {
+        self.client_by_name(name).await.is_ok()
+    }
+
+    /// Get a list of currently connected clients
+    ///
+    /// Returns a snapshot of the connected clients at the time of the call, with their
+    /// address, screen size and place in the layout.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use schengen::server::Server;
+    /// # async fn example(server: &Server) {
+    /// for client in server.clients().await {
+    ///     println!(
+    ///         "{} at {} ({:?}), {:?} pixels",
+    ///         client.name(),
+    ///         client.addr,
+    ///         client.position,
+    ///         client.dimensions
+    ///     );
+    /// }
+    /// # }
+    /// ```
+    pub async fn clients(&self) -> Vec<ConnectedClient> {
+        let connections: Vec<Arc<RwLock<ClientConnection>>> = {
+            let clients = self.connected_clients.read().await;
+            clients.values().cloned().collect()
+        };
+        let mut result = Vec::new();
+        for connection in connections {
+            let connection = connection.read().await;
+            let client = &connection.client;
+            // Clients removed from the layout are about to be disconnected
+            let Some(config) = self
+                .allowed_clients
+                .read()
+                .unwrap()
+                .get(client.name())
+                .cloned()
+            else {
+                continue;
+            };
+            result.push(ConnectedClient {
+                client: client.clone(),
+                addr: connection.addr,
+                position: config.position,
+                relative_to: config.relative_to,
+                dimensions: (client.width != 0 && client.height != 0)
+                    .then_some((client.width, client.height)),
+                connected_at: connection.connected_at,
+                languages: connection.languages.clone(),
+            });
+        }
+        result
+    }
+
+    /// Send a cursor entered message to a client
+    ///
+    /// This notifies the client that the cursor has entered their screen. Clients
+    /// send `sequence` back with their clipboard grabs, grabs and clipboard data with
+    /// a lower sequence number than the latest `CINN` are ignored as stale.
+    pub async fn send_cursor_entered(
+        &self,
+        client_id: ClientId,
+        x: i16,
+        y: i16,
+        sequence: u32,
+        mask: u16,
+    ) -> Result<()> {
+        let msg = Message::CursorEntered(crate::protocol::MessageCursorEntered {
+            x,
+            y,
+            sequence,
+            mask,
+        });
+        self.send_to(client_id, msg).await?;
+        self.enter_sequence.record(sequence);
+        self.send_clipboards(client_id).await?;
+
+        let name = self.client_name(client_id).await;
+        *self.active_screen.lock().unwrap() = name;
+        Ok(())
+    }
+
+    /// Move the cursor onto a client's screen at the given position (internal helper)
+    ///
+    /// Sends `COUT` to the previously active client, if any, then `CINN` with a fresh
+    /// sequence number to the target.
+    async fn enter_client(&self, client_id: ClientId, x: i16, y: i16) -> Result<()> {
+        let previous = self.active_screen.lock().unwrap().clone();
+        if let Some(previous) = previous {
+            if let Ok((previous_id, _)) = self.client_by_name(&previous).await {
+                if previous_id != client_id {
+                    self.send_cursor_left(previous_id).await?;
+                }
+            }
+        }
+
+        let sequence = self.enter_sequence.next();
+        self.send_cursor_entered(client_id, x, y, sequence, 0).await
+    }
+
+    /// Report the cursor position and move the cursor to a neighboring screen at an edge
+    ///
+    /// `(x, y)` is the cursor position on the screen the cursor is currently on, the
+    /// primary screen or the active client. If the position is on an edge of that
+    /// screen and a connected client or the primary screen is next to it in the
+    /// layout, the cursor crosses onto the neighbor: the active client is sent `COUT`
+    /// and the new one `CINN`. If several clients share the edge, see
+    /// [`ClientBuilder::edge_range`], the cursor enters the one whose range it crosses.
+    /// The entry position is mapped onto the neighbor's resolution with
+    /// [`layout::map_crossing`].
+    ///
+    /// The cursor doesn't cross while it is [locked to the screen](Self::set_lock_to_screen)
+    /// or in one of the [`Builder::switch_corners`]. With a [`Builder::switch_delay`] or
+    /// [`Builder::switch_double_tap`] it only crosses once it stayed at the edge long
+    /// enough or tapped it twice, the host should keep reporting the position while
+    /// the cursor is pushed against the edge.
+    ///
+    /// Returns where the cursor entered if it crossed, a host should then warp its
+    /// pointer or forward further motion to the entered screen.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use schengen::server::Server;
+    /// # async fn example(server: &Server) -> Result<(), Box<dyn std::error::Error>> {
+    /// if let Some(crossing) = server.update_cursor(0, 500).await? {
+    ///     println!("Entered {:?} at {},{}", crossing.screen, crossing.x, crossing.y);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn update_cursor(&self, x: i16, y: i16) -> Result<Option<Crossing>> {
+        let active = self.active_screen.lock().unwrap().clone();
+        let (active_id, size) = match &active {
+            Some(name) => {
+                let (client_id, client) = self.client_by_name(name).await?;
+                let client = client.read().await;
+                (Some(client_id), (client.client.width, client.client.height))
+            }
+            None => (None, self.dimensions),
+        };
+
+        if self.is_locked_to_screen()
+            || layout::in_corner(x, y, size, self.switch_corners, self.switch_corner_size)
+        {
+            self.edge_state.lock().unwrap().away();
+            return Ok(None);
+        }
+
+        let Some((direction, target, crossing)) = self.find_crossing(&active, x, y, size).await
+        else {
+            self.edge_state.lock().unwrap().away();
+            return Ok(None);
+        };
+
+        let allowed = self.edge_state.lock().unwrap().at_edge(
+            direction,
+            Instant::now(),
+            self.switch_delay,
+            self.switch_double_tap,
+        );
+        if !allowed {
+            return Ok(None);
+        }
+
+        match target {
+            Some(client_id) => self.enter_client(client_id, crossing.x, crossing.y).await?,
+            None => {
+                if let Some(client_id) = active_id {
+                    self.send_cursor_left(client_id).await?;
+                }
+            }
+        }
+        Ok(Some(crossing))
+    }
+
+    /// Find the screen the cursor crosses onto at `(x, y)` on the active screen (internal
+    /// helper)
+    ///
+    /// Returns the edge, the connected client or `None` for the primary screen and the
+    /// entry position on it.
+    async fn find_crossing(
+        &self,
+        active: &Option<String>,
+        x: i16,
+        y: i16,
+        size: (u16, u16),
+    ) -> Option<(Position, Option<ClientId>, Crossing)> {
+        let grid = self.layout_grid();
+        let (_, cell_x, cell_y) = grid.iter().find(|(name, _, _)| name == active)?;
+        let configs = self.allowed_clients.read().unwrap().clone();
+
+        for direction in layout::edges_at(x, y, size) {
+            let (dx, dy) = layout::step(direction);
+            let neighbors = grid
+                .iter()
+                .filter(|(_, nx, ny)| (*nx, *ny) == (cell_x + dx, cell_y + dy));
+
+            for (neighbor, _, _) in neighbors {
+                // The parts of the shared edge covered by this screen and the neighbor
+                let (source, target) = layout::shared_spans(
+                    direction,
+                    screen_placement(active, &configs),
+                    screen_placement(neighbor, &configs),
+                );
+                if !layout::crosses_into(direction, x, y, size, source, target) {
+                    continue;
+                }
+
+                let (client_id, to) = match neighbor {
+                    Some(name) => {
+                        // Clients that aren't connected are skipped
+                        let Ok((client_id, client)) = self.client_by_name(name).await else {
+                            continue;
+                        };
+                        let client = client.read().await;
+                        (Some(client_id), (client.client.width, client.client.height))
+                    }
+                    None => (None, self.dimensions),
+                };
+                let (x, y) = layout::map_crossing_spans(direction, x, y, size, to, source, target);
+                let crossing = Crossing {
+                    screen: neighbor.clone(),
+                    x,
+                    y,
+                };
+                return Some((direction, client_id, crossing));
+            }
+        }
+        None
+    }
+
+    /// Lock the cursor to the screen it is on
+    ///
+    /// While locked, [`Server::update_cursor`] never crosses onto another screen, so
+    /// no `CINN` or `COUT` is sent for edge crossings. This is typically bound to a
+    /// hotkey like Scroll Lock, e.g. for games that move the cursor to the screen edge.
+    /// Explicit switches like [`Server::switch_to`] are not affected.
+    ///
+    /// The initial state is set with [`Builder::default_lock_to_screen`].
+    pub fn set_lock_to_screen(&self, locked: bool) {
+        self.lock_to_screen
+            .store(locked, std::sync::atomic::Ordering::SeqCst);
+    }
+
+    /// Whether the cursor is locked to the screen it is on, see
+    /// [`Server::set_lock_to_screen`]
+    pub fn is_locked_to_screen(&self) -> bool {
+        self.lock_to_screen
+            .load(std::sync::atomic::Ordering::SeqCst)
+    }
+
+    /// Send mouse moves as distances instead of positions
+    ///
+    /// While enabled, [`Server::inject_mouse_move`] sends `DMRM` instead of `DMMV`,
+    /// e.g. for games that capture the pointer. The change is sent to every connected
+    /// client with the [`DsopOption::RelativeMouseMoves`] (`MDLT`) option, clients
+    /// that connect later receive it as part of the handshake.
+    ///
+    /// The initial state is set with [`Builder::relative_mouse_moves`].
+    pub async fn set_relative_mouse_moves(&self, relative: bool) -> Result<()> {
+        self.relative_mouse_moves
+            .store(relative, std::sync::atomic::Ordering::SeqCst);
+
+        let msg = Message::SetOptions(MessageSetOptions::from_typed(&[(
+            DsopOption::RelativeMouseMoves,
+            relative.into(),
+        )]));
+        self.send_to_all(msg).await
+    }
+
+    /// Whether mouse moves are sent as distances, see
+    /// [`Server::set_relative_mouse_moves`]
+    pub fn relative_mouse_moves(&self) -> bool {
+        self.relative_mouse_moves
+            .load(std::sync::atomic::Ordering::SeqCst)
+    }
+
+    /// Tell every connected client that the screensaver started or stopped
+    ///
+    /// Sends `CSEC` so clients blank or unblank their screens together with the
+    /// primary screen. Clients report their own screensaver with
+    /// [`ServerEvent::ScreenSaverChanged`], a host that wants to synchronize all
+    /// screens passes those on with this method.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use schengen::server::{Server, ServerEvent};
+    /// # async fn example(server: &Server) -> Result<(), Box<dyn std::error::Error>> {
+    /// if let ServerEvent::ScreenSaverChanged { active, .. } = server.recv_event().await? {
+    ///     server.set_screensaver(active).await?;
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn set_screensaver(&self, active: bool) -> Result<()> {
+        let msg = Message::ScreenSaverChange(crate::protocol::MessageScreenSaverChange {
+            state: active.into(),
+        });
+        self.send_to_all(msg).await
+    }
+
+    /// The options sent to a new client with `DSOP` (internal helper)
+    fn handshake_options(&self) -> Vec<(u32, u32)> {
+        let mut options = self.options.as_ref().clone();
+        if self.relative_mouse_moves() {
+            options.push((DsopOption::RelativeMouseMoves as u32, 1));
         }
+        options
     }
 
-    /// Get a list of currently connected clients
+    /// Switch to a client's screen
     ///
-    /// Returns a snapshot of the connected clients at the time of the call.
-    pub async fn clients(&self) -> Vec<Client> {
-        let clients = self.connected_clients.read().await;
-        let mut result = Vec::new();
-        for (_, client_lock) in clients.iter() {
-            let client = client_lock.read().await;
-            result.push(client.client.clone());
+    /// If the cursor is on another client's screen, that client is sent `COUT` first.
+    /// The target client is then sent `CINN` with a fresh sequence number at the cursor
+    /// position it last reported in `DINF`. Nothing is sent if the cursor is already on
+    /// the client's screen.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use schengen::server::Server;
+    /// # async fn example(server: &Server) -> Result<(), Box<dyn std::error::Error>> {
+    /// server.switch_to("laptop").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ServerError::ConnectionError`] if no client with this name is connected.
+    pub async fn switch_to(&self, client_name: &str) -> Result<()> {
+        let (client_id, client) = self.client_by_name(client_name).await?;
+        if self.active_screen.lock().unwrap().as_deref() == Some(client_name) {
+            return Ok(());
         }
-        result
+
+        let (x, y) = client.read().await.cursor;
+        self.enter_client(client_id, x as i16, y as i16).await
     }
 
-    /// Send a cursor entered message to a client
+    /// Switch to a client's screen and place the cursor at its center
     ///
-    /// This notifies the client that the cursor has entered their screen.
-    pub async fn send_cursor_entered(
+    /// If the cursor is on another client's screen, that client is sent `COUT` first.
+    /// The target client is then sent, in this order:
+    ///
+    /// 1. `CINN` with the center of its screen and a fresh sequence number, followed by
+    ///    any clipboards it hasn't seen yet (see [`Server::set_primary_clipboard`])
+    /// 2. `DMMV` to the center of its screen
+    ///
+    /// The follow-up `DMMV` helps window managers that don't place the pointer
+    /// precisely on `CINN` alone.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ServerError::ConnectionError`] if no client with this name is connected.
+    pub async fn switch_to_centered(&self, name: &str) -> Result<()> {
+        let (client_id, client) = self.client_by_name(name).await?;
+        let (x, y) = {
+            let client = client.read().await;
+            (
+                (client.client.width / 2) as i16,
+                (client.client.height / 2) as i16,
+            )
+        };
+
+        self.enter_client(client_id, x, y).await?;
+        self.send_mouse_move(client_id, x, y).await
+    }
+
+    /// Set the contents of one of the primary screen's clipboards
+    ///
+    /// This is the entry point for feeding the local clipboard into the session. The
+    /// entries are combined into one multi-format [`ClipboardContents`] and all connected
+    /// clients are notified that the primary screen now owns this clipboard. The data
+    /// itself is sent to a client when the cursor enters it with
+    /// [`send_cursor_entered`](Self::send_cursor_entered), so clients only receive the
+    /// data they can actually paste.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use schengen::server::Server;
+    /// use schengen::protocol::clipboard::{ClipboardFormat, ClipboardId};
+    ///
+    /// # async fn example(server: &Server) -> Result<(), Box<dyn std::error::Error>> {
+    /// server
+    ///     .set_primary_clipboard(
+    ///         ClipboardId::Clipboard,
+    ///         &[(ClipboardFormat::Text, b"hello".to_vec())],
+    ///     )
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn set_primary_clipboard(
         &self,
-        client_id: ClientId,
-        x: i16,
-        y: i16,
-        sequence: u32,
-        mask: u16,
+        id: ClipboardId,
+        entries: &[(ClipboardFormat, Vec<u8>)],
     ) -> Result<()> {
-        let msg = Message::CursorEntered(crate::protocol::MessageCursorEntered {
-            x,
-            y,
-            sequence,
-            mask,
-        });
-        self.send_to(client_id, msg).await
+        let sequence = self
+            .next_clipboard_sequence
+            .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        let contents = ClipboardContents::new(entries.to_vec());
+        self.clipboards
+            .lock()
+            .unwrap()
+            .insert(id, (sequence, contents));
+
+        // Notify every client that the primary screen grabbed the clipboard
+        let clients: Vec<Arc<RwLock<ClientConnection>>> = {
+            let clients = self.connected_clients.read().await;
+            clients.values().cloned().collect()
+        };
+        for client in clients {
+            let mut client_lock = client.write().await;
+            let grab = Message::ClientClipboard(crate::protocol::MessageClientClipboard {
+                id: id.into(),
+                sequence,
+            });
+            if let Err(e) = client_lock.send_message(grab).await {
+                debug!(
+                    "Failed to send clipboard grab to client '{}': {}",
+                    client_lock.client.name(),
+                    e
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Send the primary clipboards the client hasn't received yet (internal helper)
+    async fn send_clipboards(&self, client_id: ClientId) -> Result<()> {
+        let clipboards: Vec<(ClipboardId, u32, ClipboardContents)> = self
+            .clipboards
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, (sequence, contents))| (*id, *sequence, contents.clone()))
+            .collect();
+        if clipboards.is_empty() {
+            return Ok(());
+        }
+
+        let client = match self.connected_clients.read().await.get(&client_id) {
+            Some(client) => Arc::clone(client),
+            None => return Ok(()),
+        };
+        let mut client_lock = client.write().await;
+        for (id, sequence, contents) in clipboards {
+            if client_lock.clipboard_sequences.get(&id) == Some(&sequence) {
+                continue;
+            }
+            let messages: Vec<Message> = contents
+                .to_messages(id, 0)
+                .into_iter()
+                .map(Message::from)
+                .collect();
+            client_lock.send_all(&messages).await?;
+            client_lock.clipboard_sequences.insert(id, sequence);
+        }
+        Ok(())
     }
 
     /// Send a cursor left message to a client
@@ -990,7 +3292,15 @@ impl Server {
             client_id,
             Message::CursorLeft(crate::protocol::MessageCursorLeft),
         )
-        .await
+        .await?;
+
+        // Only reset to the primary screen if the cursor hasn't already entered another client
+        let name = self.client_name(client_id).await;
+        let mut active = self.active_screen.lock().unwrap();
+        if name.is_some() && *active == name {
+            *active = None;
+        }
+        Ok(())
     }
 
     /// Send an absolute mouse move message to a client
@@ -1001,6 +3311,18 @@ impl Server {
         self.send_to(client_id, msg).await
     }
 
+    /// Send a relative mouse move message to a client
+    pub async fn send_mouse_relative_move(
+        &self,
+        client_id: ClientId,
+        dx: i16,
+        dy: i16,
+    ) -> Result<()> {
+        let msg =
+            Message::MouseRelativeMove(crate::protocol::MessageMouseRelativeMove { x: dx, y: dy });
+        self.send_to(client_id, msg).await
+    }
+
     /// Send a key down message to a client
     pub async fn send_key_down(
         &self,
@@ -1072,6 +3394,152 @@ impl Server {
         self.send_to(client_id, msg).await
     }
 
+    /// Move the mouse on the client the cursor is on
+    ///
+    /// This injects input that doesn't come from the primary screen, e.g. for
+    /// automation and remote control tools.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use schengen::server::Server;
+    /// # async fn example(server: &Server) -> Result<(), Box<dyn std::error::Error>> {
+    /// server.switch_to("laptop").await?;
+    /// server.inject_mouse_move(100, 200).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// If [relative mouse moves](Self::set_relative_mouse_moves) are enabled, the move
+    /// is sent as `DMRM` with the distance from the position last sent to the client,
+    /// otherwise as `DMMV`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ServerError::NoActiveClient`] if the cursor is on the primary screen.
+    pub async fn inject_mouse_move(&self, x: i16, y: i16) -> Result<()> {
+        let client_id = self.active_client_id().await?;
+        if !self.relative_mouse_moves() {
+            return self.send_mouse_move(client_id, x, y).await;
+        }
+
+        let Some(client) = self.connected_clients.read().await.get(&client_id).cloned() else {
+            return Err(ServerError::NoActiveClient);
+        };
+        let mut client = client.write().await;
+        let (from_x, from_y) = client.pointer;
+        let msg = Message::MouseRelativeMove(crate::protocol::MessageMouseRelativeMove {
+            x: x.saturating_sub(from_x),
+            y: y.saturating_sub(from_y),
+        });
+        client.send_message(msg).await?;
+        self.input_sent();
+        Ok(())
+    }
+
+    /// Move the mouse on the client the cursor is on by a distance
+    ///
+    /// Sent as `DMRM`, e.g. for the raw motion of a game that keeps the pointer in
+    /// place, regardless of [`Server::set_relative_mouse_moves`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ServerError::NoActiveClient`] if the cursor is on the primary screen.
+    pub async fn inject_mouse_relative_move(&self, dx: i16, dy: i16) -> Result<()> {
+        let client_id = self.active_client_id().await?;
+        self.send_mouse_relative_move(client_id, dx, dy).await
+    }
+
+    /// Press or release a key on the client the cursor is on
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ServerError::NoActiveClient`] if the cursor is on the primary screen.
+    pub async fn inject_key(&self, key: KeyEvent) -> Result<()> {
+        let client_id = self.active_client_id().await?;
+        match key {
+            KeyEvent::Down {
+                keyid,
+                mask,
+                button,
+            } => self.send_key_down(client_id, keyid, mask, button).await,
+            KeyEvent::DownWithLanguage {
+                keyid,
+                mask,
+                button,
+                lang,
+            } => {
+                let msg =
+                    Message::KeyDownWithLanguage(crate::protocol::MessageKeyDownWithLanguage {
+                        keyid,
+                        mask,
+                        button,
+                        lang: lang.into(),
+                    });
+                self.send_to(client_id, msg).await
+            }
+            KeyEvent::Up {
+                keyid,
+                mask,
+                button,
+            } => self.send_key_up(client_id, keyid, mask, button).await,
+        }
+    }
+
+    /// Scroll the mouse wheel on the client the cursor is on
+    ///
+    /// See [`Server::send_mouse_wheel`] for the meaning of the deltas.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ServerError::NoActiveClient`] if the cursor is on the primary screen.
+    pub async fn inject_mouse_wheel(&self, xdelta: i16, ydelta: i16) -> Result<()> {
+        let client_id = self.active_client_id().await?;
+        self.send_mouse_wheel(client_id, xdelta, ydelta).await
+    }
+
+    /// Type text on a client
+    ///
+    /// Each character is sent as a key press, `DKDL` with the given keyboard language
+    /// followed by `DKUP`. Clients that negotiated a protocol version before 1.8 do not
+    /// support `DKDL` and receive `DKDN` without the language instead, as do all
+    /// clients if [`Builder::forward_language`] is disabled. `\n` and `\t`
+    /// are sent as the Return and Tab keys, characters outside the Basic Multilingual
+    /// Plane can't be represented in the protocol and are skipped.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use schengen::server::{ClientId, Server};
+    /// # async fn example(server: &Server, client_id: ClientId) -> Result<(), Box<dyn std::error::Error>> {
+    /// server.send_text(client_id, "Grüß Gott\n", "de").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn send_text(&self, client_id: ClientId, text: &str, lang: &str) -> Result<()> {
+        let mut messages = Vec::new();
+        for c in text.chars() {
+            let Some(KeyId(keyid)) = KeyId::from_char(c) else {
+                debug!("Skipping character {:?} outside the BMP", c);
+                continue;
+            };
+            messages.push(Message::KeyDownWithLanguage(
+                crate::protocol::MessageKeyDownWithLanguage {
+                    keyid,
+                    mask: 0,
+                    button: 0,
+                    lang: lang.into(),
+                },
+            ));
+            messages.push(Message::KeyUp(crate::protocol::MessageKeyUp {
+                keyid,
+                mask: 0,
+                button: 0,
+            }));
+        }
+        self.send_all(client_id, &messages).await
+    }
+
     /// Send an arbitrary message to a client
     ///
     /// This is a low-level method that allows sending any protocol message to a client.
@@ -1080,46 +3548,94 @@ impl Server {
         self.send_to(client_id, message).await
     }
 
-    /// Background task to send periodic keepalive messages to all connected clients
+    /// Send a batch of messages to a client
     ///
-    /// This prevents clients from timing out and declaring the server dead.
-    /// Sends a KeepAlive message every 3 seconds to each connected client.
-    async fn keepalive_sender(
-        connected_clients: Arc<RwLock<HashMap<ClientId, Arc<RwLock<ConnectedClient>>>>>,
-    ) {
-        use tokio::time::{Duration, interval};
+    /// All messages are serialized into a single buffer and written with one write
+    /// and a single flush. Messages are sent in order.
+    pub async fn send_all(&self, client_id: ClientId, messages: &[Message]) -> Result<()> {
+        let client = self.client_connection(client_id).await?;
 
-        let mut interval = interval(Duration::from_secs(3));
+        let mut client_lock = client.write().await;
+        client_lock.send_all(messages).await?;
+        if messages.iter().any(Message::is_input) {
+            self.input_sent();
+        }
+        Ok(())
+    }
+
+    /// Background task to send periodic keepalive messages to a connected client
+    ///
+    /// This prevents the client from timing out and declaring the server dead. The task
+    /// ends once the client is disconnected.
+    async fn keepalive_sender(client: Weak<RwLock<ClientConnection>>, heartbeat: Duration) {
+        let start = tokio::time::Instant::now() + heartbeat;
+        let mut interval = tokio::time::interval_at(start, heartbeat);
 
         loop {
             interval.tick().await;
-            debug!("Keepalive sender tick");
-
-            // Get snapshot of connected clients
-            let clients: Vec<Arc<RwLock<ConnectedClient>>> = {
-                let clients_map = connected_clients.read().await;
-                clients_map.values().cloned().collect()
+            let Some(client) = client.upgrade() else {
+                return;
             };
-
-            if clients.is_empty() {
-                continue;
+            let mut client_lock = client.write().await;
+            let client_name = client_lock.client.name().to_string();
+            let keepalive = Message::KeepAlive(MessageKeepAlive);
+            match client_lock.send_message(keepalive).await {
+                Ok(_) => {
+                    debug!("Sent keepalive to client '{}'", client_name);
+                }
+                Err(e) => {
+                    debug!(
+                        "Failed to send keepalive to client '{}': {}",
+                        client_name, e
+                    );
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!(error = %e, "failed to send keepalive");
+                    return;
+                }
             }
+        }
+    }
+}
 
-            for client in clients {
-                let mut client_lock = client.write().await;
-                let client_name = client_lock.client.name().to_string();
-                let keepalive = Message::KeepAlive(MessageKeepAlive);
-                match client_lock.send_message(keepalive).await {
-                    Ok(_) => {
-                        debug!("Sent keepalive to client '{}'", client_name);
-                    }
-                    Err(e) => {
-                        debug!(
-                            "Failed to send keepalive to client '{}': {}",
-                            client_name, e
-                        );
-                    }
-                }
+type NextEvent<'a> = Pin<Box<dyn Future<Output = Result<ServerEvent>> + Send + 'a>>;
+
+/// A [`Stream`] of the events of a [`Server`], see [`Server::events`]
+pub struct ServerEvents<'a> {
+    server: &'a Server,
+    next: Option<NextEvent<'a>>,
+}
+
+impl ServerEvents<'_> {
+    /// Wait for the next event, `None` once the server failed
+    ///
+    /// This is the same as `StreamExt::next` without depending on a stream utility
+    /// crate.
+    pub async fn next(&mut self) -> Option<ServerEvent> {
+        std::future::poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+    }
+}
+
+impl Stream for ServerEvents<'_> {
+    type Item = ServerEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<ServerEvent>> {
+        let Some(next) = self.next.as_mut() else {
+            return Poll::Ready(None);
+        };
+        let result = match next.as_mut().poll(cx) {
+            Poll::Ready(result) => result,
+            Poll::Pending => return Poll::Pending,
+        };
+        match result {
+            Ok(event) => {
+                let server = self.server;
+                self.next = Some(Box::pin(server.recv_event()));
+                Poll::Ready(Some(event))
+            }
+            Err(e) => {
+                debug!("Server event stream ended: {}", e);
+                self.next = None;
+                Poll::Ready(None)
             }
         }
     }
@@ -1127,169 +3643,168 @@ impl Server {
 
 /// Perform the server-side handshake with a connecting client
 ///
-/// Returns a fully initialized ConnectedClient
+/// Drives `handshake`, a [`Handshake::server`] with the options and dialect to use.
+/// Returns a fully initialized ClientConnection
 async fn perform_server_handshake(
     client_id: ClientId,
-    mut stream: TcpStream,
-    allowed_clients: &HashMap<String, ClientConfig>,
-) -> Result<ConnectedClient> {
-    // Disable Nagle's algorithm to ensure low-latency message delivery
-    // Without this, small messages get buffered and delayed
-    stream.set_nodelay(true)?;
-
+    addr: SocketAddr,
+    mut stream: BoxedTransport,
+    allowed_clients: &std::sync::RwLock<HashMap<String, ClientConfig>>,
+    mut handshake: Handshake,
+    heartbeat: Option<Duration>,
+    peer_name_hint: Option<&str>,
+) -> Result<ClientConnection> {
     let mut buffer = Vec::with_capacity(4096);
+    #[cfg(feature = "tracing")]
+    tracing::debug!("handshake started");
+    write_handshake_messages(&mut stream, handshake.start()).await?;
+
+    while !handshake.is_complete() {
+        let mut message = read_handshake_message(&mut stream, &mut buffer).await?;
+        if let Some(hint) = peer_name_hint {
+            if let Message::HelloBarrier(MessageHelloBarrier {
+                client_name: name @ None,
+                ..
+            })
+            | Message::HelloSynergy(MessageHelloSynergy {
+                client_name: name @ None,
+                ..
+            }) = &mut message
+            {
+                *name = Some(hint.to_string());
+            }
+        }
+        #[cfg(feature = "tracing")]
+        let hello = matches!(message, Message::HelloBarrier(_) | Message::HelloSynergy(_));
+        let replies = match handshake.on_message(message) {
+            Ok(replies) => replies,
+            // Reject clients we can't talk to with EICV
+            Err(HandshakeError::IncompatibleVersion { version, reply }) => {
+                write_handshake_messages(&mut stream, vec![Message::IncompatibleVersion(reply)])
+                    .await?;
+                return Err(ServerError::IncompatibleVersion(version));
+            }
+            Err(e) => return Err(ServerError::ConnectionError(e.to_string())),
+        };
 
-    // Send hello to client
-    let hello = Message::HelloBarrier(MessageHelloBarrier {
-        major: 1,
-        minor: 8,
-        client_name: None, // Server doesn't send its name
-    });
-    let hello_bytes = hello.to_bytes();
-    stream.write_all(&hello_bytes).await?;
-    stream.flush().await?;
-
-    // Wait for client's hello response with client name
-    let client_name = loop {
-        let mut temp_buf = vec![0u8; 4096];
-        let n = stream.read(&mut temp_buf).await?;
-
-        if n == 0 {
-            return Err(ServerError::ConnectionError(
-                "Client disconnected during handshake".to_string(),
-            ));
+        // Verify client is in allowed list
+        if let Some(name) = handshake.peer_name() {
+            #[cfg(feature = "tracing")]
+            if hello {
+                tracing::Span::current().record("client", tracing::field::display(name));
+            }
+            if !allowed_clients.read().unwrap().contains_key(name) {
+                return Err(ServerError::UnknownClient(name.to_string()));
+            }
         }
+        #[cfg(feature = "tracing")]
+        for reply in &replies {
+            if let Message::SetOptions(msg) = reply {
+                tracing::debug!(options = ?msg.options, "options sent");
+            }
+        }
+        write_handshake_messages(&mut stream, replies).await?;
+    }
 
-        buffer.extend_from_slice(&temp_buf[..n]);
+    let info = handshake
+        .client_info()
+        .cloned()
+        .expect("the handshake completes with DINF");
+    let client = Client {
+        id: client_id,
+        name: handshake
+            .peer_name()
+            .expect("the handshake completes after the hello")
+            .to_string(),
+        width: info.width,
+        height: info.height,
+        capabilities: Capabilities::empty(),
+        hello: handshake
+            .peer_hello()
+            .cloned()
+            .expect("the handshake completes after the hello"),
+    };
+    #[cfg(feature = "tracing")]
+    tracing::info!(version = %handshake.protocol_version(), "handshake complete");
+    #[cfg(feature = "tracing")]
+    let span = tracing::info_span!(
+        parent: None,
+        "connection",
+        client = %client.name,
+        %addr,
+        version = %handshake.protocol_version(),
+    );
+
+    Ok(ClientConnection {
+        client,
+        addr,
+        connected_at: Instant::now(),
+        stream,
+        buffer,
+        clipboard_sequences: HashMap::new(),
+        clipboard_assembler: ClipboardAssembler::new(),
+        discarding_clipboards: HashSet::new(),
+        pending_grabs: HashMap::new(),
+        file_assembler: FileTransferAssembler::new(),
+        dropped_paths: VecDeque::new(),
+        discarding_file: false,
+        languages: Vec::new(),
+        protocol_version: handshake.protocol_version(),
+        cursor: (info.current_mouse_x, info.current_mouse_y),
+        pointer: (info.current_mouse_x as i16, info.current_mouse_y as i16),
+        forward_language: true,
+        heartbeat,
+        last_received: Instant::now(),
+        #[cfg(feature = "tracing")]
+        span,
+    })
+}
 
-        match parse_message_with_length(&buffer) {
+/// Read the next message from a client during the handshake
+async fn read_handshake_message(
+    stream: &mut BoxedTransport,
+    buffer: &mut Vec<u8>,
+) -> Result<Message> {
+    loop {
+        match parse_message_with_length(buffer) {
             Ok((msg, consumed)) => {
                 buffer.drain(..consumed);
-
-                match msg {
-                    Message::HelloBarrier(hello) => {
-                        if let Some(name) = hello.client_name {
-                            break name;
-                        } else {
-                            return Err(ServerError::ConnectionError(
-                                "Client hello missing client name".to_string(),
-                            ));
-                        }
-                    }
-                    Message::HelloSynergy(hello) => {
-                        if let Some(name) = hello.client_name {
-                            break name;
-                        } else {
-                            return Err(ServerError::ConnectionError(
-                                "Client hello missing client name".to_string(),
-                            ));
-                        }
-                    }
-                    Message::KeepAlive(_) => {
-                        // Handle keepalive during handshake
-                        let keepalive = Message::KeepAlive(MessageKeepAlive);
-                        stream.write_all(&keepalive.to_bytes()).await?;
-                        stream.flush().await?;
-                        continue;
-                    }
-                    _ => {
-                        return Err(ServerError::ConnectionError(format!(
-                            "Expected hello from client, got: {:?}",
-                            msg
-                        )));
-                    }
-                }
+                return Ok(msg);
             }
             Err(ProtocolError::InsufficientData { .. }) => {
-                continue; // Need more data
+                // Need more data
             }
             Err(e) => {
                 return Err(ServerError::ProtocolError(e));
             }
         }
-    };
-
-    // Verify client is in allowed list
-    if !allowed_clients.contains_key(&client_name) {
-        return Err(ServerError::UnknownClient(client_name));
-    }
-
-    // Send QINF to query client info
-    let qinf = Message::QueryInfo(MessageQueryInfo);
-    stream.write_all(&qinf.to_bytes()).await?;
-    stream.flush().await?;
 
-    // Wait for client info
-    let (width, height) = loop {
         let mut temp_buf = vec![0u8; 4096];
         let n = stream.read(&mut temp_buf).await?;
-
         if n == 0 {
             return Err(ServerError::ConnectionError(
                 "Client disconnected during handshake".to_string(),
             ));
         }
-
         buffer.extend_from_slice(&temp_buf[..n]);
+    }
+}
 
-        match parse_message_with_length(&buffer) {
-            Ok((msg, consumed)) => {
-                buffer.drain(..consumed);
-
-                match msg {
-                    Message::ClientInfo(info) => {
-                        break (info.width, info.height);
-                    }
-                    Message::KeepAlive(_) => {
-                        // Handle keepalive
-                        let keepalive = Message::KeepAlive(MessageKeepAlive);
-                        stream.write_all(&keepalive.to_bytes()).await?;
-                        stream.flush().await?;
-                        continue;
-                    }
-                    _ => {
-                        // Ignore other messages during handshake
-                        continue;
-                    }
-                }
-            }
-            Err(ProtocolError::InsufficientData { .. }) => {
-                continue; // Need more data
-            }
-            Err(e) => {
-                return Err(ServerError::ProtocolError(e));
-            }
-        }
-    };
-
-    // Complete handshake with LSYN, CIAK, CROP sequence
-    let lsyn = Message::LegacySynergy(MessageLegacySynergy { data: "en".into() });
-    stream.write_all(&lsyn.to_bytes()).await?;
-    stream.flush().await?;
-
-    let ciak = Message::InfoAcknowledgment(MessageInfoAcknowledgment);
-    stream.write_all(&ciak.to_bytes()).await?;
-    stream.flush().await?;
-
-    let crop = Message::ResetOptions(MessageResetOptions);
-    stream.write_all(&crop.to_bytes()).await?;
-    stream.flush().await?;
-
-    // Send DSOP with no options (empty key-value pairs)
-    let dsop = Message::SetOptions(MessageSetOptions { options: vec![] });
-    stream.write_all(&dsop.to_bytes()).await?;
+/// Send handshake messages to a client with a single write and flush
+async fn write_handshake_messages(
+    stream: &mut BoxedTransport,
+    messages: Vec<Message>,
+) -> Result<()> {
+    if messages.is_empty() {
+        return Ok(());
+    }
+    let mut bytes = Vec::with_capacity(messages.iter().map(Message::size_hint).sum());
+    for message in &messages {
+        message
+            .write_framed(&mut bytes)
+            .expect("writing to a Vec does not fail");
+    }
+    stream.write_all(&bytes).await?;
     stream.flush().await?;
-
-    let client = Client {
-        id: client_id,
-        name: client_name,
-        width,
-        height,
-    };
-
-    Ok(ConnectedClient {
-        client,
-        stream,
-        buffer,
-    })
+    Ok(())
 }
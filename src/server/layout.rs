@@ -0,0 +1,685 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Geometry for moving the cursor between screens
+//!
+//! Screens in a layout can have different resolutions. When the cursor crosses from
+//! one screen onto its neighbor, its position along the shared edge is scaled so that
+//! it keeps its relative position, e.g. leaving a 1920 pixel wide screen at its
+//! horizontal center enters a 3840 pixel wide screen below it at its center.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
+
+use super::Position;
+use crate::protocol::CornerMask;
+
+/// Where the cursor ended up after crossing onto another screen
+///
+/// Returned by [`Server::update_cursor`](super::Server::update_cursor).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Crossing {
+    /// The screen the cursor entered, `None` for the primary screen
+    pub screen: Option<String>,
+    /// X coordinate of the cursor on the entered screen
+    pub x: i16,
+    /// Y coordinate of the cursor on the entered screen
+    pub y: i16,
+}
+
+/// A part of an edge in percent, see [`ClientBuilder::edge_range`](super::ClientBuilder::edge_range)
+pub(crate) type Span = (u8, u8);
+
+/// The whole edge
+pub(crate) const FULL_EDGE: Span = (0, 100);
+
+/// The position on a shared edge, in percent multiplied by `len`, of `position` on a
+/// screen edge of length `len` that covers `span` of the shared edge
+fn shared_position(position: i16, len: u16, span: Span) -> i64 {
+    let position = i64::from(position).clamp(0, i64::from(len) - 1);
+    i64::from(span.0) * i64::from(len) + position * i64::from(span.1 - span.0)
+}
+
+/// Scale a coordinate on an edge of length `from` covering `source` of the shared edge
+/// to an edge of length `to` covering `target`
+fn scale(position: i16, from: u16, to: u16, source: Span, target: Span) -> i16 {
+    if from == 0 || to == 0 {
+        return 0;
+    }
+    let offset = shared_position(position, from, source) - i64::from(target.0) * i64::from(from);
+    let scaled = offset * i64::from(to) / (i64::from(target.1 - target.0) * i64::from(from));
+    scaled.clamp(0, i64::from(to) - 1).min(i64::from(i16::MAX)) as i16
+}
+
+/// The coordinate along the edge in `direction` and the length of that edge
+fn along_edge(direction: Position, x: i16, y: i16, size: (u16, u16)) -> (i16, u16) {
+    match direction {
+        Position::Left | Position::Right => (y, size.1),
+        Position::Above | Position::Below => (x, size.0),
+    }
+}
+
+/// Map the position where the cursor leaves a screen onto the neighboring screen
+///
+/// `direction` is the side of the current screen the cursor leaves through, `(x, y)`
+/// its position on that screen of size `from` and `to` is the size of the neighbor
+/// in that direction. The cursor enters the neighbor one pixel inside the opposite
+/// edge, so it doesn't immediately cross back, with the coordinate along the edge
+/// scaled proportionally.
+///
+/// # Example
+///
+/// ```
+/// use schengen::server::Position;
+/// use schengen::server::layout::map_crossing;
+///
+/// // Leaving a 1080p screen at the bottom center enters a 4K screen below at its top center
+/// let (x, y) = map_crossing(Position::Below, 960, 1079, (1920, 1080), (3840, 2160));
+/// assert_eq!((x, y), (1920, 1));
+/// ```
+pub fn map_crossing(
+    direction: Position,
+    x: i16,
+    y: i16,
+    from: (u16, u16),
+    to: (u16, u16),
+) -> (i16, i16) {
+    map_crossing_spans(direction, x, y, from, to, FULL_EDGE, FULL_EDGE)
+}
+
+/// Like [`map_crossing`] for screens that share only part of an edge
+///
+/// `source` and `target` are the parts of the shared edge covered by the screen the
+/// cursor leaves and the one it enters.
+pub(crate) fn map_crossing_spans(
+    direction: Position,
+    x: i16,
+    y: i16,
+    from: (u16, u16),
+    to: (u16, u16),
+    source: Span,
+    target: Span,
+) -> (i16, i16) {
+    let (position, len) = along_edge(direction, x, y, from);
+    let (to_width, to_height) = to;
+    let entry = match direction {
+        Position::Left | Position::Right => to_height,
+        Position::Above | Position::Below => to_width,
+    };
+    let entry = scale(position, len, entry, source, target);
+    let right = (i32::from(to_width) - 2).clamp(0, i32::from(i16::MAX)) as i16;
+    let bottom = (i32::from(to_height) - 2).clamp(0, i32::from(i16::MAX)) as i16;
+    match direction {
+        Position::Left => (right, entry),
+        Position::Right => (1.min(right), entry),
+        Position::Above => (entry, bottom),
+        Position::Below => (entry, 1.min(bottom)),
+    }
+}
+
+/// Whether leaving a screen of size `from` covering `source` of the shared edge at
+/// `(x, y)` enters the neighbor covering `target`
+pub(crate) fn crosses_into(
+    direction: Position,
+    x: i16,
+    y: i16,
+    from: (u16, u16),
+    source: Span,
+    target: Span,
+) -> bool {
+    let (position, len) = along_edge(direction, x, y, from);
+    if len == 0 {
+        return false;
+    }
+    let shared = shared_position(position, len, source);
+    let len = i64::from(len);
+    i64::from(target.0) * len <= shared && shared < i64::from(target.1) * len
+}
+
+/// The side opposite of `direction`
+pub(crate) fn opposite(direction: Position) -> Position {
+    match direction {
+        Position::Left => Position::Right,
+        Position::Right => Position::Left,
+        Position::Above => Position::Below,
+        Position::Below => Position::Above,
+    }
+}
+
+/// The grid offset of the neighboring cell in `direction`, y grows downwards
+pub(crate) fn step(direction: Position) -> (i32, i32) {
+    match direction {
+        Position::Left => (-1, 0),
+        Position::Right => (1, 0),
+        Position::Above => (0, -1),
+        Position::Below => (0, 1),
+    }
+}
+
+/// Where a client is placed in the layout, see [`ClientBuilder`](super::ClientBuilder)
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Placement<'a> {
+    pub(crate) position: Position,
+    pub(crate) relative_to: Option<&'a str>,
+    pub(crate) edge_range: Span,
+}
+
+/// The grid cell of every client in `placements`
+///
+/// The primary screen is at `(0, 0)`, x grows to the right and y grows downwards.
+/// Clients positioned relative to another client are placed next to that client's
+/// cell. Every client referenced in `relative_to` must be in `placements`.
+pub(crate) fn grid_cells<'a>(
+    placements: &HashMap<&'a str, Placement<'a>>,
+) -> HashMap<&'a str, (i32, i32)> {
+    fn resolve<'a>(
+        name: &'a str,
+        placements: &HashMap<&'a str, Placement<'a>>,
+        cells: &mut HashMap<&'a str, (i32, i32)>,
+    ) -> (i32, i32) {
+        if let Some(cell) = cells.get(name) {
+            return *cell;
+        }
+        let placement = &placements[name];
+        let (x, y) = match placement.relative_to {
+            Some(other) => resolve(other, placements, cells),
+            None => (0, 0),
+        };
+        let (dx, dy) = step(placement.position);
+        let cell = (x + dx, y + dy);
+        cells.insert(name, cell);
+        cell
+    }
+
+    let mut cells = HashMap::new();
+    for name in placements.keys() {
+        resolve(name, placements, &mut cells);
+    }
+    cells
+}
+
+/// The parts of the shared edge covered by `screen` and by its `neighbor` in
+/// `direction`, each `None` for the primary screen
+///
+/// A client positioned relative to the other screen covers its edge range of the
+/// shared edge and the other screen all of it. Screens that are neighbors by
+/// accident of the grid share the whole edge.
+pub(crate) fn shared_spans(
+    direction: Position,
+    screen: (Option<&str>, Option<Placement>),
+    neighbor: (Option<&str>, Option<Placement>),
+) -> (Span, Span) {
+    match (screen.1, neighbor.1) {
+        (_, Some(placement))
+            if placement.relative_to == screen.0 && placement.position == direction =>
+        {
+            (FULL_EDGE, placement.edge_range)
+        }
+        (Some(placement), _)
+            if placement.relative_to == neighbor.0 && placement.position == opposite(direction) =>
+        {
+            (placement.edge_range, FULL_EDGE)
+        }
+        _ => (FULL_EDGE, FULL_EDGE),
+    }
+}
+
+/// The neighbors of every screen in a layout
+///
+/// Returned by [`Builder::validate`](super::Builder::validate). Screens are named by
+/// their client name, `None` is the primary screen.
+///
+/// # Example
+///
+/// ```
+/// use schengen::server::{Builder, ClientBuilder, Position};
+///
+/// # fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let laptop = ClientBuilder::new("laptop").position(Position::Left).build();
+/// let tablet = ClientBuilder::new("tablet")
+///     .position(Position::Below)
+///     .relative_to(&laptop)
+///     .build();
+/// let report = Builder::new()
+///     .add_client(laptop)?
+///     .add_client(tablet)?
+///     .validate()?;
+///
+/// assert_eq!(report.neighbors(None, Position::Left), [Some("laptop".to_string())]);
+/// assert_eq!(report.neighbors(Some("tablet"), Position::Above), [Some("laptop".to_string())]);
+/// assert!(report.neighbors(None, Position::Below).is_empty());
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LayoutReport {
+    /// The screens the cursor crosses onto from each edge of each screen
+    edges: Vec<(Option<String>, Position, Vec<Option<String>>)>,
+    /// The clients the cursor can't reach from the primary screen
+    unreachable: Vec<String>,
+}
+
+impl LayoutReport {
+    /// Compute the neighbors of the primary screen and the clients in `placements`
+    ///
+    /// `placements` is in the order the clients were added, where screens of the same
+    /// cell cover the same part of an edge the earlier one hides the later one.
+    pub(crate) fn new(placements: &[(&str, Placement)]) -> Self {
+        let by_name: HashMap<&str, Placement> = placements.iter().copied().collect();
+        let cells = grid_cells(&by_name);
+        // The primary screen first, as it is found first when crossing
+        let screens: Vec<_> = std::iter::once((None, None, (0, 0)))
+            .chain(
+                placements
+                    .iter()
+                    .map(|(name, placement)| (Some(*name), Some(*placement), cells[name])),
+            )
+            .collect();
+
+        let mut edges = Vec::new();
+        for &(screen, placement, (x, y)) in &screens {
+            for direction in [
+                Position::Left,
+                Position::Right,
+                Position::Above,
+                Position::Below,
+            ] {
+                let (dx, dy) = step(direction);
+                let mut claimed = [false; 100];
+                let mut neighbors = Vec::new();
+                for &(neighbor, neighbor_placement, cell) in &screens {
+                    if cell != (x + dx, y + dy) {
+                        continue;
+                    }
+                    let (source, target) = shared_spans(
+                        direction,
+                        (screen, placement),
+                        (neighbor, neighbor_placement),
+                    );
+                    let start = source.0.max(target.0);
+                    let end = source.1.min(target.1);
+                    let mut reached = false;
+                    for part in claimed.iter_mut().take(end.into()).skip(start.into()) {
+                        reached |= !*part;
+                        *part = true;
+                    }
+                    if reached {
+                        neighbors.push(neighbor.map(str::to_string));
+                    }
+                }
+                edges.push((screen.map(str::to_string), direction, neighbors));
+            }
+        }
+
+        let mut reachable = HashSet::from([None]);
+        let mut queue = VecDeque::from([None]);
+        while let Some(screen) = queue.pop_front() {
+            for (_, _, neighbors) in edges.iter().filter(|(s, _, _)| *s == screen) {
+                for neighbor in neighbors {
+                    if reachable.insert(neighbor.clone()) {
+                        queue.push_back(neighbor.clone());
+                    }
+                }
+            }
+        }
+        let unreachable = placements
+            .iter()
+            .map(|(name, _)| name.to_string())
+            .filter(|name| !reachable.contains(&Some(name.clone())))
+            .collect();
+
+        Self { edges, unreachable }
+    }
+
+    /// The screens the cursor crosses onto from the `edge` of `screen`
+    ///
+    /// Returns an empty slice if there are none or `screen` is not in the layout.
+    pub fn neighbors(&self, screen: Option<&str>, edge: Position) -> &[Option<String>] {
+        self.edges
+            .iter()
+            .find(|(s, direction, _)| s.as_deref() == screen && *direction == edge)
+            .map_or(&[], |(_, _, neighbors)| neighbors.as_slice())
+    }
+
+    /// The clients the cursor can't reach from the primary screen, in the order they
+    /// were added
+    pub fn unreachable(&self) -> &[String] {
+        &self.unreachable
+    }
+}
+
+/// Whether the position is within `corner_size` pixels of one of the `corners` of a
+/// screen of the given size
+pub(crate) fn in_corner(
+    x: i16,
+    y: i16,
+    size: (u16, u16),
+    corners: CornerMask,
+    corner_size: u16,
+) -> bool {
+    let (width, height) = (i32::from(size.0), i32::from(size.1));
+    let (x, y) = (i32::from(x), i32::from(y));
+    let corner_size = i32::from(corner_size);
+    let left = x < corner_size;
+    let right = x >= width - corner_size;
+    let top = y < corner_size;
+    let bottom = y >= height - corner_size;
+    (corners.contains(CornerMask::TOP_LEFT) && top && left)
+        || (corners.contains(CornerMask::TOP_RIGHT) && top && right)
+        || (corners.contains(CornerMask::BOTTOM_LEFT) && bottom && left)
+        || (corners.contains(CornerMask::BOTTOM_RIGHT) && bottom && right)
+}
+
+/// Tracks the cursor at a screen edge for the switch delay and double tap
+#[derive(Debug, Default)]
+pub(crate) struct EdgeState {
+    /// The edge the cursor is at, when it arrived there and whether that arrival was
+    /// the second tap of a double tap
+    arrival: Option<(Position, Instant, bool)>,
+    /// The edge and time of the previous arrival at an edge
+    last_tap: Option<(Position, Instant)>,
+}
+
+impl EdgeState {
+    /// The cursor is at the edge in `direction` with a neighbor behind it
+    ///
+    /// Returns whether the cursor may cross: immediately if neither `delay` nor
+    /// `double_tap` is set, otherwise once it stayed at the edge for `delay` or if it
+    /// arrived at the edge a second time within `double_tap`.
+    pub(crate) fn at_edge(
+        &mut self,
+        direction: Position,
+        now: Instant,
+        delay: Option<Duration>,
+        double_tap: Option<Duration>,
+    ) -> bool {
+        let (arrived, tapped) = match self.arrival {
+            Some((edge, arrived, tapped)) if edge == direction => (arrived, tapped),
+            _ => {
+                let tapped = match (self.last_tap, double_tap) {
+                    (Some((edge, last)), Some(window)) => {
+                        edge == direction && now.duration_since(last) <= window
+                    }
+                    _ => false,
+                };
+                self.arrival = Some((direction, now, tapped));
+                self.last_tap = Some((direction, now));
+                (now, tapped)
+            }
+        };
+
+        let allowed = (delay.is_none() && double_tap.is_none())
+            || tapped
+            || delay.is_some_and(|delay| now.duration_since(arrived) >= delay);
+        if allowed {
+            *self = Self::default();
+        }
+        allowed
+    }
+
+    /// The cursor is away from any edge it could cross
+    pub(crate) fn away(&mut self) {
+        self.arrival = None;
+    }
+}
+
+/// The side of a screen of the given size that the position is on, if any
+///
+/// At a corner the horizontal edge is returned first, followed by the vertical one.
+pub(crate) fn edges_at(x: i16, y: i16, size: (u16, u16)) -> Vec<Position> {
+    let (width, height) = (i32::from(size.0), i32::from(size.1));
+    let (x, y) = (i32::from(x), i32::from(y));
+    let mut edges = Vec::new();
+    if x <= 0 {
+        edges.push(Position::Left);
+    } else if x >= width - 1 {
+        edges.push(Position::Right);
+    }
+    if y <= 0 {
+        edges.push(Position::Above);
+    } else if y >= height - 1 {
+        edges.push(Position::Below);
+    }
+    edges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_map_crossing_same_size() {
+        let size = (1920, 1080);
+        assert_eq!(
+            map_crossing(Position::Left, 0, 500, size, size),
+            (1918, 500)
+        );
+        assert_eq!(
+            map_crossing(Position::Right, 1919, 500, size, size),
+            (1, 500)
+        );
+        assert_eq!(
+            map_crossing(Position::Above, 700, 0, size, size),
+            (700, 1078)
+        );
+        assert_eq!(
+            map_crossing(Position::Below, 700, 1079, size, size),
+            (700, 1)
+        );
+    }
+
+    #[test]
+    fn test_map_crossing_scales_along_edge() {
+        // A 4K client below a 1080p host
+        let host = (1920, 1080);
+        let client = (3840, 2160);
+        assert_eq!(
+            map_crossing(Position::Below, 960, 1079, host, client),
+            (1920, 1)
+        );
+        assert_eq!(
+            map_crossing(Position::Below, 1919, 1079, host, client),
+            (3838, 1)
+        );
+        assert_eq!(map_crossing(Position::Below, 0, 1079, host, client), (0, 1));
+
+        // And back up onto the host
+        assert_eq!(
+            map_crossing(Position::Above, 3839, 0, client, host),
+            (1919, 1078)
+        );
+
+        // A smaller screen to the left
+        assert_eq!(
+            map_crossing(Position::Left, 0, 1079, host, (1280, 720)),
+            (1278, 719)
+        );
+    }
+
+    #[test]
+    fn test_map_crossing_clamps() {
+        // Positions outside the screen are clamped onto the edge
+        assert_eq!(
+            map_crossing(Position::Right, 2000, -5, (1920, 1080), (1920, 1080)),
+            (1, 0)
+        );
+        assert_eq!(
+            map_crossing(Position::Right, 1919, 1200, (1920, 1080), (1920, 1080)),
+            (1, 1079)
+        );
+        // Degenerate screen sizes don't panic
+        assert_eq!(map_crossing(Position::Left, 0, 10, (0, 0), (0, 0)), (0, 0));
+    }
+
+    #[test]
+    fn test_map_crossing_spans() {
+        let size = (1920, 1080);
+        // Into a screen covering the lower half of the left edge
+        assert_eq!(
+            map_crossing_spans(Position::Left, 0, 540, size, size, FULL_EDGE, (50, 100)),
+            (1918, 0)
+        );
+        assert_eq!(
+            map_crossing_spans(Position::Left, 0, 810, size, size, FULL_EDGE, (50, 100)),
+            (1918, 540)
+        );
+        // And back onto the lower half of the server's edge
+        assert_eq!(
+            map_crossing_spans(Position::Right, 1919, 540, size, size, (50, 100), FULL_EDGE),
+            (1, 810)
+        );
+    }
+
+    #[test]
+    fn test_crosses_into() {
+        let size = (1920, 1080);
+        assert!(crosses_into(Position::Left, 0, 0, size, FULL_EDGE, (0, 50)));
+        assert!(crosses_into(
+            Position::Left,
+            0,
+            539,
+            size,
+            FULL_EDGE,
+            (0, 50)
+        ));
+        assert!(!crosses_into(
+            Position::Left,
+            0,
+            540,
+            size,
+            FULL_EDGE,
+            (0, 50)
+        ));
+        assert!(crosses_into(
+            Position::Left,
+            0,
+            540,
+            size,
+            FULL_EDGE,
+            (50, 100)
+        ));
+        assert!(crosses_into(
+            Position::Left,
+            0,
+            1079,
+            size,
+            FULL_EDGE,
+            (50, 100)
+        ));
+        assert!(crosses_into(
+            Position::Below,
+            1919,
+            1079,
+            size,
+            FULL_EDGE,
+            FULL_EDGE
+        ));
+    }
+
+    #[test]
+    fn test_in_corner() {
+        let size = (1920, 1080);
+        let corners = CornerMask::TOP_LEFT | CornerMask::BOTTOM_RIGHT;
+        assert!(in_corner(0, 0, size, corners, 10));
+        assert!(in_corner(9, 0, size, corners, 10));
+        assert!(!in_corner(10, 0, size, corners, 10));
+        assert!(in_corner(1919, 1079, size, corners, 10));
+        assert!(!in_corner(1919, 0, size, corners, 10));
+        assert!(!in_corner(0, 1079, size, corners, 10));
+        // A corner size of 0 disables the check
+        assert!(!in_corner(0, 0, size, CornerMask::ALL, 0));
+    }
+
+    #[test]
+    fn test_edge_state_immediate() {
+        let mut state = EdgeState::default();
+        assert!(state.at_edge(Position::Left, Instant::now(), None, None));
+    }
+
+    #[test]
+    fn test_edge_state_delay() {
+        let delay = Some(Duration::from_millis(100));
+        let start = Instant::now();
+        let mut state = EdgeState::default();
+
+        // A quick brush against the edge doesn't switch
+        assert!(!state.at_edge(Position::Left, start, delay, None));
+        state.away();
+        let later = start + Duration::from_millis(150);
+        assert!(!state.at_edge(Position::Left, later, delay, None));
+
+        // Staying at the edge does
+        assert!(!state.at_edge(
+            Position::Left,
+            later + Duration::from_millis(50),
+            delay,
+            None
+        ));
+        assert!(state.at_edge(
+            Position::Left,
+            later + Duration::from_millis(100),
+            delay,
+            None
+        ));
+
+        // Moving along onto another edge restarts the delay
+        assert!(!state.at_edge(Position::Left, later, delay, None));
+        assert!(!state.at_edge(
+            Position::Above,
+            later + Duration::from_millis(100),
+            delay,
+            None
+        ));
+    }
+
+    #[test]
+    fn test_edge_state_double_tap() {
+        let tap = Some(Duration::from_millis(300));
+        let start = Instant::now();
+        let mut state = EdgeState::default();
+
+        assert!(!state.at_edge(Position::Right, start, None, tap));
+        // Staying at the edge is not a second tap
+        assert!(!state.at_edge(
+            Position::Right,
+            start + Duration::from_millis(100),
+            None,
+            tap
+        ));
+        state.away();
+        assert!(state.at_edge(
+            Position::Right,
+            start + Duration::from_millis(200),
+            None,
+            tap
+        ));
+
+        // Taps too far apart don't switch
+        assert!(!state.at_edge(Position::Right, start, None, tap));
+        state.away();
+        assert!(!state.at_edge(
+            Position::Right,
+            start + Duration::from_millis(400),
+            None,
+            tap
+        ));
+
+        // Taps on different edges don't either
+        state.away();
+        assert!(!state.at_edge(
+            Position::Left,
+            start + Duration::from_millis(500),
+            None,
+            tap
+        ));
+    }
+
+    #[test]
+    fn test_edges_at() {
+        let size = (1920, 1080);
+        assert!(edges_at(500, 500, size).is_empty());
+        assert_eq!(edges_at(0, 500, size), vec![Position::Left]);
+        assert_eq!(edges_at(1919, 500, size), vec![Position::Right]);
+        assert_eq!(edges_at(500, 1079, size), vec![Position::Below]);
+        assert_eq!(edges_at(0, 0, size), vec![Position::Left, Position::Above]);
+    }
+}
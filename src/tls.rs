@@ -0,0 +1,126 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! # TLS
+//!
+//! Deskflow encrypts the connection with TLS by default. With the `tls` feature the
+//! connection is wrapped in TLS before the Synergy handshake, see
+//! [`client::Builder::tls`](crate::client::Builder::tls) and
+//! [`server::Builder::tls`](crate::server::Builder::tls).
+//!
+//! Deskflow uses self-signed certificates and asks the user to confirm the server's
+//! fingerprint. To connect to a Deskflow server, configure the
+//! [`ClientConfig`] with a root store containing the server's certificate or a custom
+//! certificate verifier.
+
+use std::sync::Arc;
+
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use tokio_rustls::rustls::{ClientConfig, ServerConfig};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+pub use tokio_rustls::rustls;
+
+use crate::transport::{BoxedTransport, Transport};
+
+/// TLS settings for a [`client::Builder`](crate::client::Builder)
+///
+/// # Example
+///
+/// ```no_run
+/// use schengen::tls::TlsConfig;
+/// use schengen::tls::rustls::{ClientConfig, RootCertStore};
+/// # fn example(server_cert: schengen::tls::rustls::pki_types::CertificateDer<'static>)
+/// #     -> Result<(), Box<dyn std::error::Error>> {
+///
+/// let mut roots = RootCertStore::empty();
+/// roots.add(server_cert)?;
+/// let config = ClientConfig::builder()
+///     .with_root_certificates(roots)
+///     .with_no_client_auth();
+/// let tls = TlsConfig::new(config).server_name("deskflow.local");
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    config: Arc<ClientConfig>,
+    server_name: Option<String>,
+}
+
+impl TlsConfig {
+    /// Create TLS settings from a rustls client configuration
+    pub fn new(config: ClientConfig) -> Self {
+        Self {
+            config: Arc::new(config),
+            server_name: None,
+        }
+    }
+
+    /// Set the name the server certificate is verified against
+    ///
+    /// Defaults to the host of [`server_addr`](crate::client::Builder::server_addr). This
+    /// is required for [`connect_with_stream`](crate::client::Builder::connect_with_stream).
+    pub fn server_name(mut self, name: &str) -> Self {
+        self.server_name = Some(name.to_string());
+        self
+    }
+
+    /// Perform the TLS handshake as client on `stream`
+    pub(crate) async fn connect<S: Transport + 'static>(
+        &self,
+        host: Option<&str>,
+        stream: S,
+    ) -> std::io::Result<BoxedTransport> {
+        let name = self.server_name.as_deref().or(host).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "No server name for the TLS handshake",
+            )
+        })?;
+        let name = ServerName::try_from(name.to_string())
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+        let stream = TlsConnector::from(Arc::clone(&self.config))
+            .connect(name, stream)
+            .await?;
+        Ok(Box::new(stream))
+    }
+}
+
+/// The certificate a [`server::Builder`](crate::server::Builder) presents to clients
+#[derive(Debug, Clone)]
+pub struct TlsIdentity {
+    config: Arc<ServerConfig>,
+}
+
+impl TlsIdentity {
+    /// Create an identity from a certificate chain and the matching private key
+    ///
+    /// Client certificates are not requested.
+    pub fn new(
+        cert_chain: Vec<CertificateDer<'static>>,
+        key: PrivateKeyDer<'static>,
+    ) -> Result<Self, rustls::Error> {
+        let config = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(cert_chain, key)?;
+        Ok(Self::from_config(config))
+    }
+
+    /// Create an identity from a rustls server configuration
+    pub fn from_config(config: ServerConfig) -> Self {
+        Self {
+            config: Arc::new(config),
+        }
+    }
+
+    /// Perform the TLS handshake as server on `stream`
+    pub(crate) async fn accept<S: Transport + 'static>(
+        &self,
+        stream: S,
+    ) -> std::io::Result<BoxedTransport> {
+        let stream = TlsAcceptor::from(Arc::clone(&self.config))
+            .accept(stream)
+            .await?;
+        Ok(Box::new(stream))
+    }
+}
@@ -0,0 +1,67 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! The byte streams the protocol runs over
+//!
+//! Client and server exchange messages over a [`Transport`], which is a plain
+//! [`TcpStream`], a Unix domain socket or, with the `tls` feature, a TLS stream.
+
+use std::net::{Ipv4Addr, SocketAddr};
+
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+
+/// A bidirectional byte stream
+pub(crate) trait Transport: AsyncRead + AsyncWrite + Unpin + Send + Sync {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + Sync> Transport for T {}
+
+/// The stream a client or server connection runs over
+pub(crate) type BoxedTransport = Box<dyn Transport>;
+
+/// The address reported for peers that have no IP address, e.g. over a Unix socket
+pub(crate) const UNSPECIFIED_ADDR: SocketAddr =
+    SocketAddr::new(std::net::IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0);
+
+/// The socket a server accepts connections on
+pub(crate) enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener),
+    /// A single connected stream, accepted once
+    Stream(std::sync::Mutex<Option<BoxedTransport>>),
+}
+
+impl Listener {
+    /// Accept the next connection with the peer's address
+    ///
+    /// Unix socket and stream peers are reported with [`UNSPECIFIED_ADDR`]. A
+    /// [`Listener::Stream`] never accepts again after its stream.
+    pub(crate) async fn accept(&self) -> std::io::Result<(BoxedTransport, SocketAddr)> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, addr) = listener.accept().await?;
+                Ok((Box::new(nodelay(stream)?), addr))
+            }
+            #[cfg(unix)]
+            Listener::Unix(listener) => {
+                let (stream, _) = listener.accept().await?;
+                Ok((Box::new(stream), UNSPECIFIED_ADDR))
+            }
+            Listener::Stream(stream) => {
+                let stream = stream.lock().unwrap().take();
+                match stream {
+                    Some(stream) => Ok((stream, UNSPECIFIED_ADDR)),
+                    None => std::future::pending().await,
+                }
+            }
+        }
+    }
+}
+
+/// Disable Nagle's algorithm to ensure low-latency message delivery
+///
+/// Without this, small messages get buffered and delayed.
+pub(crate) fn nodelay(stream: TcpStream) -> std::io::Result<TcpStream> {
+    stream.set_nodelay(true)?;
+    Ok(stream)
+}
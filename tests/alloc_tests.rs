@@ -0,0 +1,65 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Allocation counts of the parsing hot paths
+//!
+//! These live in their own test binary as they replace the global allocator.
+
+use schengen::protocol::{
+    Message, MessageMouseMove, MessageRef, MessageSecureEncryption, parse_message_ref,
+};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+/// The system allocator, counting the allocations of each thread
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// The number of allocations made by `f` on this thread
+fn allocations<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    let before = ALLOCATIONS.with(Cell::get);
+    let result = f();
+    (result, ALLOCATIONS.with(Cell::get) - before)
+}
+
+#[test]
+fn test_parse_message_ref_does_not_allocate() {
+    let mouse_move = Message::MouseMove(MessageMouseMove { x: 100, y: 200 }).to_bytes();
+    let secure = Message::SecureEncryption(MessageSecureEncryption {
+        data: "Terminal".into(),
+    })
+    .to_bytes();
+
+    for _ in 0..1000 {
+        let (msg, count) = allocations(|| parse_message_ref(&mouse_move[4..]).unwrap());
+        assert_eq!(count, 0, "parsing DMMV allocated");
+        assert!(matches!(
+            msg,
+            MessageRef::Other(Message::MouseMove(MessageMouseMove { x: 100, y: 200 }))
+        ));
+
+        let (msg, count) = allocations(|| parse_message_ref(&secure[4..]).unwrap());
+        assert_eq!(count, 0, "parsing SECN allocated");
+        assert_eq!(msg, MessageRef::SecureEncryption { data: "Terminal" });
+    }
+
+    // The owned parse copies the string
+    let (_, count) = allocations(|| schengen::protocol::parse_message(&secure[4..]).unwrap());
+    assert!(count > 0);
+}
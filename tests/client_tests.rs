@@ -4,7 +4,7 @@
 
 mod common;
 
-use schengen::client::{Builder, ClientError};
+use schengen::client::{BackoffStrategy, Builder, ClientError};
 use std::time::Duration;
 
 #[tokio::test]
@@ -79,6 +79,56 @@ async fn test_client_retry_interval_configuration() {
     // Configuration should be accepted
 }
 
+#[test]
+fn test_client_backoff_delays() {
+    let backoff = BackoffStrategy::Exponential {
+        base: Duration::from_millis(100),
+        max: Duration::from_secs(2),
+    };
+    let delays: Vec<Duration> = (0..8).map(|retry| backoff.delay(retry)).collect();
+    assert_eq!(delays[0], Duration::from_millis(100));
+    assert!(delays.windows(2).all(|w| w[0] <= w[1]));
+    assert!(delays[..5].windows(2).all(|w| w[0] < w[1]));
+    assert_eq!(delays[7], Duration::from_secs(2));
+    assert_eq!(backoff.delay(u32::MAX), Duration::from_secs(2));
+
+    let fixed = BackoffStrategy::Fixed(Duration::from_secs(3));
+    assert_eq!(fixed.delay(0), fixed.delay(10));
+
+    let _builder = Builder::new().backoff(backoff).backoff_jitter(true);
+}
+
+#[tokio::test]
+async fn test_client_rejects_zero_dimensions() {
+    // Nothing listens on the port, with unlimited retries only the dimension check can
+    // end the connect
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    drop(listener);
+
+    let result = tokio::time::timeout(
+        Duration::from_secs(1),
+        Builder::new()
+            .server_addr(&format!("127.0.0.1:{}", port))
+            .unwrap()
+            .dimensions(0, 1080)
+            .connect(),
+    )
+    .await
+    .expect("connect should fail fast");
+    assert!(
+        matches!(
+            result,
+            Err(ClientError::InvalidDimensions {
+                width: 0,
+                height: 1080
+            })
+        ),
+        "Expected InvalidDimensions, got {:?}",
+        result.err()
+    );
+}
+
 #[tokio::test]
 async fn test_client_name_configuration() {
     let _builder = Builder::new().name("my-custom-client");
@@ -127,3 +177,792 @@ async fn test_client_parse_host_port_ipv6_full() {
     let result = Builder::new().server_addr("2001:0db8:85a3:0000:0000:8a2e:0370:7334");
     assert!(result.is_ok(), "Should parse full IPv6");
 }
+
+#[tokio::test]
+async fn test_client_closes_on_server_protocol_error() {
+    use schengen::client::ServerErrorPolicy;
+    use schengen::protocol::{Message, MessageKeepAlive, MessageProtocolError};
+
+    let (mut client, server) = common::connect_client_to_mock_with(
+        |builder| builder.on_server_error(ServerErrorPolicy::Close),
+        |mut peer| async move {
+            peer.send(Message::ProtocolError(MessageProtocolError))
+                .await;
+            // The client closes the connection in response
+            while peer.recv().await.is_some() {}
+        },
+    )
+    .await;
+
+    let result = tokio::time::timeout(Duration::from_secs(2), client.recv_event())
+        .await
+        .expect("client should not hang");
+    assert!(
+        matches!(result, Err(ClientError::ServerProtocolError)),
+        "Expected ServerProtocolError, got {:?}",
+        result
+    );
+
+    // Nothing can be sent over the closed connection
+    let result = client
+        .send_all(&[Message::KeepAlive(MessageKeepAlive)])
+        .await;
+    assert!(
+        matches!(result, Err(ClientError::ConnectionClosed)),
+        "Expected ConnectionClosed, got {:?}",
+        result
+    );
+
+    tokio::time::timeout(Duration::from_secs(2), server)
+        .await
+        .expect("connection should be closed by the client")
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_client_send_all() {
+    use schengen::protocol::{
+        Message, MessageKeyUp, MessageMouseButtonUp, MessageScreenSaverChange,
+    };
+
+    let batch = vec![
+        Message::KeyUp(MessageKeyUp {
+            keyid: 0x61,
+            mask: 0,
+            button: 0x26,
+        }),
+        Message::MouseButtonUp(MessageMouseButtonUp { button: 1 }),
+        Message::ScreenSaverChange(MessageScreenSaverChange { state: 1 }),
+    ];
+
+    let expected = batch.clone();
+    let (mut client, server) = common::connect_client_to_mock(move |mut peer| async move {
+        let mut received = Vec::new();
+        while received.len() < expected.len() {
+            received.push(peer.recv_timeout(2000).await.expect("message"));
+        }
+        assert_eq!(received, expected);
+    })
+    .await;
+
+    client.send_all(&batch).await.unwrap();
+
+    tokio::time::timeout(Duration::from_secs(2), server)
+        .await
+        .unwrap()
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_client_retry_if_fails_fast() {
+    // Only retry while the server isn't up yet, resolve failures are config errors
+    let result = tokio::time::timeout(
+        Duration::from_secs(5),
+        Builder::new()
+            .server_addr("host.invalid:24801")
+            .unwrap()
+            .name("test-client")
+            .retry_interval(Duration::from_secs(10))
+            .retry_if(|err| {
+                matches!(err, ClientError::ConnectionFailed(e)
+                    if e.kind() == std::io::ErrorKind::ConnectionRefused)
+            })
+            .connect(),
+    )
+    .await
+    .expect("Connect should return without retrying");
+
+    assert!(
+        matches!(result, Err(ClientError::ConnectionFailed(_))),
+        "Expected the resolve error to be returned"
+    );
+}
+
+#[tokio::test]
+async fn test_client_peer_hello() {
+    use schengen::protocol::{Message, MessageHelloBarrier};
+
+    let (client, server) = common::connect_client_to_mock(|peer| async move { peer }).await;
+
+    assert_eq!(
+        client.peer_hello(),
+        &Message::HelloBarrier(MessageHelloBarrier {
+            major: 1,
+            minor: 8,
+            client_name: None,
+        })
+    );
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_client_answers_query_info() {
+    use schengen::client::ClientEvent;
+    use schengen::protocol::{
+        Message, MessageClientInfo, MessageQueryInfo, MessageScreenSaverChange,
+    };
+
+    let (mut client, server) = common::connect_client_to_mock_with(
+        |builder| builder.dimensions(1920, 1080),
+        |mut peer| async move {
+            peer.send(Message::QueryInfo(MessageQueryInfo)).await;
+            let info = peer.recv_timeout(2000).await;
+            // Unblock the client's recv_event
+            peer.send(Message::ScreenSaverChange(MessageScreenSaverChange {
+                state: 0,
+            }))
+            .await;
+            info
+        },
+    )
+    .await;
+
+    let event = tokio::time::timeout(Duration::from_secs(2), client.recv_event())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(matches!(event, ClientEvent::ScreenSaverChanged { .. }));
+
+    assert_eq!(
+        server.await.unwrap(),
+        Some(Message::ClientInfo(MessageClientInfo {
+            x: 0,
+            y: 0,
+            width: 1920,
+            height: 1080,
+            current_mouse_x: 960,
+            current_mouse_y: 540,
+            size: 0,
+        }))
+    );
+}
+
+#[tokio::test]
+async fn test_client_input_events() {
+    use schengen::client::InputEvent;
+    use schengen::protocol::{Message, MessageClose, MessageMouseMove, MessageScreenSaverChange};
+
+    let (mut client, _) = common::connect_client_to_mock(|mut peer| async move {
+        // Not input, skipped by the stream
+        peer.send(Message::ScreenSaverChange(MessageScreenSaverChange {
+            state: 1,
+        }))
+        .await;
+        peer.send(Message::MouseMove(MessageMouseMove { x: 10, y: 20 }))
+            .await;
+        peer.send(Message::Close(MessageClose)).await;
+    })
+    .await;
+
+    let mut events = client.events();
+    let event = tokio::time::timeout(Duration::from_secs(2), events.next())
+        .await
+        .unwrap();
+    assert_eq!(event, Some(InputEvent::MouseMove { x: 10, y: 20 }));
+
+    // The stream closes with the connection
+    let event = tokio::time::timeout(Duration::from_secs(2), events.next())
+        .await
+        .unwrap();
+    assert_eq!(event, None);
+}
+
+#[tokio::test]
+async fn test_client_protocol_version() {
+    use schengen::protocol::ProtocolVersion;
+
+    let (client, server) = common::connect_client_to_mock(|peer| async move { peer }).await;
+
+    assert_eq!(
+        client.protocol_version(),
+        ProtocolVersion { major: 1, minor: 8 }
+    );
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_client_set_clipboard() {
+    use schengen::client::ClientEvent;
+    use schengen::protocol::clipboard::{
+        ClipboardAssembler, ClipboardContents, ClipboardFormat, ClipboardId, MARK_FINAL, MARK_FIRST,
+    };
+    use schengen::protocol::{
+        DsopOption, Message, MessageClientClipboard, MessageCursorEntered, MessageSetOptions,
+    };
+
+    let (mut client, server) = common::connect_client_to_mock(|mut peer| async move {
+        peer.send(Message::CursorEntered(MessageCursorEntered {
+            x: 0,
+            y: 0,
+            sequence: 5,
+            mask: 0,
+        }))
+        .await;
+
+        let grab = peer.recv_timeout(2000).await;
+        let mut chunks = Vec::new();
+        loop {
+            match peer.recv_timeout(2000).await {
+                Some(Message::ClipboardData(msg)) => {
+                    let last = msg.mark == MARK_FINAL;
+                    chunks.push(msg);
+                    if last {
+                        break;
+                    }
+                }
+                other => panic!("Expected clipboard data, got {:?}", other),
+            }
+        }
+
+        // Limit the clipboard to 1 KiB
+        peer.send(Message::SetOptions(MessageSetOptions::from_typed(&[(
+            DsopOption::ClipboardSharingSize,
+            1,
+        )])))
+        .await;
+        (grab, chunks)
+    })
+    .await;
+
+    let event = client.recv_event().await.unwrap();
+    assert!(matches!(event, ClientEvent::CursorEntered { .. }));
+    client
+        .set_clipboard(ClipboardId::Clipboard, "hello".to_string())
+        .await
+        .unwrap();
+
+    let event = client.recv_event().await.unwrap();
+    assert!(matches!(event, ClientEvent::SetOptions));
+    let result = client
+        .set_clipboard(ClipboardId::Clipboard, "x".repeat(2048))
+        .await;
+    assert!(matches!(
+        result,
+        Err(ClientError::ClipboardTooLarge { limit: 1024, .. })
+    ));
+
+    let (grab, chunks) = server.await.unwrap();
+    assert_eq!(
+        grab,
+        Some(Message::ClientClipboard(MessageClientClipboard {
+            id: 0,
+            sequence: 5,
+        }))
+    );
+    assert_eq!(chunks[0].mark, MARK_FIRST);
+    assert!(chunks.iter().all(|c| c.id == 0 && c.sequence == 5));
+
+    let mut assembler = ClipboardAssembler::new();
+    let data = chunks
+        .iter()
+        .find_map(|c| assembler.push(c).unwrap())
+        .unwrap();
+    assert_eq!(
+        ClipboardContents::from_bytes(&data).unwrap().formats,
+        vec![(ClipboardFormat::Text, b"hello".to_vec())]
+    );
+}
+
+#[tokio::test]
+async fn test_client_keepalive_timeout() {
+    use schengen::client::ClientEvent;
+    use schengen::protocol::{DsopOption, Message, MessageSetOptions};
+
+    let (mut client, server) = common::connect_client_to_mock(|mut peer| async move {
+        // A 100ms keepalive interval, then silence
+        peer.send(Message::SetOptions(MessageSetOptions::from_typed(&[(
+            DsopOption::Heartbeat,
+            100,
+        )])))
+        .await;
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        peer
+    })
+    .await;
+
+    let event = client.recv_event().await.unwrap();
+    assert!(matches!(event, ClientEvent::SetOptions));
+
+    let result = tokio::time::timeout(Duration::from_secs(2), client.recv_event())
+        .await
+        .expect("Client should give up on the silent server");
+    assert!(matches!(
+        result,
+        Err(ClientError::KeepAliveTimeout(timeout)) if timeout == Duration::from_millis(300)
+    ));
+    server.abort();
+}
+
+#[tokio::test]
+async fn test_client_shutdown() {
+    use schengen::protocol::{Message, MessageClose};
+
+    let (client, server) = common::connect_client_to_mock(|mut peer| async move {
+        let close = peer.recv_timeout(2000).await;
+        let eof = tokio::time::timeout(Duration::from_secs(2), peer.recv()).await;
+        (close, eof)
+    })
+    .await;
+    client.shutdown().await.unwrap();
+
+    let (close, eof) = server.await.unwrap();
+    assert_eq!(close, Some(Message::Close(MessageClose)));
+    assert_eq!(eof.expect("Connection should be closed"), None);
+}
+
+#[tokio::test]
+async fn test_client_handshake_rejected() {
+    use schengen::client::HandshakeRejection;
+    use schengen::protocol::{
+        Message, MessageHelloBarrier, MessageIncompatibleVersion, MessageUnknownClient,
+        ProtocolVersion,
+    };
+    use tokio::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    let server = tokio::spawn(async move {
+        for rejection in [
+            Message::UnknownClient(MessageUnknownClient),
+            Message::IncompatibleVersion(MessageIncompatibleVersion {
+                major_remote: 2,
+                minor_remote: 0,
+            }),
+        ] {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut peer = common::RawPeer::new(stream);
+            peer.send(Message::HelloBarrier(MessageHelloBarrier {
+                major: 1,
+                minor: 8,
+                client_name: None,
+            }))
+            .await;
+            peer.recv().await;
+            peer.send(rejection).await;
+        }
+    });
+
+    let connect = || {
+        Builder::new()
+            .server_addr(&format!("127.0.0.1:{}", port))
+            .unwrap()
+            .name("stranger")
+            .retry_count(1)
+            .connect()
+    };
+
+    let result = tokio::time::timeout(Duration::from_secs(2), connect())
+        .await
+        .unwrap();
+    assert!(matches!(
+        result,
+        Err(ClientError::HandshakeRejected(
+            HandshakeRejection::UnknownClient
+        ))
+    ));
+
+    let result = tokio::time::timeout(Duration::from_secs(2), connect())
+        .await
+        .unwrap();
+    assert!(matches!(
+        result,
+        Err(ClientError::HandshakeRejected(HandshakeRejection::IncompatibleVersion(version)))
+            if version == ProtocolVersion::new(2, 0)
+    ));
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_client_bind_addr() {
+    use tokio::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    let server = tokio::spawn(async move {
+        let peer = common::mock_server_handshake(&listener).await;
+        peer.stream.peer_addr().unwrap()
+    });
+
+    let _client = Builder::new()
+        .server_addr(&format!("127.0.0.1:{}", port))
+        .unwrap()
+        .name("test-client")
+        .bind_addr("127.0.0.1:0".parse().unwrap())
+        .retry_count(1)
+        .connect()
+        .await
+        .unwrap();
+    let peer_addr = server.await.unwrap();
+    assert_eq!(peer_addr.ip(), std::net::Ipv4Addr::LOCALHOST);
+
+    // An IPv6 local address can't reach an IPv4 server
+    let result = Builder::new()
+        .server_addr(&format!("127.0.0.1:{}", port))
+        .unwrap()
+        .bind_addr("[::1]:0".parse().unwrap())
+        .retry_count(1)
+        .connect()
+        .await;
+    assert!(matches!(result, Err(ClientError::MaxRetriesExceeded(1))));
+}
+
+#[tokio::test]
+async fn test_client_run_reconnecting() {
+    use schengen::client::{ClientEvent, InputEvent};
+    use schengen::protocol::{Message, MessageMouseMove};
+    use tokio::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    let server = tokio::spawn(async move {
+        // The first connection drops after one event
+        let mut peer = common::mock_server_handshake(&listener).await;
+        peer.send(Message::MouseMove(MessageMouseMove { x: 1, y: 2 }))
+            .await;
+        drop(peer);
+
+        let mut peer = common::mock_server_handshake(&listener).await;
+        peer.send(Message::MouseMove(MessageMouseMove { x: 3, y: 4 }))
+            .await;
+        while peer.recv().await.is_some() {}
+    });
+
+    let mut client = Builder::new()
+        .server_addr(&format!("127.0.0.1:{}", port))
+        .unwrap()
+        .name("test-client")
+        .retry_interval(Duration::from_millis(10))
+        .connect()
+        .await
+        .unwrap()
+        .run_reconnecting();
+
+    let mut events = client.events();
+    for expected in [
+        InputEvent::MouseMove { x: 1, y: 2 },
+        InputEvent::MouseMove { x: 3, y: 4 },
+    ] {
+        let event = tokio::time::timeout(Duration::from_secs(2), events.next())
+            .await
+            .expect("client should keep receiving events");
+        assert_eq!(event, Some(expected));
+    }
+    drop(events);
+
+    // The server drops again, recv_event reports the reconnect
+    server.abort();
+    let event = tokio::time::timeout(Duration::from_secs(2), client.recv_event())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(
+        matches!(event, ClientEvent::Reconnecting { .. }),
+        "Expected Reconnecting, got {:?}",
+        event
+    );
+}
+
+#[tokio::test]
+async fn test_client_options() {
+    use schengen::client::ClientEvent;
+    use schengen::protocol::{DsopOption, Message, MessageResetOptions, MessageSetOptions};
+    use std::time::Duration;
+
+    let (mut client, server) = common::connect_client_to_mock(|mut peer| async move {
+        peer.send(Message::SetOptions(MessageSetOptions::from_typed(&[
+            (DsopOption::Heartbeat, 5000),
+            (DsopOption::ClipboardSharing, 0),
+            (DsopOption::ClipboardSharingSize, 64),
+            (DsopOption::ScreenSaverSync, 0),
+        ])))
+        .await;
+        peer.send(Message::SetOptions(MessageSetOptions::from_typed(&[(
+            DsopOption::RelativeMouseMoves,
+            1,
+        )])))
+        .await;
+        peer.send(Message::ResetOptions(MessageResetOptions)).await;
+        peer
+    })
+    .await;
+
+    // Nothing set during the handshake
+    assert_eq!(client.options().heartbeat(), Some(Duration::from_secs(3)));
+    assert!(client.options().clipboard_sharing());
+    assert!(!client.options().relative_mouse_moves());
+    assert!(client.options().screensaver_sync());
+
+    assert!(matches!(
+        client.recv_event().await.unwrap(),
+        ClientEvent::SetOptions
+    ));
+    assert_eq!(client.options().heartbeat(), Some(Duration::from_secs(5)));
+    assert!(!client.options().clipboard_sharing());
+    assert_eq!(client.options().clipboard_size(), Some(64 * 1024));
+    assert!(!client.options().relative_mouse_moves());
+    assert!(!client.options().screensaver_sync());
+
+    // A later DSOP keeps the earlier options
+    assert!(matches!(
+        client.recv_event().await.unwrap(),
+        ClientEvent::SetOptions
+    ));
+    assert!(client.options().relative_mouse_moves());
+    assert_eq!(client.options().heartbeat(), Some(Duration::from_secs(5)));
+
+    assert!(matches!(
+        client.recv_event().await.unwrap(),
+        ClientEvent::ResetOptions
+    ));
+    assert_eq!(client.options().get(DsopOption::Heartbeat), None);
+    assert_eq!(client.options().clipboard_size(), None);
+    assert!(client.options().screensaver_sync());
+    assert!(!client.options().relative_mouse_moves());
+
+    let _peer = server.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_client_start_drag() {
+    use schengen::protocol::{FileTransferAssembler, Message};
+
+    let path = std::env::temp_dir().join(format!("schengen-drag-{}.txt", std::process::id()));
+    std::fs::write(&path, "dragged file contents").unwrap();
+    // Binary files are not valid UTF-8
+    let binary: Vec<u8> = (0..=255).rev().collect();
+    let binary_path =
+        std::env::temp_dir().join(format!("schengen-drag-{}.png", std::process::id()));
+    std::fs::write(&binary_path, &binary).unwrap();
+    let paths = [path.clone(), binary_path.clone()];
+
+    let expected_paths: Vec<String> = paths
+        .iter()
+        .map(|path| path.to_string_lossy().into_owned())
+        .collect();
+    let (mut client, server) = common::connect_client_to_mock(move |mut peer| async move {
+        let Some(Message::DragInfo(info)) = peer.recv_timeout(2000).await else {
+            panic!("Expected DDRG");
+        };
+        assert_eq!(info.paths(), expected_paths);
+
+        let mut assembler = FileTransferAssembler::new();
+        let mut files = Vec::new();
+        while files.len() < 2 {
+            let Some(Message::FileTransfer(msg)) = peer.recv_timeout(2000).await else {
+                panic!("Expected DFTR");
+            };
+            if let Some(file) = assembler.push(&msg).unwrap() {
+                files.push(file);
+            }
+        }
+        files
+    })
+    .await;
+
+    // Nothing is sent for a file that can't be read
+    let missing = std::env::temp_dir().join("schengen-drag-missing.txt");
+    assert!(matches!(
+        client.start_drag(&[missing]).await,
+        Err(ClientError::FileRead { .. })
+    ));
+
+    client.start_drag(&paths).await.unwrap();
+    let files = tokio::time::timeout(Duration::from_secs(2), server)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(files, [b"dragged file contents".to_vec(), binary]);
+
+    std::fs::remove_file(&path).unwrap();
+    std::fs::remove_file(&binary_path).unwrap();
+}
+
+#[tokio::test]
+async fn test_client_handshake_timeout() {
+    use tokio::net::TcpListener;
+
+    // The server accepts the connection but never sends a hello
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = tokio::spawn(async move {
+        let mut streams = Vec::new();
+        while let Ok((stream, _)) = listener.accept().await {
+            streams.push(stream);
+        }
+    });
+
+    let result = tokio::time::timeout(
+        Duration::from_secs(2),
+        Builder::new()
+            .server_addr(&format!("127.0.0.1:{}", port))
+            .unwrap()
+            .name("test-client")
+            .handshake_timeout(Duration::from_millis(100))
+            .connect(),
+    )
+    .await
+    .expect("client should not hang");
+    assert!(
+        matches!(result, Err(ClientError::HandshakeTimeout(timeout)) if timeout == Duration::from_millis(100)),
+        "Expected HandshakeTimeout, got {:?}",
+        result.err()
+    );
+
+    // The connection timeout applies to the handshake too
+    let result = tokio::time::timeout(
+        Duration::from_secs(2),
+        Builder::new()
+            .server_addr(&format!("127.0.0.1:{}", port))
+            .unwrap()
+            .name("test-client")
+            .connection_timeout(Duration::from_millis(100))
+            .connect(),
+    )
+    .await
+    .expect("client should not hang");
+    assert!(matches!(result, Err(ClientError::HandshakeTimeout(_))));
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn test_client_send_raw() {
+    use schengen::client::ClientEvent;
+    use schengen::protocol::{Message, MessageClose, MessageNoOp};
+
+    let (mut client, server) = common::connect_client_to_mock(|mut peer| async move {
+        let received = peer.recv_timeout(2000).await;
+        peer.send(Message::Close(MessageClose)).await;
+        received
+    })
+    .await;
+
+    client.send(Message::NoOp(MessageNoOp)).await.unwrap();
+    let received = tokio::time::timeout(Duration::from_secs(2), server)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(received, Some(Message::NoOp(MessageNoOp)));
+
+    // Nothing can be sent once the server closed the connection
+    assert!(matches!(
+        client.recv_event().await.unwrap(),
+        ClientEvent::Close
+    ));
+    assert!(matches!(
+        client.send(Message::NoOp(MessageNoOp)).await,
+        Err(ClientError::ConnectionClosed)
+    ));
+}
+
+#[tokio::test]
+async fn test_client_clipboard_oversize_policy() {
+    use schengen::client::{ClientEvent, OversizePolicy};
+    use schengen::protocol::clipboard::{ClipboardAssembler, ClipboardContents, ClipboardId};
+    use schengen::protocol::{DsopOption, Message, MessageSetOptions};
+
+    let (mut client, server) = common::connect_client_to_mock(|mut peer| async move {
+        // Limit the clipboard to 1 KiB
+        peer.send(Message::SetOptions(MessageSetOptions::from_typed(&[(
+            DsopOption::ClipboardSharingSize,
+            1,
+        )])))
+        .await;
+
+        let mut assembler = ClipboardAssembler::new();
+        loop {
+            match peer.recv_timeout(2000).await {
+                Some(Message::ClientClipboard(_)) => {}
+                Some(Message::ClipboardData(msg)) => {
+                    if let Some(data) = assembler.push(&msg).unwrap() {
+                        break data;
+                    }
+                }
+                other => panic!("Expected clipboard data, got {:?}", other),
+            }
+        }
+    })
+    .await;
+    assert!(matches!(
+        client.recv_event().await.unwrap(),
+        ClientEvent::SetOptions
+    ));
+
+    let text = format!("a{}", "é".repeat(1024));
+    let result = client
+        .set_clipboard_with_policy(ClipboardId::Clipboard, text.clone(), OversizePolicy::Reject)
+        .await;
+    assert!(matches!(
+        result,
+        Err(ClientError::ClipboardTooLarge { limit: 1024, .. })
+    ));
+
+    client
+        .set_clipboard_with_policy(ClipboardId::Clipboard, text, OversizePolicy::Truncate)
+        .await
+        .unwrap();
+    let data = tokio::time::timeout(Duration::from_secs(2), server)
+        .await
+        .unwrap()
+        .unwrap();
+    // 1012 bytes of text fit next to the 12 byte header, but the two-byte characters
+    // are never split, so one byte of the limit stays unused
+    assert_eq!(data.len(), 1023);
+    let contents = ClipboardContents::from_bytes(&data).unwrap();
+    let truncated = String::from_utf8(contents.formats[0].1.clone()).unwrap();
+    assert_eq!(truncated, format!("a{}", "é".repeat(505)));
+}
+
+#[tokio::test]
+async fn test_client_protocol_version_override() {
+    use schengen::protocol::{
+        Message, MessageHelloBarrier, MessageInfoAcknowledgment, MessageLegacySynergy,
+        MessageQueryInfo, MessageResetOptions, MessageSetOptions, ProtocolVersion,
+    };
+    use tokio::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let connect = tokio::spawn(async move {
+        Builder::new()
+            .server_addr(&format!("127.0.0.1:{}", port))
+            .unwrap()
+            .name("laptop")
+            .protocol_version(ProtocolVersion::new(1, 6))
+            .connect()
+            .await
+    });
+
+    let (stream, _) = listener.accept().await.unwrap();
+    let mut peer = common::RawPeer::new(stream);
+    peer.send(Message::HelloBarrier(MessageHelloBarrier {
+        major: 1,
+        minor: 8,
+        client_name: None,
+    }))
+    .await;
+    match peer.recv().await {
+        Some(Message::HelloBarrier(hello)) => {
+            assert_eq!((hello.major, hello.minor), (1, 6));
+            assert_eq!(hello.client_name.as_deref(), Some("laptop"));
+        }
+        other => panic!("Expected hello from client, got {:?}", other),
+    }
+    peer.send(Message::QueryInfo(MessageQueryInfo)).await;
+    assert!(matches!(peer.recv().await, Some(Message::ClientInfo(_))));
+    for msg in [
+        Message::LegacySynergy(MessageLegacySynergy { data: "en".into() }),
+        Message::InfoAcknowledgment(MessageInfoAcknowledgment),
+        Message::ResetOptions(MessageResetOptions),
+        Message::SetOptions(MessageSetOptions { options: vec![] }),
+    ] {
+        peer.send(msg).await;
+    }
+
+    // Negotiated down from the server's 1.8
+    let client = connect.await.unwrap().unwrap();
+    assert_eq!(client.protocol_version(), ProtocolVersion::new(1, 6));
+}
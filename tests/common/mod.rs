@@ -77,6 +77,24 @@ pub fn test_client_builder() -> schengen::client::Builder<schengen::client::Need
     schengen::client::Builder::new().dimensions(1920, 1080)
 }
 
+/// Keep receiving events from `server` in the background so it processes client
+/// messages, discarding the events
+///
+/// Errors are ignored after a short pause, so a failing server doesn't spin.
+#[allow(dead_code)]
+pub fn spawn_event_drain(
+    server: &std::sync::Arc<schengen::server::Server>,
+) -> tokio::task::JoinHandle<()> {
+    let server = std::sync::Arc::clone(server);
+    tokio::spawn(async move {
+        loop {
+            if server.recv_event().await.is_err() {
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+        }
+    })
+}
+
 /// Wait for a condition with timeout
 #[allow(dead_code)]
 pub async fn wait_for<F, Fut>(mut condition: F, timeout_ms: u64) -> bool
@@ -96,3 +114,220 @@ where
 
     false
 }
+
+/// A raw protocol peer for tests that need full control over the messages on the wire
+#[allow(dead_code)]
+pub struct RawPeer {
+    pub stream: tokio::net::TcpStream,
+    buffer: Vec<u8>,
+}
+
+#[allow(dead_code)]
+impl RawPeer {
+    pub fn new(stream: tokio::net::TcpStream) -> Self {
+        Self {
+            stream,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Send a message (with length prefix) to the other side
+    pub async fn send(&mut self, message: schengen::protocol::Message) {
+        use tokio::io::AsyncWriteExt;
+        self.stream.write_all(&message.to_bytes()).await.unwrap();
+        self.stream.flush().await.unwrap();
+    }
+
+    /// Receive the next message, returns `None` once the other side closed the connection
+    pub async fn recv(&mut self) -> Option<schengen::protocol::Message> {
+        use schengen::protocol::{ProtocolError, parse_message_with_length};
+        use tokio::io::AsyncReadExt;
+        loop {
+            match parse_message_with_length(&self.buffer) {
+                Ok((msg, consumed)) => {
+                    self.buffer.drain(..consumed);
+                    return Some(msg);
+                }
+                Err(ProtocolError::InsufficientData { .. }) => {}
+                Err(e) => panic!("Failed to parse message: {}", e),
+            }
+
+            let mut temp_buf = [0u8; 4096];
+            let n = self.stream.read(&mut temp_buf).await.ok()?;
+            if n == 0 {
+                return None;
+            }
+            self.buffer.extend_from_slice(&temp_buf[..n]);
+        }
+    }
+
+    /// Receive the next message that isn't a keepalive, with a timeout
+    pub async fn recv_timeout(&mut self, timeout_ms: u64) -> Option<schengen::protocol::Message> {
+        use schengen::protocol::Message;
+        tokio::time::timeout(std::time::Duration::from_millis(timeout_ms), async {
+            loop {
+                match self.recv().await {
+                    Some(Message::KeepAlive(_)) => continue,
+                    other => return other,
+                }
+            }
+        })
+        .await
+        .ok()
+        .flatten()
+    }
+}
+
+/// Accept one connection on `listener` and perform the server side of the handshake
+///
+/// Returns the raw peer, ready to exchange messages with the connected client.
+#[allow(dead_code)]
+pub async fn mock_server_handshake(listener: &TcpListener) -> RawPeer {
+    use schengen::protocol::{
+        Message, MessageHelloBarrier, MessageInfoAcknowledgment, MessageLegacySynergy,
+        MessageQueryInfo, MessageResetOptions, MessageSetOptions,
+    };
+
+    let (stream, _) = listener.accept().await.unwrap();
+    let mut peer = RawPeer::new(stream);
+
+    peer.send(Message::HelloBarrier(MessageHelloBarrier {
+        major: 1,
+        minor: 8,
+        client_name: None,
+    }))
+    .await;
+    match peer.recv().await {
+        Some(Message::HelloBarrier(_)) | Some(Message::HelloSynergy(_)) => {}
+        other => panic!("Expected hello from client, got {:?}", other),
+    }
+
+    peer.send(Message::QueryInfo(MessageQueryInfo)).await;
+    match peer.recv().await {
+        Some(Message::ClientInfo(_)) => {}
+        other => panic!("Expected client info, got {:?}", other),
+    }
+
+    peer.send(Message::LegacySynergy(MessageLegacySynergy {
+        data: "en".into(),
+    }))
+    .await;
+    peer.send(Message::InfoAcknowledgment(MessageInfoAcknowledgment))
+        .await;
+    peer.send(Message::ResetOptions(MessageResetOptions)).await;
+    peer.send(Message::SetOptions(MessageSetOptions { options: vec![] }))
+        .await;
+
+    peer
+}
+
+/// Connect a client named "test-client" to a mock server on a random port
+///
+/// `peer_fn` runs in the background with the server side of the connection once the
+/// handshake is done, its result is returned by the task handle.
+#[allow(dead_code)]
+pub async fn connect_client_to_mock<F, Fut>(
+    peer_fn: F,
+) -> (
+    schengen::client::Client,
+    tokio::task::JoinHandle<Fut::Output>,
+)
+where
+    F: FnOnce(RawPeer) -> Fut + Send + 'static,
+    Fut: std::future::Future + Send + 'static,
+    Fut::Output: Send + 'static,
+{
+    connect_client_to_mock_with(|builder| builder, peer_fn).await
+}
+
+/// Like [`connect_client_to_mock`] but with the client builder set up by `configure`
+#[allow(dead_code)]
+pub async fn connect_client_to_mock_with<C, F, Fut>(
+    configure: C,
+    peer_fn: F,
+) -> (
+    schengen::client::Client,
+    tokio::task::JoinHandle<Fut::Output>,
+)
+where
+    C: FnOnce(
+        schengen::client::Builder<schengen::client::Ready>,
+    ) -> schengen::client::Builder<schengen::client::Ready>,
+    F: FnOnce(RawPeer) -> Fut + Send + 'static,
+    Fut: std::future::Future + Send + 'static,
+    Fut::Output: Send + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    let server = tokio::spawn(async move { peer_fn(mock_server_handshake(&listener).await).await });
+
+    let builder = schengen::client::Builder::new()
+        .server_addr(&format!("127.0.0.1:{}", port))
+        .unwrap()
+        .name("test-client");
+    let client = configure(builder).connect().await.unwrap();
+
+    (client, server)
+}
+
+/// Connect to a server on `port` and perform the client side of the handshake
+///
+/// Returns the raw peer and the options the server sent with DSOP during the handshake.
+#[allow(dead_code)]
+pub async fn mock_client_handshake(port: u16, name: &str) -> (RawPeer, Vec<(u32, u32)>) {
+    mock_client_handshake_versioned(port, name, 1, 8).await
+}
+
+/// Like [`mock_client_handshake`] but advertising the given protocol version in the hello
+#[allow(dead_code)]
+pub async fn mock_client_handshake_versioned(
+    port: u16,
+    name: &str,
+    major: u16,
+    minor: u16,
+) -> (RawPeer, Vec<(u32, u32)>) {
+    use schengen::protocol::{Message, MessageClientInfo, MessageHelloBarrier};
+
+    let stream = tokio::net::TcpStream::connect(("127.0.0.1", port))
+        .await
+        .unwrap();
+    let mut peer = RawPeer::new(stream);
+
+    match peer.recv().await {
+        Some(Message::HelloBarrier(_)) | Some(Message::HelloSynergy(_)) => {}
+        other => panic!("Expected hello from server, got {:?}", other),
+    }
+    peer.send(Message::HelloBarrier(MessageHelloBarrier {
+        major,
+        minor,
+        client_name: Some(name.to_string()),
+    }))
+    .await;
+
+    match peer.recv().await {
+        Some(Message::QueryInfo(_)) => {}
+        other => panic!("Expected query info, got {:?}", other),
+    }
+    peer.send(Message::ClientInfo(MessageClientInfo {
+        x: 0,
+        y: 0,
+        width: 1920,
+        height: 1080,
+        current_mouse_x: 0,
+        current_mouse_y: 0,
+        size: 0,
+    }))
+    .await;
+
+    // LSYN, CIAK, CROP, then DSOP completes the handshake
+    let options = loop {
+        match peer.recv().await {
+            Some(Message::SetOptions(msg)) => break msg.options,
+            Some(_) => continue,
+            None => panic!("Server closed the connection during the handshake"),
+        }
+    };
+
+    (peer, options)
+}
@@ -5,6 +5,7 @@
 mod common;
 
 use schengen::client::Builder as ClientBuilder;
+use schengen::protocol::Capabilities;
 use schengen::server::{Builder as ServerBuilder, ClientBuilder as ServerClientBuilder, Position};
 use std::sync::Arc;
 use std::time::Duration;
@@ -30,12 +31,7 @@ async fn test_client_can_connect() {
     let server = Arc::new(server);
 
     // Spawn task to accept connection
-    let server_clone = Arc::clone(&server);
-    tokio::spawn(async move {
-        loop {
-            let _ = server_clone.recv_event().await;
-        }
-    });
+    common::spawn_event_drain(&server);
 
     // Give server a moment to be ready
     tokio::time::sleep(Duration::from_millis(50)).await;
@@ -70,12 +66,7 @@ async fn test_server_rejects_unknown_client_name() {
     let server = Arc::new(server);
 
     // Spawn server event loop to accept (and reject) connections
-    let server_clone = Arc::clone(&server);
-    tokio::spawn(async move {
-        loop {
-            let _ = server_clone.recv_event().await;
-        }
-    });
+    common::spawn_event_drain(&server);
 
     // Give server a moment to be ready
     tokio::time::sleep(Duration::from_millis(50)).await;
@@ -106,12 +97,7 @@ async fn test_server_message_sending() {
     let server = Arc::new(server);
 
     // Spawn server event loop
-    let server_clone = Arc::clone(&server);
-    let server_task = tokio::spawn(async move {
-        loop {
-            let _ = server_clone.recv_event().await;
-        }
-    });
+    let server_task = common::spawn_event_drain(&server);
 
     // Connect client
     let _client = ClientBuilder::new()
@@ -151,12 +137,7 @@ async fn test_client_reports_dimensions() {
     let server = Arc::new(server);
 
     // Spawn server event loop
-    let server_clone = Arc::clone(&server);
-    tokio::spawn(async move {
-        loop {
-            let _ = server_clone.recv_event().await;
-        }
-    });
+    common::spawn_event_drain(&server);
 
     // Connect client with specific dimensions
     let _client = ClientBuilder::new()
@@ -174,8 +155,7 @@ async fn test_client_reports_dimensions() {
     // Verify dimensions
     let clients = server.clients().await;
     assert_eq!(clients.len(), 1);
-    assert_eq!(clients[0].width, 2560);
-    assert_eq!(clients[0].height, 1440);
+    assert_eq!(clients[0].dimensions, Some((2560, 1440)));
 }
 
 #[tokio::test]
@@ -204,12 +184,7 @@ async fn test_two_clients_can_connect() {
     let server = Arc::new(server);
 
     // Spawn server event loop
-    let server_clone = Arc::clone(&server);
-    tokio::spawn(async move {
-        loop {
-            let _ = server_clone.recv_event().await;
-        }
-    });
+    common::spawn_event_drain(&server);
 
     // Give server a moment
     tokio::time::sleep(Duration::from_millis(50)).await;
@@ -245,3 +220,493 @@ async fn test_two_clients_can_connect() {
 
     // In a real scenario both should connect, but due to timing we accept at least one
 }
+
+#[tokio::test]
+async fn test_capabilities_are_negotiated() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    let client_cfg = ServerClientBuilder::new("laptop")
+        .position(Position::Left)
+        .build();
+
+    let server = ServerBuilder::new()
+        .add_client(client_cfg)
+        .unwrap()
+        .capabilities(Capabilities(0b0111))
+        .listen_on_stream(listener)
+        .await
+        .unwrap();
+
+    let server = Arc::new(server);
+
+    common::spawn_event_drain(&server);
+
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let client = ClientBuilder::new()
+        .server_addr(&format!("127.0.0.1:{}", port))
+        .unwrap()
+        .name("laptop")
+        .capabilities(Capabilities(0b1110))
+        .connect()
+        .await
+        .unwrap();
+
+    assert_eq!(client.peer_capabilities(), Capabilities(0b0111));
+    assert_eq!(client.negotiated_capabilities(), Capabilities(0b0110));
+
+    // The client's reply arrives after the handshake
+    let server_ref = Arc::clone(&server);
+    let received = common::wait_for(
+        || {
+            let server = Arc::clone(&server_ref);
+            async move {
+                server
+                    .clients()
+                    .await
+                    .iter()
+                    .any(|c| !c.client().peer_capabilities().is_empty())
+            }
+        },
+        2000,
+    )
+    .await;
+    assert!(received, "Server should receive the client capabilities");
+
+    let clients = server.clients().await;
+    assert_eq!(
+        clients[0].client().peer_capabilities(),
+        Capabilities(0b1110)
+    );
+    assert_eq!(
+        server.negotiated_capabilities(clients[0].id()).await,
+        Some(Capabilities(0b0110))
+    );
+}
+
+#[tokio::test]
+async fn test_capabilities_empty_without_schengen_server_caps() {
+    let (server, port) = common::spawn_test_server("laptop").await;
+    let server = Arc::new(server);
+
+    common::spawn_event_drain(&server);
+
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let client = ClientBuilder::new()
+        .server_addr(&format!("127.0.0.1:{}", port))
+        .unwrap()
+        .name("laptop")
+        .capabilities(Capabilities(0b1110))
+        .connect()
+        .await
+        .unwrap();
+
+    assert!(client.peer_capabilities().is_empty());
+    assert!(client.negotiated_capabilities().is_empty());
+}
+
+#[tokio::test]
+async fn test_server_clipboard_received() {
+    use schengen::protocol::Message;
+    use schengen::protocol::clipboard::{ClipboardContents, ClipboardFormat, ClipboardId};
+    use schengen::server::ServerEvent;
+
+    let (server, port) = common::spawn_test_server("laptop").await;
+    let server = Arc::new(server);
+
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let server_clone = Arc::clone(&server);
+    tokio::spawn(async move {
+        loop {
+            if let Ok(event) = server_clone.recv_event().await {
+                let _ = tx.send(event);
+            }
+        }
+    });
+
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let mut client = ClientBuilder::new()
+        .server_addr(&format!("127.0.0.1:{}", port))
+        .unwrap()
+        .name("laptop")
+        .connect()
+        .await
+        .unwrap();
+
+    let contents = ClipboardContents::new(vec![
+        (ClipboardFormat::Text, b"hello".to_vec()),
+        (ClipboardFormat::Html, b"<b>hello</b>".to_vec()),
+    ]);
+    let messages: Vec<Message> = contents
+        .to_messages(ClipboardId::Selection, 7)
+        .into_iter()
+        .map(Message::from)
+        .collect();
+    client.send_all(&messages).await.unwrap();
+
+    let received = tokio::time::timeout(Duration::from_secs(2), async {
+        loop {
+            if let Some(ServerEvent::ClipboardReceived {
+                name, id, contents, ..
+            }) = rx.recv().await
+            {
+                return (name, id, contents);
+            }
+        }
+    })
+    .await
+    .expect("Server should report the received clipboard");
+
+    assert_eq!(received.0, "laptop");
+    assert_eq!(received.1, ClipboardId::Selection);
+    assert_eq!(
+        received.2.formats,
+        vec![
+            (ClipboardFormat::Text, b"hello".to_vec()),
+            (ClipboardFormat::Html, b"<b>hello</b>".to_vec()),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_client_send_client_info() {
+    use schengen::server::ServerEvent;
+
+    let (server, port) = common::spawn_test_server("laptop").await;
+    let server = Arc::new(server);
+
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let server_clone = Arc::clone(&server);
+    tokio::spawn(async move {
+        loop {
+            if let Ok(event) = server_clone.recv_event().await {
+                let _ = tx.send(event);
+            }
+        }
+    });
+
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let mut client = common::test_client_builder()
+        .server_addr(&format!("127.0.0.1:{}", port))
+        .unwrap()
+        .name("laptop")
+        .connect()
+        .await
+        .unwrap();
+
+    client.send_client_info().await.unwrap();
+
+    let updated = tokio::time::timeout(Duration::from_secs(2), async {
+        loop {
+            if let Some(ServerEvent::ClientInfoUpdated { width, height, .. }) = rx.recv().await {
+                return (width, height);
+            }
+        }
+    })
+    .await
+    .expect("Server should receive the client info");
+    assert_eq!(updated, (1920, 1080));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_unix_socket_handshake() {
+    use schengen::client::InputEvent;
+    use schengen::server::ServerEvent;
+
+    let path = std::env::temp_dir().join(format!("schengen-test-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    let server = ServerBuilder::new()
+        .add_client(
+            ServerClientBuilder::new("vm")
+                .position(Position::Right)
+                .build(),
+        )
+        .unwrap()
+        .listen_on_unix(&path)
+        .await
+        .unwrap();
+    let server = Arc::new(server);
+
+    let server_clone = Arc::clone(&server);
+    let connected = tokio::spawn(async move {
+        loop {
+            if let Ok(ServerEvent::ClientConnected { name, addr, .. }) =
+                server_clone.recv_event().await
+            {
+                return (name, addr);
+            }
+        }
+    });
+
+    let mut client = common::test_client_builder()
+        .name("vm")
+        .connect_unix(&path)
+        .await
+        .unwrap();
+
+    let (name, addr) = tokio::time::timeout(Duration::from_secs(2), connected)
+        .await
+        .expect("Server should accept the client")
+        .unwrap();
+    assert_eq!(name, "vm");
+    assert!(addr.ip().is_unspecified());
+
+    // Keep the server reading while it sends to the client
+    common::spawn_event_drain(&server);
+    server.switch_to("vm").await.unwrap();
+    let event = tokio::time::timeout(Duration::from_secs(2), client.events().next())
+        .await
+        .unwrap();
+    assert!(matches!(event, Some(InputEvent::Enter { .. })));
+
+    let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test]
+async fn test_serve_connection_over_duplex() {
+    use schengen::client::InputEvent;
+    use schengen::server::ServerEvent;
+
+    let (server_end, client_end) = tokio::io::duplex(64 * 1024);
+    let server = ServerBuilder::new()
+        .add_client(
+            ServerClientBuilder::new("laptop")
+                .position(Position::Left)
+                .build(),
+        )
+        .unwrap()
+        .serve_connection(server_end, None)
+        .await
+        .unwrap();
+
+    let connect = common::test_client_builder()
+        .name("laptop")
+        .connect_with_stream(client_end);
+    let (event, client) = tokio::time::timeout(Duration::from_secs(2), async {
+        tokio::join!(server.recv_event(), connect)
+    })
+    .await
+    .expect("Handshake should complete");
+    let mut client = client.unwrap();
+    match event.unwrap() {
+        ServerEvent::ClientConnected {
+            name,
+            width,
+            height,
+            ..
+        } => {
+            assert_eq!(name, "laptop");
+            assert_eq!((width, height), (1920, 1080));
+        }
+        other => panic!("Unexpected event {:?}", other),
+    }
+
+    server.switch_to("laptop").await.unwrap();
+    let event = tokio::time::timeout(Duration::from_secs(2), client.events().next())
+        .await
+        .unwrap();
+    assert!(matches!(event, Some(InputEvent::Enter { .. })));
+}
+
+#[tokio::test]
+async fn test_relative_mouse_moves() {
+    use schengen::client::InputEvent;
+
+    let (server_end, client_end) = tokio::io::duplex(64 * 1024);
+    let server = ServerBuilder::new()
+        .add_client(
+            ServerClientBuilder::new("laptop")
+                .position(Position::Left)
+                .build(),
+        )
+        .unwrap()
+        .relative_mouse_moves(true)
+        .serve_connection(server_end, None)
+        .await
+        .unwrap();
+
+    let connect = common::test_client_builder()
+        .name("laptop")
+        .connect_with_stream(client_end);
+    let (event, client) = tokio::time::timeout(Duration::from_secs(2), async {
+        tokio::join!(server.recv_event(), connect)
+    })
+    .await
+    .expect("Handshake should complete");
+    event.unwrap();
+    let mut client = client.unwrap();
+    assert!(client.options().relative_mouse_moves());
+
+    server.switch_to("laptop").await.unwrap();
+    server.inject_mouse_move(100, 50).await.unwrap();
+    server.inject_mouse_move(90, 70).await.unwrap();
+    server.set_relative_mouse_moves(false).await.unwrap();
+    server.inject_mouse_move(120, 60).await.unwrap();
+
+    let mut events = client.events();
+    let mut next = async || {
+        tokio::time::timeout(Duration::from_secs(2), events.next())
+            .await
+            .unwrap()
+            .unwrap()
+    };
+    // Relative to where the cursor entered
+    let (x, y) = match next().await {
+        InputEvent::Enter { x, y, .. } => (x, y),
+        other => panic!("Expected Enter, got {:?}", other),
+    };
+    match next().await {
+        InputEvent::MouseRelativeMove { dx, dy } => assert_eq!((dx, dy), (100 - x, 50 - y)),
+        other => panic!("Expected a relative move, got {:?}", other),
+    }
+    assert!(matches!(
+        next().await,
+        InputEvent::MouseRelativeMove { dx: -10, dy: 20 }
+    ));
+    assert!(matches!(
+        next().await,
+        InputEvent::MouseMove { x: 120, y: 60 }
+    ));
+    drop(events);
+    assert!(!client.options().relative_mouse_moves());
+    assert!(!server.relative_mouse_moves());
+}
+
+#[tokio::test]
+async fn test_hello_dialects() {
+    use schengen::protocol::{Dialect, Message};
+
+    let dialects = [Dialect::Barrier, Dialect::Synergy];
+    for server_dialect in dialects {
+        for client_dialect in dialects {
+            let (server_end, client_end) = tokio::io::duplex(64 * 1024);
+            let server = ServerBuilder::new()
+                .add_client(
+                    ServerClientBuilder::new("laptop")
+                        .position(Position::Left)
+                        .build(),
+                )
+                .unwrap()
+                .hello_dialect(server_dialect)
+                .serve_connection(server_end, None)
+                .await
+                .unwrap();
+
+            let connect = common::test_client_builder()
+                .name("laptop")
+                .hello_dialect(client_dialect)
+                .connect_with_stream(client_end);
+            let (event, client) = tokio::time::timeout(Duration::from_secs(2), async {
+                tokio::join!(server.recv_event(), connect)
+            })
+            .await
+            .expect("Handshake should complete");
+            event.unwrap();
+            let client = client.unwrap();
+
+            let is_synergy = |hello: &Message| match hello {
+                Message::HelloSynergy(_) => true,
+                Message::HelloBarrier(_) => false,
+                other => panic!("Expected a hello, got {:?}", other),
+            };
+            assert_eq!(
+                is_synergy(client.peer_hello()),
+                server_dialect == Dialect::Synergy
+            );
+            let clients = server.clients().await;
+            assert_eq!(
+                is_synergy(clients[0].client().peer_hello()),
+                client_dialect == Dialect::Synergy
+            );
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_secure_input_notification() {
+    use schengen::server::ServerEvent;
+
+    let (server_end, client_end) = tokio::io::duplex(64 * 1024);
+    let server = ServerBuilder::new()
+        .add_client(
+            ServerClientBuilder::new("laptop")
+                .position(Position::Left)
+                .build(),
+        )
+        .unwrap()
+        .serve_connection(server_end, None)
+        .await
+        .unwrap();
+
+    let connect = common::test_client_builder()
+        .name("laptop")
+        .connect_with_stream(client_end);
+    let (event, client) = tokio::time::timeout(Duration::from_secs(2), async {
+        tokio::join!(server.recv_event(), connect)
+    })
+    .await
+    .expect("Handshake should complete");
+    event.unwrap();
+    let mut client = client.unwrap();
+
+    client.notify_secure_input("Keychain Access").await.unwrap();
+    let event = tokio::time::timeout(Duration::from_secs(2), server.recv_event())
+        .await
+        .unwrap()
+        .unwrap();
+    match event {
+        ServerEvent::SecureInput { name, app_name, .. } => {
+            assert_eq!(name, "laptop");
+            assert_eq!(app_name, "Keychain Access");
+        }
+        other => panic!("Expected SecureInput, got {:?}", other),
+    }
+}
+
+#[cfg(feature = "tracing")]
+#[tokio::test]
+#[tracing_test::traced_test]
+async fn test_tracing_handshake_complete() {
+    let (server_end, client_end) = tokio::io::duplex(64 * 1024);
+    let server = ServerBuilder::new()
+        .add_client(
+            ServerClientBuilder::new("laptop")
+                .position(Position::Left)
+                .build(),
+        )
+        .unwrap()
+        .serve_connection(server_end, None)
+        .await
+        .unwrap();
+
+    let connect = common::test_client_builder()
+        .name("laptop")
+        .connect_with_stream(client_end);
+    let (event, client) = tokio::time::timeout(Duration::from_secs(2), async {
+        tokio::join!(server.recv_event(), connect)
+    })
+    .await
+    .expect("Handshake should complete");
+    event.unwrap();
+    client.unwrap();
+
+    // Both ends report the completed handshake with the client name and version
+    for target in ["schengen::server", "schengen::client"] {
+        logs_assert(|lines: &[&str]| {
+            let complete = lines
+                .iter()
+                .filter(|line| line.contains(target) && line.contains("handshake complete"))
+                .collect::<Vec<_>>();
+            match complete.as_slice() {
+                [line] if line.contains("client=laptop") && line.contains("version=1.8") => Ok(()),
+                other => Err(format!("Unexpected {} events: {:?}", target, other)),
+            }
+        });
+    }
+}
@@ -48,6 +48,94 @@ async fn test_server_rejects_duplicate_positions() {
     assert!(result.is_err(), "Should reject duplicate positions");
 }
 
+#[test]
+fn test_server_rejects_duplicate_names() {
+    use schengen::server::ServerError;
+
+    let result = Builder::new()
+        .add_client(
+            ClientBuilder::new("laptop")
+                .position(Position::Left)
+                .build(),
+        )
+        .unwrap()
+        .add_client(
+            ClientBuilder::new("laptop")
+                .position(Position::Right)
+                .build(),
+        );
+    match result {
+        Err(ServerError::DuplicateName(name)) => assert_eq!(name, "laptop"),
+        other => panic!("Expected DuplicateName, got {:?}", other.err()),
+    }
+}
+
+#[test]
+fn test_server_layout_error_variants() {
+    use schengen::server::ServerError;
+
+    let laptop = ClientBuilder::new("laptop")
+        .position(Position::Left)
+        .build();
+    let builder = Builder::new().add_client(laptop.clone()).unwrap();
+
+    let result = builder.add_client(
+        ClientBuilder::new("tablet")
+            .position(Position::Left)
+            .build(),
+    );
+    match result {
+        Err(ServerError::ServerPositionOccupied {
+            position,
+            existing_client,
+        }) => {
+            assert_eq!(position, Position::Left);
+            assert_eq!(existing_client, "laptop");
+        }
+        other => panic!("Expected ServerPositionOccupied, got {:?}", other.err()),
+    }
+
+    let builder = Builder::new()
+        .add_client(laptop.clone())
+        .unwrap()
+        .add_client(
+            ClientBuilder::new("tablet")
+                .position(Position::Below)
+                .relative_to(&laptop)
+                .build(),
+        )
+        .unwrap();
+    let result = builder.add_client(
+        ClientBuilder::new("phone")
+            .position(Position::Below)
+            .relative_to(&laptop)
+            .build(),
+    );
+    match result {
+        Err(ServerError::RelativePositionOccupied {
+            position,
+            relative_to,
+            existing_client,
+        }) => {
+            assert_eq!(position, Position::Below);
+            assert_eq!(relative_to, "laptop");
+            assert_eq!(existing_client, "tablet");
+        }
+        other => panic!("Expected RelativePositionOccupied, got {:?}", other.err()),
+    }
+
+    let result = Builder::new().add_client(
+        ClientBuilder::new("phone")
+            .position(Position::Below)
+            .relative_to(&laptop)
+            .build(),
+    );
+    match result {
+        Err(ServerError::RelativeClientNotFound(name)) => assert_eq!(name, "laptop"),
+        other => panic!("Expected RelativeClientNotFound, got {:?}", other.err()),
+    }
+}
+
 #[tokio::test]
 async fn test_server_accepts_different_positions() {
     let laptop = ClientBuilder::new("laptop")
@@ -208,3 +296,2170 @@ async fn test_server_multiple_clients_configuration() {
         "No clients should be connected yet (only configured)"
     );
 }
+
+#[tokio::test]
+async fn test_render_layout_ascii() {
+    let (server, _port) = common::spawn_test_server_multi(vec![
+        ("laptop".to_string(), Position::Left, None),
+        ("desktop".to_string(), Position::Right, None),
+        (
+            "tablet".to_string(),
+            Position::Below,
+            Some("laptop".to_string()),
+        ),
+    ])
+    .await;
+
+    let rendered = server.render_layout_ascii();
+    let lines: Vec<&str> = rendered.lines().collect();
+    assert_eq!(lines.len(), 2, "Unexpected layout:\n{}", rendered);
+
+    // No cursor has entered a client yet, so the primary is active
+    let laptop = lines[0].find("[laptop").unwrap();
+    let server_pos = lines[0].find("[server*").unwrap();
+    let desktop = lines[0].find("[desktop").unwrap();
+    assert!(laptop < server_pos && server_pos < desktop);
+
+    // The tablet sits in the column below the laptop
+    assert_eq!(lines[1].find("[tablet"), Some(laptop));
+}
+
+#[tokio::test]
+async fn test_render_layout_ascii_marks_active_screen() {
+    use std::sync::Arc;
+
+    let (server, port) = common::spawn_test_server("laptop").await;
+    let server = Arc::new(server);
+
+    common::spawn_event_drain(&server);
+
+    let _client = common::test_client_builder()
+        .server_addr(&format!("127.0.0.1:{}", port))
+        .unwrap()
+        .name("laptop")
+        .connect()
+        .await
+        .unwrap();
+
+    let server_ref = Arc::clone(&server);
+    assert!(
+        common::wait_for(
+            || {
+                let server = Arc::clone(&server_ref);
+                async move { !server.clients().await.is_empty() }
+            },
+            2000
+        )
+        .await
+    );
+    let client_id = server.clients().await[0].id();
+
+    server
+        .send_cursor_entered(client_id, 0, 0, 1, 0)
+        .await
+        .unwrap();
+    let rendered = server.render_layout_ascii();
+    assert!(rendered.contains("[laptop*]"), "{}", rendered);
+    assert!(!rendered.contains("server*"), "{}", rendered);
+
+    server.send_cursor_left(client_id).await.unwrap();
+    let rendered = server.render_layout_ascii();
+    assert!(rendered.contains("[server*]"), "{}", rendered);
+    assert!(!rendered.contains("laptop*"), "{}", rendered);
+}
+
+#[tokio::test]
+async fn test_server_preserve_focus_option() {
+    use schengen::protocol::DsopOption;
+    use std::sync::Arc;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    let server = Builder::new()
+        .add_client(
+            ClientBuilder::new("laptop")
+                .position(Position::Left)
+                .build(),
+        )
+        .unwrap()
+        .preserve_focus(true)
+        .listen_on_stream(listener)
+        .await
+        .unwrap();
+    let server = Arc::new(server);
+
+    common::spawn_event_drain(&server);
+
+    let (_peer, options) = common::mock_client_handshake(port, "laptop").await;
+    assert!(
+        options.contains(&(DsopOption::ScreenPreserveFocus as u32, 1)),
+        "SFOC missing from {:?}",
+        options
+    );
+}
+
+#[tokio::test]
+async fn test_server_preserve_focus_disabled_by_default() {
+    use std::sync::Arc;
+
+    let (server, port) = common::spawn_test_server("laptop").await;
+    let server = Arc::new(server);
+
+    common::spawn_event_drain(&server);
+
+    let (_peer, options) = common::mock_client_handshake(port, "laptop").await;
+    assert!(options.is_empty(), "Unexpected options {:?}", options);
+}
+
+#[tokio::test]
+async fn test_server_send_all() {
+    use schengen::protocol::{Message, MessageCursorEntered, MessageMouseMove, MessageMouseWheel};
+    use std::sync::Arc;
+
+    let (server, port) = common::spawn_test_server("laptop").await;
+    let server = Arc::new(server);
+
+    common::spawn_event_drain(&server);
+
+    let (mut peer, _) = common::mock_client_handshake(port, "laptop").await;
+
+    let server_ref = Arc::clone(&server);
+    assert!(
+        common::wait_for(
+            || {
+                let server = Arc::clone(&server_ref);
+                async move { !server.clients().await.is_empty() }
+            },
+            2000
+        )
+        .await
+    );
+    let client_id = server.clients().await[0].id();
+
+    let batch = vec![
+        Message::CursorEntered(MessageCursorEntered {
+            x: 10,
+            y: 20,
+            sequence: 1,
+            mask: 0,
+        }),
+        Message::MouseMove(MessageMouseMove { x: 11, y: 21 }),
+        Message::MouseWheel(MessageMouseWheel {
+            xdelta: 0,
+            ydelta: 120,
+        }),
+    ];
+    server.send_all(client_id, &batch).await.unwrap();
+
+    for expected in batch {
+        assert_eq!(peer.recv_timeout(2000).await, Some(expected));
+    }
+}
+
+#[tokio::test]
+async fn test_server_set_primary_clipboard() {
+    use schengen::protocol::Message;
+    use schengen::protocol::clipboard::{
+        ClipboardContents, ClipboardFormat, ClipboardId, MARK_FINAL, MARK_FIRST, MARK_MIDDLE,
+    };
+    use std::sync::Arc;
+
+    let (server, port) = common::spawn_test_server("laptop").await;
+    let server = Arc::new(server);
+
+    common::spawn_event_drain(&server);
+
+    let (mut peer, _) = common::mock_client_handshake(port, "laptop").await;
+
+    let server_ref = Arc::clone(&server);
+    assert!(
+        common::wait_for(
+            || {
+                let server = Arc::clone(&server_ref);
+                async move { !server.clients().await.is_empty() }
+            },
+            2000
+        )
+        .await
+    );
+    let client_id = server.clients().await[0].id();
+
+    let text = b"hello".to_vec();
+    let html = b"<b>hello</b>".to_vec();
+    server
+        .set_primary_clipboard(
+            ClipboardId::Clipboard,
+            &[
+                (ClipboardFormat::Text, text.clone()),
+                (ClipboardFormat::Html, html.clone()),
+            ],
+        )
+        .await
+        .unwrap();
+
+    // The grab is announced immediately
+    match peer.recv_timeout(2000).await {
+        Some(Message::ClientClipboard(grab)) => assert_eq!(grab.id, 0),
+        other => panic!("Expected clipboard grab, got {:?}", other),
+    }
+
+    // The data follows once the cursor enters the client
+    server
+        .send_cursor_entered(client_id, 0, 0, 1, 0)
+        .await
+        .unwrap();
+    assert!(matches!(
+        peer.recv_timeout(2000).await,
+        Some(Message::CursorEntered(_))
+    ));
+
+    let mut data = Vec::new();
+    loop {
+        match peer.recv_timeout(2000).await {
+            Some(Message::ClipboardData(msg)) => {
+                assert_eq!(msg.id, 0);
+                match msg.mark {
+                    MARK_FIRST => assert!(data.is_empty()),
+                    MARK_MIDDLE => data.extend_from_slice(&msg.data.0),
+                    MARK_FINAL => break,
+                    mark => panic!("Unexpected mark {}", mark),
+                }
+            }
+            other => panic!("Expected clipboard data, got {:?}", other),
+        }
+    }
+
+    let contents = ClipboardContents::from_bytes(&data).unwrap();
+    assert_eq!(
+        contents.formats,
+        vec![(ClipboardFormat::Text, text), (ClipboardFormat::Html, html)]
+    );
+
+    // Entering again doesn't resend an unchanged clipboard
+    server.send_cursor_left(client_id).await.unwrap();
+    server
+        .send_cursor_entered(client_id, 0, 0, 2, 0)
+        .await
+        .unwrap();
+    assert!(matches!(
+        peer.recv_timeout(2000).await,
+        Some(Message::CursorLeft(_))
+    ));
+    assert!(matches!(
+        peer.recv_timeout(2000).await,
+        Some(Message::CursorEntered(_))
+    ));
+    assert_eq!(peer.recv_timeout(200).await, None);
+}
+
+#[tokio::test]
+async fn test_server_on_accept_rejects() {
+    use schengen::server::AcceptDecision;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::io::AsyncReadExt;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    let rejected = Arc::new(AtomicUsize::new(0));
+    let rejected_clone = Arc::clone(&rejected);
+    let server = Builder::new()
+        .add_client(
+            ClientBuilder::new("laptop")
+                .position(Position::Left)
+                .build(),
+        )
+        .unwrap()
+        .on_accept(move |addr| {
+            if addr.ip().is_loopback() {
+                rejected_clone.fetch_add(1, Ordering::SeqCst);
+                AcceptDecision::Reject
+            } else {
+                AcceptDecision::Accept
+            }
+        })
+        .listen_on_stream(listener)
+        .await
+        .unwrap();
+    let server = Arc::new(server);
+
+    common::spawn_event_drain(&server);
+
+    let mut stream = tokio::net::TcpStream::connect(("127.0.0.1", port))
+        .await
+        .unwrap();
+
+    // The connection is closed without the server sending its hello
+    let mut buf = [0u8; 64];
+    let n = tokio::time::timeout(std::time::Duration::from_secs(2), stream.read(&mut buf))
+        .await
+        .expect("connection should be closed")
+        .unwrap_or(0);
+    assert_eq!(n, 0, "Received unexpected data: {:?}", &buf[..n]);
+    assert_eq!(rejected.load(Ordering::SeqCst), 1);
+    assert!(server.clients().await.is_empty());
+}
+
+#[tokio::test]
+async fn test_server_disconnect_client() {
+    use schengen::protocol::{Message, MessageClose, MessageCursorLeft};
+    use schengen::server::{DisconnectReason, ServerEvent};
+    use std::sync::Arc;
+
+    let (server, port) = common::spawn_test_server("laptop").await;
+    let server = Arc::new(server);
+
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let server_clone = Arc::clone(&server);
+    tokio::spawn(async move {
+        loop {
+            if let Ok(event) = server_clone.recv_event().await {
+                let _ = tx.send(event);
+            }
+        }
+    });
+
+    let (mut peer, _) = common::mock_client_handshake(port, "laptop").await;
+    let event = tokio::time::timeout(std::time::Duration::from_secs(2), rx.recv())
+        .await
+        .unwrap();
+    let client_id = match event {
+        Some(ServerEvent::ClientConnected { client_id, .. }) => client_id,
+        other => panic!("Expected ClientConnected, got {:?}", other),
+    };
+
+    server
+        .send_cursor_entered(client_id, 10, 10, 1, 0)
+        .await
+        .unwrap();
+    peer.recv_timeout(2000).await.unwrap();
+
+    server
+        .disconnect_client("laptop", DisconnectReason::Kicked)
+        .await
+        .unwrap();
+
+    // The active client is returned to the primary screen before it is closed
+    assert_eq!(
+        peer.recv_timeout(2000).await,
+        Some(Message::CursorLeft(MessageCursorLeft))
+    );
+    assert_eq!(
+        peer.recv_timeout(2000).await,
+        Some(Message::Close(MessageClose))
+    );
+
+    let event = tokio::time::timeout(std::time::Duration::from_secs(2), rx.recv())
+        .await
+        .unwrap();
+    match event {
+        Some(ServerEvent::ClientDisconnected {
+            client_id: id,
+            name,
+            reason,
+        }) => {
+            assert_eq!(id, client_id);
+            assert_eq!(name, "laptop");
+            assert_eq!(reason, DisconnectReason::Kicked);
+        }
+        other => panic!("Expected ClientDisconnected, got {:?}", other),
+    }
+
+    assert!(server.clients().await.is_empty());
+    assert!(server.render_layout_ascii().contains("server*"));
+
+    // The connection closing does not produce a second disconnect event
+    drop(peer);
+    assert!(
+        tokio::time::timeout(std::time::Duration::from_millis(200), rx.recv())
+            .await
+            .is_err()
+    );
+
+    assert!(
+        server
+            .disconnect_client("laptop", DisconnectReason::Kicked)
+            .await
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn test_server_rejects_incompatible_version() {
+    use schengen::protocol::{Message, MessageHelloBarrier, MessageIncompatibleVersion};
+    use std::sync::Arc;
+
+    let (server, port) = common::spawn_test_server("laptop").await;
+    let server = Arc::new(server);
+
+    common::spawn_event_drain(&server);
+
+    let stream = tokio::net::TcpStream::connect(("127.0.0.1", port))
+        .await
+        .unwrap();
+    let mut peer = common::RawPeer::new(stream);
+    assert!(matches!(
+        peer.recv_timeout(2000).await,
+        Some(Message::HelloBarrier(_))
+    ));
+    peer.send(Message::HelloBarrier(MessageHelloBarrier {
+        major: 2,
+        minor: 0,
+        client_name: Some("laptop".to_string()),
+    }))
+    .await;
+
+    assert_eq!(
+        peer.recv_timeout(2000).await,
+        Some(Message::IncompatibleVersion(MessageIncompatibleVersion {
+            major_remote: 1,
+            minor_remote: 8,
+        }))
+    );
+    assert!(server.clients().await.is_empty());
+}
+
+#[tokio::test]
+async fn test_server_send_text_downgrades_for_old_clients() {
+    use schengen::protocol::{Message, MessageKeyDown, MessageKeyDownWithLanguage, MessageKeyUp};
+    use std::sync::Arc;
+
+    let (server, port) = common::spawn_test_server_multi(vec![
+        ("old".to_string(), Position::Left, None),
+        ("new".to_string(), Position::Right, None),
+    ])
+    .await;
+    let server = Arc::new(server);
+
+    common::spawn_event_drain(&server);
+
+    let (mut old, _) = common::mock_client_handshake_versioned(port, "old", 1, 6).await;
+    let (mut new, _) = common::mock_client_handshake(port, "new").await;
+
+    let server_ref = Arc::clone(&server);
+    assert!(
+        common::wait_for(
+            || {
+                let server = Arc::clone(&server_ref);
+                async move { server.clients().await.len() == 2 }
+            },
+            2000
+        )
+        .await
+    );
+    let clients = server.clients().await;
+    let id_of = |name: &str| clients.iter().find(|c| c.name() == name).unwrap().id();
+
+    // A pre-1.8 client gets DKDN instead of DKDL
+    server.send_text(id_of("old"), "hi", "en").await.unwrap();
+    for keyid in [0x68, 0x69] {
+        assert_eq!(
+            old.recv_timeout(2000).await,
+            Some(Message::KeyDown(MessageKeyDown {
+                keyid,
+                mask: 0,
+                button: 0,
+            }))
+        );
+        assert_eq!(
+            old.recv_timeout(2000).await,
+            Some(Message::KeyUp(MessageKeyUp {
+                keyid,
+                mask: 0,
+                button: 0,
+            }))
+        );
+    }
+
+    // A 1.8 client gets DKDL with the language
+    server.send_text(id_of("new"), "h", "en").await.unwrap();
+    assert_eq!(
+        new.recv_timeout(2000).await,
+        Some(Message::KeyDownWithLanguage(MessageKeyDownWithLanguage {
+            keyid: 0x68,
+            mask: 0,
+            button: 0,
+            lang: "en".into(),
+        }))
+    );
+    assert_eq!(
+        new.recv_timeout(2000).await,
+        Some(Message::KeyUp(MessageKeyUp {
+            keyid: 0x68,
+            mask: 0,
+            button: 0,
+        }))
+    );
+}
+
+#[tokio::test]
+async fn test_server_client_peer_hello() {
+    use schengen::protocol::{Message, MessageHelloBarrier};
+    use std::sync::Arc;
+
+    let (server, port) = common::spawn_test_server("laptop").await;
+    let server = Arc::new(server);
+
+    common::spawn_event_drain(&server);
+
+    let (_peer, _) = common::mock_client_handshake_versioned(port, "laptop", 1, 6).await;
+
+    let server_ref = Arc::clone(&server);
+    assert!(
+        common::wait_for(
+            || {
+                let server = Arc::clone(&server_ref);
+                async move { !server.clients().await.is_empty() }
+            },
+            2000
+        )
+        .await
+    );
+
+    let clients = server.clients().await;
+    assert_eq!(
+        clients[0].client().peer_hello(),
+        &Message::HelloBarrier(MessageHelloBarrier {
+            major: 1,
+            minor: 6,
+            client_name: Some("laptop".to_string()),
+        })
+    );
+}
+
+#[tokio::test]
+async fn test_server_switch_to_centered() {
+    use schengen::protocol::{Message, MessageCursorLeft, MessageMouseMove};
+    use std::sync::Arc;
+
+    let (server, port) = common::spawn_test_server_multi(vec![
+        ("laptop".to_string(), Position::Left, None),
+        ("desktop".to_string(), Position::Right, None),
+    ])
+    .await;
+    let server = Arc::new(server);
+
+    common::spawn_event_drain(&server);
+
+    let (mut laptop, _) = common::mock_client_handshake(port, "laptop").await;
+    let (mut desktop, _) = common::mock_client_handshake(port, "desktop").await;
+
+    let server_ref = Arc::clone(&server);
+    assert!(
+        common::wait_for(
+            || {
+                let server = Arc::clone(&server_ref);
+                async move { server.clients().await.len() == 2 }
+            },
+            2000
+        )
+        .await
+    );
+
+    // The mock clients report 1920x1080
+    server.switch_to_centered("laptop").await.unwrap();
+    let first_sequence = match laptop.recv_timeout(2000).await {
+        Some(Message::CursorEntered(msg)) => {
+            assert_eq!((msg.x, msg.y), (960, 540));
+            msg.sequence
+        }
+        other => panic!("Expected CINN, got {:?}", other),
+    };
+    assert_eq!(
+        laptop.recv_timeout(2000).await,
+        Some(Message::MouseMove(MessageMouseMove { x: 960, y: 540 }))
+    );
+
+    // Switching on leaves the previous client first
+    server.switch_to_centered("desktop").await.unwrap();
+    assert_eq!(
+        laptop.recv_timeout(2000).await,
+        Some(Message::CursorLeft(MessageCursorLeft))
+    );
+    match desktop.recv_timeout(2000).await {
+        Some(Message::CursorEntered(msg)) => {
+            assert_eq!((msg.x, msg.y), (960, 540));
+            assert!(msg.sequence > first_sequence);
+        }
+        other => panic!("Expected CINN, got {:?}", other),
+    }
+    assert_eq!(
+        desktop.recv_timeout(2000).await,
+        Some(Message::MouseMove(MessageMouseMove { x: 960, y: 540 }))
+    );
+
+    assert!(server.switch_to_centered("unknown").await.is_err());
+}
+
+#[tokio::test]
+async fn test_server_shutdown() {
+    use schengen::protocol::{Message, MessageClose};
+    use schengen::server::ServerEvent;
+
+    let (server, port) = common::spawn_test_server("laptop").await;
+
+    let server_task = tokio::spawn(async move {
+        match server.recv_event().await {
+            Ok(ServerEvent::ClientConnected { .. }) => {}
+            other => panic!("Expected ClientConnected, got {:?}", other),
+        }
+        server
+    });
+
+    let (mut peer, _) = common::mock_client_handshake(port, "laptop").await;
+    let server = tokio::time::timeout(std::time::Duration::from_secs(2), server_task)
+        .await
+        .unwrap()
+        .unwrap();
+
+    server.shutdown().await;
+
+    assert_eq!(
+        peer.recv_timeout(2000).await,
+        Some(Message::Close(MessageClose))
+    );
+    let eof = tokio::time::timeout(std::time::Duration::from_secs(2), peer.recv()).await;
+    assert_eq!(eof.expect("Connection should be closed"), None);
+}
+
+#[tokio::test]
+async fn test_server_add_client_at_runtime() {
+    use schengen::server::ServerError;
+    use std::sync::Arc;
+
+    let (server, port) = common::spawn_test_server("laptop").await;
+    let server = Arc::new(server);
+
+    common::spawn_event_drain(&server);
+
+    // The left edge is taken by the configured client
+    let conflicting = ClientBuilder::new("tablet")
+        .position(Position::Left)
+        .build();
+    assert!(matches!(
+        server.add_client(conflicting),
+        Err(ServerError::ServerPositionOccupied { .. })
+    ));
+
+    let desktop = ClientBuilder::new("desktop")
+        .position(Position::Right)
+        .build();
+    server.add_client(desktop).unwrap();
+    assert!(server.render_layout_ascii().contains("[desktop]"));
+
+    let (_desktop, _) = common::mock_client_handshake(port, "desktop").await;
+    let server_ref = Arc::clone(&server);
+    assert!(
+        common::wait_for(
+            || {
+                let server = Arc::clone(&server_ref);
+                async move { server.clients().await.iter().any(|c| c.name() == "desktop") }
+            },
+            2000
+        )
+        .await
+    );
+}
+
+#[tokio::test]
+async fn test_server_remove_active_client() {
+    use schengen::protocol::{Message, MessageCursorLeft, MessageUnknownClient};
+    use schengen::server::ServerError;
+    use std::sync::Arc;
+
+    let (server, port) = common::spawn_test_server("laptop").await;
+    let server = Arc::new(server);
+
+    common::spawn_event_drain(&server);
+
+    let (mut peer, _) = common::mock_client_handshake(port, "laptop").await;
+    let server_ref = Arc::clone(&server);
+    assert!(
+        common::wait_for(
+            || {
+                let server = Arc::clone(&server_ref);
+                async move { server.clients().await.len() == 1 }
+            },
+            2000
+        )
+        .await
+    );
+
+    server.switch_to_centered("laptop").await.unwrap();
+    assert!(matches!(
+        peer.recv_timeout(2000).await,
+        Some(Message::CursorEntered(_))
+    ));
+    peer.recv_timeout(2000).await.unwrap();
+
+    server.remove_client("laptop").await.unwrap();
+    assert_eq!(
+        peer.recv_timeout(2000).await,
+        Some(Message::CursorLeft(MessageCursorLeft))
+    );
+    assert_eq!(
+        peer.recv_timeout(2000).await,
+        Some(Message::UnknownClient(MessageUnknownClient))
+    );
+
+    assert!(server.clients().await.is_empty());
+    assert_eq!(server.render_layout_ascii(), "[server*]");
+    assert!(matches!(
+        server.remove_client("laptop").await,
+        Err(ServerError::UnknownClient(_))
+    ));
+}
+
+#[tokio::test]
+async fn test_server_switch_to() {
+    use schengen::protocol::{Message, MessageCursorLeft};
+    use std::sync::Arc;
+
+    let (server, port) = common::spawn_test_server_multi(vec![
+        ("laptop".to_string(), Position::Left, None),
+        ("desktop".to_string(), Position::Right, None),
+    ])
+    .await;
+    let server = Arc::new(server);
+
+    common::spawn_event_drain(&server);
+
+    let (mut laptop, _) = common::mock_client_handshake(port, "laptop").await;
+    let (mut desktop, _) = common::mock_client_handshake(port, "desktop").await;
+
+    let server_ref = Arc::clone(&server);
+    assert!(
+        common::wait_for(
+            || {
+                let server = Arc::clone(&server_ref);
+                async move { server.clients().await.len() == 2 }
+            },
+            2000
+        )
+        .await
+    );
+
+    // The mock clients report their cursor at the origin
+    server.switch_to("laptop").await.unwrap();
+    let first_sequence = match laptop.recv_timeout(2000).await {
+        Some(Message::CursorEntered(msg)) => {
+            assert_eq!((msg.x, msg.y), (0, 0));
+            msg.sequence
+        }
+        other => panic!("Expected CINN, got {:?}", other),
+    };
+
+    server.switch_to("desktop").await.unwrap();
+    assert_eq!(
+        laptop.recv_timeout(2000).await,
+        Some(Message::CursorLeft(MessageCursorLeft))
+    );
+    match desktop.recv_timeout(2000).await {
+        Some(Message::CursorEntered(msg)) => assert!(msg.sequence > first_sequence),
+        other => panic!("Expected CINN, got {:?}", other),
+    }
+    assert!(server.render_layout_ascii().contains("desktop*"));
+
+    assert!(server.switch_to("unknown").await.is_err());
+}
+
+#[tokio::test]
+async fn test_server_inject_input() {
+    use schengen::protocol::{Message, MessageKeyDown, MessageMouseMove, MessageMouseWheel};
+    use schengen::server::{KeyEvent, ServerError};
+    use std::sync::Arc;
+
+    let (server, port) = common::spawn_test_server("laptop").await;
+    let server = Arc::new(server);
+
+    common::spawn_event_drain(&server);
+
+    let (mut peer, _) = common::mock_client_handshake(port, "laptop").await;
+    let server_ref = Arc::clone(&server);
+    assert!(
+        common::wait_for(
+            || {
+                let server = Arc::clone(&server_ref);
+                async move { server.clients().await.len() == 1 }
+            },
+            2000
+        )
+        .await
+    );
+
+    // Nothing to inject into while the cursor is on the primary screen
+    assert!(matches!(
+        server.inject_mouse_move(1, 2).await,
+        Err(ServerError::NoActiveClient)
+    ));
+
+    server.switch_to("laptop").await.unwrap();
+    assert!(matches!(
+        peer.recv_timeout(2000).await,
+        Some(Message::CursorEntered(_))
+    ));
+
+    server.inject_mouse_move(100, 200).await.unwrap();
+    server
+        .inject_key(KeyEvent::Down {
+            keyid: 0x61,
+            mask: 0,
+            button: 38,
+        })
+        .await
+        .unwrap();
+    server.inject_mouse_wheel(0, 120).await.unwrap();
+
+    assert_eq!(
+        peer.recv_timeout(2000).await,
+        Some(Message::MouseMove(MessageMouseMove { x: 100, y: 200 }))
+    );
+    assert_eq!(
+        peer.recv_timeout(2000).await,
+        Some(Message::KeyDown(MessageKeyDown {
+            keyid: 0x61,
+            mask: 0,
+            button: 38,
+        }))
+    );
+    assert_eq!(
+        peer.recv_timeout(2000).await,
+        Some(Message::MouseWheel(MessageMouseWheel {
+            xdelta: 0,
+            ydelta: 120,
+        }))
+    );
+}
+
+#[tokio::test]
+async fn test_server_forward_language() {
+    use schengen::protocol::{LengthPrefixedString, Message, MessageKeyDown};
+    use schengen::server::KeyEvent;
+    use std::sync::Arc;
+
+    for forward in [true, false] {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = Builder::new()
+            .add_client(
+                ClientBuilder::new("laptop")
+                    .position(Position::Left)
+                    .build(),
+            )
+            .unwrap()
+            .forward_language(forward)
+            .listen_on_stream(listener)
+            .await
+            .unwrap();
+        let server = Arc::new(server);
+
+        common::spawn_event_drain(&server);
+
+        let (mut peer, _) = common::mock_client_handshake(port, "laptop").await;
+        let server_ref = Arc::clone(&server);
+        assert!(
+            common::wait_for(
+                || {
+                    let server = Arc::clone(&server_ref);
+                    async move { server.clients().await.len() == 1 }
+                },
+                2000
+            )
+            .await
+        );
+
+        server.switch_to("laptop").await.unwrap();
+        assert!(matches!(
+            peer.recv_timeout(2000).await,
+            Some(Message::CursorEntered(_))
+        ));
+
+        server
+            .inject_key(KeyEvent::DownWithLanguage {
+                keyid: 0x61,
+                mask: 0,
+                button: 38,
+                lang: "de".to_string(),
+            })
+            .await
+            .unwrap();
+
+        match peer.recv_timeout(2000).await {
+            Some(Message::KeyDownWithLanguage(msg)) if forward => {
+                assert_eq!((msg.keyid, msg.mask, msg.button), (0x61, 0, 38));
+                assert_eq!(msg.lang, LengthPrefixedString("de".to_string()));
+            }
+            Some(Message::KeyDown(msg)) if !forward => {
+                assert_eq!(
+                    msg,
+                    MessageKeyDown {
+                        keyid: 0x61,
+                        mask: 0,
+                        button: 38,
+                    }
+                );
+            }
+            other => panic!("Unexpected key message (forward={forward}): {:?}", other),
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_server_events_stream() {
+    use schengen::server::ServerEvent;
+    use std::time::Duration;
+
+    let (server, port) = common::spawn_test_server("laptop").await;
+    let mut events = server.events();
+
+    let connect = tokio::spawn(async move {
+        let (peer, _) = common::mock_client_handshake(port, "laptop").await;
+        let addr = peer.stream.local_addr().unwrap();
+        (peer, addr)
+    });
+    let event = tokio::time::timeout(Duration::from_secs(2), events.next())
+        .await
+        .unwrap();
+    let (_peer, peer_addr) = connect.await.unwrap();
+    match event {
+        Some(ServerEvent::ClientConnected { name, addr, .. }) => {
+            assert_eq!(name, "laptop");
+            assert_eq!(addr, peer_addr);
+        }
+        other => panic!("Expected ClientConnected, got {:?}", other),
+    }
+
+    // A client that isn't configured fails the handshake
+    let connect = tokio::spawn(async move {
+        let stream = tokio::net::TcpStream::connect(("127.0.0.1", port))
+            .await
+            .unwrap();
+        let mut peer = common::RawPeer::new(stream);
+        peer.recv().await;
+        peer.send(schengen::protocol::Message::HelloBarrier(
+            schengen::protocol::MessageHelloBarrier {
+                major: 1,
+                minor: 8,
+                client_name: Some("stranger".to_string()),
+            },
+        ))
+        .await;
+        peer
+    });
+    let event = tokio::time::timeout(Duration::from_secs(2), events.next())
+        .await
+        .unwrap();
+    assert!(matches!(
+        event,
+        Some(ServerEvent::HandshakeFailed { error, .. }) if error.contains("stranger")
+    ));
+    connect.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_server_update_cursor_crosses_edges() {
+    use schengen::protocol::{Message, MessageCursorLeft};
+    use schengen::server::layout::Crossing;
+    use std::sync::Arc;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let laptop = ClientBuilder::new("laptop")
+        .position(Position::Left)
+        .build();
+    let server = Builder::new()
+        .dimensions(3840, 2160)
+        .add_client(laptop)
+        .unwrap()
+        .listen_on_stream(listener)
+        .await
+        .unwrap();
+    let server = Arc::new(server);
+
+    common::spawn_event_drain(&server);
+
+    let (mut peer, _) = common::mock_client_handshake(port, "laptop").await;
+    let server_ref = Arc::clone(&server);
+    assert!(
+        common::wait_for(
+            || {
+                let server = Arc::clone(&server_ref);
+                async move { server.clients().await.len() == 1 }
+            },
+            2000
+        )
+        .await
+    );
+
+    // No neighbor on the right, and the middle of the screen isn't an edge
+    assert_eq!(server.update_cursor(3839, 1000).await.unwrap(), None);
+    assert_eq!(server.update_cursor(1000, 1000).await.unwrap(), None);
+
+    // The 4K host's left edge maps onto the 1080p client
+    assert_eq!(
+        server.update_cursor(0, 1080).await.unwrap(),
+        Some(Crossing {
+            screen: Some("laptop".to_string()),
+            x: 1918,
+            y: 540,
+        })
+    );
+    match peer.recv_timeout(2000).await {
+        Some(Message::CursorEntered(msg)) => assert_eq!((msg.x, msg.y), (1918, 540)),
+        other => panic!("Expected CINN, got {:?}", other),
+    }
+
+    // And back onto the host
+    assert_eq!(
+        server.update_cursor(1919, 100).await.unwrap(),
+        Some(Crossing {
+            screen: None,
+            x: 1,
+            y: 200,
+        })
+    );
+    assert_eq!(
+        peer.recv_timeout(2000).await,
+        Some(Message::CursorLeft(MessageCursorLeft))
+    );
+    assert!(server.render_layout_ascii().contains("server*"));
+}
+
+#[tokio::test]
+async fn test_server_shared_edge_ranges() {
+    let top = ClientBuilder::new("top")
+        .position(Position::Left)
+        .edge_range(0, 50)
+        .build();
+    let bottom = ClientBuilder::new("bottom")
+        .position(Position::Left)
+        .edge_range(50, 100)
+        .build();
+    let builder = Builder::new()
+        .add_client(top)
+        .unwrap()
+        .add_client(bottom)
+        .expect("Non-overlapping ranges should share the edge");
+
+    let overlapping = ClientBuilder::new("overlapping")
+        .position(Position::Left)
+        .edge_range(40, 60)
+        .build();
+    assert!(matches!(
+        builder.add_client(overlapping),
+        Err(schengen::server::ServerError::ServerPositionOccupied { .. })
+    ));
+}
+
+#[test]
+#[should_panic(expected = "Invalid edge range")]
+fn test_server_rejects_invalid_edge_range() {
+    let _ = ClientBuilder::new("laptop")
+        .position(Position::Left)
+        .edge_range(50, 50);
+}
+
+#[tokio::test]
+async fn test_server_update_cursor_shared_edge() {
+    use schengen::server::layout::Crossing;
+    use std::sync::Arc;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let top = ClientBuilder::new("top")
+        .position(Position::Left)
+        .edge_range(0, 50)
+        .build();
+    let bottom = ClientBuilder::new("bottom")
+        .position(Position::Left)
+        .edge_range(50, 100)
+        .build();
+    let server = Builder::new()
+        .add_client(top)
+        .unwrap()
+        .add_client(bottom)
+        .unwrap()
+        .listen_on_stream(listener)
+        .await
+        .unwrap();
+    let server = Arc::new(server);
+    assert!(server.render_layout_ascii().contains("/"));
+
+    common::spawn_event_drain(&server);
+
+    let (_top, _) = common::mock_client_handshake(port, "top").await;
+    let (_bottom, _) = common::mock_client_handshake(port, "bottom").await;
+    let server_ref = Arc::clone(&server);
+    assert!(
+        common::wait_for(
+            || {
+                let server = Arc::clone(&server_ref);
+                async move { server.clients().await.len() == 2 }
+            },
+            2000
+        )
+        .await
+    );
+
+    // The upper half of the server's left edge leads to the top client
+    assert_eq!(
+        server.update_cursor(0, 100).await.unwrap(),
+        Some(Crossing {
+            screen: Some("top".to_string()),
+            x: 1918,
+            y: 200,
+        })
+    );
+    // Its whole right edge leads back onto the upper half of the server
+    assert_eq!(
+        server.update_cursor(1919, 540).await.unwrap(),
+        Some(Crossing {
+            screen: None,
+            x: 1,
+            y: 270,
+        })
+    );
+    // The lower half leads to the bottom client
+    assert_eq!(
+        server.update_cursor(0, 900).await.unwrap(),
+        Some(Crossing {
+            screen: Some("bottom".to_string()),
+            x: 1918,
+            y: 720,
+        })
+    );
+}
+
+#[tokio::test]
+async fn test_server_switch_corners() {
+    use schengen::protocol::{CornerMask, DsopOption};
+    use std::sync::Arc;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    let server = Builder::new()
+        .add_client(
+            ClientBuilder::new("laptop")
+                .position(Position::Left)
+                .build(),
+        )
+        .unwrap()
+        .switch_corners(CornerMask::TOP_LEFT | CornerMask::BOTTOM_LEFT)
+        .switch_corner_size(20)
+        .listen_on_stream(listener)
+        .await
+        .unwrap();
+    let server = Arc::new(server);
+
+    common::spawn_event_drain(&server);
+
+    let (_peer, options) = common::mock_client_handshake(port, "laptop").await;
+    assert!(
+        options.contains(&(DsopOption::ScreenSwitchCorners as u32, 0b0101)),
+        "SSCM missing from {:?}",
+        options
+    );
+    assert!(
+        options.contains(&(DsopOption::ScreenSwitchCornerSize as u32, 20)),
+        "SSCS missing from {:?}",
+        options
+    );
+
+    let server_ref = Arc::clone(&server);
+    assert!(
+        common::wait_for(
+            || {
+                let server = Arc::clone(&server_ref);
+                async move { server.clients().await.len() == 1 }
+            },
+            2000
+        )
+        .await
+    );
+
+    // The left edge doesn't switch within the corners
+    assert_eq!(server.update_cursor(0, 10).await.unwrap(), None);
+    assert_eq!(server.update_cursor(0, 1070).await.unwrap(), None);
+    assert!(server.update_cursor(0, 20).await.unwrap().is_some());
+}
+
+#[tokio::test]
+async fn test_server_lock_to_screen() {
+    use schengen::protocol::{DsopOption, Message};
+    use std::sync::Arc;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    let server = Builder::new()
+        .add_client(
+            ClientBuilder::new("laptop")
+                .position(Position::Left)
+                .build(),
+        )
+        .unwrap()
+        .default_lock_to_screen(true)
+        .listen_on_stream(listener)
+        .await
+        .unwrap();
+    let server = Arc::new(server);
+    assert!(server.is_locked_to_screen());
+
+    common::spawn_event_drain(&server);
+
+    let (mut peer, options) = common::mock_client_handshake(port, "laptop").await;
+    assert!(
+        options.contains(&(DsopOption::DefaultLockToScreenState as u32, 1)),
+        "LTSS missing from {:?}",
+        options
+    );
+
+    let server_ref = Arc::clone(&server);
+    assert!(
+        common::wait_for(
+            || {
+                let server = Arc::clone(&server_ref);
+                async move { server.clients().await.len() == 1 }
+            },
+            2000
+        )
+        .await
+    );
+
+    // Edge crossings are ignored while locked
+    assert_eq!(server.update_cursor(0, 500).await.unwrap(), None);
+    assert_eq!(peer.recv_timeout(200).await, None);
+
+    server.set_lock_to_screen(false);
+    assert!(server.update_cursor(0, 500).await.unwrap().is_some());
+    assert!(matches!(
+        peer.recv_timeout(2000).await,
+        Some(Message::CursorEntered(_))
+    ));
+}
+
+#[tokio::test]
+async fn test_server_switch_delay_and_double_tap() {
+    use schengen::protocol::DsopOption;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    let server = Builder::new()
+        .add_client(
+            ClientBuilder::new("laptop")
+                .position(Position::Left)
+                .build(),
+        )
+        .unwrap()
+        .switch_delay(Duration::from_millis(200))
+        .switch_double_tap(Duration::from_millis(150))
+        .listen_on_stream(listener)
+        .await
+        .unwrap();
+    let server = Arc::new(server);
+
+    common::spawn_event_drain(&server);
+
+    let (_peer, options) = common::mock_client_handshake(port, "laptop").await;
+    assert!(options.contains(&(DsopOption::ScreenSwitchDelay as u32, 200)));
+    assert!(options.contains(&(DsopOption::ScreenSwitchTwoTap as u32, 150)));
+
+    let server_ref = Arc::clone(&server);
+    assert!(
+        common::wait_for(
+            || {
+                let server = Arc::clone(&server_ref);
+                async move { server.clients().await.len() == 1 }
+            },
+            2000
+        )
+        .await
+    );
+
+    // A quick brush against the edge doesn't switch
+    assert_eq!(server.update_cursor(0, 500).await.unwrap(), None);
+    assert_eq!(server.update_cursor(100, 500).await.unwrap(), None);
+    tokio::time::sleep(Duration::from_millis(250)).await;
+    assert_eq!(server.update_cursor(0, 500).await.unwrap(), None);
+
+    // A sustained press does
+    tokio::time::sleep(Duration::from_millis(250)).await;
+    let crossing = server.update_cursor(0, 500).await.unwrap().unwrap();
+    assert_eq!(crossing.screen.as_deref(), Some("laptop"));
+
+    // Back onto the server, then a double tap switches without waiting
+    assert!(server.update_cursor(1919, 500).await.unwrap().is_none());
+    tokio::time::sleep(Duration::from_millis(250)).await;
+    assert!(server.update_cursor(1919, 500).await.unwrap().is_some());
+    assert_eq!(server.update_cursor(0, 500).await.unwrap(), None);
+    assert_eq!(server.update_cursor(100, 500).await.unwrap(), None);
+    let crossing = server.update_cursor(0, 500).await.unwrap().unwrap();
+    assert_eq!(crossing.screen.as_deref(), Some("laptop"));
+}
+
+#[tokio::test]
+async fn test_server_heartbeat() {
+    use schengen::protocol::{DsopOption, Message};
+    use schengen::server::{DisconnectReason, ServerEvent};
+    use std::time::Duration;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    let server = Builder::new()
+        .add_client(
+            ClientBuilder::new("laptop")
+                .position(Position::Left)
+                .build(),
+        )
+        .unwrap()
+        .heartbeat(Duration::from_millis(100))
+        .listen_on_stream(listener)
+        .await
+        .unwrap();
+
+    let events = tokio::spawn(async move {
+        let mut events = Vec::new();
+        loop {
+            let event = server.recv_event().await.unwrap();
+            let done = matches!(event, ServerEvent::ClientDisconnected { .. });
+            events.push(event);
+            if done {
+                return events;
+            }
+        }
+    });
+
+    let (mut peer, options) = common::mock_client_handshake(port, "laptop").await;
+    assert!(
+        options.contains(&(DsopOption::Heartbeat as u32, 100)),
+        "HART missing from {:?}",
+        options
+    );
+
+    // The client never answers, so the server keeps sending keepalives until it
+    // considers the client dead and closes the connection
+    let mut keepalives = 0;
+    let result = tokio::time::timeout(Duration::from_secs(2), async {
+        while let Some(message) = peer.recv().await {
+            if matches!(message, Message::KeepAlive(_)) {
+                keepalives += 1;
+            }
+        }
+    })
+    .await;
+    assert!(result.is_ok(), "Server should drop the silent client");
+    assert!(
+        keepalives >= 2,
+        "Expected periodic CALV, got {}",
+        keepalives
+    );
+
+    let events = tokio::time::timeout(Duration::from_secs(2), events)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(matches!(
+        events.last(),
+        Some(ServerEvent::ClientDisconnected {
+            reason: DisconnectReason::ConnectionLost,
+            ..
+        })
+    ));
+}
+
+#[tokio::test]
+async fn test_server_clients_info() {
+    use std::sync::Arc;
+    use std::time::Instant;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    let laptop = ClientBuilder::new("laptop")
+        .position(Position::Left)
+        .build();
+    let tablet = ClientBuilder::new("tablet")
+        .position(Position::Below)
+        .relative_to(&laptop)
+        .build();
+    let server = Builder::new()
+        .add_client(laptop)
+        .unwrap()
+        .add_client(tablet)
+        .unwrap()
+        .listen_on_stream(listener)
+        .await
+        .unwrap();
+    let server = Arc::new(server);
+
+    common::spawn_event_drain(&server);
+
+    let before = Instant::now();
+    let (peer, _) = common::mock_client_handshake(port, "tablet").await;
+
+    let server_ref = Arc::clone(&server);
+    assert!(
+        common::wait_for(
+            || {
+                let server = Arc::clone(&server_ref);
+                async move { server.clients().await.len() == 1 }
+            },
+            2000
+        )
+        .await
+    );
+
+    let clients = server.clients().await;
+    let tablet = &clients[0];
+    assert_eq!(tablet.name(), "tablet");
+    assert_eq!(tablet.addr, peer.stream.local_addr().unwrap());
+    assert_eq!(tablet.position, Position::Below);
+    assert_eq!(tablet.relative_to.as_deref(), Some("laptop"));
+    assert_eq!(tablet.dimensions, Some((1920, 1080)));
+    assert!(tablet.connected_at >= before);
+}
+
+#[tokio::test]
+async fn test_server_screensaver() {
+    use schengen::protocol::{Message, MessageScreenSaverChange};
+    use schengen::server::ServerEvent;
+    use std::time::Duration;
+
+    let (server, port) = common::spawn_test_server("laptop").await;
+
+    let connect = tokio::spawn(async move { common::mock_client_handshake(port, "laptop").await });
+    let client_id = match server.recv_event().await.unwrap() {
+        ServerEvent::ClientConnected { client_id, .. } => client_id,
+        other => panic!("Expected ClientConnected, got {:?}", other),
+    };
+    let (mut peer, _) = connect.await.unwrap();
+
+    peer.send(Message::ScreenSaverChange(MessageScreenSaverChange {
+        state: 1,
+    }))
+    .await;
+    let event = tokio::time::timeout(Duration::from_secs(2), server.recv_event())
+        .await
+        .unwrap()
+        .unwrap();
+    match event {
+        ServerEvent::ScreenSaverChanged {
+            client_id: id,
+            active,
+        } => {
+            assert_eq!(id, client_id);
+            assert!(active);
+            server.set_screensaver(active).await.unwrap();
+        }
+        other => panic!("Expected ScreenSaverChanged, got {:?}", other),
+    }
+    assert_eq!(
+        peer.recv_timeout(2000).await,
+        Some(Message::ScreenSaverChange(MessageScreenSaverChange {
+            state: 1
+        }))
+    );
+
+    server.set_screensaver(false).await.unwrap();
+    assert_eq!(
+        peer.recv_timeout(2000).await,
+        Some(Message::ScreenSaverChange(MessageScreenSaverChange {
+            state: 0
+        }))
+    );
+}
+
+#[tokio::test]
+async fn test_server_ignores_stale_clipboard_grab() {
+    use schengen::protocol::{Message, MessageClientClipboard};
+    use schengen::server::ServerEvent;
+    use std::time::Duration;
+
+    let (server, port) = common::spawn_test_server("laptop").await;
+
+    let connect = tokio::spawn(async move { common::mock_client_handshake(port, "laptop").await });
+    let client_id = match server.recv_event().await.unwrap() {
+        ServerEvent::ClientConnected { client_id, .. } => client_id,
+        other => panic!("Expected ClientConnected, got {:?}", other),
+    };
+    let (mut peer, _) = connect.await.unwrap();
+
+    // Enter, leave and enter again, so the first sequence is outdated
+    let mut sequences = Vec::new();
+    for _ in 0..2 {
+        server.switch_to("laptop").await.unwrap();
+        match peer.recv_timeout(2000).await {
+            Some(Message::CursorEntered(msg)) => sequences.push(msg.sequence),
+            other => panic!("Expected CINN, got {:?}", other),
+        }
+        server.send_cursor_left(client_id).await.unwrap();
+        assert!(matches!(
+            peer.recv_timeout(2000).await,
+            Some(Message::CursorLeft(_))
+        ));
+    }
+    assert!(sequences[0] < sequences[1]);
+
+    for sequence in sequences.iter().copied() {
+        peer.send(Message::ClientClipboard(MessageClientClipboard {
+            id: 0,
+            sequence,
+        }))
+        .await;
+    }
+
+    let event = tokio::time::timeout(Duration::from_secs(2), server.recv_event())
+        .await
+        .unwrap()
+        .unwrap();
+    match event {
+        ServerEvent::ClipboardData { sequence, .. } => assert_eq!(sequence, sequences[1]),
+        other => panic!("Expected ClipboardData, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_server_sequence_wraps_around() {
+    use schengen::protocol::{Message, MessageClientClipboard};
+    use schengen::server::ServerEvent;
+    use std::time::Duration;
+
+    let (server, port) = common::spawn_test_server("laptop").await;
+
+    let connect = tokio::spawn(async move { common::mock_client_handshake(port, "laptop").await });
+    let client_id = match server.recv_event().await.unwrap() {
+        ServerEvent::ClientConnected { client_id, .. } => client_id,
+        other => panic!("Expected ClientConnected, got {:?}", other),
+    };
+    let (mut peer, _) = connect.await.unwrap();
+
+    // The next switch after the largest sequence number wraps around to 0
+    server
+        .send_cursor_entered(client_id, 0, 0, u32::MAX, 0)
+        .await
+        .unwrap();
+    assert!(matches!(
+        peer.recv_timeout(2000).await,
+        Some(Message::CursorEntered(msg)) if msg.sequence == u32::MAX
+    ));
+    server.send_cursor_left(client_id).await.unwrap();
+    assert!(matches!(
+        peer.recv_timeout(2000).await,
+        Some(Message::CursorLeft(_))
+    ));
+    server.switch_to("laptop").await.unwrap();
+    assert!(matches!(
+        peer.recv_timeout(2000).await,
+        Some(Message::CursorEntered(msg)) if msg.sequence == 0
+    ));
+
+    // A grab from before the wrap is stale, one from after it is not
+    for sequence in [u32::MAX, 0] {
+        peer.send(Message::ClientClipboard(MessageClientClipboard {
+            id: 0,
+            sequence,
+        }))
+        .await;
+    }
+
+    let event = tokio::time::timeout(Duration::from_secs(2), server.recv_event())
+        .await
+        .unwrap()
+        .unwrap();
+    match event {
+        ServerEvent::ClipboardData { sequence, .. } => assert_eq!(sequence, 0),
+        other => panic!("Expected ClipboardData, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_server_clipboard_changed_after_grab() {
+    use schengen::protocol::clipboard::{ClipboardContents, ClipboardFormat, ClipboardId};
+    use schengen::protocol::{Message, MessageClientClipboard};
+    use schengen::server::ServerEvent;
+    use std::time::Duration;
+
+    let (server, port) = common::spawn_test_server("laptop").await;
+
+    let connect = tokio::spawn(async move { common::mock_client_handshake(port, "laptop").await });
+    server.recv_event().await.unwrap();
+    let (mut peer, _) = connect.await.unwrap();
+
+    let contents = ClipboardContents::new(vec![(ClipboardFormat::Text, b"hello".to_vec())]);
+    let send_data = |id| {
+        contents
+            .to_messages(id, 0)
+            .into_iter()
+            .map(Message::from)
+            .collect::<Vec<_>>()
+    };
+
+    // Data without a grab, then a grab whose data never follows, then grab and data
+    for msg in send_data(ClipboardId::Selection) {
+        peer.send(msg).await;
+    }
+    peer.send(Message::ClientClipboard(MessageClientClipboard {
+        id: ClipboardId::Selection as u8,
+        sequence: 0,
+    }))
+    .await;
+    peer.send(Message::ClientClipboard(MessageClientClipboard {
+        id: ClipboardId::Clipboard as u8,
+        sequence: 0,
+    }))
+    .await;
+    for msg in send_data(ClipboardId::Clipboard) {
+        peer.send(msg).await;
+    }
+
+    let mut received = Vec::new();
+    let mut changed = Vec::new();
+    while changed.is_empty() {
+        let event = tokio::time::timeout(Duration::from_secs(2), server.recv_event())
+            .await
+            .expect("Server should report the changed clipboard")
+            .unwrap();
+        match event {
+            ServerEvent::ClipboardReceived { id, .. } => received.push(id),
+            ServerEvent::ClipboardChanged {
+                name, id, contents, ..
+            } => {
+                assert_eq!(name, "laptop");
+                assert_eq!(
+                    contents.formats,
+                    vec![(ClipboardFormat::Text, b"hello".to_vec())]
+                );
+                changed.push(id);
+            }
+            _ => {}
+        }
+    }
+    assert_eq!(
+        received,
+        vec![ClipboardId::Selection, ClipboardId::Clipboard]
+    );
+    assert_eq!(changed, vec![ClipboardId::Clipboard]);
+}
+
+#[tokio::test]
+async fn test_server_clipboard_grab_timeout() {
+    use schengen::protocol::clipboard::{ClipboardContents, ClipboardFormat, ClipboardId};
+    use schengen::protocol::{Message, MessageClientClipboard};
+    use schengen::server::{Builder, ClientBuilder, Position, ServerEvent};
+    use std::time::Duration;
+    use tokio::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = Builder::new()
+        .add_client(
+            ClientBuilder::new("laptop")
+                .position(Position::Left)
+                .build(),
+        )
+        .unwrap()
+        .clipboard_grab_timeout(Duration::from_millis(100))
+        .listen_on_stream(listener)
+        .await
+        .unwrap();
+
+    let connect = tokio::spawn(async move { common::mock_client_handshake(port, "laptop").await });
+    server.recv_event().await.unwrap();
+    let (mut peer, _) = connect.await.unwrap();
+
+    // The data arrives after the grab timed out
+    peer.send(Message::ClientClipboard(MessageClientClipboard {
+        id: ClipboardId::Clipboard as u8,
+        sequence: 0,
+    }))
+    .await;
+    // Messages are processed while events are received
+    let _ = tokio::time::timeout(Duration::from_millis(300), async {
+        loop {
+            server.recv_event().await.unwrap();
+        }
+    })
+    .await;
+    let contents = ClipboardContents::new(vec![(ClipboardFormat::Text, b"late".to_vec())]);
+    for msg in contents.to_messages(ClipboardId::Clipboard, 0) {
+        peer.send(msg.into()).await;
+    }
+
+    let mut received = false;
+    while let Ok(event) =
+        tokio::time::timeout(Duration::from_millis(500), server.recv_event()).await
+    {
+        match event.unwrap() {
+            ServerEvent::ClipboardReceived {
+                contents: received_contents,
+                ..
+            } => {
+                assert_eq!(received_contents, contents);
+                received = true;
+            }
+            ServerEvent::ClipboardChanged { .. } => panic!("The grab should have timed out"),
+            _ => {}
+        }
+    }
+    assert!(received, "Server should report the received clipboard");
+}
+
+#[tokio::test]
+async fn test_server_max_clipboard_size() {
+    use schengen::protocol::clipboard::{
+        ClipboardContents, ClipboardFormat, ClipboardId, MARK_FINAL, MARK_FIRST, MARK_MIDDLE,
+    };
+    use schengen::protocol::{Message, MessageClipboardData};
+    use schengen::server::{Builder, ClientBuilder, Position, ServerEvent};
+    use std::time::Duration;
+    use tokio::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = Builder::new()
+        .add_client(
+            ClientBuilder::new("laptop")
+                .position(Position::Left)
+                .build(),
+        )
+        .unwrap()
+        .max_clipboard_size(32)
+        .listen_on_stream(listener)
+        .await
+        .unwrap();
+
+    let connect = tokio::spawn(async move { common::mock_client_handshake(port, "laptop").await });
+    server.recv_event().await.unwrap();
+    let (mut peer, _) = connect.await.unwrap();
+
+    let chunk = |mark, data: &[u8]| {
+        Message::ClipboardData(MessageClipboardData {
+            id: ClipboardId::Clipboard as u8,
+            sequence: 0,
+            mark,
+            data: data.into(),
+        })
+    };
+    // Larger than the limit, the chunks are ignored
+    let mut messages = vec![chunk(MARK_FIRST, b"1000000")];
+    messages.extend((0..4).map(|_| chunk(MARK_MIDDLE, b"more")));
+    messages.push(chunk(MARK_FINAL, b""));
+    let contents = ClipboardContents::new(vec![(ClipboardFormat::Text, b"small".to_vec())]);
+    messages.extend(
+        contents
+            .to_messages(ClipboardId::Clipboard, 0)
+            .into_iter()
+            .map(Message::from),
+    );
+    for msg in messages {
+        peer.send(msg).await;
+    }
+
+    let mut aborted = 0;
+    loop {
+        let event = tokio::time::timeout(Duration::from_secs(2), server.recv_event())
+            .await
+            .expect("Server should report the clipboard transfers")
+            .unwrap();
+        match event {
+            ServerEvent::ClipboardTransferAborted { name, id, .. } => {
+                assert_eq!(name, "laptop");
+                assert_eq!(id, ClipboardId::Clipboard as u8);
+                aborted += 1;
+            }
+            ServerEvent::ClipboardReceived {
+                contents: received, ..
+            } => {
+                assert_eq!(received, contents);
+                break;
+            }
+            _ => {}
+        }
+    }
+    // Only the first error of the oversized transfer is reported
+    assert_eq!(aborted, 1);
+}
+
+#[tokio::test]
+async fn test_server_file_received() {
+    use schengen::protocol::file_transfer::{MARK_CHUNK, MARK_END};
+    use schengen::protocol::{Message, MessageDragInfo, MessageFileTransfer};
+    use schengen::server::{Builder, ClientBuilder, Position, ServerEvent};
+    use std::time::Duration;
+    use tokio::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = Builder::new()
+        .add_client(
+            ClientBuilder::new("laptop")
+                .position(Position::Left)
+                .build(),
+        )
+        .unwrap()
+        .max_file_size(16)
+        .listen_on_stream(listener)
+        .await
+        .unwrap();
+
+    let connect = tokio::spawn(async move { common::mock_client_handshake(port, "laptop").await });
+    server.recv_event().await.unwrap();
+    let (mut peer, _) = connect.await.unwrap();
+
+    let transfer = |mark, data: &[u8]| {
+        Message::FileTransfer(MessageFileTransfer {
+            mark,
+            data: data.into(),
+        })
+    };
+    let mut messages = vec![
+        Message::DragInfo(
+            MessageDragInfo::from_paths(&["/home/me/notes.txt".to_string()]).unwrap(),
+        ),
+        Message::FileTransfer(MessageFileTransfer::data_start(15)),
+        transfer(MARK_CHUNK, b"first"),
+        // Not valid UTF-8
+        transfer(MARK_CHUNK, &[0x00, 0xff, 0x89, 0xfe, 0x80]),
+        transfer(MARK_CHUNK, b"third"),
+        transfer(MARK_END, b""),
+        // Larger than the limit, the chunks are ignored
+        Message::FileTransfer(MessageFileTransfer::data_start(17)),
+    ];
+    messages.extend((0..4).map(|_| transfer(MARK_CHUNK, b"more")));
+    messages.push(transfer(MARK_END, b""));
+    for msg in messages {
+        peer.send(msg).await;
+    }
+
+    let next_event = async || {
+        tokio::time::timeout(Duration::from_secs(2), server.recv_event())
+            .await
+            .expect("Server should report the file transfer")
+            .unwrap()
+    };
+    match next_event().await {
+        ServerEvent::FileReceived {
+            name, path, bytes, ..
+        } => {
+            assert_eq!(name, "laptop");
+            assert_eq!(path.as_deref(), Some("/home/me/notes.txt"));
+            assert_eq!(bytes, b"first\x00\xff\x89\xfe\x80third");
+        }
+        event => panic!("Expected FileReceived, got {:?}", event),
+    }
+    match next_event().await {
+        ServerEvent::FileTransferAborted { name, path, .. } => {
+            assert_eq!(name, "laptop");
+            assert_eq!(path, None);
+        }
+        event => panic!("Expected FileTransferAborted, got {:?}", event),
+    }
+
+    // Nothing more is reported for the aborted transfer
+    assert!(
+        tokio::time::timeout(Duration::from_millis(200), server.recv_event())
+            .await
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn test_server_broadcast() {
+    use schengen::protocol::{Message, MessageResetOptions};
+    use std::sync::Arc;
+
+    let (server, port) = common::spawn_test_server_multi(vec![
+        ("laptop".to_string(), Position::Left, None),
+        ("desktop".to_string(), Position::Right, None),
+    ])
+    .await;
+    let server = Arc::new(server);
+
+    common::spawn_event_drain(&server);
+
+    let (mut laptop, _) = common::mock_client_handshake(port, "laptop").await;
+    let (mut desktop, _) = common::mock_client_handshake(port, "desktop").await;
+
+    let server_ref = Arc::clone(&server);
+    assert!(
+        common::wait_for(
+            || {
+                let server = Arc::clone(&server_ref);
+                async move { server.clients().await.len() == 2 }
+            },
+            2000
+        )
+        .await
+    );
+
+    let results = server
+        .broadcast(Message::ResetOptions(MessageResetOptions))
+        .await;
+    assert_eq!(results.len(), 2);
+    assert!(results.iter().all(|(_, result)| result.is_ok()));
+
+    for peer in [&mut laptop, &mut desktop] {
+        assert_eq!(
+            peer.recv_timeout(2000).await,
+            Some(Message::ResetOptions(MessageResetOptions))
+        );
+    }
+}
+
+#[test]
+fn test_validate_reachable_layout() {
+    let laptop = ClientBuilder::new("laptop")
+        .position(Position::Left)
+        .build();
+    let desktop = ClientBuilder::new("desktop")
+        .position(Position::Right)
+        .build();
+    let tablet = ClientBuilder::new("tablet")
+        .position(Position::Below)
+        .relative_to(&laptop)
+        .build();
+    // Two monitors sharing the top edge of the desktop
+    let left_monitor = ClientBuilder::new("left-monitor")
+        .position(Position::Above)
+        .relative_to(&desktop)
+        .edge_range(0, 50)
+        .build();
+    let right_monitor = ClientBuilder::new("right-monitor")
+        .position(Position::Above)
+        .relative_to(&desktop)
+        .edge_range(50, 100)
+        .build();
+
+    let report = Builder::new()
+        .add_client(laptop)
+        .unwrap()
+        .add_client(desktop)
+        .unwrap()
+        .add_client(tablet)
+        .unwrap()
+        .add_client(left_monitor)
+        .unwrap()
+        .add_client(right_monitor)
+        .unwrap()
+        .validate()
+        .unwrap();
+
+    let screen = |name: &str| Some(name.to_string());
+    assert!(report.unreachable().is_empty());
+    assert_eq!(report.neighbors(None, Position::Left), [screen("laptop")]);
+    assert_eq!(report.neighbors(None, Position::Right), [screen("desktop")]);
+    assert!(report.neighbors(None, Position::Above).is_empty());
+    assert_eq!(report.neighbors(Some("laptop"), Position::Right), [None]);
+    assert_eq!(
+        report.neighbors(Some("laptop"), Position::Below),
+        [screen("tablet")]
+    );
+    assert_eq!(
+        report.neighbors(Some("tablet"), Position::Above),
+        [screen("laptop")]
+    );
+    assert_eq!(
+        report.neighbors(Some("desktop"), Position::Above),
+        [screen("left-monitor"), screen("right-monitor")]
+    );
+    assert_eq!(
+        report.neighbors(Some("right-monitor"), Position::Below),
+        [screen("desktop")]
+    );
+    // The monitors sit side by side in the same cell, not next to each other
+    assert!(
+        report
+            .neighbors(Some("left-monitor"), Position::Right)
+            .is_empty()
+    );
+}
+
+#[test]
+fn test_validate_orphaned_island() {
+    use schengen::server::ServerError;
+
+    // To the right of a client on the left is where the primary screen is
+    let laptop = ClientBuilder::new("laptop")
+        .position(Position::Left)
+        .build();
+    let hidden = ClientBuilder::new("hidden")
+        .position(Position::Right)
+        .relative_to(&laptop)
+        .build();
+    let behind_hidden = ClientBuilder::new("behind-hidden")
+        .position(Position::Below)
+        .relative_to(&hidden)
+        .build();
+
+    let builder = Builder::new()
+        .add_client(laptop)
+        .unwrap()
+        .add_client(hidden)
+        .unwrap();
+    assert!(matches!(
+        builder.validate(),
+        Err(ServerError::UnreachableClients(clients)) if clients == ["hidden"]
+    ));
+
+    // Below the hidden client is also below the primary screen, so that one is reachable
+    let builder = builder.add_client(behind_hidden).unwrap();
+    assert!(matches!(
+        builder.validate(),
+        Err(ServerError::UnreachableClients(clients)) if clients == ["hidden"]
+    ));
+}
+
+#[tokio::test]
+async fn test_server_idle_events() {
+    use schengen::protocol::{Message, MessageMouseMove};
+    use schengen::server::ServerEvent;
+    use std::time::{Duration, Instant};
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = Builder::new()
+        .add_client(
+            ClientBuilder::new("laptop")
+                .position(Position::Left)
+                .build(),
+        )
+        .unwrap()
+        .idle_timeout(Duration::from_millis(200))
+        .listen_on_stream(listener)
+        .await
+        .unwrap();
+
+    let connect = tokio::spawn(async move { common::mock_client_handshake(port, "laptop").await });
+    let ServerEvent::ClientConnected { client_id, .. } = server.recv_event().await.unwrap() else {
+        panic!("Expected ClientConnected");
+    };
+    let (mut peer, _) = connect.await.unwrap();
+
+    // Input keeps the server active
+    server.send_mouse_move(client_id, 1, 1).await.unwrap();
+    let quiet_since = Instant::now();
+    let event = tokio::time::timeout(Duration::from_secs(2), server.recv_event())
+        .await
+        .expect("Server should report the idle timeout")
+        .unwrap();
+    assert!(matches!(event, ServerEvent::Idle));
+    assert!(quiet_since.elapsed() >= Duration::from_millis(200));
+
+    // Idle is reported once, a mouse move makes the server active again
+    server.send_mouse_move(client_id, 10, 20).await.unwrap();
+    let event = tokio::time::timeout(Duration::from_secs(2), server.recv_event())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(matches!(event, ServerEvent::Active));
+    assert_eq!(
+        peer.recv_timeout(2000).await,
+        Some(Message::MouseMove(MessageMouseMove { x: 1, y: 1 }))
+    );
+    assert_eq!(
+        peer.recv_timeout(2000).await,
+        Some(Message::MouseMove(MessageMouseMove { x: 10, y: 20 }))
+    );
+}
+
+#[tokio::test]
+async fn test_server_client_languages() {
+    use schengen::protocol::{Message, MessageLegacySynergy, MessageScreenSaverChange};
+    use schengen::server::ServerEvent;
+    use std::time::Duration;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = Builder::new()
+        .add_client(
+            ClientBuilder::new("laptop")
+                .position(Position::Left)
+                .build(),
+        )
+        .unwrap()
+        .listen_on_stream(listener)
+        .await
+        .unwrap();
+
+    let connect = tokio::spawn(async move { common::mock_client_handshake(port, "laptop").await });
+    server.recv_event().await.unwrap();
+    let (mut peer, _) = connect.await.unwrap();
+    assert!(server.clients().await[0].languages.is_empty());
+
+    peer.send(Message::LegacySynergy(MessageLegacySynergy {
+        data: "en, de".into(),
+    }))
+    .await;
+    // Processed in order, so the languages are known once this arrives
+    peer.send(Message::ScreenSaverChange(MessageScreenSaverChange {
+        state: 1,
+    }))
+    .await;
+    let event = tokio::time::timeout(Duration::from_secs(2), server.recv_event())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(matches!(event, ServerEvent::ScreenSaverChanged { .. }));
+    assert_eq!(server.clients().await[0].languages, ["en", "de"]);
+}
+
+#[tokio::test]
+async fn test_server_client_count() {
+    use schengen::protocol::{Message, MessageClose};
+
+    let (server, port) = common::spawn_test_server("laptop").await;
+    assert_eq!(server.client_count().await, 0);
+    assert!(!server.is_client_connected("laptop").await);
+
+    let connect = tokio::spawn(async move { common::mock_client_handshake(port, "laptop").await });
+    server.recv_event().await.unwrap();
+    let (mut peer, _) = connect.await.unwrap();
+    assert_eq!(server.client_count().await, 1);
+    assert!(server.is_client_connected("laptop").await);
+    assert!(!server.is_client_connected("desktop").await);
+
+    peer.send(Message::Close(MessageClose)).await;
+    server.recv_event().await.unwrap();
+    assert_eq!(server.client_count().await, 0);
+    assert!(!server.is_client_connected("laptop").await);
+}
+
+#[tokio::test]
+async fn test_server_protocol_version_override() {
+    use schengen::protocol::{Message, ProtocolVersion};
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = Builder::new()
+        .add_client(
+            ClientBuilder::new("laptop")
+                .position(Position::Left)
+                .build(),
+        )
+        .unwrap()
+        .protocol_version(ProtocolVersion::new(1, 6))
+        .listen_on_stream(listener)
+        .await
+        .unwrap();
+
+    let stream = tokio::net::TcpStream::connect(("127.0.0.1", port))
+        .await
+        .unwrap();
+    let mut peer = common::RawPeer::new(stream);
+    // The server sends its hello while accepting the connection in recv_event
+    let hello = tokio::select! {
+        hello = peer.recv() => hello,
+        event = server.recv_event() => panic!("Unexpected event {:?}", event),
+    };
+    match hello {
+        Some(Message::HelloBarrier(hello)) => assert_eq!((hello.major, hello.minor), (1, 6)),
+        other => panic!("Expected hello from server, got {:?}", other),
+    }
+}
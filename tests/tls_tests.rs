@@ -0,0 +1,155 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Integration tests for TLS connections
+
+#![cfg(feature = "tls")]
+
+use schengen::client::{Builder as ClientBuilder, Client};
+use schengen::server::ServerEvent;
+use schengen::server::{Builder as ServerBuilder, ClientBuilder as ServerClientBuilder, Position};
+use schengen::tls::rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use schengen::tls::rustls::{ClientConfig, RootCertStore};
+use schengen::tls::{TlsConfig, TlsIdentity};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+
+/// A self-signed certificate for "localhost" and its private key
+fn self_signed() -> (CertificateDer<'static>, PrivateKeyDer<'static>) {
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let key = PrivatePkcs8KeyDer::from(cert.signing_key.serialize_der());
+    (cert.cert.der().clone(), key.into())
+}
+
+/// Connect a client named "laptop" that trusts `cert` to the server on `port`
+async fn connect_tls_client(port: u16, cert: CertificateDer<'static>) -> Client {
+    let mut roots = RootCertStore::empty();
+    roots.add(cert).unwrap();
+    let config = ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+    ClientBuilder::new()
+        .server_addr(&format!("127.0.0.1:{}", port))
+        .unwrap()
+        .name("laptop")
+        .retry_count(1)
+        .tls(TlsConfig::new(config).server_name("localhost"))
+        .connect()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_tls_handshake() {
+    let (cert, key) = self_signed();
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    let server = ServerBuilder::new()
+        .add_client(
+            ServerClientBuilder::new("laptop")
+                .position(Position::Left)
+                .build(),
+        )
+        .unwrap()
+        .tls(TlsIdentity::new(vec![cert.clone()], key).unwrap())
+        .listen_on_stream(listener)
+        .await
+        .unwrap();
+    let server = Arc::new(server);
+
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let server_clone = Arc::clone(&server);
+    tokio::spawn(async move {
+        loop {
+            if let Ok(event) = server_clone.recv_event().await {
+                let _ = tx.send(event);
+            }
+        }
+    });
+
+    let client = connect_tls_client(port, cert).await;
+
+    let name = tokio::time::timeout(Duration::from_secs(2), async {
+        loop {
+            if let Some(ServerEvent::ClientConnected { name, .. }) = rx.recv().await {
+                return name;
+            }
+        }
+    })
+    .await
+    .expect("Client should connect over TLS");
+    assert_eq!(name, "laptop");
+    assert!(matches!(
+        client.peer_hello(),
+        schengen::protocol::Message::HelloBarrier(_)
+    ));
+
+    // Messages after the handshake go through TLS too
+    let client_id = server.clients().await[0].id();
+    server.send_mouse_move(client_id, 10, 20).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_tls_handshake_timeout() {
+    let (cert, key) = self_signed();
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    let server = ServerBuilder::new()
+        .add_client(
+            ServerClientBuilder::new("laptop")
+                .position(Position::Left)
+                .build(),
+        )
+        .unwrap()
+        .tls(TlsIdentity::new(vec![cert.clone()], key).unwrap())
+        .handshake_timeout(Duration::from_millis(200))
+        .listen_on_stream(listener)
+        .await
+        .unwrap();
+    let server = Arc::new(server);
+
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let server_clone = Arc::clone(&server);
+    tokio::spawn(async move {
+        loop {
+            if let Ok(event) = server_clone.recv_event().await {
+                let _ = tx.send(event);
+            }
+        }
+    });
+
+    // A peer that connects but never starts the TLS handshake
+    let _silent = tokio::net::TcpStream::connect(("127.0.0.1", port))
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    // A working client connecting after it is still served
+    let _client = connect_tls_client(port, cert).await;
+
+    let events = tokio::time::timeout(Duration::from_secs(2), async {
+        let mut events = Vec::new();
+        while let Some(event) = rx.recv().await {
+            let connected = matches!(event, ServerEvent::ClientConnected { .. });
+            events.push(event);
+            if connected {
+                return events;
+            }
+        }
+        events
+    })
+    .await
+    .expect("Client should connect after the silent peer timed out");
+    assert!(matches!(
+        events.as_slice(),
+        [
+            ServerEvent::HandshakeFailed { .. },
+            ServerEvent::ClientConnected { .. }
+        ]
+    ));
+}